@@ -0,0 +1,241 @@
+use sha2::{Digest, Sha256};
+use serde::{Deserialize, Serialize};
+use crate::confirmation_evidence::ConfirmationEvidence;
+use crate::email_confirmation_request::{EmailConfirmationRequest, Status};
+
+/// Payload version of the callbacks, chosen per client. Version 1 is the original message and stays the default.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Default)]
+pub enum CallbackPayloadVersion {
+    #[default]
+    V1,
+    V2,
+}
+
+/// Callback payload of version 1.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct ConfirmationMessage {
+    pub email: String,
+    pub status: Status,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// Callback payload of version 2, identifies the request and the event.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct CallbackEvent {
+    pub version: u8,
+    pub event_id: String, // the same for every delivery attempt of the event, for deduplication
+    pub event_type: CallbackEventType,
+    pub request_id: String,
+    pub client_id: String,
+    pub pk: String,
+    pub email: String,
+    pub status: Status,
+    pub previous_status: Option<Status>,
+    pub occurred_at: u64,
+    pub confirmed_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirmation_evidence: Option<Box<ConfirmationEvidence>>, // for confirmations by the confirm form
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub enum CallbackEventType {
+    #[serde(rename = "request.pending")]
+    RequestPending,
+    #[serde(rename = "request.confirmed")]
+    RequestConfirmed,
+    #[serde(rename = "request.expired")]
+    RequestExpired,
+    #[serde(rename = "request.bounced")]
+    RequestBounced,
+    #[serde(rename = "request.complained")]
+    RequestComplained,
+    #[serde(rename = "request.suppressed")]
+    RequestSuppressed,
+    #[serde(rename = "request.cancelled")]
+    RequestCancelled,
+    #[serde(rename = "request.declined")]
+    RequestDeclined,
+}
+
+/// Events a client gets when it has not chosen, the ones sent before subscriptions existed and the decline.
+pub const DEFAULT_SUBSCRIBED_EVENTS: [CallbackEventType; 6] = [
+    CallbackEventType::RequestConfirmed,
+    CallbackEventType::RequestDeclined,
+    CallbackEventType::RequestExpired,
+    CallbackEventType::RequestBounced,
+    CallbackEventType::RequestComplained,
+    CallbackEventType::RequestSuppressed,
+];
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[serde(untagged)]
+pub enum CallbackPayload {
+    V1(ConfirmationMessage),
+    V2(CallbackEvent),
+}
+
+impl CallbackEventType {
+    /// None for statuses that are not reported to the client.
+    pub fn for_status(status: &Status) -> Option<Self> {
+        match status {
+            Status::Pending => Some(CallbackEventType::RequestPending),
+            Status::Confirmed => Some(CallbackEventType::RequestConfirmed),
+            Status::Expired => Some(CallbackEventType::RequestExpired),
+            Status::Bounced => Some(CallbackEventType::RequestBounced),
+            Status::Complained => Some(CallbackEventType::RequestComplained),
+            Status::Suppressed => Some(CallbackEventType::RequestSuppressed),
+            Status::Cancelled => Some(CallbackEventType::RequestCancelled),
+            Status::Declined => Some(CallbackEventType::RequestDeclined),
+            Status::Queued | Status::Done | Status::CallbackFailed => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CallbackEventType::RequestPending => "request.pending",
+            CallbackEventType::RequestConfirmed => "request.confirmed",
+            CallbackEventType::RequestExpired => "request.expired",
+            CallbackEventType::RequestBounced => "request.bounced",
+            CallbackEventType::RequestComplained => "request.complained",
+            CallbackEventType::RequestSuppressed => "request.suppressed",
+            CallbackEventType::RequestCancelled => "request.cancelled",
+            CallbackEventType::RequestDeclined => "request.declined",
+        }
+    }
+}
+
+impl CallbackEvent {
+    /// The status change set updated_at, so the event is identified by the request, the event type and updated_at.
+    pub fn event_id(request: &EmailConfirmationRequest, event_type: CallbackEventType) -> String {
+        let digest = Sha256::digest(format!("{}|{}|{}", request.pk, event_type.as_str(), request.updated_at).as_bytes());
+        format!("evt_{}", &hex::encode(digest)[..32])
+    }
+}
+
+impl CallbackPayload {
+    /// None if the status of the request is not reported to the client.
+    pub fn new(version: CallbackPayloadVersion, request: &EmailConfirmationRequest) -> Option<Self> {
+        let event_type = CallbackEventType::for_status(&request.status)?;
+        let payload = match version {
+            CallbackPayloadVersion::V1 => CallbackPayload::V1(ConfirmationMessage {
+                email: request.email.clone(),
+                status: request.status.clone(),
+                reason: request.status_reason.clone(),
+            }),
+            CallbackPayloadVersion::V2 => CallbackPayload::V2(CallbackEvent {
+                version: 2,
+                event_id: CallbackEvent::event_id(request, event_type),
+                event_type,
+                request_id: request.request_id.clone(),
+                client_id: request.client_id.clone(),
+                pk: request.pk.clone(),
+                email: request.email.clone(),
+                status: request.status.clone(),
+                previous_status: request.previous_status.clone(),
+                occurred_at: request.updated_at,
+                confirmed_at: match (&request.status, &request.confirmation_evidence) {
+                    (Status::Confirmed, Some(evidence)) => Some(evidence.confirmed_at),
+                    (Status::Confirmed, None) => Some(request.updated_at),
+                    _ => None,
+                },
+                reason: request.status_reason.clone(),
+                confirmation_evidence: match request.status {
+                    Status::Confirmed => request.confirmation_evidence.clone().map(Box::new),
+                    _ => None,
+                },
+            }),
+        };
+        Some(payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::confirmation_evidence::IpAddressMode;
+
+    fn request(status: Status, previous_status: Status) -> EmailConfirmationRequest {
+        let mut request = EmailConfirmationRequest::new(
+            "email@example.com".to_string(), "client-1".to_string(), "request-1".to_string(), "https://client.example.com/callback".to_string());
+        request.signature_key = "75af2381-ecde-4113-af22-75c2c1407d98".to_string();
+        request.created_at = 1_741_592_476;
+        request.expires_at = 1_741_596_076;
+        request.updated_at = 1_741_592_536;
+        request.status = status;
+        request.previous_status = Some(previous_status);
+        request
+    }
+
+    fn assert_snapshot(payload: &CallbackPayload, snapshot: &str) {
+        let expected: serde_json::Value = serde_json::from_str(snapshot).unwrap();
+        assert_eq!(expected, serde_json::to_value(payload).unwrap(), "{}", serde_json::to_string_pretty(payload).unwrap());
+    }
+
+    #[test]
+    fn test_v1_payload_snapshot() {
+        let payload = CallbackPayload::new(CallbackPayloadVersion::V1, &request(Status::Confirmed, Status::Pending)).unwrap();
+        assert_snapshot(&payload, include_str!("../fixtures/callback-payload-v1-confirmed.json"));
+    }
+
+    #[test]
+    fn test_v2_confirmed_payload_snapshot() {
+        let payload = CallbackPayload::new(CallbackPayloadVersion::V2, &request(Status::Confirmed, Status::Pending)).unwrap();
+        assert_snapshot(&payload, include_str!("../fixtures/callback-payload-v2-confirmed.json"));
+    }
+
+    #[test]
+    fn test_v2_confirmed_with_evidence_payload_snapshot() {
+        let mut request = request(Status::Confirmed, Status::Pending);
+        request.confirmation_evidence = Some(ConfirmationEvidence {
+            confirmed_at: 1_741_592_535,
+            ip_address: Some("203.0.113.0".to_string()),
+            ip_address_mode: IpAddressMode::Truncated,
+            user_agent: Some("Mozilla/5.0 (X11; Linux x86_64; rv:131.0) Gecko/20100101 Firefox/131.0".to_string()),
+            locale: Some("sv-SE".to_string()),
+        });
+        let payload = CallbackPayload::new(CallbackPayloadVersion::V2, &request).unwrap();
+        assert_snapshot(&payload, include_str!("../fixtures/callback-payload-v2-confirmed-with-evidence.json"));
+        // Version 1 stays the original message
+        let payload = CallbackPayload::new(CallbackPayloadVersion::V1, &request).unwrap();
+        assert_snapshot(&payload, include_str!("../fixtures/callback-payload-v1-confirmed.json"));
+    }
+
+    #[test]
+    fn test_v2_bounced_payload_snapshot() {
+        let mut request = request(Status::Bounced, Status::Pending);
+        request.status_reason = Some("Permanent bounce (General)".to_string());
+        let payload = CallbackPayload::new(CallbackPayloadVersion::V2, &request).unwrap();
+        assert_snapshot(&payload, include_str!("../fixtures/callback-payload-v2-bounced.json"));
+    }
+
+    #[test]
+    fn test_event_id_is_stable_per_event() {
+        let confirmed = request(Status::Confirmed, Status::Pending);
+        let mut later = confirmed.clone();
+        later.updated_at += 1;
+        let event_id = CallbackEvent::event_id(&confirmed, CallbackEventType::RequestConfirmed);
+        assert_eq!(event_id, CallbackEvent::event_id(&confirmed.clone(), CallbackEventType::RequestConfirmed));
+        assert_ne!(event_id, CallbackEvent::event_id(&later, CallbackEventType::RequestConfirmed));
+    }
+
+    #[test]
+    fn test_v2_cancelled_payload_snapshot() {
+        let payload = CallbackPayload::new(CallbackPayloadVersion::V2, &request(Status::Cancelled, Status::Pending)).unwrap();
+        assert_snapshot(&payload, include_str!("../fixtures/callback-payload-v2-cancelled.json"));
+    }
+
+    #[test]
+    fn test_v2_declined_payload_snapshot() {
+        let payload = CallbackPayload::new(CallbackPayloadVersion::V2, &request(Status::Declined, Status::Pending)).unwrap();
+        assert_snapshot(&payload, include_str!("../fixtures/callback-payload-v2-declined.json"));
+    }
+
+    #[test]
+    fn test_unreported_status_has_no_payload() {
+        assert_eq!(None, CallbackPayload::new(CallbackPayloadVersion::V2, &request(Status::Queued, Status::Queued)));
+        assert_eq!(None, CallbackPayload::new(CallbackPayloadVersion::V2, &request(Status::Done, Status::Confirmed)));
+    }
+}
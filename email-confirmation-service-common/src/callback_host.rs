@@ -0,0 +1,230 @@
+use std::fmt;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::callback_url::CallbackTarget;
+
+// Consecutive failures after which the circuit of a host opens
+pub const CIRCUIT_FAILURE_THRESHOLD: u32 = 5;
+// How long an open circuit defers deliveries before one probe is let through
+pub const CIRCUIT_OPEN_PERIOD: Duration = Duration::from_secs(5 * 60);
+// Deliveries to one host at the same time, across all invocations of the callback lambda
+pub const HOST_CONCURRENCY_LIMIT: usize = 10;
+// A delivery that has not finished in this time is considered gone, e.g. the lambda timed out. Well above the callback timeout
+pub const IN_FLIGHT_LEASE: Duration = Duration::from_secs(60);
+// Deferral of a delivery that found the host busy, or a probe already under way
+pub const BUSY_HOST_DEFERRAL: Duration = Duration::from_secs(60);
+
+/// Health of one callback host, stored in its own table keyed by host. Only HTTP callbacks have one.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct CallbackHostHealth {
+    pub host: String, // PK
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub opened_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub in_flight: Vec<u64>, // start times of the deliveries in progress
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_failure_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_success_at: Option<u64>,
+    #[serde(default)]
+    pub version: u64, // for optimistic locking, every write increments it
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen, // one probe delivery is under way
+}
+
+/// Whether a delivery may go ahead.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Admission {
+    Deliver,
+    // The first delivery after the open period, its result closes or reopens the circuit
+    Probe,
+    Defer { until: u64, reason: String },
+}
+
+/// The host the health of a callback target is tracked under, with the port if it is not the default one.
+/// Queues and event buses are AWS services, they have no circuit.
+pub fn callback_host(target: &CallbackTarget) -> Option<String> {
+    let CallbackTarget::Http(url) = target else { return None };
+    let host = url.host_str()?.to_ascii_lowercase();
+    match url.port() {
+        Some(port) => Some(format!("{}:{}", host, port)),
+        None => Some(host),
+    }
+}
+
+impl CallbackHostHealth {
+    pub fn new(host: &str) -> Self {
+        CallbackHostHealth {
+            host: host.to_string(),
+            state: CircuitState::Closed,
+            consecutive_failures: 0,
+            opened_at: None,
+            in_flight: vec![],
+            last_error: None,
+            last_failure_at: None,
+            last_success_at: None,
+            version: 0,
+        }
+    }
+
+    /// Admitted deliveries hold a slot in in_flight until their result is recorded, or the lease runs out.
+    pub fn admit(&mut self, now: u64) -> Admission {
+        self.in_flight.retain(|started_at| now < started_at + IN_FLIGHT_LEASE.as_secs());
+        match self.state {
+            CircuitState::Open => {
+                let closes_at = self.opened_at.unwrap_or(now) + CIRCUIT_OPEN_PERIOD.as_secs();
+                if now < closes_at {
+                    return Admission::Defer { until: closes_at, reason: format!("Circuit open for {}", self.host) }
+                }
+                self.state = CircuitState::HalfOpen;
+                self.in_flight.push(now);
+                Admission::Probe
+            },
+            CircuitState::HalfOpen => {
+                if !self.in_flight.is_empty() {
+                    return Admission::Defer { until: now + BUSY_HOST_DEFERRAL.as_secs(), reason: format!("Circuit of {} is being probed", self.host) }
+                }
+                self.in_flight.push(now);
+                Admission::Probe
+            },
+            CircuitState::Closed => {
+                if self.in_flight.len() >= HOST_CONCURRENCY_LIMIT {
+                    return Admission::Defer { until: now + BUSY_HOST_DEFERRAL.as_secs(), reason: format!("{} deliveries to {} in progress", self.in_flight.len(), self.host) }
+                }
+                self.in_flight.push(now);
+                Admission::Deliver
+            },
+        }
+    }
+
+    /// Frees the slot taken at started_at. A failure is one that says the host is unhealthy, e.g. a timeout or a 503.
+    pub fn record(&mut self, started_at: u64, error: Option<&str>, now: u64) {
+        if let Some(position) = self.in_flight.iter().position(|in_flight| *in_flight == started_at) {
+            self.in_flight.remove(position);
+        }
+        match error {
+            None => {
+                self.state = CircuitState::Closed;
+                self.consecutive_failures = 0;
+                self.opened_at = None;
+                self.last_success_at = Some(now);
+            },
+            Some(error) => {
+                self.consecutive_failures += 1;
+                self.last_error = Some(error.to_string());
+                self.last_failure_at = Some(now);
+                if self.state == CircuitState::HalfOpen || self.consecutive_failures >= CIRCUIT_FAILURE_THRESHOLD {
+                    self.state = CircuitState::Open;
+                    self.opened_at = Some(now);
+                }
+            },
+        }
+    }
+}
+
+impl fmt::Display for CircuitState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CircuitState::Closed => write!(f, "Closed"),
+            CircuitState::Open => write!(f, "Open"),
+            CircuitState::HalfOpen => write!(f, "HalfOpen"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_741_592_476;
+
+    fn fail(health: &mut CallbackHostHealth, now: u64) {
+        assert_ne!(Admission::Probe, health.admit(now));
+        health.record(now, Some("Callback timed out"), now);
+    }
+
+    #[test]
+    fn test_callback_host() {
+        let target = |callback_url: &str| CallbackTarget::parse(callback_url).unwrap();
+        assert_eq!(Some("callback.example.com".to_string()), callback_host(&target("https://Callback.Example.com/hook?x=1")));
+        assert_eq!(Some("callback.example.com".to_string()), callback_host(&target("https://callback.example.com:443/hook")));
+        assert_eq!(Some("callback.example.com:8443".to_string()), callback_host(&target("https://callback.example.com:8443/hook")));
+        assert_eq!(None, callback_host(&target("eventbridge://confirmations")));
+    }
+
+    #[test]
+    fn test_circuit_opens_after_consecutive_failures() {
+        let mut health = CallbackHostHealth::new("callback.example.com");
+        for _ in 0..CIRCUIT_FAILURE_THRESHOLD - 1 {
+            fail(&mut health, NOW);
+        }
+        assert_eq!(CircuitState::Closed, health.state);
+        assert_eq!(Admission::Deliver, health.admit(NOW));
+        health.record(NOW, None, NOW);
+        assert_eq!(0, health.consecutive_failures);
+
+        for _ in 0..CIRCUIT_FAILURE_THRESHOLD {
+            fail(&mut health, NOW);
+        }
+        assert_eq!(CircuitState::Open, health.state);
+        assert_eq!(Admission::Defer { until: NOW + CIRCUIT_OPEN_PERIOD.as_secs(), reason: "Circuit open for callback.example.com".to_string() }, health.admit(NOW + 1));
+        assert!(health.in_flight.is_empty());
+    }
+
+    #[test]
+    fn test_one_probe_after_open_period() {
+        let mut health = CallbackHostHealth::new("callback.example.com");
+        for _ in 0..CIRCUIT_FAILURE_THRESHOLD {
+            fail(&mut health, NOW);
+        }
+        let probe_at = NOW + CIRCUIT_OPEN_PERIOD.as_secs();
+        assert_eq!(Admission::Probe, health.admit(probe_at));
+        assert_eq!(CircuitState::HalfOpen, health.state);
+        assert!(matches!(health.admit(probe_at), Admission::Defer { .. }));
+
+        // A failed probe opens the circuit again right away
+        health.record(probe_at, Some("Callback responded 503 Service Unavailable"), probe_at + 1);
+        assert_eq!(CircuitState::Open, health.state);
+        assert_eq!(Some(probe_at + 1), health.opened_at);
+
+        let probe_at = probe_at + 1 + CIRCUIT_OPEN_PERIOD.as_secs();
+        assert_eq!(Admission::Probe, health.admit(probe_at));
+        health.record(probe_at, None, probe_at);
+        assert_eq!(CircuitState::Closed, health.state);
+        assert_eq!(Admission::Deliver, health.admit(probe_at));
+    }
+
+    #[test]
+    fn test_lost_probe_is_replaced_after_lease() {
+        let mut health = CallbackHostHealth::new("callback.example.com");
+        health.state = CircuitState::HalfOpen;
+        health.in_flight = vec![NOW];
+        assert!(matches!(health.admit(NOW + 1), Admission::Defer { .. }));
+        assert_eq!(Admission::Probe, health.admit(NOW + IN_FLIGHT_LEASE.as_secs()));
+    }
+
+    #[test]
+    fn test_concurrency_is_capped_per_host() {
+        let mut health = CallbackHostHealth::new("callback.example.com");
+        for _ in 0..HOST_CONCURRENCY_LIMIT {
+            assert_eq!(Admission::Deliver, health.admit(NOW));
+        }
+        assert_eq!(Admission::Defer { until: NOW + BUSY_HOST_DEFERRAL.as_secs(), reason: "10 deliveries to callback.example.com in progress".to_string() }, health.admit(NOW));
+
+        health.record(NOW, None, NOW + 1);
+        assert_eq!(Admission::Deliver, health.admit(NOW + 1));
+        assert!(matches!(health.admit(NOW + 1), Admission::Defer { .. }));
+        // Slots of deliveries that never reported back expire
+        assert_eq!(Admission::Deliver, health.admit(NOW + IN_FLIGHT_LEASE.as_secs()));
+        assert_eq!(1, health.in_flight.len() - 1);
+    }
+}
@@ -0,0 +1,247 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use url::{Host, Url};
+
+// Callback urls with these schemes are delivered to an SQS queue or an EventBridge event bus, instead of by HTTP
+pub const QUEUE_CALLBACK_SCHEME: &str = "sqs";
+pub const EVENT_BUS_CALLBACK_SCHEME: &str = "eventbridge";
+// Queues and event buses the callback lambda may send to are named this, followed by the client_id
+pub const CALLBACK_TARGET_NAME_PREFIX: &str = "email-confirmation-callback-";
+
+/// Where a callback goes, chosen by the scheme of the callback url:
+/// - `https://callback.example.com/hook`
+/// - `sqs://sqs.eu-north-1.amazonaws.com/123456789012/queue-name`, the queue url with `sqs` as the scheme
+/// - `eventbridge://event-bus-name`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum CallbackTarget {
+    Http(Url),
+    Queue { queue_url: String },
+    EventBus { event_bus_name: String },
+}
+
+/// Why a callback url is refused. Callbacks are sent from inside the VPC, so they must not reach internal addresses.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum CallbackUrlError {
+    Invalid(String),
+    UnsupportedScheme(String),
+    NotHttps,
+    ForbiddenAddress(IpAddr),
+    ForbiddenHost(String),
+    DomainNotAllowed(String),
+    TargetNotAllowed(String),
+}
+
+impl fmt::Display for CallbackUrlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CallbackUrlError::Invalid(error) => write!(f, "callback_url is not a valid url: {}", error),
+            CallbackUrlError::UnsupportedScheme(scheme) => write!(f, "callback_url scheme {} is not one of https, {} and {}", scheme, QUEUE_CALLBACK_SCHEME, EVENT_BUS_CALLBACK_SCHEME),
+            CallbackUrlError::NotHttps => write!(f, "callback_url must be an https url"),
+            CallbackUrlError::ForbiddenAddress(ip) => write!(f, "callback_url must not point to the internal address {}", ip),
+            CallbackUrlError::ForbiddenHost(host) => write!(f, "callback_url must not point to the internal host {}", host),
+            CallbackUrlError::DomainNotAllowed(host) => write!(f, "callback_url host {} is not in the allowed callback domains of the client", host),
+            CallbackUrlError::TargetNotAllowed(name) => write!(f, "callback_url queue or event bus {} is not named {}<client_id>", name, CALLBACK_TARGET_NAME_PREFIX),
+        }
+    }
+}
+
+impl std::error::Error for CallbackUrlError {}
+
+impl CallbackTarget {
+    /// Only the form of the url is checked here, see validate_callback_url for what a client may use.
+    pub fn parse(callback_url: &str) -> Result<Self, CallbackUrlError> {
+        let url = Url::parse(callback_url).map_err(|error| CallbackUrlError::Invalid(error.to_string()))?;
+        match url.scheme() {
+            "https" | "http" => Ok(CallbackTarget::Http(url)),
+            QUEUE_CALLBACK_SCHEME => {
+                let host = url.host_str().unwrap_or_default();
+                let path_segments: Vec<&str> = url.path().trim_start_matches('/').split('/').collect();
+                let is_queue_url = host.starts_with("sqs.") && host.ends_with(".amazonaws.com")
+                    && path_segments.len() == 2
+                    && path_segments[0].len() == 12 && path_segments[0].chars().all(|c| c.is_ascii_digit())
+                    && !path_segments[1].is_empty();
+                if !is_queue_url {
+                    return Err(CallbackUrlError::Invalid(format!("not a queue url: {}", callback_url)))
+                }
+                Ok(CallbackTarget::Queue { queue_url: format!("https://{}{}", host, url.path()) })
+            },
+            EVENT_BUS_CALLBACK_SCHEME => {
+                let event_bus_name = url.host_str().unwrap_or_default();
+                if event_bus_name.is_empty() || !matches!(url.path(), "" | "/") {
+                    return Err(CallbackUrlError::Invalid(format!("not an event bus: {}", callback_url)))
+                }
+                Ok(CallbackTarget::EventBus { event_bus_name: event_bus_name.to_string() })
+            },
+            scheme => Err(CallbackUrlError::UnsupportedScheme(scheme.to_string())),
+        }
+    }
+}
+
+/// Checked when a request is created. An empty allowlist allows any public domain,
+/// otherwise the host must be one of the domains or a subdomain of one.
+/// The host is resolved only when the callback is sent, so the resolved addresses are checked then too.
+/// A queue or an event bus must be named for the client, see target_name_is_allowed. The permissions of the callback lambda
+/// limit it to the names with CALLBACK_TARGET_NAME_PREFIX, this keeps a client off the queues of the others.
+pub fn validate_callback_url(callback_url: &str, client_id: &str, allowed_domains: &[String]) -> Result<CallbackTarget, CallbackUrlError> {
+    let target = CallbackTarget::parse(callback_url)?;
+    let url = match &target {
+        CallbackTarget::Http(url) => url,
+        CallbackTarget::Queue { queue_url } => {
+            let queue_name = queue_url.rsplit('/').next().unwrap_or_default();
+            return match target_name_is_allowed(queue_name, client_id) {
+                true => Ok(target),
+                false => Err(CallbackUrlError::TargetNotAllowed(queue_name.to_string())),
+            }
+        },
+        CallbackTarget::EventBus { event_bus_name } => return match target_name_is_allowed(event_bus_name, client_id) {
+            true => Ok(target),
+            false => Err(CallbackUrlError::TargetNotAllowed(event_bus_name.to_string())),
+        },
+    };
+    if url.scheme() != "https" {
+        return Err(CallbackUrlError::NotHttps)
+    }
+    match url.host() {
+        None => return Err(CallbackUrlError::Invalid("no host".to_string())),
+        Some(Host::Ipv4(ip)) => check_ip_host(IpAddr::V4(ip), allowed_domains)?,
+        Some(Host::Ipv6(ip)) => check_ip_host(IpAddr::V6(ip), allowed_domains)?,
+        Some(Host::Domain(domain)) => {
+            let domain = domain.trim_end_matches('.').to_lowercase();
+            if domain == "localhost" || domain.ends_with(".localhost") {
+                return Err(CallbackUrlError::ForbiddenHost(domain))
+            }
+            if !domain_is_allowed(&domain, allowed_domains) {
+                return Err(CallbackUrlError::DomainNotAllowed(domain))
+            }
+        },
+    }
+    Ok(target)
+}
+
+fn check_ip_host(ip: IpAddr, allowed_domains: &[String]) -> Result<(), CallbackUrlError> {
+    if is_forbidden_ip(ip) {
+        return Err(CallbackUrlError::ForbiddenAddress(ip))
+    }
+    if !allowed_domains.is_empty() {
+        return Err(CallbackUrlError::DomainNotAllowed(ip.to_string()))
+    }
+    Ok(())
+}
+
+/// `email-confirmation-callback-<client_id>`, optionally followed by `-<anything>`, or `.fifo` for a FIFO queue.
+/// The dash keeps client-1 off the queues of client-10.
+fn target_name_is_allowed(name: &str, client_id: &str) -> bool {
+    let Some(rest) = name.strip_prefix(CALLBACK_TARGET_NAME_PREFIX).and_then(|rest| rest.strip_prefix(client_id)) else { return false };
+    !client_id.is_empty() && (rest.is_empty() || rest.starts_with('-') || rest == ".fifo")
+}
+
+pub(crate) fn domain_is_allowed(domain: &str, allowed_domains: &[String]) -> bool {
+    allowed_domains.is_empty() || allowed_domains.iter().any(|allowed_domain| {
+        let allowed_domain = allowed_domain.trim_end_matches('.').to_lowercase();
+        domain == allowed_domain || domain.ends_with(&format!(".{}", allowed_domain))
+    })
+}
+
+/// Loopback, private, link-local (e.g. the instance metadata service) and other non-public addresses.
+pub fn is_forbidden_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_forbidden_ipv4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_forbidden_ipv4(ip),
+            None => is_forbidden_ipv6(ip),
+        },
+    }
+}
+
+fn is_forbidden_ipv4(ip: Ipv4Addr) -> bool {
+    let octets = ip.octets();
+    ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        || octets[0] == 0 // "this network"
+        || (octets[0] == 100 && (octets[1] & 0b1100_0000) == 64) // shared address space 100.64.0.0/10
+        || (octets[0] == 192 && octets[1] == 0 && octets[2] == 0) // IETF protocol assignments
+        || (octets[0] == 198 && (octets[1] & 0b1111_1110) == 18) // benchmarking 198.18.0.0/15
+        || octets[0] >= 240 // reserved
+}
+
+fn is_forbidden_ipv6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        || ip.is_unique_local()
+        || ip.is_unicast_link_local()
+        || (segments[0] == 0x2001 && segments[1] == 0x0db8) // documentation
+        || (segments[0] == 0x0064 && segments[1] == 0xff9b) // NAT64, embeds an IPv4 address
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_https_only() {
+        assert!(validate_callback_url("https://callback.example.com/hook", "client-1", &[]).is_ok());
+        assert_eq!(Err(CallbackUrlError::NotHttps), validate_callback_url("http://callback.example.com/hook", "client-1", &[]));
+        assert_eq!(Err(CallbackUrlError::UnsupportedScheme("file".to_string())), validate_callback_url("file:///etc/passwd", "client-1", &[]));
+        assert!(matches!(validate_callback_url("not a url", "client-1", &[]), Err(CallbackUrlError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_internal_hosts_are_refused() {
+        for callback_url in ["https://127.0.0.1/hook", "https://169.254.169.254/latest/meta-data", "https://10.1.2.3/hook",
+                             "https://192.168.0.1/hook", "https://[::1]/hook", "https://[fd00::1]/hook", "https://[::ffff:10.0.0.1]/hook"] {
+            assert!(matches!(validate_callback_url(callback_url, "client-1", &[]), Err(CallbackUrlError::ForbiddenAddress(_))), "{}", callback_url);
+        }
+        assert!(matches!(validate_callback_url("https://localhost/hook", "client-1", &[]), Err(CallbackUrlError::ForbiddenHost(_))));
+        assert!(validate_callback_url("https://93.184.215.14/hook", "client-1", &[]).is_ok());
+    }
+
+    #[test]
+    fn test_domain_allowlist() {
+        let allowed_domains = vec!["example.com".to_string()];
+        assert!(validate_callback_url("https://example.com/hook", "client-1", &allowed_domains).is_ok());
+        assert!(validate_callback_url("https://Hooks.Example.com/hook", "client-1", &allowed_domains).is_ok());
+        assert_eq!(Err(CallbackUrlError::DomainNotAllowed("notexample.com".to_string())), validate_callback_url("https://notexample.com/hook", "client-1", &allowed_domains));
+        assert_eq!(Err(CallbackUrlError::DomainNotAllowed("example.com.evil.net".to_string())), validate_callback_url("https://example.com.evil.net/hook", "client-1", &allowed_domains));
+        assert!(matches!(validate_callback_url("https://93.184.215.14/hook", "client-1", &allowed_domains), Err(CallbackUrlError::DomainNotAllowed(_))));
+    }
+
+    #[test]
+    fn test_queue_and_event_bus_targets() {
+        assert_eq!(Ok(CallbackTarget::Queue { queue_url: "https://sqs.eu-north-1.amazonaws.com/123456789012/email-confirmation-callback-client-1".to_string() }),
+                   validate_callback_url("sqs://sqs.eu-north-1.amazonaws.com/123456789012/email-confirmation-callback-client-1", "client-1", &["example.com".to_string()]));
+        assert_eq!(Ok(CallbackTarget::EventBus { event_bus_name: "email-confirmation-callback-client-1-confirmations".to_string() }),
+                   validate_callback_url("eventbridge://email-confirmation-callback-client-1-confirmations", "client-1", &[]));
+        assert!(validate_callback_url("sqs://sqs.eu-north-1.amazonaws.com/123456789012/email-confirmation-callback-client-1.fifo", "client-1", &[]).is_ok());
+
+        // Queues and event buses of other clients, or that the callback lambda is not allowed to use
+        for callback_url in ["sqs://sqs.eu-north-1.amazonaws.com/123456789012/email-confirmation-callback-client-2",
+                             "sqs://sqs.eu-north-1.amazonaws.com/123456789012/email-confirmation-callback-client-10",
+                             "sqs://sqs.eu-north-1.amazonaws.com/123456789012/confirmations",
+                             "eventbridge://email-confirmation-callback-client-2",
+                             "eventbridge://email-confirmation-callback-client-1confirmations",
+                             "eventbridge://confirmations"] {
+            assert!(matches!(validate_callback_url(callback_url, "client-1", &["example.com".to_string()]), Err(CallbackUrlError::TargetNotAllowed(_))), "{}", callback_url);
+        }
+
+        for callback_url in ["sqs://evil.example.com/123456789012/queue", "sqs://sqs.eu-north-1.amazonaws.com/queue",
+                             "eventbridge://", "eventbridge://confirmations/extra"] {
+            assert!(matches!(CallbackTarget::parse(callback_url), Err(CallbackUrlError::Invalid(_))), "{}", callback_url);
+        }
+    }
+
+    #[test]
+    fn test_forbidden_ips() {
+        assert!(is_forbidden_ip("100.64.0.1".parse().unwrap()));
+        assert!(is_forbidden_ip("fe80::1".parse().unwrap()));
+        assert!(is_forbidden_ip("0.0.0.0".parse().unwrap()));
+        assert!(!is_forbidden_ip("8.8.8.8".parse().unwrap()));
+        assert!(!is_forbidden_ip("2606:4700:4700::1111".parse().unwrap()));
+    }
+}
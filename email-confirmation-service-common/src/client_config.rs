@@ -0,0 +1,430 @@
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use url::{Host, Url};
+use uuid::Uuid;
+use crate::callback_event::{CallbackEventType, CallbackPayloadVersion, DEFAULT_SUBSCRIBED_EVENTS};
+use crate::confirmation_evidence::IpAddressMode;
+use crate::link_click::LinkClickSettings;
+use crate::webhook_signature::{WEBHOOK_SIGNATURE_HEADER, WEBHOOK_TIMESTAMP_HEADER};
+
+// After a rotation the previous webhook secret keeps signing callbacks for this long, next to the new one
+pub const WEBHOOK_SECRET_ROTATION_OVERLAP: Duration = Duration::from_secs(24 * 60 * 60);
+// Start of every webhook secret. Base64 has no _, so it also tells the secrets stored before they were encrypted apart
+pub const WEBHOOK_SECRET_PREFIX: &str = "whsec_";
+// Secrets of a client are encrypted with this key of the encryption context set to its client_id,
+// so that a secret copied to another client cannot be decrypted
+pub const SECRET_ENCRYPTION_CONTEXT_KEY: &str = "client_id";
+// Headers the service sets itself, custom headers cannot replace them
+pub const RESERVED_CALLBACK_HEADERS: [&str; 7] = [
+    "content-type",
+    "content-length",
+    "transfer-encoding",
+    "host",
+    "connection",
+    WEBHOOK_TIMESTAMP_HEADER,
+    WEBHOOK_SIGNATURE_HEADER,
+];
+pub const BRANDING_PRODUCT_NAME_LIMIT: usize = 100;
+
+/// Per client settings, stored in their own table keyed by client_id.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct ClientConfig {
+    pub client_id: String, // PK
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub webhook_secrets: Vec<WebhookSecret>, // newest last
+    #[serde(default)]
+    pub callback_payload_version: CallbackPayloadVersion,
+    #[serde(default = "default_subscribed_events")]
+    pub subscribed_events: Vec<CallbackEventType>,
+    // Callback urls must be on one of these domains or their subdomains, any public domain if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_callback_domains: Vec<String>,
+    // Sent with every HTTP callback, e.g. an API key the callback endpoint expects
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub callback_headers: Vec<CallbackHeader>,
+    // An access token of the client's authorization server is sent as a bearer token with every HTTP callback
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callback_oauth2: Option<OAuth2ClientCredentials>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branding: Option<Branding>,
+    // Web origins, e.g. https://app.example.com, whose scripts may call the link click endpoint for the client's requests
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_origins: Vec<String>,
+    // How the IP address of the browser that confirmed a request is kept in its confirmation evidence
+    #[serde(default)]
+    pub confirmation_ip_address_mode: IpAddressMode,
+    #[serde(default)]
+    pub link_click: LinkClickSettings,
+}
+
+/// How the landing pages of the client's confirmation links look. Anything left out gets the default look.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Default)]
+pub struct Branding {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub product_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logo_url: Option<String>, // https only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub primary_color: Option<String>, // #rgb or #rrggbb
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub background_color: Option<String>,
+}
+
+impl Branding {
+    /// Colors end up in a style sheet, where HTML escaping does not help, so only hex colors are accepted.
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(product_name) = &self.product_name {
+            if product_name.trim().is_empty() || product_name.chars().count() > BRANDING_PRODUCT_NAME_LIMIT {
+                return Err(format!("product_name must have 1 to {} characters", BRANDING_PRODUCT_NAME_LIMIT))
+            }
+        }
+        if let Some(logo_url) = &self.logo_url {
+            if !is_valid_logo_url(logo_url) {
+                return Err(format!("logo_url must be an https url: {}", logo_url))
+            }
+        }
+        for color in [&self.primary_color, &self.background_color].into_iter().flatten() {
+            if !is_valid_color(color) {
+                return Err(format!("Colors must be #rgb or #rrggbb: {}", color))
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn is_valid_color(color: &str) -> bool {
+    match color.strip_prefix('#') {
+        Some(hex) => (hex.len() == 3 || hex.len() == 6) && hex.chars().all(|c| c.is_ascii_hexdigit()),
+        None => false,
+    }
+}
+
+pub fn is_valid_logo_url(logo_url: &str) -> bool {
+    matches!(Url::parse(logo_url), Ok(url) if url.scheme() == "https" && url.host().is_some())
+}
+
+/// An origin as browsers send it in the Origin header: https, a host and an optional port, nothing else.
+/// Returns it the way browsers serialize it, so it can be compared as a string.
+pub fn normalize_origin(origin: &str) -> Result<String, String> {
+    let invalid = || format!("Origins must be https://host or https://host:port: {}", origin);
+    let url = Url::parse(origin.trim()).map_err(|_| invalid())?;
+    if url.scheme() != "https" || url.host().is_none() || !url.username().is_empty() || url.password().is_some()
+        || url.path() != "/" || url.query().is_some() || url.fragment().is_some() || origin.trim().ends_with('/') {
+        return Err(invalid())
+    }
+    Ok(url.origin().ascii_serialization())
+}
+
+/// A domain of allowed_callback_domains: a plain domain name of at least two labels, no scheme, port, path, wildcard or IP address.
+/// Its subdomains are allowed too. Returns it in lower case and in punycode, the way url hosts are compared.
+pub fn normalize_allowed_domain(domain: &str) -> Result<String, String> {
+    let invalid = || format!("Allowed callback domains must be domain names like example.com: {}", domain);
+    let trimmed = domain.trim().trim_end_matches('.');
+    let Ok(Host::Domain(ascii)) = Host::parse(trimmed) else { return Err(invalid()) };
+    let labels: Vec<&str> = ascii.split('.').collect();
+    let is_domain_name = ascii.len() <= 253 && labels.len() >= 2
+        && labels.iter().all(|label| !label.is_empty() && label.len() <= 63 && !label.starts_with('-') && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+        && !labels[labels.len() - 1].chars().all(|c| c.is_ascii_digit())
+        && labels[labels.len() - 1] != "localhost";
+    match is_domain_name {
+        true => Ok(ascii),
+        false => Err(invalid()),
+    }
+}
+
+/// A secret of a client, encrypted with KMS by the REST API. Only the callback lambda can decrypt it.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone)]
+#[serde(transparent)]
+pub struct EncryptedSecret(pub String); // base64 of the KMS ciphertext
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct CallbackHeader {
+    pub name: String,
+    pub value: EncryptedSecret,
+}
+
+/// The OAuth2 client credentials grant, the token is requested with the client id and secret as basic auth.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone)]
+pub struct OAuth2ClientCredentials {
+    pub token_url: String,
+    pub client_id: String,
+    pub client_secret: EncryptedSecret,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct SanitizedOAuth2ClientCredentials {
+    pub token_url: String,
+    pub client_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+}
+
+/// Header names are HTTP tokens, values are visible ASCII with spaces, so nothing can be smuggled in with a line break.
+pub fn validate_callback_header(name: &str, value: &str) -> Result<(), String> {
+    let is_token_char = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+    if name.is_empty() || !name.chars().all(is_token_char) {
+        return Err(format!("Invalid header name: {}", name))
+    }
+    if RESERVED_CALLBACK_HEADERS.iter().any(|reserved| reserved.eq_ignore_ascii_case(name)) {
+        return Err(format!("Header {} is set by the service", name))
+    }
+    if value.trim().is_empty() || !value.chars().all(|c| c == ' ' || c == '\t' || c.is_ascii_graphic()) {
+        return Err(format!("Invalid value for header {}", name))
+    }
+    Ok(())
+}
+
+fn default_subscribed_events() -> Vec<CallbackEventType> {
+    DEFAULT_SUBSCRIBED_EVENTS.to_vec()
+}
+
+/// The secret is encrypted like the callback auth secrets, its plaintext is shown once, when it is created.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct WebhookSecret {
+    pub secret_id: String,
+    pub secret: EncryptedSecret,
+    pub created_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>, // set when the secret is rotated out
+}
+
+/// Client config without the secrets, the secret value is shown only once, when it is created.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct SanitizedClientConfig {
+    pub client_id: String,
+    pub webhook_secrets: Vec<SanitizedWebhookSecret>,
+    pub callback_payload_version: CallbackPayloadVersion,
+    pub subscribed_events: Vec<CallbackEventType>,
+    pub allowed_callback_domains: Vec<String>,
+    pub callback_headers: Vec<String>, // names only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callback_oauth2: Option<SanitizedOAuth2ClientCredentials>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branding: Option<Branding>,
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    #[serde(default)]
+    pub confirmation_ip_address_mode: IpAddressMode,
+    #[serde(default)]
+    pub link_click: LinkClickSettings,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct SanitizedWebhookSecret {
+    pub secret_id: String,
+    pub created_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+/// A new secret to sign callbacks with, in plaintext. It is encrypted before it is stored.
+pub fn new_webhook_secret() -> String {
+    format!("{}{}{}", WEBHOOK_SECRET_PREFIX, Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+impl WebhookSecret {
+    pub fn new(secret: EncryptedSecret, created_at: u64) -> Self {
+        WebhookSecret {
+            secret_id: Uuid::new_v4().to_string(),
+            secret,
+            created_at,
+            expires_at: None,
+        }
+    }
+
+    pub fn is_active(&self, now: u64) -> bool {
+        match self.expires_at {
+            Some(expires_at) => now < expires_at,
+            None => true,
+        }
+    }
+}
+
+impl ClientConfig {
+    pub fn new(client_id: &str) -> Self {
+        ClientConfig {
+            client_id: client_id.to_string(),
+            webhook_secrets: vec![],
+            callback_payload_version: CallbackPayloadVersion::default(),
+            subscribed_events: default_subscribed_events(),
+            allowed_callback_domains: vec![],
+            callback_headers: vec![],
+            callback_oauth2: None,
+            branding: None,
+            allowed_origins: vec![],
+            confirmation_ip_address_mode: IpAddressMode::default(),
+            link_click: LinkClickSettings::default(),
+        }
+    }
+
+    pub fn is_subscribed_to(&self, event_type: CallbackEventType) -> bool {
+        self.subscribed_events.contains(&event_type)
+    }
+
+    /// Secrets to sign callbacks with, newest first.
+    pub fn active_webhook_secrets(&self, now: u64) -> Vec<&EncryptedSecret> {
+        self.webhook_secrets.iter().rev()
+            .filter(|webhook_secret| webhook_secret.is_active(now))
+            .map(|webhook_secret| &webhook_secret.secret)
+            .collect()
+    }
+
+    /// Adds the new secret and lets the current ones expire after the overlap window. Expired secrets are dropped.
+    pub fn rotate_webhook_secret(&mut self, new_secret: WebhookSecret, now: u64) {
+        let overlap_ends_at = now + WEBHOOK_SECRET_ROTATION_OVERLAP.as_secs();
+        self.webhook_secrets.retain(|webhook_secret| webhook_secret.is_active(now));
+        for webhook_secret in self.webhook_secrets.iter_mut() {
+            webhook_secret.expires_at = Some(webhook_secret.expires_at.map_or(overlap_ends_at, |expires_at| expires_at.min(overlap_ends_at)));
+        }
+        self.webhook_secrets.push(new_secret);
+    }
+}
+
+impl From<ClientConfig> for SanitizedClientConfig {
+    fn from(client_config: ClientConfig) -> Self {
+        SanitizedClientConfig {
+            client_id: client_config.client_id,
+            webhook_secrets: client_config.webhook_secrets.into_iter()
+                .map(|webhook_secret| SanitizedWebhookSecret {
+                    secret_id: webhook_secret.secret_id,
+                    created_at: webhook_secret.created_at,
+                    expires_at: webhook_secret.expires_at,
+                })
+                .collect(),
+            callback_payload_version: client_config.callback_payload_version,
+            subscribed_events: client_config.subscribed_events,
+            allowed_callback_domains: client_config.allowed_callback_domains,
+            callback_headers: client_config.callback_headers.into_iter().map(|callback_header| callback_header.name).collect(),
+            callback_oauth2: client_config.callback_oauth2.map(|oauth2| SanitizedOAuth2ClientCredentials {
+                token_url: oauth2.token_url,
+                client_id: oauth2.client_id,
+                scope: oauth2.scope,
+            }),
+            branding: client_config.branding,
+            allowed_origins: client_config.allowed_origins,
+            confirmation_ip_address_mode: client_config.confirmation_ip_address_mode,
+            link_click: client_config.link_click,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_741_592_476;
+
+    fn webhook_secret(created_at: u64) -> WebhookSecret {
+        WebhookSecret::new(EncryptedSecret(format!("ciphertext-{}", created_at)), created_at)
+    }
+
+    #[test]
+    fn test_rotation_keeps_previous_secret_during_overlap() {
+        let mut client_config = ClientConfig::new("client-1");
+        let first = webhook_secret(NOW);
+        client_config.rotate_webhook_secret(first.clone(), NOW);
+        assert_eq!(vec![&first.secret], client_config.active_webhook_secrets(NOW));
+
+        let second = webhook_secret(NOW + 60);
+        client_config.rotate_webhook_secret(second.clone(), NOW + 60);
+        assert_eq!(vec![&second.secret, &first.secret], client_config.active_webhook_secrets(NOW + 60));
+
+        let overlap_ended = NOW + 60 + WEBHOOK_SECRET_ROTATION_OVERLAP.as_secs();
+        assert_eq!(vec![&second.secret], client_config.active_webhook_secrets(overlap_ended));
+    }
+
+    #[test]
+    fn test_repeated_rotation_does_not_extend_overlap() {
+        let mut client_config = ClientConfig::new("client-1");
+        client_config.rotate_webhook_secret(webhook_secret(NOW), NOW);
+        client_config.rotate_webhook_secret(webhook_secret(NOW + 10), NOW + 10);
+        client_config.rotate_webhook_secret(webhook_secret(NOW + 20), NOW + 20);
+
+        let overlap = WEBHOOK_SECRET_ROTATION_OVERLAP.as_secs();
+        let expiries: Vec<Option<u64>> = client_config.webhook_secrets.iter().map(|webhook_secret| webhook_secret.expires_at).collect();
+        assert_eq!(vec![Some(NOW + 10 + overlap), Some(NOW + 20 + overlap), None], expiries);
+    }
+
+    #[test]
+    fn test_stored_config_without_subscriptions_gets_the_original_events() {
+        let client_config: ClientConfig = serde_json::from_str(r#"{"client_id": "client-1"}"#).unwrap();
+        assert!(client_config.is_subscribed_to(CallbackEventType::RequestConfirmed));
+        assert!(client_config.is_subscribed_to(CallbackEventType::RequestExpired));
+        assert!(client_config.is_subscribed_to(CallbackEventType::RequestDeclined));
+        assert!(!client_config.is_subscribed_to(CallbackEventType::RequestPending));
+        assert!(!client_config.is_subscribed_to(CallbackEventType::RequestCancelled));
+    }
+
+    #[test]
+    fn test_sanitized_config_has_no_secrets() {
+        let mut client_config = ClientConfig::new("client-1");
+        client_config.rotate_webhook_secret(webhook_secret(NOW), NOW);
+        client_config.callback_headers = vec![CallbackHeader { name: "X-Api-Key".to_string(), value: EncryptedSecret("AQICAHh-ciphertext-1".to_string()) }];
+        client_config.callback_oauth2 = Some(OAuth2ClientCredentials {
+            token_url: "https://auth.example.com/oauth2/token".to_string(),
+            client_id: "email-confirmation".to_string(),
+            client_secret: EncryptedSecret("AQICAHh-ciphertext-2".to_string()),
+            scope: None,
+        });
+        let json = serde_json::to_string(&SanitizedClientConfig::from(client_config)).unwrap();
+        assert!(!json.contains("whsec_"));
+        assert!(!json.contains("ciphertext"));
+        assert!(json.contains("X-Api-Key"));
+    }
+
+    #[test]
+    fn test_branding_validation() {
+        let branding = Branding {
+            product_name: Some("Example App".to_string()),
+            logo_url: Some("https://cdn.example.com/logo.png".to_string()),
+            primary_color: Some("#0a66C2".to_string()),
+            background_color: Some("#fff".to_string()),
+        };
+        assert_eq!(Ok(()), branding.validate());
+        assert_eq!(Ok(()), Branding::default().validate());
+        assert!(Branding { logo_url: Some("javascript:alert(1)".to_string()), ..Default::default() }.validate().is_err());
+        assert!(Branding { logo_url: Some("http://cdn.example.com/logo.png".to_string()), ..Default::default() }.validate().is_err());
+        assert!(Branding { primary_color: Some("red".to_string()), ..Default::default() }.validate().is_err());
+        assert!(Branding { primary_color: Some("#fff;background:url(https://evil.example)".to_string()), ..Default::default() }.validate().is_err());
+        assert!(Branding { background_color: Some("#ggg".to_string()), ..Default::default() }.validate().is_err());
+        assert!(Branding { product_name: Some(" ".to_string()), ..Default::default() }.validate().is_err());
+        assert!(Branding { product_name: Some("x".repeat(BRANDING_PRODUCT_NAME_LIMIT + 1)), ..Default::default() }.validate().is_err());
+    }
+
+    #[test]
+    fn test_normalize_origin() {
+        assert_eq!(Ok("https://app.example.com".to_string()), normalize_origin("https://App.Example.com"));
+        assert_eq!(Ok("https://app.example.com:8443".to_string()), normalize_origin("https://app.example.com:8443"));
+        assert_eq!(Ok("https://app.example.com".to_string()), normalize_origin("https://app.example.com:443"));
+        for origin in ["http://app.example.com", "https://app.example.com/", "https://app.example.com/spa", "https://app.example.com?x=1",
+                       "https://user@app.example.com", "app.example.com", "*", "null"] {
+            assert!(normalize_origin(origin).is_err(), "{}", origin);
+        }
+    }
+
+    #[test]
+    fn test_normalize_allowed_domain() {
+        assert_eq!(Ok("example.com".to_string()), normalize_allowed_domain(" Example.COM. "));
+        assert_eq!(Ok("hooks.example.co.uk".to_string()), normalize_allowed_domain("hooks.example.co.uk"));
+        assert_eq!(Ok("xn--bcher-kva.example".to_string()), normalize_allowed_domain("bücher.example"));
+        for domain in ["", "com", "*.example.com", "https://example.com", "example.com:443", "example.com/hooks", "user@example.com",
+                       "10.0.0.1", "[::1]", "1.2.3", "app.localhost", "-example.com", "exa mple.com", "example..com"] {
+            assert!(normalize_allowed_domain(domain).is_err(), "{}", domain);
+        }
+    }
+
+    #[test]
+    fn test_callback_header_validation() {
+        assert_eq!(Ok(()), validate_callback_header("X-Api-Key", "k3y with spaces"));
+        assert_eq!(Ok(()), validate_callback_header("Authorization", "Basic dXNlcjpwYXNz"));
+        assert!(validate_callback_header("X Api Key", "key").is_err());
+        assert!(validate_callback_header("", "key").is_err());
+        assert!(validate_callback_header("X-Api-Key", "key\r\nX-Injected: 1").is_err());
+        assert!(validate_callback_header("X-Api-Key", " ").is_err());
+        assert!(validate_callback_header("content-type", "text/plain").is_err());
+        assert!(validate_callback_header("x-webhook-signature", "v1=forged").is_err());
+    }
+}
@@ -0,0 +1,68 @@
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+use crate::email_confirmation_request::EmailConfirmationRequest;
+
+/// Counts over the requests of a client that are still in the table.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Default)]
+pub struct ClientStats {
+    pub client_id: String,
+    pub requests: u64,
+    pub by_status: BTreeMap<String, u64>,
+    pub clicked_requests: u64, // clicked at least once by the recipient
+    pub scanner_only_requests: u64, // clicked by mail scanners only
+    pub clicks: u64,
+    pub scanner_clicks: u64,
+}
+
+impl ClientStats {
+    pub fn new(client_id: &str, requests: &[EmailConfirmationRequest]) -> Self {
+        let mut stats = ClientStats { client_id: client_id.to_string(), ..Default::default() };
+        for request in requests.iter().filter(|request| request.client_id == client_id) {
+            stats.requests += 1;
+            *stats.by_status.entry(request.status.to_string()).or_default() += 1;
+            stats.clicks += u64::from(request.click_count);
+            stats.scanner_clicks += u64::from(request.scanner_click_count);
+            if request.click_count > 0 {
+                stats.clicked_requests += 1;
+            } else if request.scanner_click_count > 0 {
+                stats.scanner_only_requests += 1;
+            }
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email_confirmation_request::Status;
+
+    fn request(request_id: &str, status: Status, click_count: u32, scanner_click_count: u32) -> EmailConfirmationRequest {
+        let mut request = EmailConfirmationRequest::new("email@example.com".to_string(), "client-1".to_string(), request_id.to_string(), "https://client.example.com/callback".to_string());
+        request.status = status;
+        request.click_count = click_count;
+        request.scanner_click_count = scanner_click_count;
+        request
+    }
+
+    #[test]
+    fn test_client_stats() {
+        let mut other_client = request("request-4", Status::Confirmed, 1, 0);
+        other_client.client_id = "client-2".to_string();
+        let requests = vec![
+            request("request-1", Status::Confirmed, 2, 1),
+            request("request-2", Status::Pending, 0, 3),
+            request("request-3", Status::Pending, 0, 0),
+            other_client,
+        ];
+        assert_eq!(ClientStats {
+            client_id: "client-1".to_string(),
+            requests: 3,
+            by_status: BTreeMap::from([("Confirmed".to_string(), 1), ("Pending".to_string(), 2)]),
+            clicked_requests: 1,
+            scanner_only_requests: 1,
+            clicks: 2,
+            scanner_clicks: 4,
+        }, ClientStats::new("client-1", &requests));
+    }
+}
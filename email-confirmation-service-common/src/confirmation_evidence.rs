@@ -0,0 +1,137 @@
+//! Proof of a confirmation for double opt-in: when it happened and what the browser that posted the confirm form sent.
+//!
+//! The link click handler passes the source IP, the user agent and the preferred locale of the POST with the status update.
+//! The REST API stores them with the request in the same write as the Confirmed status, the IP address as the client chose.
+
+use std::net::IpAddr;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+// Longer user agents are cut, they are evidence and not a place to store arbitrary data
+pub const USER_AGENT_LIMIT: usize = 512;
+pub const LOCALE_LIMIT: usize = 35;
+
+/// How the IP address of a confirmation is stored, chosen per client.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Default)]
+pub enum IpAddressMode {
+    #[default]
+    Full,
+    Truncated, // the network only: the last octet of an IPv4 address and all but the first 48 bits of an IPv6 address are zeroed
+    Hashed, // SHA-256 of the client id and the address, the same address gives the same hash within a client
+}
+
+/// What the link click handler saw, before the IP address mode of the client is applied.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Default)]
+pub struct ConfirmationContext {
+    #[serde(default)]
+    pub ip_address: Option<String>,
+    #[serde(default)]
+    pub user_agent: Option<String>,
+    #[serde(default)]
+    pub locale: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct ConfirmationEvidence {
+    pub confirmed_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip_address: Option<String>,
+    #[serde(default)]
+    pub ip_address_mode: IpAddressMode,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+}
+
+impl ConfirmationEvidence {
+    /// Anything that is not an IP address, or is empty, is left out.
+    pub fn new(confirmed_at: u64, context: ConfirmationContext, ip_address_mode: IpAddressMode, client_id: &str) -> Self {
+        let ip_address = context.ip_address
+            .and_then(|ip_address| ip_address.trim().parse::<IpAddr>().ok())
+            .map(|ip_address| stored_ip_address(ip_address, ip_address_mode, client_id));
+        let user_agent = context.user_agent
+            .map(|user_agent| user_agent.trim().chars().take(USER_AGENT_LIMIT).collect::<String>())
+            .filter(|user_agent| !user_agent.is_empty());
+        let locale = context.locale
+            .map(|locale| locale.trim().to_string())
+            .filter(|locale| !locale.is_empty() && locale.len() <= LOCALE_LIMIT && locale.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'));
+        ConfirmationEvidence { confirmed_at, ip_address, ip_address_mode, user_agent, locale }
+    }
+}
+
+fn stored_ip_address(ip_address: IpAddr, ip_address_mode: IpAddressMode, client_id: &str) -> String {
+    match ip_address_mode {
+        IpAddressMode::Full => ip_address.to_string(),
+        IpAddressMode::Truncated => match ip_address {
+            IpAddr::V4(ip) => {
+                let [a, b, c, _] = ip.octets();
+                IpAddr::from([a, b, c, 0]).to_string()
+            },
+            IpAddr::V6(ip) => {
+                let segments = ip.segments();
+                IpAddr::from([segments[0], segments[1], segments[2], 0, 0, 0, 0, 0]).to_string()
+            },
+        },
+        IpAddressMode::Hashed => hex::encode(Sha256::digest(format!("{}|{}", client_id, ip_address).as_bytes())),
+    }
+}
+
+/// The locale the browser prefers most, the first language of an Accept-Language header.
+pub fn preferred_locale(accept_language: &str) -> Option<String> {
+    accept_language.split(',')
+        .map(|language| language.split(';').next().unwrap_or_default().trim())
+        .find(|language| !language.is_empty() && *language != "*")
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(ip_address: &str) -> ConfirmationContext {
+        ConfirmationContext {
+            ip_address: Some(ip_address.to_string()),
+            user_agent: Some("Mozilla/5.0 (X11; Linux x86_64; rv:131.0) Gecko/20100101 Firefox/131.0".to_string()),
+            locale: Some("sv-SE".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_ip_address_modes() {
+        let full = ConfirmationEvidence::new(1741592536, context("203.0.113.74"), IpAddressMode::Full, "client-1");
+        assert_eq!(Some("203.0.113.74"), full.ip_address.as_deref());
+        assert_eq!(Some("sv-SE"), full.locale.as_deref());
+
+        let truncated = ConfirmationEvidence::new(1741592536, context("203.0.113.74"), IpAddressMode::Truncated, "client-1");
+        assert_eq!(Some("203.0.113.0"), truncated.ip_address.as_deref());
+        let truncated = ConfirmationEvidence::new(1741592536, context("2001:db8:85a3:8d3:1319:8a2e:370:7348"), IpAddressMode::Truncated, "client-1");
+        assert_eq!(Some("2001:db8:85a3::"), truncated.ip_address.as_deref());
+
+        let hashed = ConfirmationEvidence::new(1741592536, context("203.0.113.74"), IpAddressMode::Hashed, "client-1");
+        assert_eq!(64, hashed.ip_address.as_ref().unwrap().len());
+        assert!(!hashed.ip_address.as_ref().unwrap().contains("203.0.113"));
+        assert_eq!(hashed, ConfirmationEvidence::new(1741592536, context("203.0.113.74"), IpAddressMode::Hashed, "client-1"));
+        assert_ne!(hashed.ip_address, ConfirmationEvidence::new(1741592536, context("203.0.113.74"), IpAddressMode::Hashed, "client-2").ip_address);
+    }
+
+    #[test]
+    fn test_unusable_values_are_left_out() {
+        let evidence = ConfirmationEvidence::new(1741592536, ConfirmationContext {
+            ip_address: Some("not an ip".to_string()),
+            user_agent: Some(" ".to_string()),
+            locale: Some("<script>".to_string()),
+        }, IpAddressMode::Full, "client-1");
+        assert_eq!(ConfirmationEvidence { confirmed_at: 1741592536, ip_address: None, ip_address_mode: IpAddressMode::Full, user_agent: None, locale: None }, evidence);
+
+        let long_user_agent = ConfirmationEvidence::new(1741592536, ConfirmationContext { user_agent: Some("x".repeat(2000)), ..Default::default() }, IpAddressMode::Full, "client-1");
+        assert_eq!(USER_AGENT_LIMIT, long_user_agent.user_agent.unwrap().len());
+    }
+
+    #[test]
+    fn test_preferred_locale() {
+        assert_eq!(Some("sv-SE".to_string()), preferred_locale("sv-SE,sv;q=0.9,en-US;q=0.8,en;q=0.7"));
+        assert_eq!(Some("en".to_string()), preferred_locale(" *, en;q=0.5"));
+        assert_eq!(None, preferred_locale(""));
+    }
+}
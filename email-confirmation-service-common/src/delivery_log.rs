@@ -0,0 +1,77 @@
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::callback_event::CallbackEventType;
+
+// Deliveries are removed by DynamoDB TTL after this period
+pub const DELIVERY_LOG_TIME_TO_LIVE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+// Errors may quote the client's response, only the beginning is kept
+pub const DELIVERY_LOG_ERROR_LIMIT: usize = 256;
+
+/// One attempt to deliver a callback, successful or not. Stored in its own table, keyed by pk and delivery_id.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct CallbackDelivery {
+    pub pk: String, // PK, of the confirmation request
+    pub delivery_id: String, // SK, sorts in the order of the attempts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_type: Option<CallbackEventType>,
+    pub callback_url: String,
+    pub attempted_at: u64,
+    pub latency_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_status: Option<u16>, // None if there was no response, e.g. a timeout
+    pub result: DeliveryResult,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub replay: bool,
+    pub payload: String, // the exact body that was sent, a replay sends it again
+    pub expires_at: u64, // DynamoDB TTL attribute
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub enum DeliveryResult {
+    Delivered,
+    Retryable,
+    Permanent,
+    Deferred, // not attempted, the circuit of the host was open or the host was busy
+}
+
+impl CallbackDelivery {
+    /// Zero padded milliseconds first, so that the ids sort by time. The nonce keeps attempts in the same millisecond apart.
+    pub fn delivery_id(attempted_at_millis: u64, nonce: u32) -> String {
+        format!("{:016}-{:08x}", attempted_at_millis, nonce)
+    }
+}
+
+pub fn truncate_error(error: &str) -> String {
+    if error.len() <= DELIVERY_LOG_ERROR_LIMIT {
+        return error.to_string()
+    }
+    let mut end = DELIVERY_LOG_ERROR_LIMIT;
+    while !error.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}...", &error[..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delivery_ids_sort_by_time() {
+        let earlier = CallbackDelivery::delivery_id(999_999, u32::MAX);
+        let later = CallbackDelivery::delivery_id(1_741_592_476_000, 0);
+        assert!(earlier < later);
+        assert_eq!("0001741592476000-00000000", later);
+    }
+
+    #[test]
+    fn test_long_errors_are_truncated() {
+        assert_eq!("Callback responded 404 Not Found", truncate_error("Callback responded 404 Not Found"));
+        let truncated = truncate_error(&"ä".repeat(DELIVERY_LOG_ERROR_LIMIT));
+        assert_eq!(DELIVERY_LOG_ERROR_LIMIT + "...".len(), truncated.len());
+    }
+}
@@ -0,0 +1,117 @@
+//! Cross-device confirmation: the recipient opens the email on another device than the one they signed up on.
+//!
+//! A request created with `cross_device` gets a short display code. Once the request is confirmed, the landing page
+//! shows the code, and the recipient enters it on the device they signed up on. The client checks it there, so only
+//! the person who confirmed can finish the sign up, not whoever started it with their address.
+
+use uuid::Uuid;
+use crate::email_confirmation_request::{EmailConfirmationRequest, Status};
+
+// No 0, O, 1 or I, they are easily mistaken for one another. 32 characters, so a random byte maps to one without bias
+pub const DISPLAY_CODE_ALPHABET: &[u8; 32] = b"23456789ABCDEFGHJKLMNPQRSTUVWXYZ";
+pub const DISPLAY_CODE_LENGTH: usize = 6;
+pub const DISPLAY_CODE_ATTEMPTS_LIMIT: u32 = 5;
+pub const DISPLAY_CODE_INVALID_ERROR_CODE: &str = "DISPLAY_CODE_INVALID";
+// The original device waits at most this long for a result, below the 29 seconds API Gateway allows
+pub const RESULT_WAIT_LIMIT_SECS: u64 = 25;
+
+/// 30 random bits: the first bytes of a v4 UUID are all random.
+pub fn new_display_code() -> String {
+    Uuid::new_v4().as_bytes()[..DISPLAY_CODE_LENGTH].iter()
+        .map(|byte| char::from(DISPLAY_CODE_ALPHABET[usize::from(*byte) % DISPLAY_CODE_ALPHABET.len()]))
+        .collect()
+}
+
+/// The code as shown, in two groups of three: `K7M-4QX`.
+pub fn format_display_code(display_code: &str) -> String {
+    let (first, second) = display_code.split_at(display_code.len().min(DISPLAY_CODE_LENGTH / 2));
+    format!("{}-{}", first, second)
+}
+
+/// What the recipient typed, without the dash or spaces and in upper case.
+pub fn normalize_display_code(input: &str) -> String {
+    input.chars().filter(|c| c.is_ascii_alphanumeric()).map(|c| c.to_ascii_uppercase()).collect()
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DisplayCodeCheck {
+    Valid,
+    Invalid,
+    NotCrossDevice,
+    NotConfirmed,
+    AlreadyVerified,
+    TooManyAttempts,
+}
+
+pub fn check_display_code(confirmation_request: &EmailConfirmationRequest, input: &str) -> DisplayCodeCheck {
+    let Some(display_code) = confirmation_request.display_code.as_deref().filter(|_| confirmation_request.cross_device) else {
+        return DisplayCodeCheck::NotCrossDevice
+    };
+    if confirmation_request.status != Status::Confirmed && confirmation_request.status != Status::Done {
+        return DisplayCodeCheck::NotConfirmed
+    }
+    if confirmation_request.display_code_verified_at.is_some() {
+        return DisplayCodeCheck::AlreadyVerified
+    }
+    if confirmation_request.display_code_attempts >= DISPLAY_CODE_ATTEMPTS_LIMIT {
+        return DisplayCodeCheck::TooManyAttempts
+    }
+    match normalize_display_code(input) == display_code {
+        true => DisplayCodeCheck::Valid,
+        false => DisplayCodeCheck::Invalid,
+    }
+}
+
+/// The code for the landing page: only once the request is confirmed, and while it can still be verified.
+pub fn shown_display_code(confirmation_request: &EmailConfirmationRequest) -> Option<String> {
+    match check_display_code(confirmation_request, "") {
+        DisplayCodeCheck::Valid | DisplayCodeCheck::Invalid => confirmation_request.display_code.as_deref().map(format_display_code),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cross_device_request(status: Status) -> EmailConfirmationRequest {
+        let mut request = EmailConfirmationRequest::new("email@example.com".to_string(), "client-1".to_string(), "request-1".to_string(), "https://client.example.com/callback".to_string());
+        request.cross_device = true;
+        request.display_code = Some("K7M4QX".to_string());
+        request.status = status;
+        request
+    }
+
+    #[test]
+    fn test_new_display_code() {
+        let display_code = new_display_code();
+        assert_eq!(DISPLAY_CODE_LENGTH, display_code.len());
+        assert!(display_code.bytes().all(|byte| DISPLAY_CODE_ALPHABET.contains(&byte)));
+        assert_eq!("K7M-4QX", format_display_code("K7M4QX"));
+        assert_eq!("K7M4QX", normalize_display_code(" k7m-4qx "));
+    }
+
+    #[test]
+    fn test_check_display_code() {
+        let confirmed = cross_device_request(Status::Confirmed);
+        assert_eq!(DisplayCodeCheck::Valid, check_display_code(&confirmed, "k7m-4qx"));
+        assert_eq!(DisplayCodeCheck::Valid, check_display_code(&cross_device_request(Status::Done), "K7M4QX"));
+        assert_eq!(DisplayCodeCheck::Invalid, check_display_code(&confirmed, "K7M4QY"));
+        assert_eq!(DisplayCodeCheck::Invalid, check_display_code(&confirmed, ""));
+        assert_eq!(DisplayCodeCheck::NotConfirmed, check_display_code(&cross_device_request(Status::Pending), "K7M4QX"));
+        assert_eq!(DisplayCodeCheck::NotCrossDevice, check_display_code(&EmailConfirmationRequest { cross_device: false, ..confirmed.clone() }, "K7M4QX"));
+        assert_eq!(DisplayCodeCheck::AlreadyVerified, check_display_code(&EmailConfirmationRequest { display_code_verified_at: Some(1741592536), ..confirmed.clone() }, "K7M4QX"));
+        // Also the right code, the code could have been guessed
+        let guessed = EmailConfirmationRequest { display_code_attempts: DISPLAY_CODE_ATTEMPTS_LIMIT, ..confirmed };
+        assert_eq!(DisplayCodeCheck::TooManyAttempts, check_display_code(&guessed, "K7M4QX"));
+    }
+
+    #[test]
+    fn test_shown_display_code() {
+        assert_eq!(Some("K7M-4QX".to_string()), shown_display_code(&cross_device_request(Status::Confirmed)));
+        assert_eq!(None, shown_display_code(&cross_device_request(Status::Pending)));
+        assert_eq!(None, shown_display_code(&cross_device_request(Status::Declined)));
+        let verified = EmailConfirmationRequest { display_code_verified_at: Some(1741592536), ..cross_device_request(Status::Done) };
+        assert_eq!(None, shown_display_code(&verified));
+    }
+}
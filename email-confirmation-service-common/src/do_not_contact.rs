@@ -0,0 +1,33 @@
+use serde::{Deserialize, Serialize};
+use crate::suppression::normalize_email;
+
+pub const EMAIL_DO_NOT_CONTACT_ERROR_CODE: &str = "EMAIL_DO_NOT_CONTACT";
+
+/// An address that declined a confirmation request of a client and asked not to get more.
+/// Unlike a suppression it only blocks the requests of that client.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct DoNotContactEntry {
+    pub client_id: String, // PK
+    pub email: String, // SK, normalized
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>, // the declined request, none when added through the admin API
+    pub created_at: u64,
+}
+
+impl DoNotContactEntry {
+    pub fn new(client_id: &str, email: &str, request_id: Option<String>, created_at: u64) -> Self {
+        DoNotContactEntry { client_id: client_id.to_string(), email: normalize_email(email), request_id, created_at }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_email_is_normalized() {
+        let entry = DoNotContactEntry::new("client-1", " Someone@Example.com", Some("request-1".to_string()), 1_000);
+        assert_eq!("someone@example.com", entry.email);
+        assert_eq!("client-1", entry.client_id);
+    }
+}
@@ -3,8 +3,27 @@ use std::ops::Add;
 use serde::{Deserialize, Serialize};
 use std::time::*;
 use uuid::Uuid;
+use crate::confirmation_evidence::ConfirmationEvidence;
+use crate::display_code::new_display_code;
 
 pub const EMAIL_REQUEST_EXPIRATION_PERIOD:Duration = Duration::from_secs(60 * 60);
+// Global secondary index of the request table, used to find a request by the SES message id of its email
+pub const SES_MESSAGE_ID_INDEX_NAME: &str = "ses_message_id-index";
+// Global secondary index of the request table, used to find the requests of a client, sorted by created_at
+pub const CLIENT_ID_INDEX_NAME: &str = "client_id-index";
+// Sparse global secondary index of the request table: only Pending requests have the PENDING_INDEX_KEY attribute,
+// sorted by expires_at, so expired requests are found with a query instead of a scan
+pub const PENDING_EXPIRY_INDEX_NAME: &str = "pending-expiry-index";
+pub const PENDING_INDEX_KEY: &str = "pending_index_key";
+pub const PENDING_INDEX_KEY_VALUE: &str = "Pending";
+// Sparse global secondary index of the request table for reminders that have not been sent yet, sorted by reminder_due_at
+pub const REMINDER_DUE_INDEX_NAME: &str = "reminder-due-index";
+pub const REMINDER_INDEX_KEY: &str = "reminder_index_key";
+pub const REMINDER_INDEX_KEY_VALUE: &str = "Due";
+// Sparse global secondary index of the request table for failed callbacks waiting for a retry, sorted by callback_next_attempt_at
+pub const CALLBACK_RETRY_INDEX_NAME: &str = "callback-retry-index";
+pub const CALLBACK_RETRY_INDEX_KEY: &str = "callback_retry_index_key";
+pub const CALLBACK_RETRY_INDEX_KEY_VALUE: &str = "Due";
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct EmailConfirmationServiceApiResponse {
@@ -17,7 +36,15 @@ pub struct EmailConfirmationMinimalRequest {
     pub email: String,
     pub client_id: String,
     pub request_id: String,
-    pub callback_url: String
+    pub callback_url: String,
+    #[serde(default)]
+    pub reminder_at_percent: Option<u8>, // opt-in reminder email at this percentage of the link lifetime, 1-99
+    #[serde(default)]
+    pub success_redirect_url: Option<String>, // where the landing page sends the browser once confirmed
+    #[serde(default)]
+    pub expired_redirect_url: Option<String>, // and once the link expired
+    #[serde(default)]
+    pub cross_device: bool, // the landing page shows a display code to enter on the device the request was made on
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
@@ -31,7 +58,63 @@ pub struct EmailConfirmationRequest {
     pub created_at: u64,
     pub expires_at: u64, // SK
     pub updated_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signed_at: Option<u64>, // the links are signed with this, status changes leave it alone
     pub status: Status,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_status: Option<Status>, // status before the last status change
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ses_message_id: Option<String>, // set once the confirmation email has been accepted by SES
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_reason: Option<String>, // e.g. bounce or complaint details
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reminder_due_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reminder_requested_at: Option<u64>, // set by the sweeper when the reminder is due and the request still Pending
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reminder_message_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub callback_attempts: Vec<CallbackAttempt>, // failed callback deliveries, oldest first
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callback_last_error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callback_next_attempt_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callback_retry_requested_at: Option<u64>, // set by the sweeper when callback_next_attempt_at has passed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callback_replay_requested_at: Option<u64>, // set through the API to send the latest delivered callback again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub form_token_hash: Option<String>, // token of the latest confirm form, removed when it is used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub form_token_expires_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub form_token_not_before: Option<u64>, // the form is refused before, see LinkClickSettings::confirm_delay_secs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub success_redirect_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expired_redirect_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirmation_evidence: Option<ConfirmationEvidence>, // set with the Confirmed status by the confirm form
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_clicked_at: Option<u64>, // first click of the link by the recipient, not by a mail scanner
+    #[serde(default)]
+    pub click_count: u32,
+    #[serde(default)]
+    pub scanner_click_count: u32,
+    #[serde(default)]
+    pub cross_device: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_code: Option<String>, // see display_code, never returned to the client
+    #[serde(default)]
+    pub display_code_attempts: u32, // wrong codes entered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_code_verified_at: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct CallbackAttempt {
+    pub attempted_at: u64,
+    pub error: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
@@ -43,11 +126,46 @@ pub struct SanitizedEmailConfirmationRequest {
     pub callback_url: String,
     pub expires_at: u64,
     pub status: Status,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub callback_attempts: Vec<CallbackAttempt>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callback_last_error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callback_next_attempt_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub success_redirect_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expired_redirect_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirmation_evidence: Option<ConfirmationEvidence>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_clicked_at: Option<u64>,
+    #[serde(default)]
+    pub click_count: u32,
+    #[serde(default)]
+    pub scanner_click_count: u32,
+    #[serde(default)]
+    pub cross_device: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_code_verified_at: Option<u64>,
 }
 
 impl From<EmailConfirmationMinimalRequest> for EmailConfirmationRequest {
     fn from(minimal_request: EmailConfirmationMinimalRequest) -> Self {
-        EmailConfirmationRequest::new(minimal_request.email, minimal_request.client_id, minimal_request.request_id, minimal_request.callback_url)
+        let mut request = EmailConfirmationRequest::new(minimal_request.email, minimal_request.client_id, minimal_request.request_id, minimal_request.callback_url);
+        if let Some(reminder_at_percent) = minimal_request.reminder_at_percent {
+            let lifetime = request.expires_at - request.created_at;
+            request.reminder_due_at = Some(request.created_at + lifetime * u64::from(reminder_at_percent) / 100);
+        }
+        request.success_redirect_url = minimal_request.success_redirect_url;
+        request.expired_redirect_url = minimal_request.expired_redirect_url;
+        if minimal_request.cross_device {
+            request.cross_device = true;
+            request.display_code = Some(new_display_code());
+        }
+        request
     }
 }
 
@@ -61,6 +179,18 @@ impl From<EmailConfirmationRequest> for SanitizedEmailConfirmationRequest {
             callback_url: original_request.callback_url,
             expires_at: original_request.expires_at,
             status: original_request.status,
+            status_reason: original_request.status_reason,
+            callback_attempts: original_request.callback_attempts,
+            callback_last_error: original_request.callback_last_error,
+            callback_next_attempt_at: original_request.callback_next_attempt_at,
+            success_redirect_url: original_request.success_redirect_url,
+            expired_redirect_url: original_request.expired_redirect_url,
+            confirmation_evidence: original_request.confirmation_evidence,
+            first_clicked_at: original_request.first_clicked_at,
+            click_count: original_request.click_count,
+            scanner_click_count: original_request.scanner_click_count,
+            cross_device: original_request.cross_device,
+            display_code_verified_at: original_request.display_code_verified_at,
         }
     }
 }
@@ -71,13 +201,32 @@ impl EmailConfirmationRequest {
         let created_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
         let expires_at = SystemTime::now().add(EMAIL_REQUEST_EXPIRATION_PERIOD).duration_since(UNIX_EPOCH).unwrap().as_secs();
         let updated_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-        EmailConfirmationRequest { pk, email, client_id, request_id, callback_url, signature_key, created_at, expires_at, updated_at, status: Status::Queued }
+        EmailConfirmationRequest { pk, email, client_id, request_id, callback_url, signature_key, created_at, expires_at, updated_at, signed_at: Some(updated_at), status: Status::Queued, previous_status: None, ses_message_id: None, status_reason: None,
+            reminder_due_at: None, reminder_requested_at: None, reminder_message_id: None,
+            callback_attempts: vec![], callback_last_error: None, callback_next_attempt_at: None, callback_retry_requested_at: None, callback_replay_requested_at: None,
+            form_token_hash: None, form_token_expires_at: None, form_token_not_before: None, success_redirect_url: None, expired_redirect_url: None,
+            confirmation_evidence: None, first_clicked_at: None, click_count: 0, scanner_click_count: 0,
+            cross_device: false, display_code: None, display_code_attempts: 0, display_code_verified_at: None }
     }
 
     pub fn pk_from_params (email: &str, client_id: &str, request_id: &str) -> String {
         format!("{email}#{client_id}#{request_id}")
     }
 
+    /// The time the links of the request are signed with. Requests stored before signed_at were signed with updated_at,
+    /// their links stop working once the status changes.
+    pub fn signature_timestamp(&self) -> u64 {
+        self.signed_at.unwrap_or(self.updated_at)
+    }
+
+}
+
+/// The emails sent for one request, each has its own SES message id.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Default)]
+pub enum EmailKind {
+    #[default]
+    Confirmation,
+    Reminder,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
@@ -86,7 +235,13 @@ pub enum Status {
     Pending,
     Confirmed,
     Expired,
-    Done
+    Done,
+    Bounced,
+    Complained,
+    Suppressed,
+    CallbackFailed,
+    Cancelled,
+    Declined, // the recipient did not ask for the email
 }
 
 impl fmt::Display for Status {
@@ -97,6 +252,65 @@ impl fmt::Display for Status {
             Status::Confirmed => write!(f, "Confirmed"),
             Status::Expired => write!(f, "Expired"),
             Status::Done => write!(f, "Done"),
+            Status::Bounced => write!(f, "Bounced"),
+            Status::Complained => write!(f, "Complained"),
+            Status::Suppressed => write!(f, "Suppressed"),
+            Status::CallbackFailed => write!(f, "CallbackFailed"),
+            Status::Cancelled => write!(f, "Cancelled"),
+            Status::Declined => write!(f, "Declined"),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reminder_due_at_is_a_percentage_of_the_lifetime() {
+        let request = EmailConfirmationRequest::from(EmailConfirmationMinimalRequest {
+            email: "email@example.com".to_string(),
+            client_id: "client-1".to_string(),
+            request_id: "request-1".to_string(),
+            callback_url: "https://client.example.com/callback".to_string(),
+            reminder_at_percent: Some(50),
+            success_redirect_url: None,
+            expired_redirect_url: None,
+            cross_device: false,
+        });
+        assert_eq!(Some(request.created_at + EMAIL_REQUEST_EXPIRATION_PERIOD.as_secs() / 2), request.reminder_due_at);
+    }
+
+    #[test]
+    fn test_reminder_is_opt_in() {
+        let minimal_request: EmailConfirmationMinimalRequest = serde_json::from_str(
+            r#"{"email": "email@example.com", "client_id": "client-1", "request_id": "request-1", "callback_url": "https://client.example.com/callback"}"#).unwrap();
+        assert_eq!(None, EmailConfirmationRequest::from(minimal_request).reminder_due_at);
+    }
+
+    #[test]
+    fn test_redirect_urls_are_kept() {
+        let minimal_request: EmailConfirmationMinimalRequest = serde_json::from_str(
+            r#"{"email": "email@example.com", "client_id": "client-1", "request_id": "request-1", "callback_url": "https://client.example.com/callback",
+                "success_redirect_url": "https://app.example.com/welcome", "expired_redirect_url": "https://app.example.com/retry"}"#).unwrap();
+        let request = SanitizedEmailConfirmationRequest::from(EmailConfirmationRequest::from(minimal_request));
+        assert_eq!(Some("https://app.example.com/welcome"), request.success_redirect_url.as_deref());
+        assert_eq!(Some("https://app.example.com/retry"), request.expired_redirect_url.as_deref());
+    }
+
+    #[test]
+    fn test_cross_device_request_gets_a_display_code() {
+        let minimal_request: EmailConfirmationMinimalRequest = serde_json::from_str(
+            r#"{"email": "email@example.com", "client_id": "client-1", "request_id": "request-1", "callback_url": "https://client.example.com/callback", "cross_device": true}"#).unwrap();
+        let request = EmailConfirmationRequest::from(minimal_request);
+        assert!(request.cross_device && request.display_code.is_some());
+        // The client never sees the code
+        let sanitized = serde_json::to_value(SanitizedEmailConfirmationRequest::from(request)).unwrap();
+        assert_eq!(true, sanitized["cross_device"]);
+        assert!(sanitized.get("display_code").is_none());
+
+        let minimal_request: EmailConfirmationMinimalRequest = serde_json::from_str(
+            r#"{"email": "email@example.com", "client_id": "client-1", "request_id": "request-1", "callback_url": "https://client.example.com/callback"}"#).unwrap();
+        assert_eq!(None, EmailConfirmationRequest::from(minimal_request).display_code);
+    }
+}
@@ -0,0 +1,88 @@
+//! Tokens of the confirm form shown when a confirmation link is opened.
+//!
+//! A token is issued for every GET of the confirm page and stored with the request, only its SHA-256 hash is kept.
+//! The POST of the form has to carry it: the status update checks that the token is the latest one issued
+//! for the request and has not expired, and removes it in the same write, so a token confirms at most once.
+//! A client can hold the form back for a few seconds after it is shown, against mail scanners that submit forms.
+
+use std::time::Duration;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use crate::email_confirmation_request::{EmailConfirmationRequest, Status};
+
+pub const FORM_TOKEN_PERIOD: Duration = Duration::from_secs(15 * 60);
+pub const FORM_TOKEN_INVALID_ERROR_CODE: &str = "FORM_TOKEN_INVALID";
+
+/// 244 random bits, hex encoded.
+pub fn new_form_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+pub fn form_token_hash(form_token: &str) -> String {
+    hex::encode(Sha256::digest(form_token.as_bytes()))
+}
+
+pub fn form_token_is_valid(confirmation_request: &EmailConfirmationRequest, form_token: &str, now: u64) -> bool {
+    match (&confirmation_request.form_token_hash, confirmation_request.form_token_expires_at) {
+        (Some(stored_hash), Some(expires_at)) => now < expires_at && *stored_hash == form_token_hash(form_token)
+            && confirmation_request.form_token_not_before.is_none_or(|not_before| not_before <= now),
+        _ => false,
+    }
+}
+
+/// Confirmed and Declined are only set through a link, with the token of the form the link showed, a missing token is refused.
+/// The statuses the service sets itself, e.g. Pending or Done, carry no token.
+pub fn form_token_permits_status(confirmation_request: &EmailConfirmationRequest, status: &Status, form_token: Option<&str>, now: u64) -> bool {
+    match form_token {
+        Some(form_token) => form_token_is_valid(confirmation_request, form_token, now),
+        None => !matches!(status, Status::Confirmed | Status::Declined),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request_with_token(form_token: &str, expires_at: u64) -> EmailConfirmationRequest {
+        let mut request = EmailConfirmationRequest::new("email@example.com".to_string(), "client-1".to_string(), "request-1".to_string(), "https://client.example.com/callback".to_string());
+        request.form_token_hash = Some(form_token_hash(form_token));
+        request.form_token_expires_at = Some(expires_at);
+        request
+    }
+
+    #[test]
+    fn test_form_token_is_valid() {
+        let form_token = new_form_token();
+        assert_eq!(64, form_token.len());
+        assert_ne!(form_token, new_form_token());
+
+        let request = request_with_token(&form_token, 1741592536);
+        assert!(form_token_is_valid(&request, &form_token, 1741592535));
+        // Stale
+        assert!(!form_token_is_valid(&request, &form_token, 1741592536));
+        // Another token, or one issued before the latest
+        assert!(!form_token_is_valid(&request, &new_form_token(), 1741592535));
+        assert!(!form_token_is_valid(&request, "", 1741592535));
+        // Held back
+        let held_back = EmailConfirmationRequest { form_token_not_before: Some(1741592530), ..request.clone() };
+        assert!(!form_token_is_valid(&held_back, &form_token, 1741592529));
+        assert!(form_token_is_valid(&held_back, &form_token, 1741592530));
+        // Used, the status update removes it
+        let used = EmailConfirmationRequest { form_token_hash: None, form_token_expires_at: None, ..request };
+        assert!(!form_token_is_valid(&used, &form_token, 1741592535));
+    }
+
+    #[test]
+    fn test_link_statuses_need_a_form_token() {
+        let form_token = new_form_token();
+        let request = request_with_token(&form_token, 1741592536);
+        for status in [Status::Confirmed, Status::Declined] {
+            assert!(form_token_permits_status(&request, &status, Some(&form_token), 1741592535));
+            assert!(!form_token_permits_status(&request, &status, None, 1741592535), "{}", status);
+            assert!(!form_token_permits_status(&request, &status, Some(&new_form_token()), 1741592535));
+        }
+        for status in [Status::Pending, Status::Suppressed, Status::Done, Status::CallbackFailed] {
+            assert!(form_token_permits_status(&request, &status, None, 1741592535), "{}", status);
+        }
+    }
+}
@@ -1,2 +1,18 @@
+pub mod callback_event;
+pub mod callback_host;
+pub mod callback_url;
+pub mod client_config;
+pub mod client_stats;
+pub mod confirmation_evidence;
+pub mod delivery_log;
+pub mod display_code;
+pub mod do_not_contact;
 pub mod email_confirmation_request;
+pub mod form_token;
+pub mod link_click;
+pub mod redirect_url;
 pub mod signature_request;
+pub mod suppression;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+pub mod webhook_signature;
@@ -0,0 +1,116 @@
+//! Clicks of confirmation links. Mail scanners open links to check them, before or instead of the recipient.
+//! Their clicks are recorded apart from the others, so the click count is the recipient's.
+
+use serde::{Deserialize, Serialize};
+
+pub const CONFIRM_DELAY_LIMIT_SECS: u8 = 30;
+pub const SCANNER_USER_AGENTS_LIMIT: usize = 50;
+
+/// Parts of the user agents of known link scanners and of HTTP libraries, compared in lower case.
+/// A click without a user agent counts as a scanner's too, browsers always send one.
+pub const KNOWN_SCANNER_USER_AGENTS: [&str; 14] = [
+    "barracuda",
+    "bingpreview",
+    "proofpoint",
+    "mimecast",
+    "symantec",
+    "trendmicro",
+    "forcepoint",
+    "fireeye",
+    "urldefense",
+    "safelinks",
+    "headlesschrome",
+    "python-requests",
+    "go-http-client",
+    "curl/",
+];
+
+/// How the link click endpoint treats the clicks of a client's links.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct LinkClickSettings {
+    #[serde(default = "default_true")]
+    pub record_clicks: bool,
+    #[serde(default = "default_true")]
+    pub detect_scanners: bool,
+    // Parts of user agents to treat as scanners, next to the known ones
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scanner_user_agents: Vec<String>,
+    // The confirm and decline buttons become active this long after the page is shown, and the form is refused before
+    #[serde(default)]
+    pub confirm_delay_secs: u8,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for LinkClickSettings {
+    fn default() -> Self {
+        LinkClickSettings { record_clicks: true, detect_scanners: true, scanner_user_agents: vec![], confirm_delay_secs: 0 }
+    }
+}
+
+impl LinkClickSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.confirm_delay_secs > CONFIRM_DELAY_LIMIT_SECS {
+            return Err(format!("confirm_delay_secs must be at most {}", CONFIRM_DELAY_LIMIT_SECS))
+        }
+        if self.scanner_user_agents.len() > SCANNER_USER_AGENTS_LIMIT {
+            return Err(format!("At most {} scanner_user_agents", SCANNER_USER_AGENTS_LIMIT))
+        }
+        if self.scanner_user_agents.iter().any(|user_agent| user_agent.trim().len() < 3) {
+            return Err("scanner_user_agents must have at least 3 characters each".to_string())
+        }
+        Ok(())
+    }
+
+    pub fn is_scanner_user_agent(&self, user_agent: Option<&str>) -> bool {
+        if !self.detect_scanners {
+            return false
+        }
+        let Some(user_agent) = user_agent.map(str::trim).filter(|user_agent| !user_agent.is_empty()) else {
+            return true
+        };
+        let user_agent = user_agent.to_lowercase();
+        KNOWN_SCANNER_USER_AGENTS.iter().any(|scanner| user_agent.contains(scanner))
+            || self.scanner_user_agents.iter().any(|scanner| user_agent.contains(&scanner.trim().to_lowercase()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIREFOX: &str = "Mozilla/5.0 (X11; Linux x86_64; rv:131.0) Gecko/20100101 Firefox/131.0";
+
+    #[test]
+    fn test_scanner_user_agents() {
+        let settings = LinkClickSettings::default();
+        assert!(!settings.is_scanner_user_agent(Some(FIREFOX)));
+        assert!(settings.is_scanner_user_agent(Some("Mozilla/5.0 (compatible; BingPreview/1.0b)")));
+        assert!(settings.is_scanner_user_agent(Some("python-requests/2.32.3")));
+        assert!(settings.is_scanner_user_agent(Some(" ")));
+        assert!(settings.is_scanner_user_agent(None));
+
+        let with_own_scanner = LinkClickSettings { scanner_user_agents: vec!["ExampleGateway".to_string()], ..Default::default() };
+        assert!(with_own_scanner.is_scanner_user_agent(Some("Mozilla/5.0 examplegateway/3.1")));
+
+        let without_detection = LinkClickSettings { detect_scanners: false, ..with_own_scanner };
+        assert!(!without_detection.is_scanner_user_agent(Some("python-requests/2.32.3")));
+        assert!(!without_detection.is_scanner_user_agent(None));
+    }
+
+    #[test]
+    fn test_settings_of_a_client_without_any() {
+        let settings: LinkClickSettings = serde_json::from_str("{}").unwrap();
+        assert_eq!(LinkClickSettings::default(), settings);
+        assert!(settings.record_clicks && settings.detect_scanners);
+    }
+
+    #[test]
+    fn test_validation() {
+        assert_eq!(Ok(()), LinkClickSettings { confirm_delay_secs: CONFIRM_DELAY_LIMIT_SECS, ..Default::default() }.validate());
+        assert!(LinkClickSettings { confirm_delay_secs: CONFIRM_DELAY_LIMIT_SECS + 1, ..Default::default() }.validate().is_err());
+        assert!(LinkClickSettings { scanner_user_agents: vec!["a".to_string()], ..Default::default() }.validate().is_err());
+    }
+}
@@ -0,0 +1,109 @@
+use std::fmt;
+use url::{Host, Url};
+use crate::callback_url::domain_is_allowed;
+
+/// Where the landing page sends the browser after a click, set per request by the client.
+/// The outcome and the request_id are appended to the query of the url.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RedirectOutcome {
+    Confirmed, // to success_redirect_url
+    Expired, // to expired_redirect_url
+}
+
+impl RedirectOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RedirectOutcome::Confirmed => "confirmed",
+            RedirectOutcome::Expired => "expired",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum RedirectUrlError {
+    Invalid(String),
+    NotHttps,
+    NoAllowedDomains,
+    DomainNotAllowed(String),
+}
+
+impl fmt::Display for RedirectUrlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RedirectUrlError::Invalid(error) => write!(f, "not a valid url: {}", error),
+            RedirectUrlError::NotHttps => write!(f, "must be an https url"),
+            RedirectUrlError::NoAllowedDomains => write!(f, "needs the allowed callback domains of the client to be set"),
+            RedirectUrlError::DomainNotAllowed(host) => write!(f, "host {} is not in the allowed callback domains of the client", host),
+        }
+    }
+}
+
+impl std::error::Error for RedirectUrlError {}
+
+/// Checked when a request is created, against the same allowlist as the callback url, so a confirmation link
+/// cannot be used to send people to a site the client does not own. Unlike for callbacks, an empty allowlist allows no domain:
+/// the link would otherwise redirect anywhere.
+/// The browser follows the redirect, not the service, so only the form of the host is checked: no IP addresses or localhost.
+pub fn validate_redirect_url(redirect_url: &str, allowed_domains: &[String]) -> Result<Url, RedirectUrlError> {
+    let url = Url::parse(redirect_url).map_err(|error| RedirectUrlError::Invalid(error.to_string()))?;
+    if url.scheme() != "https" {
+        return Err(RedirectUrlError::NotHttps)
+    }
+    if allowed_domains.is_empty() {
+        return Err(RedirectUrlError::NoAllowedDomains)
+    }
+    match url.host() {
+        None => return Err(RedirectUrlError::Invalid("no host".to_string())),
+        Some(Host::Ipv4(ip)) => return Err(RedirectUrlError::DomainNotAllowed(ip.to_string())),
+        Some(Host::Ipv6(ip)) => return Err(RedirectUrlError::DomainNotAllowed(ip.to_string())),
+        Some(Host::Domain(domain)) => {
+            let domain = domain.trim_end_matches('.').to_lowercase();
+            if domain == "localhost" || domain.ends_with(".localhost") || !domain_is_allowed(&domain, allowed_domains) {
+                return Err(RedirectUrlError::DomainNotAllowed(domain))
+            }
+        },
+    }
+    Ok(url)
+}
+
+/// The redirect url with `request_id` and `outcome` added to its query, None if it is not a valid url.
+pub fn redirect_location(redirect_url: &str, request_id: &str, outcome: RedirectOutcome) -> Option<String> {
+    let mut url = Url::parse(redirect_url).ok()?;
+    url.query_pairs_mut()
+        .append_pair("request_id", request_id)
+        .append_pair("outcome", outcome.as_str());
+    Some(url.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_redirect_url() {
+        let allowed_domains = vec!["example.com".to_string()];
+        assert!(validate_redirect_url("https://App.Example.com/welcome", &allowed_domains).is_ok());
+        assert_eq!(Err(RedirectUrlError::NotHttps), validate_redirect_url("http://app.example.com/welcome", &allowed_domains));
+        assert_eq!(Err(RedirectUrlError::NotHttps), validate_redirect_url("javascript:alert(1)", &allowed_domains));
+        assert!(matches!(validate_redirect_url("/welcome", &allowed_domains), Err(RedirectUrlError::Invalid(_))));
+        assert!(matches!(validate_redirect_url("https://127.0.0.1/welcome", &allowed_domains), Err(RedirectUrlError::DomainNotAllowed(_))));
+        assert!(matches!(validate_redirect_url("https://localhost/welcome", &["localhost".to_string()]), Err(RedirectUrlError::DomainNotAllowed(_))));
+        assert_eq!(Err(RedirectUrlError::DomainNotAllowed("example.com.evil.net".to_string())), validate_redirect_url("https://example.com.evil.net/welcome", &allowed_domains));
+    }
+
+    #[test]
+    fn test_empty_allowlist_allows_no_redirect() {
+        assert_eq!(Err(RedirectUrlError::NoAllowedDomains), validate_redirect_url("https://app.example.com/welcome", &[]));
+        assert_eq!(Err(RedirectUrlError::NoAllowedDomains), validate_redirect_url("https://evil.example.net/", &[]));
+    }
+
+    #[test]
+    fn test_redirect_location() {
+        assert_eq!(Some("https://app.example.com/welcome?request_id=request-1&outcome=confirmed".to_string()),
+                   redirect_location("https://app.example.com/welcome", "request-1", RedirectOutcome::Confirmed));
+        // The client's own query is kept, the appended values are encoded
+        assert_eq!(Some("https://app.example.com/welcome?lang=sv&request_id=a%26b%3Dc&outcome=expired#top".to_string()),
+                   redirect_location("https://app.example.com/welcome?lang=sv#top", "a&b=c", RedirectOutcome::Expired));
+        assert_eq!(None, redirect_location("not a url", "request-1", RedirectOutcome::Confirmed));
+    }
+}
@@ -1,5 +1,6 @@
+use std::fmt;
 use serde::{Deserialize, Serialize};
-use crate::email_confirmation_request::EmailConfirmationRequest;
+use crate::email_confirmation_request::{EmailConfirmationRequest, Status};
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct SignatureRequest {
@@ -26,6 +27,38 @@ pub struct SignatureCreationData {
     pub request_id: String,
     pub updated_at: u64,
     pub signature_key: String,
+    #[serde(default)]
+    pub purpose: SignaturePurpose,
+}
+
+/// What a signed link may be used for. Each purpose gets a different signature for the same request,
+/// e.g. a reminder link does not replace the link in the original email.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Default)]
+pub enum SignaturePurpose {
+    #[default]
+    Confirm,
+    Reminder,
+    Decline, // the "This wasn't me" link, can only decline
+}
+
+impl SignaturePurpose {
+    /// Only a Decline signature can set the Declined status, and it cannot set any other.
+    pub fn permits_status(&self, status: &Status) -> bool {
+        match self {
+            SignaturePurpose::Decline => *status == Status::Declined,
+            _ => *status != Status::Declined,
+        }
+    }
+}
+
+impl fmt::Display for SignaturePurpose {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignaturePurpose::Confirm => write!(f, "Confirm"),
+            SignaturePurpose::Reminder => write!(f, "Reminder"),
+            SignaturePurpose::Decline => write!(f, "Decline"),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
@@ -36,6 +69,8 @@ pub struct SignatureVerificationData {
     pub updated_at: u64,
     pub signature_key: String,
     pub signature_value: String,
+    #[serde(default)]
+    pub purpose: SignaturePurpose,
 }
 
 impl From<SignatureVerificationData> for SignatureCreationData {
@@ -46,6 +81,7 @@ impl From<SignatureVerificationData> for SignatureCreationData {
             request_id: signature_verification_data.request_id,
             updated_at: signature_verification_data.updated_at,
             signature_key: signature_verification_data.signature_key,
+            purpose: signature_verification_data.purpose,
         }
     }
 }
@@ -53,6 +89,11 @@ impl From<SignatureVerificationData> for SignatureCreationData {
 impl SignatureRequest {
     pub fn signature_verification_request(
         email_confirmation_request: &EmailConfirmationRequest, signature: String) -> Self {
+        Self::signature_verification_request_for(email_confirmation_request, signature, SignaturePurpose::Confirm)
+    }
+
+    pub fn signature_verification_request_for(
+        email_confirmation_request: &EmailConfirmationRequest, signature: String, purpose: SignaturePurpose) -> Self {
             SignatureRequest {
                 signature_request_type: SignatureRequestType::SignatureVerificationRequest,
                 signature_request_payload: SignatureRequestPayload::SignatureVerificationRequest(
@@ -61,8 +102,9 @@ impl SignatureRequest {
                         email: email_confirmation_request.email.clone(),
                         client_id: email_confirmation_request.client_id.clone(),
                         request_id: email_confirmation_request.request_id.clone(),
-                        updated_at: email_confirmation_request.updated_at,
+                        updated_at: email_confirmation_request.signature_timestamp(),
                         signature_key: email_confirmation_request.signature_key.clone(),
+                        purpose,
                     }
                 )
             }
@@ -70,6 +112,12 @@ impl SignatureRequest {
 
     pub fn signature_creation_request(
         email_confirmation_request: EmailConfirmationRequest) -> Self {
+        Self::signature_creation_request_for(email_confirmation_request, SignaturePurpose::Confirm)
+    }
+
+    pub fn signature_creation_request_for(
+        email_confirmation_request: EmailConfirmationRequest, purpose: SignaturePurpose) -> Self {
+        let updated_at = email_confirmation_request.signature_timestamp();
         SignatureRequest {
             signature_request_type: SignatureRequestType::SignatureCreationRequest,
             signature_request_payload: SignatureRequestPayload::SignatureCreationRequest(
@@ -77,8 +125,9 @@ impl SignatureRequest {
                     email: email_confirmation_request.email,
                     client_id: email_confirmation_request.client_id,
                     request_id: email_confirmation_request.request_id,
-                    updated_at: email_confirmation_request.updated_at,
+                    updated_at,
                     signature_key: email_confirmation_request.signature_key,
+                    purpose,
                 }
             )
         }
@@ -95,4 +144,39 @@ pub enum SignatureResponse {
 pub enum SignatureVerificationResult {
     Success,
     Fail
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_decline_signatures_decline() {
+        assert!(SignaturePurpose::Decline.permits_status(&Status::Declined));
+        assert!(!SignaturePurpose::Decline.permits_status(&Status::Confirmed));
+        assert!(!SignaturePurpose::Decline.permits_status(&Status::Pending));
+        for purpose in [SignaturePurpose::Confirm, SignaturePurpose::Reminder] {
+            assert!(purpose.permits_status(&Status::Confirmed));
+            assert!(!purpose.permits_status(&Status::Declined));
+        }
+    }
+
+    #[test]
+    fn test_status_change_keeps_the_signed_data() {
+        let mut request = EmailConfirmationRequest::new("email@example.com".to_string(), "client-1".to_string(), "request-1".to_string(),
+            "https://client.example.com/callback".to_string());
+        let SignatureRequestPayload::SignatureCreationRequest(signed) = SignatureRequest::signature_creation_request(request.clone())
+            .signature_request_payload else { panic!("Expected a creation request") };
+        request.status = Status::Confirmed;
+        request.updated_at += 60;
+        let SignatureRequestPayload::SignatureVerificationRequest(verified) = SignatureRequest::signature_verification_request(&request, "signature".to_string())
+            .signature_request_payload else { panic!("Expected a verification request") };
+        assert_eq!(signed, SignatureCreationData::from(verified));
+
+        // A request stored before signed_at was signed with updated_at
+        request.signed_at = None;
+        let SignatureRequestPayload::SignatureVerificationRequest(verified) = SignatureRequest::signature_verification_request(&request, "signature".to_string())
+            .signature_request_payload else { panic!("Expected a verification request") };
+        assert_eq!(request.updated_at, verified.updated_at);
+    }
+}
@@ -0,0 +1,88 @@
+use std::fmt;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+
+// Transient (soft) bounces only keep the address suppressed for a while, hard bounces and complaints stay until removed
+pub const SOFT_BOUNCE_SUPPRESSION_PERIOD: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+pub const EMAIL_SUPPRESSED_ERROR_CODE: &str = "EMAIL_SUPPRESSED";
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct SuppressionEntry {
+    pub email: String, // PK, normalized
+    pub reason: SuppressionReason,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub created_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>, // also the DynamoDB TTL attribute
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub enum SuppressionReason {
+    HardBounce,
+    SoftBounce,
+    Complaint,
+    Manual,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct SuppressionApiResponse {
+    pub error: bool,
+    pub suppression: SuppressionEntry,
+}
+
+impl SuppressionEntry {
+    pub fn new(email: &str, reason: SuppressionReason, detail: Option<String>, created_at: u64) -> Self {
+        let expires_at = match reason {
+            SuppressionReason::SoftBounce => Some(created_at + SOFT_BOUNCE_SUPPRESSION_PERIOD.as_secs()),
+            _ => None,
+        };
+        SuppressionEntry { email: normalize_email(email), reason, detail, created_at, expires_at }
+    }
+
+    /// DynamoDB TTL deletes expired items lazily, so the expiry has to be checked on read too.
+    pub fn is_active(&self, now: u64) -> bool {
+        match self.expires_at {
+            Some(expires_at) => now < expires_at,
+            None => true,
+        }
+    }
+}
+
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+impl fmt::Display for SuppressionReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SuppressionReason::HardBounce => write!(f, "HardBounce"),
+            SuppressionReason::SoftBounce => write!(f, "SoftBounce"),
+            SuppressionReason::Complaint => write!(f, "Complaint"),
+            SuppressionReason::Manual => write!(f, "Manual"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_soft_bounce_expires() {
+        let entry = SuppressionEntry::new("Someone@Example.com ", SuppressionReason::SoftBounce, None, 1_000);
+        assert_eq!("someone@example.com", entry.email);
+        assert!(entry.is_active(1_000));
+        assert!(!entry.is_active(1_000 + SOFT_BOUNCE_SUPPRESSION_PERIOD.as_secs()));
+    }
+
+    #[test]
+    fn test_hard_bounce_and_complaint_do_not_expire() {
+        let hard_bounce = SuppressionEntry::new("a@example.com", SuppressionReason::HardBounce, None, 1_000);
+        let complaint = SuppressionEntry::new("b@example.com", SuppressionReason::Complaint, None, 1_000);
+        assert_eq!(None, hard_bounce.expires_at);
+        assert!(hard_bounce.is_active(u64::MAX));
+        assert!(complaint.is_active(u64::MAX));
+    }
+}
@@ -0,0 +1,80 @@
+//! Local stub servers for the tests of the lambdas. Built with the test-util feature, which only dev-dependencies enable.
+
+use std::sync::{Arc, Mutex};
+use aws_sdk_dynamodb::config::{BehaviorVersion, Credentials, Region};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+pub const CONDITIONAL_CHECK_FAILED: &str = r#"{"__type":"com.amazonaws.dynamodb.v20120810#ConditionalCheckFailedException","message":"The conditional request failed"}"#;
+
+/// The requests a stub got, head and body, in the order they came in.
+pub type StubRequests = Arc<Mutex<Vec<String>>>;
+
+/// Local HTTP server that answers each request with the status and body `respond` returns for it, and keeps the requests it got.
+/// Returns its base url, e.g. `http://127.0.0.1:49152`.
+pub async fn http_stub<F>(content_type: &'static str, respond: F) -> (String, StubRequests)
+where
+    F: Fn(&str) -> (u16, String) + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let requests: StubRequests = Arc::default();
+    let received = requests.clone();
+    let respond = Arc::new(respond);
+    tokio::spawn(async move {
+        loop {
+            let Ok((mut socket, _)) = listener.accept().await else { return };
+            let received = received.clone();
+            let respond = respond.clone();
+            tokio::spawn(async move {
+                let request = read_request(&mut socket).await;
+                let (status, body) = respond(&request);
+                received.lock().unwrap().push(request);
+                let response = format!("HTTP/1.1 {} Stub\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, content_type, body.len(), body);
+                let _ = socket.write_all(response.as_bytes()).await;
+                let _ = socket.shutdown().await;
+            });
+        }
+    });
+    (format!("http://{}", address), requests)
+}
+
+/// Local DynamoDB endpoint that answers each operation with a canned status and body, e.g. `("UpdateItem", 400, CONDITIONAL_CHECK_FAILED)`.
+pub async fn dynamodb_stub(responses: &'static [(&'static str, u16, &'static str)]) -> (aws_sdk_dynamodb::Client, StubRequests) {
+    let (url, requests) = http_stub("application/x-amz-json-1.0", |request| {
+        responses.iter()
+            .find(|(operation, _, _)| request.contains(&format!("DynamoDB_20120810.{}", operation)))
+            .map(|(_, status, body)| (*status, body.to_string()))
+            .unwrap_or((400, r#"{"__type":"com.amazon.coral.service#UnknownOperationException"}"#.to_string()))
+    }).await;
+
+    let config = aws_sdk_dynamodb::Config::builder()
+        .behavior_version(BehaviorVersion::latest())
+        .region(Region::new("eu-north-1"))
+        .credentials_provider(Credentials::new("test", "test", None, None, "test"))
+        .endpoint_url(url)
+        .build();
+    (aws_sdk_dynamodb::Client::from_conf(config), requests)
+}
+
+/// Reads the head and as much of the body as Content-Length says.
+async fn read_request(socket: &mut TcpStream) -> String {
+    let mut request: Vec<u8> = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while let Ok(read) = socket.read(&mut chunk).await {
+        if read == 0 {
+            break
+        }
+        request.extend_from_slice(&chunk[..read]);
+        let text = String::from_utf8_lossy(&request).to_string();
+        if let Some(head_end) = text.find("\r\n\r\n") {
+            let content_length = text[..head_end].lines()
+                .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length:").map(|length| length.trim().parse::<usize>().unwrap_or(0)))
+                .unwrap_or(0);
+            if request.len() >= head_end + 4 + content_length {
+                break
+            }
+        }
+    }
+    String::from_utf8_lossy(&request).to_string()
+}
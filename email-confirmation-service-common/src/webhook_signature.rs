@@ -0,0 +1,124 @@
+//! Signatures of the callbacks (webhooks) sent to clients.
+//!
+//! Every callback carries two headers:
+//! - `X-Webhook-Timestamp`: the time of signing in epoch seconds
+//! - `X-Webhook-Signature`: one or more `v1=<hex>` entries separated by commas, where `<hex>` is
+//!   HMAC-SHA256 with the client's webhook secret over `<timestamp>.<raw request body>`
+//!
+//! While a secret is being rotated, the callback is signed with both the new and the old secret,
+//! so a client can switch secrets at any point of the overlap window. A client verifies a callback with
+//! [`verify_webhook`], or an equivalent routine in its own language:
+//! 1. Check that the timestamp is within 5 minutes of the current time, to stop replays.
+//! 2. Compute the HMAC over the timestamp, a dot and the raw body exactly as received, before parsing it.
+//! 3. Accept the callback if any of the `v1` signatures matches, comparing in constant time.
+
+use std::fmt;
+use std::time::Duration;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+pub const WEBHOOK_TIMESTAMP_HEADER: &str = "X-Webhook-Timestamp";
+pub const WEBHOOK_SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+pub const WEBHOOK_SIGNATURE_TOLERANCE: Duration = Duration::from_secs(5 * 60);
+const WEBHOOK_SIGNATURE_SCHEME: &str = "v1";
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum WebhookVerificationError {
+    InvalidTimestamp,
+    TimestampOutsideTolerance,
+    NoValidSignature,
+}
+
+impl fmt::Display for WebhookVerificationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WebhookVerificationError::InvalidTimestamp => write!(f, "Invalid webhook timestamp"),
+            WebhookVerificationError::TimestampOutsideTolerance => write!(f, "Webhook timestamp outside tolerance"),
+            WebhookVerificationError::NoValidSignature => write!(f, "No valid webhook signature"),
+        }
+    }
+}
+
+impl std::error::Error for WebhookVerificationError {}
+
+fn webhook_mac(secret: &str, timestamp: u64, body: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac
+}
+
+/// Hex encoded HMAC-SHA256 of `<timestamp>.<body>`.
+pub fn sign_webhook(secret: &str, timestamp: u64, body: &[u8]) -> String {
+    hex::encode(webhook_mac(secret, timestamp, body).finalize().into_bytes())
+}
+
+/// Value of the signature header, with one signature per active secret, newest first.
+pub fn webhook_signature_header(secrets: &[&str], timestamp: u64, body: &[u8]) -> String {
+    secrets.iter()
+        .map(|secret| format!("{}={}", WEBHOOK_SIGNATURE_SCHEME, sign_webhook(secret, timestamp, body)))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Verifies a received callback against the client's secret, see the module documentation.
+pub fn verify_webhook(secret: &str, timestamp_header: &str, signature_header: &str, body: &[u8], now: u64) -> Result<(), WebhookVerificationError> {
+    let timestamp: u64 = timestamp_header.trim().parse().map_err(|_| WebhookVerificationError::InvalidTimestamp)?;
+    if timestamp.abs_diff(now) > WEBHOOK_SIGNATURE_TOLERANCE.as_secs() {
+        return Err(WebhookVerificationError::TimestampOutsideTolerance)
+    }
+
+    let signature_matches = signature_header.split(',')
+        .filter_map(|entry| entry.trim().split_once('='))
+        .filter(|(scheme, _)| *scheme == WEBHOOK_SIGNATURE_SCHEME)
+        .filter_map(|(_, signature)| hex::decode(signature).ok())
+        .any(|signature| webhook_mac(secret, timestamp, body).verify_slice(&signature).is_ok());
+
+    if signature_matches {
+        Ok(())
+    } else {
+        Err(WebhookVerificationError::NoValidSignature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_741_592_476;
+    const BODY: &[u8] = br#"{"email":"email@example.com","status":"Confirmed"}"#;
+
+    #[test]
+    fn test_signed_webhook_is_verified() {
+        let header = webhook_signature_header(&["whsec_new"], NOW, BODY);
+        assert!(header.starts_with("v1="));
+        assert_eq!(Ok(()), verify_webhook("whsec_new", &NOW.to_string(), &header, BODY, NOW + 10));
+    }
+
+    #[test]
+    fn test_both_secrets_verify_during_rotation() {
+        let header = webhook_signature_header(&["whsec_new", "whsec_old"], NOW, BODY);
+        assert_eq!(2, header.split(',').count());
+        assert_eq!(Ok(()), verify_webhook("whsec_new", &NOW.to_string(), &header, BODY, NOW));
+        assert_eq!(Ok(()), verify_webhook("whsec_old", &NOW.to_string(), &header, BODY, NOW));
+        assert_eq!(Err(WebhookVerificationError::NoValidSignature), verify_webhook("whsec_other", &NOW.to_string(), &header, BODY, NOW));
+    }
+
+    #[test]
+    fn test_tampered_body_is_rejected() {
+        let header = webhook_signature_header(&["whsec_new"], NOW, BODY);
+        let forged = br#"{"email":"someone@example.com","status":"Confirmed"}"#;
+        assert_eq!(Err(WebhookVerificationError::NoValidSignature), verify_webhook("whsec_new", &NOW.to_string(), &header, forged, NOW));
+    }
+
+    #[test]
+    fn test_old_or_invalid_timestamp_is_rejected() {
+        let header = webhook_signature_header(&["whsec_new"], NOW, BODY);
+        let replayed_at = NOW + WEBHOOK_SIGNATURE_TOLERANCE.as_secs() + 1;
+        assert_eq!(Err(WebhookVerificationError::TimestampOutsideTolerance), verify_webhook("whsec_new", &NOW.to_string(), &header, BODY, replayed_at));
+        assert_eq!(Err(WebhookVerificationError::InvalidTimestamp), verify_webhook("whsec_new", "yesterday", &header, BODY, NOW));
+    }
+}
@@ -4,12 +4,14 @@ import { CdkStack } from '../lib/cdk-stack';
 
 const signatureServiceLambdaFunctionNameFromEnv = process.env.SIGNATURE_SERVICE_LAMBDA_FUNCTION_NAME || "default-value";
 const emailConfirmationDynamoTableNameFromEnv = process.env.EMAIL_CONFIRMATION_REQUEST_SERVICE_DYNAMO_TABLE_NAME || "default-value";
+const adminApiKeyIdsFromEnv = process.env.ADMIN_API_KEY_IDS || "";
 
 const app = new cdk.App();
 new CdkStack(app, 'EcrsStack', {
 
     signatureServiceLambdaFunctionName: signatureServiceLambdaFunctionNameFromEnv,
-    emailConfirmationDynamoTableName : emailConfirmationDynamoTableNameFromEnv
+    emailConfirmationDynamoTableName : emailConfirmationDynamoTableNameFromEnv,
+    adminApiKeyIds: adminApiKeyIdsFromEnv
 
   /* If you don't specify 'env', this stack will be environment-agnostic.
    * Account/Region-dependent features and context lookups will not work,
@@ -1,14 +1,16 @@
 import {join} from 'path';
 import {RustFunction} from 'cargo-lambda-cdk';
 import {EndpointType, LambdaRestApi} from 'aws-cdk-lib/aws-apigateway'
-import {AttributeType, BillingMode, Table, StreamViewType} from 'aws-cdk-lib/aws-dynamodb';
-import {RemovalPolicy, Stack, StackProps} from "aws-cdk-lib";
+import {AttributeType, BillingMode, ProjectionType, Table, StreamViewType} from 'aws-cdk-lib/aws-dynamodb';
+import {Duration, RemovalPolicy, Stack, StackProps} from "aws-cdk-lib";
 import * as lambda from 'aws-cdk-lib/aws-lambda';
+import * as kms from 'aws-cdk-lib/aws-kms';
 import {Construct} from "constructs";
 
 export interface ECLFStackProps extends StackProps {
   signatureServiceLambdaFunctionName: string;
   emailConfirmationDynamoTableName: string;
+  adminApiKeyIds: string;
 }
 
 export class CdkStack extends Stack {
@@ -22,11 +24,104 @@ export class CdkStack extends Stack {
       removalPolicy: RemovalPolicy.RETAIN,
     });
 
+    // Used to find the request an SES bounce or complaint notification is about
+    dynamoTable.addGlobalSecondaryIndex({
+      indexName: 'ses_message_id-index',
+      partitionKey: { name: 'ses_message_id', type: AttributeType.STRING },
+      projectionType: ProjectionType.ALL,
+    });
+
+    // The requests of a client, for its stats
+    dynamoTable.addGlobalSecondaryIndex({
+      indexName: 'client_id-index',
+      partitionKey: { name: 'client_id', type: AttributeType.STRING },
+      sortKey: { name: 'created_at', type: AttributeType.NUMBER },
+      projectionType: ProjectionType.ALL,
+    });
+
+    // Sparse index: only Pending requests have pending_index_key, so the expiry sweeper can query instead of scan
+    dynamoTable.addGlobalSecondaryIndex({
+      indexName: 'pending-expiry-index',
+      partitionKey: { name: 'pending_index_key', type: AttributeType.STRING },
+      sortKey: { name: 'expires_at', type: AttributeType.NUMBER },
+      projectionType: ProjectionType.ALL,
+    });
+
+    // Sparse index: only requests with a reminder not yet requested have reminder_index_key
+    dynamoTable.addGlobalSecondaryIndex({
+      indexName: 'reminder-due-index',
+      partitionKey: { name: 'reminder_index_key', type: AttributeType.STRING },
+      sortKey: { name: 'reminder_due_at', type: AttributeType.NUMBER },
+      projectionType: ProjectionType.ALL,
+    });
+
+    // Sparse index: only requests with a failed callback waiting for its next attempt have callback_retry_index_key
+    dynamoTable.addGlobalSecondaryIndex({
+      indexName: 'callback-retry-index',
+      partitionKey: { name: 'callback_retry_index_key', type: AttributeType.STRING },
+      sortKey: { name: 'callback_next_attempt_at', type: AttributeType.NUMBER },
+      projectionType: ProjectionType.ALL,
+    });
+
+    // Addresses that must not be sent to, fed by bounces, complaints and the admin API
+    const suppressionTable = new Table(this, 'EmailSuppressionTable', {
+      partitionKey: { name: 'email', type: AttributeType.STRING },
+      billingMode: BillingMode.PAY_PER_REQUEST,
+      timeToLiveAttribute: 'expires_at',
+      removalPolicy: RemovalPolicy.RETAIN,
+    });
+
+    // Addresses that declined a request of a client and asked not to be contacted by it again
+    const doNotContactTable = new Table(this, 'DoNotContactTable', {
+      partitionKey: { name: 'client_id', type: AttributeType.STRING },
+      sortKey: { name: 'email', type: AttributeType.STRING },
+      billingMode: BillingMode.PAY_PER_REQUEST,
+      removalPolicy: RemovalPolicy.RETAIN,
+    });
+
+    // Per client settings, e.g. webhook secrets. Read directly by the callback lambda, secrets never leave through the API
+    const clientConfigTable = new Table(this, 'ClientConfigTable', {
+      partitionKey: { name: 'client_id', type: AttributeType.STRING },
+      billingMode: BillingMode.PAY_PER_REQUEST,
+      removalPolicy: RemovalPolicy.RETAIN,
+    });
+
+    // Every callback attempt, written by the callback lambda. Kept for 30 days
+    const callbackDeliveryTable = new Table(this, 'CallbackDeliveryTable', {
+      partitionKey: { name: 'pk', type: AttributeType.STRING },
+      sortKey: { name: 'delivery_id', type: AttributeType.STRING },
+      billingMode: BillingMode.PAY_PER_REQUEST,
+      timeToLiveAttribute: 'expires_at',
+      removalPolicy: RemovalPolicy.RETAIN,
+    });
+
+    // Circuit breaker state per callback host, written by the callback lambda
+    const callbackHostTable = new Table(this, 'CallbackHostTable', {
+      partitionKey: { name: 'host', type: AttributeType.STRING },
+      billingMode: BillingMode.PAY_PER_REQUEST,
+      removalPolicy: RemovalPolicy.RETAIN,
+    });
+
+    // Encrypts the callback header values and OAuth2 client secrets of the clients. The API only encrypts, the callback lambda decrypts
+    const callbackAuthKey = new kms.Key(this, 'CallbackAuthKey', {
+      enableKeyRotation: true,
+      removalPolicy: RemovalPolicy.RETAIN,
+    });
+
     const lambdaHandler = new RustFunction(this, 'EmailConfirmationLambdaFunction', {
       manifestPath: join(__dirname, '..', '..'),
+      // GET /email-confirmation-requests/{pk}/result waits up to 25 seconds, API Gateway gives up after 29
+      timeout: Duration.seconds(29),
       environment: {
         "EMAIL_CONFIRMATION_REQUEST_SERVICE_DYNAMO_TABLE_NAME": dynamoTable.tableName,
-        "SIGNATURE_SERVICE_LAMBDA_FUNCTION_NAME": props.signatureServiceLambdaFunctionName
+        "SIGNATURE_SERVICE_LAMBDA_FUNCTION_NAME": props.signatureServiceLambdaFunctionName,
+        "EMAIL_SUPPRESSION_DYNAMO_TABLE_NAME": suppressionTable.tableName,
+        "CLIENT_CONFIG_DYNAMO_TABLE_NAME": clientConfigTable.tableName,
+        "CALLBACK_DELIVERY_DYNAMO_TABLE_NAME": callbackDeliveryTable.tableName,
+        "CALLBACK_HOST_DYNAMO_TABLE_NAME": callbackHostTable.tableName,
+        "CALLBACK_AUTH_KMS_KEY_ID": callbackAuthKey.keyArn,
+        "DO_NOT_CONTACT_DYNAMO_TABLE_NAME": doNotContactTable.tableName,
+        "ADMIN_API_KEY_IDS": props.adminApiKeyIds
       }
     });
 
@@ -36,6 +131,12 @@ export class CdkStack extends Stack {
 
     targetLambda.grantInvoke(lambdaHandler);
     dynamoTable.grantFullAccess(lambdaHandler);
+    suppressionTable.grantReadWriteData(lambdaHandler);
+    doNotContactTable.grantReadWriteData(lambdaHandler);
+    clientConfigTable.grantReadWriteData(lambdaHandler);
+    callbackDeliveryTable.grantReadData(lambdaHandler);
+    callbackHostTable.grantReadData(lambdaHandler);
+    callbackAuthKey.grantEncrypt(lambdaHandler);
 
     new LambdaRestApi(this, 'EmailConfirmationLambdaAPIGateway', {
       handler: lambdaHandler,
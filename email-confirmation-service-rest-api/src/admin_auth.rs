@@ -0,0 +1,77 @@
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use lambda_http::request::RequestContext;
+use serde_json::json;
+
+/// The API Gateway API keys allowed to use the admin endpoints, which see and change the data of every client.
+/// API Gateway has checked the key by then, the request context only carries its id.
+#[derive(Clone, Debug, Default)]
+pub struct AdminApiKeys {
+    api_key_ids: Vec<String>,
+}
+
+impl AdminApiKeys {
+    /// Comma separated ids, e.g. the internal API key and the keys of the operators. An empty list allows no one.
+    pub fn new(api_key_ids: &str) -> Self {
+        Self {
+            api_key_ids: api_key_ids.split(',')
+                .map(|api_key_id| api_key_id.trim().to_string())
+                .filter(|api_key_id| !api_key_id.is_empty())
+                .collect(),
+        }
+    }
+
+    /// Only REST API requests have an API key, anything else is refused.
+    pub fn allow(&self, request_context: Option<&RequestContext>) -> bool {
+        let api_key_id = match request_context {
+            Some(RequestContext::ApiGatewayV1(request_context)) => request_context.identity.api_key_id.as_deref(),
+            _ => None,
+        };
+        api_key_id.is_some_and(|api_key_id| self.api_key_ids.iter().any(|admin_api_key_id| admin_api_key_id == api_key_id))
+    }
+}
+
+pub async fn require_admin(
+    State(admin_api_keys): State<AdminApiKeys>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !admin_api_keys.allow(request.extensions().get::<RequestContext>()) {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(json!({
+                "error": true,
+                "message": "Admin API key required"
+            }))
+        ).into_response()
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use lambda_http::aws_lambda_events::apigw::{ApiGatewayProxyRequestContext, ApiGatewayRequestIdentity};
+    use super::*;
+
+    pub(crate) fn api_key_context(api_key_id: Option<&str>) -> RequestContext {
+        RequestContext::ApiGatewayV1(ApiGatewayProxyRequestContext {
+            identity: ApiGatewayRequestIdentity { api_key_id: api_key_id.map(str::to_string), ..Default::default() },
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_allow() {
+        let admin_api_keys = AdminApiKeys::new(" internal-key-id, operator-key-id ,");
+        assert!(admin_api_keys.allow(Some(&api_key_context(Some("internal-key-id")))));
+        assert!(admin_api_keys.allow(Some(&api_key_context(Some("operator-key-id")))));
+        assert!(!admin_api_keys.allow(Some(&api_key_context(Some("client-key-id")))));
+        assert!(!admin_api_keys.allow(Some(&api_key_context(Some("")))));
+        assert!(!admin_api_keys.allow(Some(&api_key_context(None))));
+        assert!(!admin_api_keys.allow(None));
+        assert!(!AdminApiKeys::new("").allow(Some(&api_key_context(Some("")))));
+    }
+}
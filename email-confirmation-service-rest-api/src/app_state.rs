@@ -0,0 +1,69 @@
+use axum::extract::FromRef;
+use crate::admin_auth::AdminApiKeys;
+use crate::callback_host_service::CallbackHostService;
+use crate::client_config_service::ClientConfigService;
+use crate::delivery_log_service::DeliveryLogService;
+use crate::do_not_contact_service::DoNotContactService;
+use crate::email_confirmation_request_service::EmailConfirmationRequestService;
+use crate::secret_encryption_service::SecretEncryptionService;
+use crate::suppression_service::SuppressionService;
+
+#[derive(Clone, Debug)]
+pub struct AppState {
+    pub email_confirmation_request_service: EmailConfirmationRequestService,
+    pub suppression_service: SuppressionService,
+    pub client_config_service: ClientConfigService,
+    pub delivery_log_service: DeliveryLogService,
+    pub callback_host_service: CallbackHostService,
+    pub secret_encryption_service: SecretEncryptionService,
+    pub do_not_contact_service: DoNotContactService,
+    pub admin_api_keys: AdminApiKeys,
+}
+
+impl FromRef<AppState> for EmailConfirmationRequestService {
+    fn from_ref(app_state: &AppState) -> Self {
+        app_state.email_confirmation_request_service.clone()
+    }
+}
+
+impl FromRef<AppState> for SuppressionService {
+    fn from_ref(app_state: &AppState) -> Self {
+        app_state.suppression_service.clone()
+    }
+}
+
+impl FromRef<AppState> for ClientConfigService {
+    fn from_ref(app_state: &AppState) -> Self {
+        app_state.client_config_service.clone()
+    }
+}
+
+impl FromRef<AppState> for DeliveryLogService {
+    fn from_ref(app_state: &AppState) -> Self {
+        app_state.delivery_log_service.clone()
+    }
+}
+
+impl FromRef<AppState> for CallbackHostService {
+    fn from_ref(app_state: &AppState) -> Self {
+        app_state.callback_host_service.clone()
+    }
+}
+
+impl FromRef<AppState> for SecretEncryptionService {
+    fn from_ref(app_state: &AppState) -> Self {
+        app_state.secret_encryption_service.clone()
+    }
+}
+
+impl FromRef<AppState> for DoNotContactService {
+    fn from_ref(app_state: &AppState) -> Self {
+        app_state.do_not_contact_service.clone()
+    }
+}
+
+impl FromRef<AppState> for AdminApiKeys {
+    fn from_ref(app_state: &AppState) -> Self {
+        app_state.admin_api_keys.clone()
+    }
+}
@@ -0,0 +1,49 @@
+use axum::http::StatusCode;
+use axum::{
+    extract::{Path, State},
+    response::Json,
+};
+use serde_json::{json, Value};
+
+use crate::callback_host_service::CallbackHostService;
+use crate::email_confirmation_request_service::INVALID_REQUEST;
+use crate::handler::result_to_response;
+
+pub async fn get_callback_hosts(
+    State(service): State<CallbackHostService>,
+) -> (StatusCode, Json<Value>) {
+    match service.get_callback_hosts().await {
+        Ok(hosts) => (
+            StatusCode::OK,
+            Json(json!({
+                "error": false,
+                "hosts": hosts
+            }))
+        ),
+        Err(error) => result_to_response(Err(error)),
+    }
+}
+
+/// Hosts without an entry have not been called back yet, their circuit is closed.
+pub async fn get_callback_host_single(
+    State(service): State<CallbackHostService>,
+    Path(host): Path<String>,
+) -> (StatusCode, Json<Value>) {
+    match service.get_callback_host(&host).await {
+        Ok(Some(health)) => (
+            StatusCode::OK,
+            Json(json!({
+                "error": false,
+                "host": health
+            }))
+        ),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "error": true,
+                "message": format!("{INVALID_REQUEST}: {host}!")
+            }))
+        ),
+        Err(error) => result_to_response(Err(error)),
+    }
+}
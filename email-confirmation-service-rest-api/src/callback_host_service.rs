@@ -0,0 +1,55 @@
+use anyhow::{Ok, Result};
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::types::AttributeValue;
+use serde_dynamo::{from_item, from_items};
+use email_confirmation_service_common::callback_host::CallbackHostHealth;
+
+/// Reads the health of callback hosts. The circuit breaker state is written by the callback lambda only.
+#[derive(Clone, Debug)]
+pub struct CallbackHostService {
+    db_client: Client,
+    table_name: String,
+}
+
+impl CallbackHostService {
+    pub fn new(db_client: Client, table_name: &str) -> Self {
+        Self {
+            db_client,
+            table_name: table_name.to_owned(),
+        }
+    }
+
+    pub async fn get_callback_hosts(&self) -> Result<Vec<CallbackHostHealth>> {
+        let builder = self.db_client.scan().table_name(&self.table_name).consistent_read(true);
+        let mut results = builder.clone().send().await?;
+
+        let mut hosts: Vec<CallbackHostHealth> = from_items(results.items.unwrap_or_default())?;
+        while let Some(last_evaluated_key) = &results.last_evaluated_key {
+            results = builder
+                .clone()
+                .set_exclusive_start_key(Some(last_evaluated_key.to_owned()))
+                .send()
+                .await?;
+            let mut new_hosts: Vec<CallbackHostHealth> = from_items(results.items.clone().unwrap_or_default())?;
+            hosts.append(&mut new_hosts);
+        }
+        hosts.sort_by(|a, b| a.host.cmp(&b.host));
+        Ok(hosts)
+    }
+
+    pub async fn get_callback_host(&self, host: &str) -> Result<Option<CallbackHostHealth>> {
+        let results = self
+            .db_client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("host", AttributeValue::S(host.to_ascii_lowercase()))
+            .consistent_read(true)
+            .send()
+            .await?;
+
+        match results.item {
+            Some(item) => Ok(Some(from_item(item)?)),
+            None => Ok(None),
+        }
+    }
+}
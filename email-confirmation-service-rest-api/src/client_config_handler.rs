@@ -0,0 +1,207 @@
+use axum::http::StatusCode;
+use axum::{
+    extract::{Path, State},
+    response::Json,
+};
+use std::collections::HashSet;
+use serde_json::{json, Value};
+
+use crate::client_config_service::ClientConfigService;
+use crate::email_confirmation_request_service::{EmailConfirmationRequestService, INVALID_REQUEST};
+use crate::handler::{bad_request_response, result_to_response};
+use crate::handler_params::{PutCallbackAuthParams, PutClientConfigParams};
+use crate::secret_encryption_service::SecretEncryptionService;
+use crate::suppression_service::now_as_secs;
+
+use email_confirmation_service_common::callback_url::{validate_callback_url, CallbackTarget, CallbackUrlError};
+use email_confirmation_service_common::client_stats::ClientStats;
+use email_confirmation_service_common::link_click::LinkClickSettings;
+use email_confirmation_service_common::client_config::{normalize_allowed_domain, normalize_origin, validate_callback_header, Branding, CallbackHeader, ClientConfig, OAuth2ClientCredentials, SanitizedClientConfig, WebhookSecret, new_webhook_secret};
+
+pub async fn get_client_config_single(
+    State(service): State<ClientConfigService>,
+    Path(client_id): Path<String>,
+) -> (StatusCode, Json<Value>) {
+    match service.get_client_config(&client_id).await {
+        Ok(Some(client_config)) => (
+            StatusCode::OK,
+            Json(json!({
+                "error": false,
+                "client": SanitizedClientConfig::from(client_config)
+            }))
+        ),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "error": true,
+                "message": format!("{INVALID_REQUEST}: {client_id}!")
+            }))
+        ),
+        Err(error) => result_to_response(Err(error)),
+    }
+}
+
+/// Updates the given settings, the others are left as they are.
+pub async fn put_client_config_single(
+    State(service): State<ClientConfigService>,
+    Path(client_id): Path<String>,
+    Json(put_client_config_params): Json<PutClientConfigParams>,
+) -> (StatusCode, Json<Value>) {
+    if let Some(Err(message)) = put_client_config_params.branding.as_ref().map(Branding::validate) {
+        return bad_request_response(&message)
+    }
+    if let Some(Err(message)) = put_client_config_params.link_click.as_ref().map(LinkClickSettings::validate) {
+        return bad_request_response(&message)
+    }
+    let allowed_origins = match put_client_config_params.allowed_origins.as_ref()
+        .map(|allowed_origins| allowed_origins.iter().map(|origin| normalize_origin(origin)).collect::<Result<Vec<String>, String>>()) {
+        Some(Err(message)) => return bad_request_response(&message),
+        Some(Ok(allowed_origins)) => Some(allowed_origins),
+        None => None,
+    };
+    let allowed_callback_domains = match put_client_config_params.allowed_callback_domains.as_ref()
+        .map(|allowed_domains| allowed_domains.iter().map(|allowed_domain| normalize_allowed_domain(allowed_domain)).collect::<Result<Vec<String>, String>>()) {
+        Some(Err(message)) => return bad_request_response(&message),
+        Some(Ok(allowed_callback_domains)) => Some(allowed_callback_domains),
+        None => None,
+    };
+    let result = async {
+        let mut client_config = service.get_client_config(&client_id).await?
+            .unwrap_or_else(|| ClientConfig::new(&client_id));
+        if let Some(callback_payload_version) = put_client_config_params.callback_payload_version {
+            client_config.callback_payload_version = callback_payload_version;
+        }
+        if let Some(subscribed_events) = put_client_config_params.subscribed_events {
+            client_config.subscribed_events = subscribed_events;
+        }
+        if let Some(allowed_callback_domains) = allowed_callback_domains {
+            client_config.allowed_callback_domains = allowed_callback_domains;
+        }
+        if let Some(branding) = put_client_config_params.branding {
+            client_config.branding = Some(branding).filter(|branding| *branding != Branding::default());
+        }
+        if let Some(allowed_origins) = allowed_origins {
+            client_config.allowed_origins = allowed_origins;
+        }
+        if let Some(confirmation_ip_address_mode) = put_client_config_params.confirmation_ip_address_mode {
+            client_config.confirmation_ip_address_mode = confirmation_ip_address_mode;
+        }
+        if let Some(link_click) = put_client_config_params.link_click {
+            client_config.link_click = link_click;
+        }
+        service.put_client_config(&client_config).await?;
+        Ok(Json(json!({
+            "error": false,
+            "client": SanitizedClientConfig::from(client_config)
+        })))
+    }.await;
+    result_to_response(result)
+}
+
+/// Request and click counts of a client, over the requests still in the table.
+pub async fn get_client_stats(
+    State(request_service): State<EmailConfirmationRequestService>,
+    Path(client_id): Path<String>,
+) -> (StatusCode, Json<Value>) {
+    let result = async {
+        let requests = request_service.get_client_requests(&client_id).await?;
+        Ok(Json(json!({
+            "error": false,
+            "stats": ClientStats::new(&client_id, &requests)
+        })))
+    }.await;
+    result_to_response(result)
+}
+
+/// Creates a new webhook secret, the previous one keeps signing callbacks during the overlap window.
+/// The secret is encrypted before it is stored, this is the only response that contains it.
+pub async fn post_webhook_secret(
+    State(service): State<ClientConfigService>,
+    State(secret_encryption_service): State<SecretEncryptionService>,
+    Path(client_id): Path<String>,
+) -> (StatusCode, Json<Value>) {
+    let result = async {
+        let now = now_as_secs();
+        let mut client_config = service.get_client_config(&client_id).await?
+            .unwrap_or_else(|| ClientConfig::new(&client_id));
+        let secret = new_webhook_secret();
+        let webhook_secret = WebhookSecret::new(secret_encryption_service.encrypt(&client_id, &secret).await?, now);
+        let secret_id = webhook_secret.secret_id.clone();
+        client_config.rotate_webhook_secret(webhook_secret, now);
+        service.put_client_config(&client_config).await?;
+        Ok(Json(json!({
+            "error": false,
+            "webhook_secret": {
+                "secret_id": secret_id,
+                "secret": secret,
+                "created_at": now
+            },
+            "client": SanitizedClientConfig::from(client_config)
+        })))
+    }.await;
+    result_to_response(result)
+}
+
+/// Replaces the custom headers and the OAuth2 credentials sent with the client's HTTP callbacks.
+/// The secrets are encrypted before they are stored and never returned.
+pub async fn put_callback_auth(
+    State(service): State<ClientConfigService>,
+    State(secret_encryption_service): State<SecretEncryptionService>,
+    Path(client_id): Path<String>,
+    Json(put_callback_auth_params): Json<PutCallbackAuthParams>,
+) -> (StatusCode, Json<Value>) {
+    if let Err(message) = validate_callback_auth(&put_callback_auth_params) {
+        return bad_request_response(&message)
+    }
+    let result = async {
+        let mut client_config = service.get_client_config(&client_id).await?
+            .unwrap_or_else(|| ClientConfig::new(&client_id));
+        let mut callback_headers = vec![];
+        for (name, value) in &put_callback_auth_params.headers {
+            let value = secret_encryption_service.encrypt(&client_id, value).await?;
+            callback_headers.push(CallbackHeader { name: name.clone(), value });
+        }
+        client_config.callback_headers = callback_headers;
+        client_config.callback_oauth2 = match put_callback_auth_params.oauth2 {
+            Some(oauth2) => Some(OAuth2ClientCredentials {
+                client_secret: secret_encryption_service.encrypt(&client_id, &oauth2.client_secret).await?,
+                token_url: oauth2.token_url,
+                client_id: oauth2.client_id,
+                scope: oauth2.scope,
+            }),
+            None => None,
+        };
+        service.put_client_config(&client_config).await?;
+        Ok(Json(json!({
+            "error": false,
+            "client": SanitizedClientConfig::from(client_config)
+        })))
+    }.await;
+    result_to_response(result)
+}
+
+/// The token url is held to the same rules as a callback url, except for the allowlist: authorization servers are often on another domain.
+fn validate_callback_auth(put_callback_auth_params: &PutCallbackAuthParams) -> Result<(), String> {
+    let mut header_names = HashSet::new();
+    for (name, value) in &put_callback_auth_params.headers {
+        validate_callback_header(name, value)?;
+        if !header_names.insert(name.to_ascii_lowercase()) {
+            return Err(format!("Duplicate header: {}", name))
+        }
+    }
+    if let Some(oauth2) = &put_callback_auth_params.oauth2 {
+        if header_names.contains("authorization") {
+            return Err("An Authorization header cannot be combined with oauth2".to_string())
+        }
+        if oauth2.client_id.is_empty() || oauth2.client_secret.is_empty() {
+            return Err("oauth2 needs a client_id and a client_secret".to_string())
+        }
+        // A token url is not a client's callback, queues and event buses are refused whatever their name
+        match validate_callback_url(&oauth2.token_url, "", &[]) {
+            Ok(CallbackTarget::Http(_)) => {},
+            Ok(_) | Err(CallbackUrlError::TargetNotAllowed(_)) => return Err("token_url must be an https url".to_string()),
+            Err(error) => return Err(format!("Invalid token_url: {}", error)),
+        }
+    }
+    Ok(())
+}
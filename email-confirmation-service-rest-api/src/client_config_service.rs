@@ -0,0 +1,47 @@
+use anyhow::{Ok, Result};
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::types::AttributeValue;
+use serde_dynamo::{from_item, to_item};
+use email_confirmation_service_common::client_config::ClientConfig;
+
+#[derive(Clone, Debug)]
+pub struct ClientConfigService {
+    db_client: Client,
+    table_name: String,
+}
+
+impl ClientConfigService {
+    pub fn new(db_client: Client, table_name: &str) -> Self {
+        Self {
+            db_client,
+            table_name: table_name.to_owned(),
+        }
+    }
+
+    pub async fn get_client_config(&self, client_id: &str) -> Result<Option<ClientConfig>> {
+        let results = self
+            .db_client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("client_id", AttributeValue::S(client_id.to_owned()))
+            .consistent_read(true)
+            .send()
+            .await?;
+
+        match results.item {
+            Some(item) => Ok(Some(from_item(item)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn put_client_config(&self, client_config: &ClientConfig) -> Result<()> {
+        let item = to_item(client_config)?;
+        self.db_client
+            .put_item()
+            .table_name(&self.table_name)
+            .set_item(Some(item))
+            .send()
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,65 @@
+use axum::http::StatusCode;
+use axum::{
+    extract::{Path, State},
+    response::Json,
+};
+use serde_json::{json, Value};
+
+use crate::delivery_log_service::DeliveryLogService;
+use crate::email_confirmation_request_service::{EmailConfirmationRequestService, INVALID_REQUEST};
+use crate::handler::result_to_response;
+use crate::suppression_service::now_as_secs;
+
+pub async fn get_deliveries(
+    State(service): State<DeliveryLogService>,
+    Path(pk): Path<String>,
+) -> (StatusCode, Json<Value>) {
+    match service.get_deliveries(&pk).await {
+        Ok(deliveries) => (
+            StatusCode::OK,
+            Json(json!({
+                "error": false,
+                "deliveries": deliveries
+            }))
+        ),
+        Err(error) => result_to_response(Err(error)),
+    }
+}
+
+/// Asks the callback lambda to send the latest callback again. The payload, and so the event_id, is the same as before,
+/// the signature headers are new. The outcome shows up in the delivery log.
+pub async fn post_delivery_replay(
+    State(service): State<DeliveryLogService>,
+    State(email_confirmation_request_service): State<EmailConfirmationRequestService>,
+    Path(pk): Path<String>,
+) -> (StatusCode, Json<Value>) {
+    let latest_delivery = match service.get_latest_delivery(&pk).await {
+        Ok(Some(latest_delivery)) => latest_delivery,
+        Ok(None) => return (
+            StatusCode::CONFLICT,
+            Json(json!({
+                "error": true,
+                "message": format!("No callback to replay for {pk}")
+            }))
+        ),
+        Err(error) => return result_to_response(Err(error)),
+    };
+
+    match email_confirmation_request_service.request_callback_replay(&pk, now_as_secs()).await {
+        Ok(true) => (
+            StatusCode::ACCEPTED,
+            Json(json!({
+                "error": false,
+                "replay_of": latest_delivery
+            }))
+        ),
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "error": true,
+                "message": format!("{INVALID_REQUEST}: {pk}!")
+            }))
+        ),
+        Err(error) => result_to_response(Err(error)),
+    }
+}
@@ -0,0 +1,62 @@
+use anyhow::{Ok, Result};
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::types::AttributeValue;
+use serde_dynamo::from_items;
+use email_confirmation_service_common::delivery_log::CallbackDelivery;
+
+/// Reads the callback delivery log. The log is written by the callback lambda only.
+#[derive(Clone, Debug)]
+pub struct DeliveryLogService {
+    db_client: Client,
+    table_name: String,
+}
+
+impl DeliveryLogService {
+    pub fn new(db_client: Client, table_name: &str) -> Self {
+        Self {
+            db_client,
+            table_name: table_name.to_owned(),
+        }
+    }
+
+    /// Deliveries of the request, oldest first.
+    pub async fn get_deliveries(&self, pk: &str) -> Result<Vec<CallbackDelivery>> {
+        let mut deliveries: Vec<CallbackDelivery> = vec![];
+        let mut exclusive_start_key = None;
+        loop {
+            let results = self
+                .db_client
+                .query()
+                .table_name(&self.table_name)
+                .key_condition_expression("#name = :value")
+                .expression_attribute_names("#name", "pk")
+                .expression_attribute_values(":value", AttributeValue::S(pk.to_owned()))
+                .set_exclusive_start_key(exclusive_start_key)
+                .consistent_read(true)
+                .send()
+                .await?;
+            deliveries.extend(from_items::<_, CallbackDelivery>(results.items.unwrap_or_default())?);
+            exclusive_start_key = results.last_evaluated_key;
+            if exclusive_start_key.is_none() {
+                return Ok(deliveries)
+            }
+        }
+    }
+
+    pub async fn get_latest_delivery(&self, pk: &str) -> Result<Option<CallbackDelivery>> {
+        let results = self
+            .db_client
+            .query()
+            .table_name(&self.table_name)
+            .key_condition_expression("#name = :value")
+            .expression_attribute_names("#name", "pk")
+            .expression_attribute_values(":value", AttributeValue::S(pk.to_owned()))
+            .scan_index_forward(false)
+            .limit(1)
+            .consistent_read(true)
+            .send()
+            .await?;
+        let deliveries: Vec<CallbackDelivery> = from_items(results.items.unwrap_or_default())?;
+        Ok(deliveries.into_iter().next())
+    }
+}
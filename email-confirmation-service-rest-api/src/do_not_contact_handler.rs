@@ -0,0 +1,47 @@
+use axum::http::StatusCode;
+use axum::{
+    extract::{Path, State},
+    response::Json,
+};
+use serde_json::{json, Value};
+
+use crate::do_not_contact_service::DoNotContactService;
+use crate::handler::result_to_response;
+use crate::suppression_service::now_as_secs;
+
+use email_confirmation_service_common::do_not_contact::DoNotContactEntry;
+
+pub async fn get_do_not_contact_entries(
+    State(service): State<DoNotContactService>,
+    Path(client_id): Path<String>,
+) -> (StatusCode, Json<Value>) {
+    let result = service.get_entries(&client_id).await;
+    result_to_response(result.map(|entries| Json(json!({
+        "error": false,
+        "entries": entries
+    }))))
+}
+
+/// Adds an address by hand, e.g. on a request that reached the client through another channel.
+pub async fn put_do_not_contact_single(
+    State(service): State<DoNotContactService>,
+    Path((client_id, email)): Path<(String, String)>,
+) -> (StatusCode, Json<Value>) {
+    let entry = DoNotContactEntry::new(&client_id, &email, None, now_as_secs());
+    let result = service.put_entry(&entry).await;
+    result_to_response(result.map(|_| Json(json!({
+        "error": false,
+        "entry": entry
+    }))))
+}
+
+pub async fn delete_do_not_contact_single(
+    State(service): State<DoNotContactService>,
+    Path((client_id, email)): Path<(String, String)>,
+) -> (StatusCode, Json<Value>) {
+    let result = service.delete_entry(&client_id, &email).await;
+    result_to_response(result.map(|_| Json(json!({
+        "error": false,
+        "message": format!("Do-not-contact entry for email: {} of client: {} deleted.", email, client_id)
+    }))))
+}
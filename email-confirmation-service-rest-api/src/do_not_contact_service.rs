@@ -0,0 +1,81 @@
+use anyhow::{Ok, Result};
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::types::AttributeValue;
+use serde_dynamo::{from_item, from_items, to_item};
+use email_confirmation_service_common::do_not_contact::DoNotContactEntry;
+use email_confirmation_service_common::suppression::normalize_email;
+
+/// The per client do-not-contact lists, keyed by client id and address.
+#[derive(Clone, Debug)]
+pub struct DoNotContactService {
+    db_client: Client,
+    table_name: String,
+}
+
+impl DoNotContactService {
+    pub fn new(db_client: Client, table_name: &str) -> Self {
+        Self {
+            db_client,
+            table_name: table_name.to_owned(),
+        }
+    }
+
+    pub async fn get_entries(&self, client_id: &str) -> Result<Vec<DoNotContactEntry>> {
+        let mut entries: Vec<DoNotContactEntry> = vec![];
+        let mut exclusive_start_key = None;
+        loop {
+            let results = self
+                .db_client
+                .query()
+                .table_name(&self.table_name)
+                .key_condition_expression("#name = :value")
+                .expression_attribute_names("#name", "client_id")
+                .expression_attribute_values(":value", AttributeValue::S(client_id.to_owned()))
+                .set_exclusive_start_key(exclusive_start_key)
+                .send()
+                .await?;
+            entries.extend(from_items::<_, DoNotContactEntry>(results.items.unwrap_or_default())?);
+            exclusive_start_key = results.last_evaluated_key;
+            if exclusive_start_key.is_none() {
+                return Ok(entries)
+            }
+        }
+    }
+
+    pub async fn get_entry(&self, client_id: &str, email: &str) -> Result<Option<DoNotContactEntry>> {
+        let results = self
+            .db_client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("client_id", AttributeValue::S(client_id.to_owned()))
+            .key("email", AttributeValue::S(normalize_email(email)))
+            .send()
+            .await?;
+
+        match results.item {
+            Some(item) => Ok(Some(from_item(item)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn put_entry(&self, entry: &DoNotContactEntry) -> Result<()> {
+        self.db_client
+            .put_item()
+            .table_name(&self.table_name)
+            .set_item(Some(to_item(entry)?))
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_entry(&self, client_id: &str, email: &str) -> Result<()> {
+        self.db_client
+            .delete_item()
+            .table_name(&self.table_name)
+            .key("client_id", AttributeValue::S(client_id.to_owned()))
+            .key("email", AttributeValue::S(normalize_email(email)))
+            .send()
+            .await?;
+        Ok(())
+    }
+}
@@ -1,12 +1,19 @@
+use std::collections::HashMap;
 use std::string::ToString;
 use std::time::{SystemTime, UNIX_EPOCH};
 use anyhow::{bail, Ok, Result};
 use aws_sdk_dynamodb::Client;
-use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::operation::update_item::UpdateItemError;
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue};
 use axum::Json;
-use serde_dynamo::{from_item, from_items, to_item};
+use lambda_runtime::tracing;
+use serde_dynamo::{from_item, from_items, to_attribute_value, to_item};
 use serde_json::{json, Value};
-use email_confirmation_service_common::email_confirmation_request::{ EmailConfirmationRequest, SanitizedEmailConfirmationRequest, Status};
+use email_confirmation_service_common::email_confirmation_request::{CallbackAttempt, EmailConfirmationRequest, EmailKind, CALLBACK_RETRY_INDEX_KEY, CLIENT_ID_INDEX_NAME, CALLBACK_RETRY_INDEX_KEY_VALUE, SanitizedEmailConfirmationRequest, Status, PENDING_INDEX_KEY, PENDING_INDEX_KEY_VALUE, REMINDER_INDEX_KEY, REMINDER_INDEX_KEY_VALUE};
+use email_confirmation_service_common::callback_event::CallbackEventType;
+use email_confirmation_service_common::confirmation_evidence::ConfirmationEvidence;
+use email_confirmation_service_common::display_code::DISPLAY_CODE_ATTEMPTS_LIMIT;
+use email_confirmation_service_common::form_token::form_token_hash;
 use crate::handler_params::{QueryParams};
 
 pub const INVALID_REQUEST:&str = "Invalid request";
@@ -81,7 +88,12 @@ impl EmailConfirmationRequestService {
         if self.request_exist(&ec_request.pk).await? {
             bail!("Request exists!")
         }
-        let item = to_item(ec_request)?;
+        let has_reminder = ec_request.reminder_due_at.is_some();
+        let mut item: HashMap<String, AttributeValue> = to_item(ec_request)?;
+        // Puts the request in the sparse reminder index until the reminder is sent or no longer needed
+        if has_reminder {
+            item.insert(REMINDER_INDEX_KEY.to_string(), AttributeValue::S(REMINDER_INDEX_KEY_VALUE.to_string()));
+        }
 
        let builder = self
             .db_client
@@ -124,6 +136,14 @@ impl EmailConfirmationRequestService {
     }
 
     pub(crate) async fn get_email_confirmation_request_internal(&self, pk: String) -> Result<EmailConfirmationRequest> {
+        match self.find_email_confirmation_request(&pk).await? {
+            Some(confirmation_request) => Ok(confirmation_request),
+            None => bail!("{INVALID_REQUEST}: {pk}!"),
+        }
+    }
+
+    /// Returns None if there is no request with the pk.
+    pub(crate) async fn find_email_confirmation_request(&self, pk: &str) -> Result<Option<EmailConfirmationRequest>> {
         let results = self
             .db_client
             .query()
@@ -133,17 +153,11 @@ impl EmailConfirmationRequestService {
             .expression_attribute_values(":value", AttributeValue::S(pk.to_owned()))
             .send()
             .await?;
-        if results.count == 0
-            || results.items.is_none()
-            || results.items.clone().unwrap().is_empty()
-        {
-            bail!("{INVALID_REQUEST}: {pk}!")
-        }
-
-        let item = results.items.unwrap().first().unwrap().to_owned();
-        let confirmation_request: EmailConfirmationRequest = from_item(item)?;
 
-        Ok(confirmation_request)
+        match results.items.unwrap_or_default().into_iter().next() {
+            Some(item) => Ok(Some(from_item(item)?)),
+            None => Ok(None),
+        }
     }
 
     pub async fn delete_email_confirmation_request_single(&self, pk: String) -> Result<Json<Value>> {
@@ -164,26 +178,482 @@ impl EmailConfirmationRequestService {
         })))
     }
 
-    pub async fn put_email_confirmation_request_status(&self, pk: String, status: Status) -> Result<EmailConfirmationRequest> {
+    /// Returns None if the request is in a status that cannot change to the given one, e.g. a cancelled request cannot be confirmed,
+    /// or if the given form token is not the request's unexpired one. The form token is removed with the update, so it is used once.
+    pub async fn put_email_confirmation_request_status(&self, pk: String, status: Status, reason: Option<String>, form_token: Option<String>,
+                                                       confirmation_evidence: Option<ConfirmationEvidence>) -> Result<Option<EmailConfirmationRequest>> {
         if !self.request_exist(&pk).await? {
             bail!("{INVALID_REQUEST}: {pk}!")
         }
 
         let updated_at = format!("{}", SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs());
-        self.db_client
+        // The right hand side is evaluated before the update, so previous_status gets the old status
+        let mut set_expression = vec!["#name6 = #name1", "#name1 = :value1", "#name2 = :value2"];
+        let mut remove_expression = vec![];
+        let mut builder = self.db_client
             .update_item()
             .table_name(&self.table_name)
             .key("pk", AttributeValue::S(pk.clone()))
 
-            .update_expression("set #name1 = :value1, #name2 = :value2")
             .expression_attribute_names("#name1", "status")
+            .expression_attribute_names("#name6", "previous_status")
             .expression_attribute_names("#name2", "updated_at")
+            .expression_attribute_names("#name3", "status_reason")
+            .expression_attribute_names("#name4", PENDING_INDEX_KEY)
+            .expression_attribute_names("#name5", REMINDER_INDEX_KEY)
             .expression_attribute_values(":value1", AttributeValue::S(status.to_string()))
-            .expression_attribute_values(":value2", AttributeValue::N(updated_at))
+            .expression_attribute_values(":value2", AttributeValue::N(updated_at.clone()));
+
+        match reason {
+            Some(reason) => {
+                set_expression.push("#name3 = :value3");
+                builder = builder.expression_attribute_values(":value3", AttributeValue::S(reason));
+            },
+            None => remove_expression.push("#name3"),
+        }
+
+        // Keeps the request in the sparse pending expiry index only while it is Pending
+        if status == Status::Pending {
+            set_expression.push("#name4 = :value4");
+            builder = builder.expression_attribute_values(":value4", AttributeValue::S(PENDING_INDEX_KEY_VALUE.to_string()));
+        } else {
+            remove_expression.push("#name4");
+        }
+
+        // A reminder is only sent for requests that are still waiting for confirmation
+        if status != Status::Queued && status != Status::Pending {
+            remove_expression.push("#name5");
+        }
+
+        // The callback of a new event gets its own attempts, those of an earlier event, e.g. pending, do not use them up.
+        // A retry of the earlier event is dropped, the new event supersedes it
+        if CallbackEventType::for_status(&status).is_some() {
+            remove_expression.extend(["#name11", "#name12", "#name13", "#name14"]);
+            builder = builder
+                .expression_attribute_names("#name11", "callback_attempts")
+                .expression_attribute_names("#name12", "callback_last_error")
+                .expression_attribute_names("#name13", "callback_next_attempt_at")
+                .expression_attribute_names("#name14", CALLBACK_RETRY_INDEX_KEY);
+        }
+
+        // Written with the status, so a confirmed request never lacks the evidence of how it was confirmed
+        if let Some(confirmation_evidence) = &confirmation_evidence {
+            set_expression.push("#name9 = :value9");
+            builder = builder
+                .expression_attribute_names("#name9", "confirmation_evidence")
+                .expression_attribute_values(":value9", to_attribute_value(confirmation_evidence)?);
+        }
+
+        let mut conditions = vec![];
+        if let Some(form_token) = form_token {
+            remove_expression.push("#name7");
+            remove_expression.push("#name8");
+            remove_expression.push("#name10");
+            conditions.push("#name7 = :form_token_hash AND #name8 > :now AND (attribute_not_exists(#name10) OR #name10 <= :now)".to_string());
+            builder = builder
+                .expression_attribute_names("#name7", "form_token_hash")
+                .expression_attribute_names("#name8", "form_token_expires_at")
+                .expression_attribute_names("#name10", "form_token_not_before")
+                .expression_attribute_values(":form_token_hash", AttributeValue::S(form_token_hash(&form_token)))
+                .expression_attribute_values(":now", AttributeValue::N(updated_at));
+        }
+
+        let mut update_expression = format!("set {}", set_expression.join(", "));
+        if !remove_expression.is_empty() {
+            update_expression = format!("{} remove {}", update_expression, remove_expression.join(", "));
+        }
+        let mut builder = builder.update_expression(update_expression);
+
+        if let Some(allowed_statuses) = allowed_previous_statuses(&status) {
+            let placeholders: Vec<String> = (0..allowed_statuses.len()).map(|index| format!(":allowed{index}")).collect();
+            conditions.push(format!("#name1 IN ({})", placeholders.join(", ")));
+            for (placeholder, allowed_status) in placeholders.iter().zip(allowed_statuses) {
+                builder = builder.expression_attribute_values(placeholder, AttributeValue::S(allowed_status.to_string()));
+            }
+        }
+        if !conditions.is_empty() {
+            builder = builder.condition_expression(conditions.join(" AND "));
+        }
+
+        if let Err(error) = builder.send().await {
+            return match error.into_service_error() {
+                UpdateItemError::ConditionalCheckFailedException(_) => Ok(None),
+                other => Err(other.into()),
+            }
+        }
+
+        Ok(Some(self.get_email_confirmation_request_internal(pk).await?))
+    }
+
+    /// Replaces the form token of the request, a form opened before stops working. Does not touch updated_at either.
+    pub async fn put_email_confirmation_request_form_token(&self, pk: String, form_token: &str, expires_at: u64, not_before: Option<u64>) -> Result<()> {
+        let mut builder = self.db_client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(pk.clone()))
+            .condition_expression("attribute_exists(pk)")
+            .expression_attribute_names("#name1", "form_token_hash")
+            .expression_attribute_names("#name2", "form_token_expires_at")
+            .expression_attribute_names("#name3", "form_token_not_before")
+            .expression_attribute_values(":value1", AttributeValue::S(form_token_hash(form_token)))
+            .expression_attribute_values(":value2", AttributeValue::N(expires_at.to_string()));
+        builder = match not_before {
+            Some(not_before) => builder
+                .update_expression("set #name1 = :value1, #name2 = :value2, #name3 = :value3")
+                .expression_attribute_values(":value3", AttributeValue::N(not_before.to_string())),
+            None => builder.update_expression("set #name1 = :value1, #name2 = :value2 remove #name3"),
+        };
+        let result = builder.send().await;
+
+        if let Err(error) = result {
+            match error.into_service_error() {
+                UpdateItemError::ConditionalCheckFailedException(_) => bail!("{INVALID_REQUEST}: {pk}!"),
+                other => return Err(other.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Counts a click of the link, a mail scanner's apart from the recipient's. Does not touch updated_at,
+    /// so the stream handlers do not act on it and the signature of the link stays valid.
+    pub async fn post_email_confirmation_request_click(&self, pk: String, scanner: bool, clicked_at: u64) -> Result<()> {
+        let builder = self.db_client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(pk.clone()))
+            .condition_expression("attribute_exists(pk)")
+            .expression_attribute_values(":one", AttributeValue::N("1".to_string()));
+        let builder = match scanner {
+            true => builder
+                .update_expression("add #name1 :one")
+                .expression_attribute_names("#name1", "scanner_click_count"),
+            false => builder
+                .update_expression("add #name1 :one set #name2 = if_not_exists(#name2, :value2)")
+                .expression_attribute_names("#name1", "click_count")
+                .expression_attribute_names("#name2", "first_clicked_at")
+                .expression_attribute_values(":value2", AttributeValue::N(clicked_at.to_string())),
+        };
+
+        if let Err(error) = builder.send().await {
+            match error.into_service_error() {
+                UpdateItemError::ConditionalCheckFailedException(_) => bail!("{INVALID_REQUEST}: {pk}!"),
+                other => return Err(other.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Counts an attempt at the display code before the code is compared, so that concurrent guesses cannot get past the limit.
+    /// Returns the attempts made with this one, None if they were used up already. Does not touch updated_at.
+    pub async fn post_display_code_attempt(&self, pk: String) -> Result<Option<u32>> {
+        let result = self.db_client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(pk.clone()))
+            .condition_expression("attribute_exists(pk) AND (attribute_not_exists(#name1) OR #name1 < :limit)")
+            .update_expression("add #name1 :one")
+            .expression_attribute_names("#name1", "display_code_attempts")
+            .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+            .expression_attribute_values(":limit", AttributeValue::N(DISPLAY_CODE_ATTEMPTS_LIMIT.to_string()))
+            .return_values(ReturnValue::UpdatedNew)
+            .send()
+            .await;
+
+        let output = match result.map_err(|error| error.into_service_error()) {
+            Err(UpdateItemError::ConditionalCheckFailedException(_)) => return Ok(None),
+            result => result?,
+        };
+        match output.attributes.as_ref().and_then(|attributes| attributes.get("display_code_attempts")) {
+            Some(AttributeValue::N(attempts)) => Ok(Some(attempts.parse()?)),
+            _ => bail!("No display_code_attempts returned for {pk}"),
+        }
+    }
+
+    /// Marks the display code as entered on the original device, once. None if it was verified meanwhile,
+    /// or the attempts were used up before this one. Does not touch updated_at.
+    pub async fn put_display_code_verified(&self, pk: String, verified_at: u64) -> Result<Option<EmailConfirmationRequest>> {
+        let result = self.db_client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(pk.clone()))
+            .condition_expression("attribute_exists(pk) AND attribute_not_exists(#name1) AND (attribute_not_exists(#name2) OR #name2 <= :limit)")
+            .update_expression("set #name1 = :value1")
+            .expression_attribute_names("#name1", "display_code_verified_at")
+            .expression_attribute_names("#name2", "display_code_attempts")
+            .expression_attribute_values(":value1", AttributeValue::N(verified_at.to_string()))
+            .expression_attribute_values(":limit", AttributeValue::N(DISPLAY_CODE_ATTEMPTS_LIMIT.to_string()))
+            .send()
+            .await;
+
+        if let Err(error) = result {
+            return match error.into_service_error() {
+                UpdateItemError::ConditionalCheckFailedException(_) => Ok(None),
+                other => Err(other.into()),
+            }
+        }
+        Ok(Some(self.get_email_confirmation_request_internal(pk).await?))
+    }
+
+    /// The requests of a client still in the table, oldest first, from the client_id index.
+    pub async fn get_client_requests(&self, client_id: &str) -> Result<Vec<EmailConfirmationRequest>> {
+        let builder = self.db_client
+            .query()
+            .table_name(&self.table_name)
+            .index_name(CLIENT_ID_INDEX_NAME)
+            .key_condition_expression("#name1 = :value1")
+            .expression_attribute_names("#name1", "client_id")
+            .expression_attribute_values(":value1", AttributeValue::S(client_id.to_string()));
+        let mut requests = vec![];
+        let mut exclusive_start_key = None;
+        loop {
+            let results = builder.clone().set_exclusive_start_key(exclusive_start_key).send().await?;
+            if let Some(items) = results.items {
+                let mut page: Vec<EmailConfirmationRequest> = from_items(items)?;
+                requests.append(&mut page);
+            }
+            exclusive_start_key = results.last_evaluated_key;
+            if exclusive_start_key.is_none() {
+                break;
+            }
+        }
+        Ok(requests)
+    }
+
+    // Does not touch updated_at: the signature in the already sent link depends on it.
+    pub async fn put_email_confirmation_request_message_id(&self, pk: String, message_id: String, email_kind: EmailKind) -> Result<EmailConfirmationRequest> {
+        if !self.request_exist(&pk).await? {
+            bail!("{INVALID_REQUEST}: {pk}!")
+        }
+
+        self.db_client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(pk.clone()))
+
+            .update_expression("set #name1 = :value1")
+            .condition_expression("attribute_not_exists(#name1) OR #name1 = :value1")
+            .expression_attribute_names("#name1", match email_kind {
+                EmailKind::Confirmation => "ses_message_id",
+                EmailKind::Reminder => "reminder_message_id",
+            })
+            .expression_attribute_values(":value1", AttributeValue::S(message_id))
 
             .send()
             .await?;
 
         Ok(self.get_email_confirmation_request_internal(pk).await?)
     }
-}
\ No newline at end of file
+
+    // Does not touch updated_at either: the event_id of the replayed callback depends on it.
+    // Returns false if there is no such request.
+    pub async fn request_callback_replay(&self, pk: &str, now: u64) -> Result<bool> {
+        let result = self.db_client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(pk.to_owned()))
+            .update_expression("set #name1 = :value1")
+            .condition_expression("attribute_exists(#name2)")
+            .expression_attribute_names("#name1", "callback_replay_requested_at")
+            .expression_attribute_names("#name2", "pk")
+            .expression_attribute_values(":value1", AttributeValue::N(now.to_string()))
+            .send()
+            .await;
+
+        if let Err(error) = result {
+            return match error.into_service_error() {
+                UpdateItemError::ConditionalCheckFailedException(_) => Ok(false),
+                other => Err(other.into()),
+            }
+        }
+        Ok(true)
+    }
+
+    // Does not touch updated_at either. The attempt number makes this idempotent:
+    // a retried call for an attempt that is already in the history does not append it again.
+    pub async fn post_email_confirmation_request_callback_attempt(&self, pk: String, attempt_number: usize, attempt: CallbackAttempt, next_attempt_at: Option<u64>) -> Result<EmailConfirmationRequest> {
+        if !self.request_exist(&pk).await? || attempt_number == 0 {
+            bail!("{INVALID_REQUEST}: {pk}!")
+        }
+
+        let mut set_expression = vec!["#name1 = list_append(if_not_exists(#name1, :empty), :value1)", "#name2 = :value2"];
+        let mut remove_expression = vec![];
+        let mut builder = self.db_client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(pk.clone()))
+
+            .expression_attribute_names("#name1", "callback_attempts")
+            .expression_attribute_names("#name2", "callback_last_error")
+            .expression_attribute_names("#name3", "callback_next_attempt_at")
+            .expression_attribute_names("#name4", CALLBACK_RETRY_INDEX_KEY)
+            .expression_attribute_values(":empty", AttributeValue::L(vec![]))
+            .expression_attribute_values(":value1", AttributeValue::L(vec![to_attribute_value(&attempt)?]))
+            .expression_attribute_values(":value2", AttributeValue::S(attempt.error.clone()));
+
+        // Puts the request in the sparse callback retry index until the sweeper requests the retry
+        match next_attempt_at {
+            Some(next_attempt_at) => {
+                set_expression.push("#name3 = :value3");
+                set_expression.push("#name4 = :value4");
+                builder = builder
+                    .expression_attribute_values(":value3", AttributeValue::N(next_attempt_at.to_string()))
+                    .expression_attribute_values(":value4", AttributeValue::S(CALLBACK_RETRY_INDEX_KEY_VALUE.to_string()));
+            },
+            None => {
+                remove_expression.push("#name3");
+                remove_expression.push("#name4");
+            }
+        }
+
+        builder = if attempt_number == 1 {
+            builder.condition_expression("attribute_not_exists(#name1)")
+        } else {
+            builder
+                .condition_expression("size(#name1) = :previous_attempts")
+                .expression_attribute_values(":previous_attempts", AttributeValue::N((attempt_number - 1).to_string()))
+        };
+
+        let mut update_expression = format!("set {}", set_expression.join(", "));
+        if !remove_expression.is_empty() {
+            update_expression = format!("{} remove {}", update_expression, remove_expression.join(", "));
+        }
+
+        if let Err(error) = builder.update_expression(update_expression).send().await {
+            match error.into_service_error() {
+                UpdateItemError::ConditionalCheckFailedException(_) => tracing::info!("Callback attempt {} of {} already recorded.", attempt_number, &pk),
+                other => return Err(other.into()),
+            }
+        }
+
+        Ok(self.get_email_confirmation_request_internal(pk).await?)
+    }
+
+    /// Defers the callback without counting an attempt, the sweeper requests it again at next_attempt_at.
+    pub async fn put_email_confirmation_request_callback_next_attempt(&self, pk: String, next_attempt_at: u64, reason: String) -> Result<EmailConfirmationRequest> {
+        let result = self.db_client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(pk.clone()))
+            .update_expression("set #name1 = :value1, #name2 = :value2, #name3 = :value3")
+            .condition_expression("attribute_exists(pk)")
+            .expression_attribute_names("#name1", "callback_last_error")
+            .expression_attribute_names("#name2", "callback_next_attempt_at")
+            .expression_attribute_names("#name3", CALLBACK_RETRY_INDEX_KEY)
+            .expression_attribute_values(":value1", AttributeValue::S(reason))
+            .expression_attribute_values(":value2", AttributeValue::N(next_attempt_at.to_string()))
+            .expression_attribute_values(":value3", AttributeValue::S(CALLBACK_RETRY_INDEX_KEY_VALUE.to_string()))
+            .send()
+            .await;
+
+        if let Err(error) = result {
+            match error.into_service_error() {
+                UpdateItemError::ConditionalCheckFailedException(_) => bail!("{INVALID_REQUEST}: {pk}!"),
+                other => return Err(other.into()),
+            }
+        }
+
+        Ok(self.get_email_confirmation_request_internal(pk).await?)
+    }
+}
+
+/// Whether a request in the status `from` can be set `to`. Checked again by the update, this is for answering early.
+pub(crate) fn can_change_status(from: &Status, to: &Status) -> bool {
+    allowed_previous_statuses(to).is_none_or(|allowed_statuses| allowed_statuses.contains(from))
+}
+
+/// Statuses a request must be in to move to the given status, None if any status can.
+fn allowed_previous_statuses(status: &Status) -> Option<Vec<Status>> {
+    match status {
+        Status::Pending => Some(vec![Status::Queued]),
+        Status::Confirmed => Some(vec![Status::Pending]),
+        Status::Cancelled => Some(vec![Status::Queued, Status::Pending]),
+        Status::Declined => Some(vec![Status::Pending]),
+        // Like the sweeper, which only expires Pending requests
+        Status::Expired => Some(vec![Status::Pending]),
+        // SES may report a bounce or complaint long after the recipient confirmed, that must not undo the confirmation
+        Status::Bounced | Status::Complained => Some(vec![Status::Queued, Status::Pending]),
+        // Never over a request that has ended otherwise, e.g. Expired
+        Status::CallbackFailed => Some(vec![Status::Confirmed]),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use email_confirmation_service_common::test_util::{dynamodb_stub, StubRequests, CONDITIONAL_CHECK_FAILED};
+    use super::*;
+
+    pub(crate) async fn request_service_stub(responses: &'static [(&'static str, u16, &'static str)]) -> (EmailConfirmationRequestService, StubRequests) {
+        let (db_client, requests) = dynamodb_stub(responses).await;
+        (EmailConfirmationRequestService::new(db_client, "requests"), requests)
+    }
+
+    #[tokio::test]
+    async fn test_display_code_attempt_is_counted_before_the_comparison() {
+        let (service, requests) = request_service_stub(&[("UpdateItem", 200, r#"{"Attributes":{"display_code_attempts":{"N":"3"}}}"#)]).await;
+        assert_eq!(Some(3), service.post_display_code_attempt("email@example.com#client-1#request-1".to_string()).await.unwrap());
+        let request = requests.lock().unwrap()[0].clone();
+        assert!(request.contains(r#""UpdateExpression":"add #name1 :one""#), "{}", request);
+        assert!(request.contains(r#""ConditionExpression":"attribute_exists(pk) AND (attribute_not_exists(#name1) OR #name1 < :limit)""#), "{}", request);
+        assert!(request.contains(&format!(r#"":limit":{{"N":"{}"}}"#, DISPLAY_CODE_ATTEMPTS_LIMIT)), "{}", request);
+        assert!(request.contains(r#""ReturnValues":"UPDATED_NEW""#), "{}", request);
+    }
+
+    #[tokio::test]
+    async fn test_display_code_attempts_used_up() {
+        let (service, _) = request_service_stub(&[("UpdateItem", 400, CONDITIONAL_CHECK_FAILED)]).await;
+        assert_eq!(None, service.post_display_code_attempt("email@example.com#client-1#request-1".to_string()).await.unwrap());
+    }
+
+    const ALL_STATUSES: [Status; 11] = [
+        Status::Queued, Status::Pending, Status::Confirmed, Status::Done, Status::Expired, Status::Bounced,
+        Status::Complained, Status::Suppressed, Status::Cancelled, Status::Declined, Status::CallbackFailed,
+    ];
+
+    #[test]
+    fn test_can_change_status() {
+        let restricted = [
+            (Status::Pending, vec![Status::Queued]),
+            (Status::Confirmed, vec![Status::Pending]),
+            (Status::Cancelled, vec![Status::Queued, Status::Pending]),
+            (Status::Declined, vec![Status::Pending]),
+            (Status::Expired, vec![Status::Pending]),
+            (Status::Bounced, vec![Status::Queued, Status::Pending]),
+            (Status::Complained, vec![Status::Queued, Status::Pending]),
+            (Status::CallbackFailed, vec![Status::Confirmed]),
+        ];
+        for to in ALL_STATUSES {
+            let allowed = restricted.iter().find(|(status, _)| *status == to).map(|(_, allowed)| allowed);
+            for from in ALL_STATUSES {
+                let expected = allowed.is_none_or(|allowed| allowed.contains(&from));
+                assert_eq!(expected, can_change_status(&from, &to), "{} to {}", from, to);
+            }
+        }
+        // A redelivered stream record must not set the status again
+        assert!(!can_change_status(&Status::Pending, &Status::Pending));
+        assert!(!can_change_status(&Status::Expired, &Status::CallbackFailed));
+        assert!(can_change_status(&Status::Confirmed, &Status::Done));
+        assert!(can_change_status(&Status::Pending, &Status::Expired));
+        assert!(!can_change_status(&Status::Confirmed, &Status::Expired));
+        assert!(!can_change_status(&Status::Done, &Status::Expired));
+    }
+
+    #[tokio::test]
+    async fn test_late_complaint_keeps_confirmation() {
+        assert!(!can_change_status(&Status::Confirmed, &Status::Complained));
+        assert!(!can_change_status(&Status::Done, &Status::Bounced));
+
+        // Checked in the write too, the request may be confirmed after the SES lambda read it
+        let (service, requests) = request_service_stub(&[
+            ("Query", 200, r#"{"Count":1,"Items":[{"pk":{"S":"email@example.com#client-1#request-1"}}],"ScannedCount":1}"#),
+            ("UpdateItem", 400, CONDITIONAL_CHECK_FAILED),
+        ]).await;
+        let result = service.put_email_confirmation_request_status("email@example.com#client-1#request-1".to_string(), Status::Complained,
+            Some("Complaint: abuse".to_string()), None, None).await.unwrap();
+        assert_eq!(None, result);
+        let requests = requests.lock().unwrap();
+        let update = requests.iter().find(|request| request.contains("DynamoDB_20120810.UpdateItem")).unwrap();
+        assert!(update.contains(r##""ConditionExpression":"#name1 IN (:allowed0, :allowed1)""##), "{}", update);
+        assert!(update.contains(r#"":allowed0":{"S":"Queued"}"#) && update.contains(r#"":allowed1":{"S":"Pending"}"#), "{}", update);
+    }
+}
@@ -1,4 +1,5 @@
 use std::env;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use anyhow::Result;
 use aws_sdk_lambda::Client;
 use aws_smithy_types::Blob;
@@ -10,15 +11,27 @@ use axum::{
 };
 use serde_json::{json, Value};
 use sha2::{Sha256, Digest};
-use hex;
 
-use crate::email_confirmation_request_service::{EmailConfirmationRequestService, INVALID_REQUEST};
-use crate::handler_params::{GetSingleParams, PutStatusParams, QueryParams};
+use crate::email_confirmation_request_service::{can_change_status, EmailConfirmationRequestService, INVALID_REQUEST};
+use crate::client_config_service::ClientConfigService;
+use crate::do_not_contact_service::DoNotContactService;
+use crate::suppression_service::{now_as_secs, SuppressionService};
+use crate::handler_params::{GetResultParams, GetSingleParams, PostCallbackAttemptParams, PostClickParams, PostDisplayCodeParams, PostFormTokenParams, PutCallbackNextAttemptParams, PutMessageIdParams, PutStatusParams, QueryParams};
 
-use email_confirmation_service_common::email_confirmation_request::{EmailConfirmationMinimalRequest, EmailConfirmationRequest, SanitizedEmailConfirmationRequest};
-use email_confirmation_service_common::signature_request::{SignatureRequest, SignatureResponse};
+use email_confirmation_service_common::callback_url::validate_callback_url;
+use email_confirmation_service_common::confirmation_evidence::ConfirmationEvidence;
+use email_confirmation_service_common::display_code::{check_display_code, shown_display_code, DisplayCodeCheck, DISPLAY_CODE_ATTEMPTS_LIMIT, DISPLAY_CODE_INVALID_ERROR_CODE, RESULT_WAIT_LIMIT_SECS};
+use email_confirmation_service_common::do_not_contact::{DoNotContactEntry, EMAIL_DO_NOT_CONTACT_ERROR_CODE};
+use email_confirmation_service_common::email_confirmation_request::{CallbackAttempt, EmailConfirmationMinimalRequest, EmailConfirmationRequest, SanitizedEmailConfirmationRequest, Status};
+use email_confirmation_service_common::redirect_url::validate_redirect_url;
+use email_confirmation_service_common::form_token::{form_token_permits_status, new_form_token, FORM_TOKEN_INVALID_ERROR_CODE, FORM_TOKEN_PERIOD};
+use email_confirmation_service_common::link_click::CONFIRM_DELAY_LIMIT_SECS;
+use email_confirmation_service_common::signature_request::{SignaturePurpose, SignatureRequest, SignatureResponse};
 use email_confirmation_service_common::signature_request::SignatureResponse::VerificationResult;
 use email_confirmation_service_common::signature_request::SignatureVerificationResult::Success;
+use email_confirmation_service_common::suppression::{SuppressionEntry, EMAIL_SUPPRESSED_ERROR_CODE};
+
+const RESULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
 pub async fn get_email_confirmation_requests(
     State(service): State<EmailConfirmationRequestService>,
@@ -30,8 +43,42 @@ pub async fn get_email_confirmation_requests(
 
 pub async fn post_email_confirmation_request(
     State(service): State<EmailConfirmationRequestService>,
+    State(suppression_service): State<SuppressionService>,
+    State(client_config_service): State<ClientConfigService>,
+    State(do_not_contact_service): State<DoNotContactService>,
     Json(minimal_request): Json<EmailConfirmationMinimalRequest>,
 ) -> (StatusCode, Json<Value>) {
+    match suppression_service.get_active_suppression(&minimal_request.email).await {
+        Ok(Some(suppression)) => return suppressed_response(&suppression),
+        Ok(None) => {},
+        Err(error) => return result_to_response(Err(error)),
+    }
+
+    match do_not_contact_service.get_entry(&minimal_request.client_id, &minimal_request.email).await {
+        Ok(Some(_)) => return do_not_contact_response(),
+        Ok(None) => {},
+        Err(error) => return result_to_response(Err(error)),
+    }
+
+    if let Some(reminder_at_percent) = minimal_request.reminder_at_percent {
+        if !(1..=99).contains(&reminder_at_percent) {
+            return bad_request_response("reminder_at_percent must be between 1 and 99");
+        }
+    }
+
+    let allowed_callback_domains = match client_config_service.get_client_config(&minimal_request.client_id).await {
+        Ok(client_config) => client_config.map(|client_config| client_config.allowed_callback_domains).unwrap_or_default(),
+        Err(error) => return result_to_response(Err(error)),
+    };
+    if let Err(error) = validate_callback_url(&minimal_request.callback_url, &minimal_request.client_id, &allowed_callback_domains) {
+        return bad_request_response(&error.to_string());
+    }
+    for (name, redirect_url) in [("success_redirect_url", &minimal_request.success_redirect_url), ("expired_redirect_url", &minimal_request.expired_redirect_url)] {
+        if let Some(Err(error)) = redirect_url.as_ref().map(|redirect_url| validate_redirect_url(redirect_url, &allowed_callback_domains)) {
+            return bad_request_response(&format!("{} {}", name, error));
+        }
+    }
+
     let request = EmailConfirmationRequest::from(minimal_request);
     let signature = create_signature(&request);
     let result = service.post_email_confirmation_request(request, signature).await;
@@ -44,16 +91,22 @@ pub async fn get_email_confirmation_request_single(
     Query(params): Query<GetSingleParams>,
 ) -> (StatusCode, Json<Value>) {
     if let GetSingleParams {
-        signature: Some(signature_param)
+        signature: Some(signature_param),
+        purpose
     } = params {
-        let confirmation_request = service.get_email_confirmation_request_internal(pk).await.unwrap();
-        if signature_is_valid(signature_param, &confirmation_request).await {
+        let confirmation_request = match service.find_email_confirmation_request(&pk).await {
+            Ok(Some(confirmation_request)) => confirmation_request,
+            Ok(None) => return invalid_request_response(StatusCode::NOT_FOUND),
+            Err(error) => return result_to_response(Err(error)),
+        };
+        if signature_is_valid(signature_param, &confirmation_request, purpose).await {
             return result_to_response(
                 Ok(Json(json!({
                     "error": false,
                     "request": SanitizedEmailConfirmationRequest::from(confirmation_request)
                 }))));
         }
+        return invalid_request_response(StatusCode::FORBIDDEN);
     }
 
     result_to_response(
@@ -63,13 +116,25 @@ pub async fn get_email_confirmation_request_single(
                 }))))
 }
 
-async fn signature_is_valid(signature: String, confirmation_request: &EmailConfirmationRequest) -> bool {
+/// The link click handler tells an unknown request (404) from a wrong signature (403) by the status.
+fn invalid_request_response(status: StatusCode) -> (StatusCode, Json<Value>) {
+    (
+        status,
+        Json(json!({
+            "error": true,
+            "message": INVALID_REQUEST.to_string()
+        }))
+    )
+}
+
+async fn signature_is_valid(signature: String, confirmation_request: &EmailConfirmationRequest, purpose: SignaturePurpose) -> bool {
     tracing::info!("CHECKING signature is valid");
     let config = aws_config::load_from_env().await;
     let client = Client::new(&config);
-    let payload = json!(SignatureRequest::signature_verification_request(
-            &confirmation_request,
-            signature.clone()
+    let payload = json!(SignatureRequest::signature_verification_request_for(
+            confirmation_request,
+            signature.clone(),
+            purpose
         ));
 
     let function_name = env::var("SIGNATURE_SERVICE_LAMBDA_FUNCTION_NAME");
@@ -102,15 +167,13 @@ fn create_signature(confirmation_request: &EmailConfirmationRequest) -> String {
     let client_id = confirmation_request.client_id.clone();
     let request_id = confirmation_request.request_id.clone();
     let signature_key = confirmation_request.signature_key.clone();
-    let updated_at = confirmation_request.updated_at;
+    let updated_at = confirmation_request.signature_timestamp();
 
     let data = format!("|{}|{}|{}|{}|{}|", email, client_id, request_id, signature_key, updated_at);
     let mut hasher = Sha256::new();
     hasher.update(data);
     let result = hasher.finalize();
-    let signature = hex::encode(result);
-
-    signature
+    hex::encode(result)
 }
 
 pub async fn delete_email_confirmation_request_single(
@@ -123,18 +186,264 @@ pub async fn delete_email_confirmation_request_single(
 
 pub async fn put_email_confirmation_request_status(
     State(service): State<EmailConfirmationRequestService>,
+    State(do_not_contact_service): State<DoNotContactService>,
+    State(client_config_service): State<ClientConfigService>,
     Path(pk): Path<String>,
     Json(put_status_params): Json<PutStatusParams>,
 ) -> (StatusCode, Json<Value>) {
     if let PutStatusParams {
         status: Some(status_param),
-        signature: Some(signature_param)
+        signature: Some(signature_param),
+        reason,
+        purpose,
+        form_token,
+        do_not_contact,
+        confirmation_context
     } = put_status_params {
-        let confirmation_request = service.get_email_confirmation_request_internal(pk.clone()).await.unwrap();
-        if signature_is_valid(signature_param, &confirmation_request).await {
-            let updated_request = service.put_email_confirmation_request_status(pk.clone(), status_param).await.unwrap();
-            return result_to_response(
-                Ok(Json(json!({
+        let confirmation_request = match service.find_email_confirmation_request(&pk).await {
+            Ok(Some(confirmation_request)) => confirmation_request,
+            Ok(None) => return invalid_request_response(StatusCode::NOT_FOUND),
+            Err(error) => return result_to_response(Err(error)),
+        };
+        // Before the signature: a redelivered stream record is signed for the request as it was, and has to learn it is done
+        if !can_change_status(&confirmation_request.status, &status_param) {
+            return status_update_to_response(Ok(None), &confirmation_request, &status_param);
+        }
+        if purpose.permits_status(&status_param) && signature_is_valid(signature_param, &confirmation_request, purpose).await {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+            if !form_token_permits_status(&confirmation_request, &status_param, form_token.as_deref(), now) {
+                return form_token_invalid_response();
+            }
+            // Added before the status is set: the recipient asked for it even if the request cannot be declined any more
+            if status_param == Status::Declined && do_not_contact {
+                let entry = DoNotContactEntry::new(&confirmation_request.client_id, &confirmation_request.email,
+                                                   Some(confirmation_request.request_id.clone()), now_as_secs());
+                if let Err(error) = do_not_contact_service.put_entry(&entry).await {
+                    return result_to_response(Err(error));
+                }
+            }
+            let confirmation_evidence = match confirmation_context.filter(|_| status_param == Status::Confirmed) {
+                Some(confirmation_context) => match client_config_service.get_client_config(&confirmation_request.client_id).await {
+                    Ok(client_config) => {
+                        let ip_address_mode = client_config.map(|client_config| client_config.confirmation_ip_address_mode).unwrap_or_default();
+                        Some(ConfirmationEvidence::new(now_as_secs(), confirmation_context, ip_address_mode, &confirmation_request.client_id))
+                    },
+                    Err(error) => return result_to_response(Err(error)),
+                },
+                None => None,
+            };
+            let result = service.put_email_confirmation_request_status(pk.clone(), status_param.clone(), reason, form_token, confirmation_evidence).await;
+            return status_update_to_response(result, &confirmation_request, &status_param);
+        }
+    }
+    result_to_response(
+        Ok(Json(json!({
+                    "error": true,
+                    "message": INVALID_REQUEST.to_string()
+                }))))
+}
+
+/// Issues a token for the confirm form, see form_token. Called by the link click handler every time the confirm page is shown.
+pub async fn post_email_confirmation_request_form_token(
+    State(service): State<EmailConfirmationRequestService>,
+    Path(pk): Path<String>,
+    Json(form_token_params): Json<PostFormTokenParams>,
+) -> (StatusCode, Json<Value>) {
+    if let PostFormTokenParams {
+        signature: Some(signature_param),
+        purpose,
+        delay_secs
+    } = form_token_params {
+        if delay_secs > CONFIRM_DELAY_LIMIT_SECS {
+            return bad_request_response(&format!("delay_secs must be at most {}", CONFIRM_DELAY_LIMIT_SECS));
+        }
+        let confirmation_request = match service.find_email_confirmation_request(&pk).await {
+            Ok(Some(confirmation_request)) => confirmation_request,
+            Ok(None) => return invalid_request_response(StatusCode::NOT_FOUND),
+            Err(error) => return result_to_response(Err(error)),
+        };
+        if signature_is_valid(signature_param, &confirmation_request, purpose).await {
+            let form_token = new_form_token();
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+            let expires_at = now + FORM_TOKEN_PERIOD.as_secs();
+            let not_before = Some(now + u64::from(delay_secs)).filter(|_| delay_secs > 0);
+            let result = service.put_email_confirmation_request_form_token(pk, &form_token, expires_at, not_before).await;
+            return result_to_response(result.map(|_| Json(json!({
+                        "error": false,
+                        "form_token": form_token,
+                        "expires_at": expires_at
+                    }))));
+        }
+    }
+    result_to_response(
+        Ok(Json(json!({
+                    "error": true,
+                    "message": INVALID_REQUEST.to_string()
+                }))))
+}
+
+/// Called by the link click handler for every GET or HEAD of a link with a valid signature.
+pub async fn post_email_confirmation_request_click(
+    State(service): State<EmailConfirmationRequestService>,
+    Path(pk): Path<String>,
+    Json(click_params): Json<PostClickParams>,
+) -> (StatusCode, Json<Value>) {
+    if let PostClickParams {
+        signature: Some(signature_param),
+        purpose,
+        scanner
+    } = click_params {
+        let confirmation_request = match service.find_email_confirmation_request(&pk).await {
+            Ok(Some(confirmation_request)) => confirmation_request,
+            Ok(None) => return invalid_request_response(StatusCode::NOT_FOUND),
+            Err(error) => return result_to_response(Err(error)),
+        };
+        if signature_is_valid(signature_param, &confirmation_request, purpose).await {
+            let result = service.post_email_confirmation_request_click(pk, scanner, now_as_secs()).await;
+            return result_to_response(result.map(|_| Json(json!({
+                        "error": false,
+                        "scanner": scanner
+                    }))));
+        }
+        return invalid_request_response(StatusCode::FORBIDDEN);
+    }
+    result_to_response(
+        Ok(Json(json!({
+                    "error": true,
+                    "message": INVALID_REQUEST.to_string()
+                }))))
+}
+
+/// Cancelled by the client, e.g. the user signed up again with another address.
+/// Only a request still waiting for confirmation can be cancelled.
+pub async fn post_email_confirmation_request_cancel(
+    State(service): State<EmailConfirmationRequestService>,
+    Path(pk): Path<String>,
+) -> (StatusCode, Json<Value>) {
+    let confirmation_request = match service.get_email_confirmation_request_internal(pk.clone()).await {
+        Ok(confirmation_request) => confirmation_request,
+        Err(error) => return result_to_response(Err(error)),
+    };
+    let result = service.put_email_confirmation_request_status(pk, Status::Cancelled, None, None, None).await;
+    status_update_to_response(result, &confirmation_request, &Status::Cancelled)
+}
+
+/// Lets the device a request was made on wait for its outcome, e.g. while the recipient confirms on their phone.
+/// Answers as soon as the request is no longer waiting for confirmation, or after `wait_secs`.
+pub async fn get_email_confirmation_request_result(
+    State(service): State<EmailConfirmationRequestService>,
+    Path(pk): Path<String>,
+    Query(params): Query<GetResultParams>,
+) -> (StatusCode, Json<Value>) {
+    if params.wait_secs > RESULT_WAIT_LIMIT_SECS {
+        return bad_request_response(&format!("wait_secs must be at most {}", RESULT_WAIT_LIMIT_SECS));
+    }
+    let deadline = now_as_secs() + params.wait_secs;
+    loop {
+        let confirmation_request = match service.find_email_confirmation_request(&pk).await {
+            Ok(Some(confirmation_request)) => confirmation_request,
+            Ok(None) => return invalid_request_response(StatusCode::NOT_FOUND),
+            Err(error) => return result_to_response(Err(error)),
+        };
+        let now = now_as_secs();
+        let waiting = confirmation_request.status == Status::Queued
+            || (confirmation_request.status == Status::Pending && now < confirmation_request.expires_at);
+        if !waiting || now >= deadline {
+            return result_to_response(Ok(Json(json!({
+                "error": false,
+                "waiting": waiting,
+                "request": SanitizedEmailConfirmationRequest::from(confirmation_request)
+            }))));
+        }
+        tokio::time::sleep(RESULT_POLL_INTERVAL).await;
+    }
+}
+
+/// Checks the display code the recipient entered on the device the request was made on, see display_code.
+/// A code is verified once, and a request takes a limited number of wrong codes.
+pub async fn post_email_confirmation_request_display_code(
+    State(service): State<EmailConfirmationRequestService>,
+    Path(pk): Path<String>,
+    Json(display_code_params): Json<PostDisplayCodeParams>,
+) -> (StatusCode, Json<Value>) {
+    let Some(display_code) = display_code_params.display_code else {
+        return bad_request_response("display_code is required");
+    };
+    let confirmation_request = match service.find_email_confirmation_request(&pk).await {
+        Ok(Some(confirmation_request)) => confirmation_request,
+        Ok(None) => return invalid_request_response(StatusCode::NOT_FOUND),
+        Err(error) => return result_to_response(Err(error)),
+    };
+    let check = check_display_code(&confirmation_request, &display_code);
+    // Every attempt, right or wrong, is counted first: the count read above may be behind when guesses come in at once
+    let attempts = match check {
+        DisplayCodeCheck::Valid | DisplayCodeCheck::Invalid => match service.post_display_code_attempt(pk.clone()).await {
+            Ok(Some(attempts)) => attempts,
+            Ok(None) => return too_many_display_code_attempts_response(),
+            Err(error) => return result_to_response(Err(error)),
+        },
+        _ => confirmation_request.display_code_attempts,
+    };
+    match check {
+        DisplayCodeCheck::Valid => match service.put_display_code_verified(pk, now_as_secs()).await {
+            Ok(Some(updated_request)) => result_to_response(Ok(Json(json!({
+                "error": false,
+                "request": SanitizedEmailConfirmationRequest::from(updated_request)
+            })))),
+            Ok(None) => conflict_response("Display code was verified already or has no attempts left"),
+            Err(error) => result_to_response(Err(error)),
+        },
+        DisplayCodeCheck::Invalid => display_code_invalid_response(DISPLAY_CODE_ATTEMPTS_LIMIT.saturating_sub(attempts)),
+        DisplayCodeCheck::TooManyAttempts => too_many_display_code_attempts_response(),
+        DisplayCodeCheck::NotCrossDevice => conflict_response("Request was not made for a cross-device confirmation"),
+        DisplayCodeCheck::NotConfirmed => conflict_response(&format!("Request with status {} is not confirmed", confirmation_request.status)),
+        DisplayCodeCheck::AlreadyVerified => conflict_response("Display code was verified already"),
+    }
+}
+
+/// A confirmation of a cross-device request carries the display code, for the landing page to show.
+/// The link's signature is not valid after the update, so this is the only time the code is handed out.
+fn status_update_to_response(result: Result<Option<EmailConfirmationRequest>>, confirmation_request: &EmailConfirmationRequest, status: &Status) -> (StatusCode, Json<Value>) {
+    match result {
+        Ok(Some(updated_request)) => {
+            let mut json_data = json!({
+                "error": false,
+                "request": SanitizedEmailConfirmationRequest::from(updated_request.clone())
+            });
+            if let Some(display_code) = shown_display_code(&updated_request) {
+                json_data["display_code"] = json!(display_code);
+            }
+            (StatusCode::OK, Json(json_data))
+        },
+        Ok(None) => (
+            StatusCode::CONFLICT,
+            Json(json!({
+                "error": true,
+                "message": format!("Request with status {} cannot be set {}", confirmation_request.status, status)
+            }))
+        ),
+        Err(error) => result_to_response(Err(error)),
+    }
+}
+
+pub async fn put_email_confirmation_request_message_id(
+    State(service): State<EmailConfirmationRequestService>,
+    Path(pk): Path<String>,
+    Json(put_message_id_params): Json<PutMessageIdParams>,
+) -> (StatusCode, Json<Value>) {
+    if let PutMessageIdParams {
+        message_id: Some(message_id_param),
+        signature: Some(signature_param),
+        email_kind
+    } = put_message_id_params {
+        let confirmation_request = match service.find_email_confirmation_request(&pk).await {
+            Ok(Some(confirmation_request)) => confirmation_request,
+            Ok(None) => return invalid_request_response(StatusCode::NOT_FOUND),
+            Err(error) => return result_to_response(Err(error)),
+        };
+        if signature_is_valid(signature_param, &confirmation_request, SignaturePurpose::Confirm).await {
+            let result = service.put_email_confirmation_request_message_id(pk.clone(), message_id_param, email_kind).await;
+            return result_to_response(result.map(|updated_request| Json(json!({
                         "error": false,
                         "request": SanitizedEmailConfirmationRequest::from(updated_request)
                     }))));
@@ -147,7 +456,145 @@ pub async fn put_email_confirmation_request_status(
                 }))))
 }
 
-fn result_to_response(result: Result<Json<Value>>) -> (StatusCode, Json<Value>) {
+pub async fn post_email_confirmation_request_callback_attempt(
+    State(service): State<EmailConfirmationRequestService>,
+    Path(pk): Path<String>,
+    Json(callback_attempt_params): Json<PostCallbackAttemptParams>,
+) -> (StatusCode, Json<Value>) {
+    if let PostCallbackAttemptParams {
+        attempt_number: Some(attempt_number),
+        attempted_at: Some(attempted_at),
+        error: Some(error),
+        next_attempt_at,
+        signature: Some(signature_param)
+    } = callback_attempt_params {
+        let confirmation_request = match service.find_email_confirmation_request(&pk).await {
+            Ok(Some(confirmation_request)) => confirmation_request,
+            Ok(None) => return invalid_request_response(StatusCode::NOT_FOUND),
+            Err(error) => return result_to_response(Err(error)),
+        };
+        if signature_is_valid(signature_param, &confirmation_request, SignaturePurpose::Confirm).await {
+            let attempt = CallbackAttempt { attempted_at, error };
+            let result = service.post_email_confirmation_request_callback_attempt(pk.clone(), attempt_number, attempt, next_attempt_at).await;
+            return result_to_response(result.map(|updated_request| Json(json!({
+                        "error": false,
+                        "request": SanitizedEmailConfirmationRequest::from(updated_request)
+                    }))));
+        }
+    }
+    result_to_response(
+        Ok(Json(json!({
+                    "error": true,
+                    "message": INVALID_REQUEST.to_string()
+                }))))
+}
+
+pub async fn put_email_confirmation_request_callback_next_attempt(
+    State(service): State<EmailConfirmationRequestService>,
+    Path(pk): Path<String>,
+    Json(next_attempt_params): Json<PutCallbackNextAttemptParams>,
+) -> (StatusCode, Json<Value>) {
+    if let PutCallbackNextAttemptParams {
+        next_attempt_at: Some(next_attempt_at),
+        reason: Some(reason),
+        signature: Some(signature_param)
+    } = next_attempt_params {
+        let confirmation_request = match service.find_email_confirmation_request(&pk).await {
+            Ok(Some(confirmation_request)) => confirmation_request,
+            Ok(None) => return invalid_request_response(StatusCode::NOT_FOUND),
+            Err(error) => return result_to_response(Err(error)),
+        };
+        if signature_is_valid(signature_param, &confirmation_request, SignaturePurpose::Confirm).await {
+            let result = service.put_email_confirmation_request_callback_next_attempt(pk.clone(), next_attempt_at, reason).await;
+            return result_to_response(result.map(|updated_request| Json(json!({
+                        "error": false,
+                        "request": SanitizedEmailConfirmationRequest::from(updated_request)
+                    }))));
+        }
+    }
+    result_to_response(
+        Ok(Json(json!({
+                    "error": true,
+                    "message": INVALID_REQUEST.to_string()
+                }))))
+}
+
+pub(crate) fn bad_request_response(message: &str) -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(json!({
+            "error": true,
+            "message": message
+        }))
+    )
+}
+
+fn form_token_invalid_response() -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::FORBIDDEN,
+        Json(json!({
+            "error": true,
+            "error_code": FORM_TOKEN_INVALID_ERROR_CODE,
+            "message": "Form token is missing, expired or already used"
+        }))
+    )
+}
+
+fn display_code_invalid_response(attempts_left: u32) -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::FORBIDDEN,
+        Json(json!({
+            "error": true,
+            "error_code": DISPLAY_CODE_INVALID_ERROR_CODE,
+            "message": "Display code is wrong",
+            "attempts_left": attempts_left
+        }))
+    )
+}
+
+fn too_many_display_code_attempts_response() -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        Json(json!({
+            "error": true,
+            "message": "Too many wrong display codes, the request has to be made again"
+        }))
+    )
+}
+
+fn conflict_response(message: &str) -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::CONFLICT,
+        Json(json!({
+            "error": true,
+            "message": message
+        }))
+    )
+}
+
+fn do_not_contact_response() -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::UNPROCESSABLE_ENTITY,
+        Json(json!({
+            "error": true,
+            "error_code": EMAIL_DO_NOT_CONTACT_ERROR_CODE,
+            "message": "Email address asked not to be contacted by this client"
+        }))
+    )
+}
+
+fn suppressed_response(suppression: &SuppressionEntry) -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::UNPROCESSABLE_ENTITY,
+        Json(json!({
+            "error": true,
+            "error_code": EMAIL_SUPPRESSED_ERROR_CODE,
+            "message": format!("Email address is suppressed: {}", suppression.reason)
+        }))
+    )
+}
+
+pub(crate) fn result_to_response(result: Result<Json<Value>>) -> (StatusCode, Json<Value>) {
     match result {
         Ok(json) => (StatusCode::OK, json),
         Err(error) => {
@@ -170,3 +617,49 @@ fn result_to_response(result: Result<Json<Value>>) -> (StatusCode, Json<Value>)
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use aws_sdk_dynamodb::config::BehaviorVersion;
+    use crate::email_confirmation_request_service::tests::request_service_stub;
+    use super::*;
+
+    const PK: &str = "email@example.com#client-1#request-1";
+
+    /// A request that was deleted, e.g. while its stream record is retried.
+    async fn deleted_request_service() -> EmailConfirmationRequestService {
+        request_service_stub(&[("Query", 200, r#"{"Items":[],"Count":0,"ScannedCount":0}"#)]).await.0
+    }
+
+    #[tokio::test]
+    async fn test_status_of_unknown_request_is_not_found() {
+        // Not called for an unknown request
+        let db_client = aws_sdk_dynamodb::Client::from_conf(aws_sdk_dynamodb::Config::builder().behavior_version(BehaviorVersion::latest()).build());
+        let params = serde_json::from_value(json!({"status": "Pending", "signature": "signature"})).unwrap();
+        let (status, _) = put_email_confirmation_request_status(State(deleted_request_service().await), State(DoNotContactService::new(db_client.clone(), "do-not-contact")),
+            State(ClientConfigService::new(db_client, "client-configs")), Path(PK.to_string()), Json(params)).await;
+        assert_eq!(StatusCode::NOT_FOUND, status);
+    }
+
+    #[tokio::test]
+    async fn test_message_id_of_unknown_request_is_not_found() {
+        let params = serde_json::from_value(json!({"message_id": "0100018e2f1c8a2b-message-id", "signature": "signature"})).unwrap();
+        let (status, _) = put_email_confirmation_request_message_id(State(deleted_request_service().await), Path(PK.to_string()), Json(params)).await;
+        assert_eq!(StatusCode::NOT_FOUND, status);
+    }
+
+    #[tokio::test]
+    async fn test_callback_attempt_of_unknown_request_is_not_found() {
+        let params = serde_json::from_value(json!({"attempt_number": 1, "attempted_at": 1741592476, "error": "HTTP 503", "next_attempt_at": 1741592536,
+            "signature": "signature"})).unwrap();
+        let (status, _) = post_email_confirmation_request_callback_attempt(State(deleted_request_service().await), Path(PK.to_string()), Json(params)).await;
+        assert_eq!(StatusCode::NOT_FOUND, status);
+    }
+
+    #[tokio::test]
+    async fn test_next_callback_attempt_of_unknown_request_is_not_found() {
+        let params = serde_json::from_value(json!({"next_attempt_at": 1741592536, "reason": "Circuit of callback.example.com is open", "signature": "signature"})).unwrap();
+        let (status, _) = put_email_confirmation_request_callback_next_attempt(State(deleted_request_service().await), Path(PK.to_string()), Json(params)).await;
+        assert_eq!(StatusCode::NOT_FOUND, status);
+    }
+}
@@ -1,5 +1,13 @@
+use std::collections::BTreeMap;
 use serde::{Deserialize, Serialize};
+use email_confirmation_service_common::callback_event::{CallbackEventType, CallbackPayloadVersion};
+use email_confirmation_service_common::client_config::Branding;
+use email_confirmation_service_common::confirmation_evidence::{ConfirmationContext, IpAddressMode};
+use email_confirmation_service_common::link_click::LinkClickSettings;
 use email_confirmation_service_common::email_confirmation_request;
+use email_confirmation_service_common::email_confirmation_request::EmailKind;
+use email_confirmation_service_common::signature_request::SignaturePurpose;
+use email_confirmation_service_common::suppression::SuppressionReason;
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct QueryParams {
@@ -11,10 +19,109 @@ pub struct QueryParams {
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct PutStatusParams {
     pub status: Option<email_confirmation_request::Status>,
+    pub signature: Option<String>,
+    #[serde(default)]
+    pub reason: Option<String>,
+    #[serde(default)]
+    pub purpose: SignaturePurpose,
+    #[serde(default)]
+    pub form_token: Option<String>, // set by the confirm form, must be the request's latest unexpired token
+    #[serde(default)]
+    pub do_not_contact: bool, // with the Declined status, adds the address to the client's do-not-contact list
+    #[serde(default)]
+    pub confirmation_context: Option<ConfirmationContext>, // with the Confirmed status, stored as the confirmation evidence
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PostFormTokenParams {
+    pub signature: Option<String>,
+    #[serde(default)]
+    pub purpose: SignaturePurpose,
+    #[serde(default)]
+    pub delay_secs: u8 // the form is refused until this long after the token is issued
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PostClickParams {
+    pub signature: Option<String>,
+    #[serde(default)]
+    pub purpose: SignaturePurpose,
+    #[serde(default)]
+    pub scanner: bool // a HEAD request or the user agent of a mail scanner
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PostDisplayCodeParams {
+    pub display_code: Option<String> // as the recipient typed it on the original device
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GetResultParams {
+    #[serde(default)]
+    pub wait_secs: u64 // how long to wait for the request to leave Queued or Pending, 0 answers at once
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PutMessageIdParams {
+    pub message_id: Option<String>,
+    pub signature: Option<String>,
+    #[serde(default)]
+    pub email_kind: EmailKind
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PostCallbackAttemptParams {
+    pub attempt_number: Option<usize>, // 1 for the first failed delivery
+    pub attempted_at: Option<u64>,
+    pub error: Option<String>,
+    pub next_attempt_at: Option<u64>, // none when no more retries are made
     pub signature: Option<String>
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
-pub struct GetSingleParams {
+pub struct PutCallbackNextAttemptParams {
+    pub next_attempt_at: Option<u64>,
+    pub reason: Option<String>, // why the callback was not attempted, e.g. the circuit of the host is open
     pub signature: Option<String>
 }
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GetSingleParams {
+    pub signature: Option<String>,
+    #[serde(default)]
+    pub purpose: SignaturePurpose
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PutSuppressionParams {
+    pub reason: Option<SuppressionReason>,
+    pub detail: Option<String>,
+    pub expires_at: Option<u64>
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PutClientConfigParams {
+    pub callback_payload_version: Option<CallbackPayloadVersion>,
+    pub subscribed_events: Option<Vec<CallbackEventType>>,
+    pub allowed_callback_domains: Option<Vec<String>>,
+    pub branding: Option<Branding>, // replaces the branding, {} goes back to the default look
+    pub allowed_origins: Option<Vec<String>>,
+    pub confirmation_ip_address_mode: Option<IpAddressMode>,
+    pub link_click: Option<LinkClickSettings>, // replaces the settings
+}
+
+/// Replaces the callback auth of a client, leaving a field out removes it. The secrets are encrypted before they are stored.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PutCallbackAuthParams {
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    pub oauth2: Option<PutOAuth2Params>
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PutOAuth2Params {
+    pub token_url: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub scope: Option<String>
+}
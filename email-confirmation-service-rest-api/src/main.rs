@@ -1,13 +1,33 @@
 use lambda_http::{run, tracing, Error};
+mod admin_auth;
 mod handler;
 mod email_confirmation_request_service;
 mod handler_params;
+mod router;
+mod app_state;
+mod callback_host_handler;
+mod callback_host_service;
+mod client_config_handler;
+mod client_config_service;
+mod delivery_handler;
+mod delivery_log_service;
+mod do_not_contact_handler;
+mod do_not_contact_service;
+mod secret_encryption_service;
+mod suppression_handler;
+mod suppression_service;
 
 use std::env::{self, set_var};
 use aws_sdk_dynamodb::Client;
-use axum::Router;
-use axum::routing::{get, put};
+use crate::admin_auth::AdminApiKeys;
+use crate::app_state::AppState;
+use crate::callback_host_service::CallbackHostService;
+use crate::client_config_service::ClientConfigService;
+use crate::delivery_log_service::DeliveryLogService;
+use crate::do_not_contact_service::DoNotContactService;
 use crate::email_confirmation_request_service::EmailConfirmationRequestService;
+use crate::secret_encryption_service::SecretEncryptionService;
+use crate::suppression_service::SuppressionService;
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
@@ -17,19 +37,22 @@ async fn main() -> Result<(), Error> {
     let config = aws_config::load_from_env().await;
     let db_client = Client::new(&config);
     let table_name = env::var("EMAIL_CONFIRMATION_REQUEST_SERVICE_DYNAMO_TABLE_NAME")?;
+    let suppression_table_name = env::var("EMAIL_SUPPRESSION_DYNAMO_TABLE_NAME")?;
+    let client_config_table_name = env::var("CLIENT_CONFIG_DYNAMO_TABLE_NAME")?;
+    let callback_delivery_table_name = env::var("CALLBACK_DELIVERY_DYNAMO_TABLE_NAME")?;
+    let callback_host_table_name = env::var("CALLBACK_HOST_DYNAMO_TABLE_NAME")?;
+    let callback_auth_kms_key_id = env::var("CALLBACK_AUTH_KMS_KEY_ID")?;
+    let do_not_contact_table_name = env::var("DO_NOT_CONTACT_DYNAMO_TABLE_NAME")?;
 
-    let email_confirmation_request_service = EmailConfirmationRequestService::new(db_client, &table_name);
-    let email_confirmation_request_api = Router::new()
-        .route("/", get(handler::get_email_confirmation_requests).post(handler::post_email_confirmation_request))
-        .route(
-            "/{pk}",
-            get(handler::get_email_confirmation_request_single).delete(handler::delete_email_confirmation_request_single),
-        )
-        .route("/{pk}/status", put(handler::put_email_confirmation_request_status));
-
-    let app = Router::new()
-        .nest("/email-confirmation-requests", email_confirmation_request_api)
-        .with_state(email_confirmation_request_service);
+    let email_confirmation_request_service = EmailConfirmationRequestService::new(db_client.clone(), &table_name);
+    let suppression_service = SuppressionService::new(db_client.clone(), &suppression_table_name);
+    let client_config_service = ClientConfigService::new(db_client.clone(), &client_config_table_name);
+    let delivery_log_service = DeliveryLogService::new(db_client.clone(), &callback_delivery_table_name);
+    let callback_host_service = CallbackHostService::new(db_client.clone(), &callback_host_table_name);
+    let do_not_contact_service = DoNotContactService::new(db_client, &do_not_contact_table_name);
+    let secret_encryption_service = SecretEncryptionService::new(aws_sdk_kms::Client::new(&config), &callback_auth_kms_key_id);
+    let admin_api_keys = AdminApiKeys::new(&env::var("ADMIN_API_KEY_IDS")?);
+    let app = router::app(AppState { email_confirmation_request_service, suppression_service, client_config_service, delivery_log_service, callback_host_service, secret_encryption_service, do_not_contact_service, admin_api_keys });
 
     run(app).await
 }
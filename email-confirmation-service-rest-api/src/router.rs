@@ -0,0 +1,148 @@
+use axum::middleware::from_fn_with_state;
+use axum::Router;
+use axum::routing::{get, post, put};
+use crate::admin_auth::require_admin;
+use crate::app_state::AppState;
+use crate::{callback_host_handler, client_config_handler, delivery_handler, do_not_contact_handler, handler, suppression_handler};
+
+pub(crate) fn app(app_state: AppState) -> Router {
+    let email_confirmation_request_api = Router::new()
+        .route("/", get(handler::get_email_confirmation_requests).post(handler::post_email_confirmation_request))
+        .route(
+            "/{pk}",
+            get(handler::get_email_confirmation_request_single).delete(handler::delete_email_confirmation_request_single),
+        )
+        .route("/{pk}/status", put(handler::put_email_confirmation_request_status))
+        .route("/{pk}/cancel", post(handler::post_email_confirmation_request_cancel))
+        .route("/{pk}/form-token", post(handler::post_email_confirmation_request_form_token))
+        .route("/{pk}/clicks", post(handler::post_email_confirmation_request_click))
+        .route("/{pk}/result", get(handler::get_email_confirmation_request_result))
+        .route("/{pk}/display-code", post(handler::post_email_confirmation_request_display_code))
+        .route("/{pk}/message-id", put(handler::put_email_confirmation_request_message_id))
+        .route("/{pk}/callback-attempts", post(handler::post_email_confirmation_request_callback_attempt))
+        .route("/{pk}/callback-next-attempt", put(handler::put_email_confirmation_request_callback_next_attempt))
+        .route("/{pk}/deliveries", get(delivery_handler::get_deliveries))
+        .route("/{pk}/deliveries/replay", post(delivery_handler::post_delivery_replay));
+
+    let suppression_api = Router::new()
+        .route("/", get(suppression_handler::get_suppressions))
+        .route(
+            "/{email}",
+            get(suppression_handler::get_suppression_single)
+                .put(suppression_handler::put_suppression_single)
+                .delete(suppression_handler::delete_suppression_single),
+        )
+        .route_layer(from_fn_with_state(app_state.clone(), require_admin));
+
+    let client_config_api = Router::new()
+        .route(
+            "/{client_id}",
+            get(client_config_handler::get_client_config_single).put(client_config_handler::put_client_config_single),
+        )
+        .route("/{client_id}/stats", get(client_config_handler::get_client_stats))
+        .route("/{client_id}/webhook-secrets", post(client_config_handler::post_webhook_secret))
+        .route("/{client_id}/callback-auth", put(client_config_handler::put_callback_auth))
+        .route("/{client_id}/do-not-contact", get(do_not_contact_handler::get_do_not_contact_entries))
+        .route(
+            "/{client_id}/do-not-contact/{email}",
+            put(do_not_contact_handler::put_do_not_contact_single).delete(do_not_contact_handler::delete_do_not_contact_single),
+        )
+        .route_layer(from_fn_with_state(app_state.clone(), require_admin));
+
+    let callback_host_api = Router::new()
+        .route("/", get(callback_host_handler::get_callback_hosts))
+        .route("/{host}", get(callback_host_handler::get_callback_host_single))
+        .route_layer(from_fn_with_state(app_state.clone(), require_admin));
+
+    Router::new()
+        .nest("/email-confirmation-requests", email_confirmation_request_api)
+        .nest("/suppressions", suppression_api)
+        .nest("/clients", client_config_api)
+        .nest("/callback-hosts", callback_host_api)
+        .with_state(app_state)
+}
+
+#[cfg(test)]
+mod tests {
+    use aws_sdk_dynamodb::config::BehaviorVersion;
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use lambda_http::request::RequestContext;
+    use tower::ServiceExt;
+    use crate::admin_auth::AdminApiKeys;
+    use crate::admin_auth::tests::api_key_context;
+    use crate::callback_host_service::CallbackHostService;
+    use crate::client_config_service::ClientConfigService;
+    use crate::delivery_log_service::DeliveryLogService;
+    use crate::do_not_contact_service::DoNotContactService;
+    use crate::email_confirmation_request_service::EmailConfirmationRequestService;
+    use crate::secret_encryption_service::SecretEncryptionService;
+    use crate::suppression_service::SuppressionService;
+    use super::*;
+
+    // Without a region, a request that gets through fails before AWS is called
+    fn test_app() -> Router {
+        let db_client = aws_sdk_dynamodb::Client::from_conf(aws_sdk_dynamodb::Config::builder().behavior_version(BehaviorVersion::latest()).build());
+        let kms_client = aws_sdk_kms::Client::from_conf(aws_sdk_kms::Config::builder().behavior_version(aws_sdk_kms::config::BehaviorVersion::latest()).build());
+        app(AppState {
+            email_confirmation_request_service: EmailConfirmationRequestService::new(db_client.clone(), "requests"),
+            suppression_service: SuppressionService::new(db_client.clone(), "suppressions"),
+            client_config_service: ClientConfigService::new(db_client.clone(), "client-configs"),
+            delivery_log_service: DeliveryLogService::new(db_client.clone(), "deliveries"),
+            callback_host_service: CallbackHostService::new(db_client.clone(), "callback-hosts"),
+            secret_encryption_service: SecretEncryptionService::new(kms_client, "callback-auth-key"),
+            do_not_contact_service: DoNotContactService::new(db_client, "do-not-contact"),
+            admin_api_keys: AdminApiKeys::new("internal-key-id,operator-key-id"),
+        })
+    }
+
+    async fn send(method: &str, uri: &str, request_context: Option<RequestContext>) -> StatusCode {
+        let mut request = Request::builder().method(method).uri(uri).header("content-type", "application/json").body(Body::from("{}")).unwrap();
+        if let Some(request_context) = request_context {
+            request.extensions_mut().insert(request_context);
+        }
+        test_app().oneshot(request).await.unwrap().status()
+    }
+
+    /// Every route of the list is refused without an admin API key.
+    async fn assert_admin_only(routes: &[(&str, &str)]) {
+        for (method, uri) in routes {
+            assert_eq!(StatusCode::FORBIDDEN, send(method, uri, None).await, "{} {}", method, uri);
+            assert_eq!(StatusCode::FORBIDDEN, send(method, uri, Some(api_key_context(None))).await, "{} {}", method, uri);
+            assert_eq!(StatusCode::FORBIDDEN, send(method, uri, Some(api_key_context(Some("client-key-id")))).await, "{} {}", method, uri);
+            assert_ne!(StatusCode::FORBIDDEN, send(method, uri, Some(api_key_context(Some("operator-key-id")))).await, "{} {}", method, uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_suppressions_are_admin_only() {
+        assert_admin_only(&[
+            ("GET", "/suppressions"),
+            ("GET", "/suppressions/email%40example.com"),
+            ("PUT", "/suppressions/email%40example.com"),
+            ("DELETE", "/suppressions/email%40example.com"),
+        ]).await;
+    }
+
+    #[tokio::test]
+    async fn test_client_settings_are_admin_only() {
+        assert_admin_only(&[
+            ("GET", "/clients/client-1"),
+            ("PUT", "/clients/client-1"),
+            ("GET", "/clients/client-1/stats"),
+            ("POST", "/clients/client-1/webhook-secrets"),
+            ("PUT", "/clients/client-1/callback-auth"),
+            ("GET", "/clients/client-1/do-not-contact"),
+            ("PUT", "/clients/client-1/do-not-contact/email%40example.com"),
+            ("DELETE", "/clients/client-1/do-not-contact/email%40example.com"),
+        ]).await;
+    }
+
+    #[tokio::test]
+    async fn test_callback_hosts_are_admin_only() {
+        assert_admin_only(&[
+            ("GET", "/callback-hosts"),
+            ("GET", "/callback-hosts/example.com"),
+        ]).await;
+    }
+}
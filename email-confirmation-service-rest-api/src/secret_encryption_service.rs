@@ -0,0 +1,34 @@
+use anyhow::{anyhow, Ok, Result};
+use aws_sdk_kms::Client;
+use aws_sdk_kms::primitives::Blob;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use email_confirmation_service_common::client_config::{EncryptedSecret, SECRET_ENCRYPTION_CONTEXT_KEY};
+
+/// Encrypts client secrets before they are stored. The REST API can only encrypt, decrypting is left to the callback lambda.
+#[derive(Clone, Debug)]
+pub struct SecretEncryptionService {
+    kms_client: Client,
+    key_id: String,
+}
+
+impl SecretEncryptionService {
+    pub fn new(kms_client: Client, key_id: &str) -> Self {
+        Self {
+            kms_client,
+            key_id: key_id.to_owned(),
+        }
+    }
+
+    pub async fn encrypt(&self, client_id: &str, secret: &str) -> Result<EncryptedSecret> {
+        let output = self.kms_client
+            .encrypt()
+            .key_id(&self.key_id)
+            .plaintext(Blob::new(secret.as_bytes()))
+            .encryption_context(SECRET_ENCRYPTION_CONTEXT_KEY, client_id)
+            .send()
+            .await?;
+        let ciphertext = output.ciphertext_blob.ok_or_else(|| anyhow!("KMS returned no ciphertext"))?;
+        Ok(EncryptedSecret(STANDARD.encode(ciphertext.into_inner())))
+    }
+}
@@ -0,0 +1,65 @@
+use axum::http::StatusCode;
+use axum::{
+    extract::{Path, State},
+    response::Json,
+};
+use serde_json::{json, Value};
+
+use crate::email_confirmation_request_service::INVALID_REQUEST;
+use crate::handler::result_to_response;
+use crate::handler_params::PutSuppressionParams;
+use crate::suppression_service::{now_as_secs, SuppressionService};
+
+use email_confirmation_service_common::suppression::{SuppressionEntry, SuppressionReason};
+
+pub async fn get_suppressions(
+    State(service): State<SuppressionService>,
+) -> (StatusCode, Json<Value>) {
+    let result = service.get_suppressions().await;
+    result_to_response(result)
+}
+
+pub async fn get_suppression_single(
+    State(service): State<SuppressionService>,
+    Path(email): Path<String>,
+) -> (StatusCode, Json<Value>) {
+    match service.get_active_suppression(&email).await {
+        Ok(Some(suppression)) => (
+            StatusCode::OK,
+            Json(json!({
+                "error": false,
+                "suppression": suppression
+            }))
+        ),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "error": true,
+                "message": format!("{INVALID_REQUEST}: {email}!")
+            }))
+        ),
+        Err(error) => result_to_response(Err(error)),
+    }
+}
+
+pub async fn put_suppression_single(
+    State(service): State<SuppressionService>,
+    Path(email): Path<String>,
+    Json(put_suppression_params): Json<PutSuppressionParams>,
+) -> (StatusCode, Json<Value>) {
+    let reason = put_suppression_params.reason.unwrap_or(SuppressionReason::Manual);
+    let mut suppression = SuppressionEntry::new(&email, reason, put_suppression_params.detail, now_as_secs());
+    if put_suppression_params.expires_at.is_some() {
+        suppression.expires_at = put_suppression_params.expires_at;
+    }
+    let result = service.put_suppression(suppression).await;
+    result_to_response(result)
+}
+
+pub async fn delete_suppression_single(
+    State(service): State<SuppressionService>,
+    Path(email): Path<String>,
+) -> (StatusCode, Json<Value>) {
+    let result = service.delete_suppression_single(&email).await;
+    result_to_response(result)
+}
@@ -0,0 +1,113 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::{Ok, Result};
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::types::AttributeValue;
+use axum::Json;
+use serde_dynamo::{from_item, from_items, to_item};
+use serde_json::{json, Value};
+use email_confirmation_service_common::suppression::{normalize_email, SuppressionEntry};
+
+#[derive(Clone, Debug)]
+pub struct SuppressionService {
+    db_client: Client,
+    table_name: String,
+}
+
+impl SuppressionService {
+    pub fn new(db_client: Client, table_name: &str) -> Self {
+        Self {
+            db_client,
+            table_name: table_name.to_owned(),
+        }
+    }
+
+    pub async fn get_suppressions(&self) -> Result<Json<Value>> {
+        let builder = self.db_client.scan().table_name(&self.table_name);
+        let mut results = builder.clone().send().await?;
+        let now = now_as_secs();
+
+        let mut suppressions: Vec<SuppressionEntry> = from_items(results.items.unwrap_or_default())?;
+        while let Some(last_evaluated_key) = &results.last_evaluated_key {
+            results = builder
+                .clone()
+                .set_exclusive_start_key(Some(last_evaluated_key.to_owned()))
+                .send()
+                .await?;
+            let mut new_suppressions: Vec<SuppressionEntry> = from_items(results.items.clone().unwrap_or_default())?;
+            suppressions.append(&mut new_suppressions);
+        }
+        suppressions.retain(|suppression| suppression.is_active(now));
+
+        Ok(Json(json!({
+            "error": false,
+            "suppressions": suppressions
+        })))
+    }
+
+    /// Returns only suppressions that have not expired yet.
+    pub async fn get_active_suppression(&self, email: &str) -> Result<Option<SuppressionEntry>> {
+        let results = self
+            .db_client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("email", AttributeValue::S(normalize_email(email)))
+            .send()
+            .await?;
+
+        match results.item {
+            Some(item) => {
+                let suppression: SuppressionEntry = from_item(item)?;
+                if suppression.is_active(now_as_secs()) {
+                    Ok(Some(suppression))
+                } else {
+                    Ok(None)
+                }
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// A hard bounce or complaint is never replaced by a soft bounce that would expire.
+    pub async fn put_suppression(&self, suppression: SuppressionEntry) -> Result<Json<Value>> {
+        if let Some(existing) = self.get_active_suppression(&suppression.email).await? {
+            if existing.expires_at.is_none() && suppression.expires_at.is_some() {
+                return Ok(Json(json!({
+                    "error": false,
+                    "suppression": existing
+                })))
+            }
+        }
+
+        let item = to_item(suppression.clone())?;
+        self.db_client
+            .put_item()
+            .table_name(&self.table_name)
+            .set_item(Some(item))
+            .send()
+            .await?;
+
+        Ok(Json(json!({
+            "error": false,
+            "suppression": suppression
+        })))
+    }
+
+    pub async fn delete_suppression_single(&self, email: &str) -> Result<Json<Value>> {
+        let email = normalize_email(email);
+        self.db_client
+            .delete_item()
+            .table_name(&self.table_name)
+            .key("email", AttributeValue::S(email.clone()))
+            .send()
+            .await?;
+
+        Ok(Json(json!({
+            "error": false,
+            "message": "Suppression for email: ".to_owned() + &email + " deleted."
+        })))
+    }
+}
+
+pub(crate) fn now_as_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
@@ -0,0 +1,20 @@
+use std::env;
+use lambda_http::Error;
+
+/// Where the link click handler finds the REST API, and its own url for the links on the pages.
+#[derive(Clone, Debug)]
+pub struct AppState {
+    pub service_url: String,
+    pub api_key: String,
+    pub self_service_url: String,
+}
+
+impl AppState {
+    pub fn from_env() -> Result<Self, Error> {
+        Ok(AppState {
+            service_url: env::var("EMAIL_CONFIRMATION_REQUEST_SERVICE_URL")?,
+            api_key: env::var("EMAIL_CONFIRMATION_REQUEST_SERVICE_INTERNAL_API_KEY")?,
+            self_service_url: env::var("EMAIL_LINK_CLICK_HANDLER_SERVICE_URL")?,
+        })
+    }
+}
@@ -0,0 +1,57 @@
+use lambda_http::http::header::{HeaderValue, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE, VARY};
+use lambda_http::{Body, Response};
+
+pub(crate) const PREFLIGHT_MAX_AGE_SECS: u32 = 600;
+
+/// Lets the browser hand the response to a script of the origin, if the client of the request allows that origin.
+/// The client is only known once the signature of the link is checked, so other responses never allow an origin.
+/// Credentials are never allowed, the link itself is the credential.
+pub(crate) fn add_cors_headers(response: &mut Response<Body>, origin: Option<&str>, allowed_origins: &[String], preflight: bool) {
+    let headers = response.headers_mut();
+    // The answer depends on the Origin, so caches must not mix them up
+    headers.append(VARY, HeaderValue::from_static("Origin"));
+    let Some(origin) = origin.filter(|origin| allowed_origins.iter().any(|allowed_origin| allowed_origin == origin)) else {
+        return;
+    };
+    let Ok(origin) = HeaderValue::from_str(origin) else { return };
+    headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    if preflight {
+        headers.insert(ACCESS_CONTROL_ALLOW_METHODS, HeaderValue::from_static("GET, POST"));
+        headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, HeaderValue::from_static("Accept, Content-Type"));
+        headers.insert(ACCESS_CONTROL_MAX_AGE, HeaderValue::from(PREFLIGHT_MAX_AGE_SECS));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response_with_cors(origin: Option<&str>, preflight: bool) -> Response<Body> {
+        let mut response = Response::new(Body::Empty);
+        add_cors_headers(&mut response, origin, &["https://app.example.com".to_string()], preflight);
+        response
+    }
+
+    #[test]
+    fn test_allowed_origin() {
+        let response = response_with_cors(Some("https://app.example.com"), false);
+        assert_eq!("https://app.example.com", response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN]);
+        assert_eq!("Origin", response.headers()[VARY]);
+        assert!(!response.headers().contains_key(ACCESS_CONTROL_ALLOW_METHODS));
+
+        let preflight = response_with_cors(Some("https://app.example.com"), true);
+        assert_eq!("GET, POST", preflight.headers()[ACCESS_CONTROL_ALLOW_METHODS]);
+        assert_eq!("Accept, Content-Type", preflight.headers()[ACCESS_CONTROL_ALLOW_HEADERS]);
+        assert_eq!("600", preflight.headers()[ACCESS_CONTROL_MAX_AGE]);
+    }
+
+    #[test]
+    fn test_other_origins_are_not_allowed() {
+        for origin in [None, Some("https://evil.example.com"), Some("https://app.example.com.evil.net"), Some("http://app.example.com"), Some("null")] {
+            let response = response_with_cors(origin, true);
+            assert!(!response.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN), "{:?}", origin);
+            assert!(!response.headers().contains_key(ACCESS_CONTROL_ALLOW_METHODS), "{:?}", origin);
+            assert_eq!("Origin", response.headers()[VARY]);
+        }
+    }
+}
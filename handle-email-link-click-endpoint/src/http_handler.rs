@@ -1,150 +1,698 @@
-use std::env;
 use std::time::{SystemTime, UNIX_EPOCH};
+use axum::body::Bytes;
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Query, State};
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
 use reqwest::Client;
 use urlencoding::encode;
-use lambda_http::{Body, Error, Request, RequestExt, Response};
+use lambda_http::{tracing, Error};
+use lambda_http::http::{HeaderMap, Method, StatusCode};
+use lambda_http::http::header::{ACCEPT, ACCEPT_LANGUAGE, CONTENT_TYPE, ORIGIN, USER_AGENT};
+use lambda_http::request::RequestContext;
+use serde::Deserialize;
 use serde_json::json;
-use email_confirmation_service_common::email_confirmation_request::{SanitizedEmailConfirmationRequest, EmailConfirmationServiceApiResponse};
+use email_confirmation_service_common::email_confirmation_request::{SanitizedEmailConfirmationRequest, EmailConfirmationServiceApiResponse, Status};
+use email_confirmation_service_common::signature_request::SignaturePurpose;
+use email_confirmation_service_common::client_config::Branding;
+use email_confirmation_service_common::confirmation_evidence::{preferred_locale, ConfirmationContext};
+use email_confirmation_service_common::form_token::FORM_TOKEN_INVALID_ERROR_CODE;
+use email_confirmation_service_common::link_click::LinkClickSettings;
+use email_confirmation_service_common::redirect_url::{redirect_location, RedirectOutcome};
+use crate::app_state::AppState;
+use crate::cors::add_cors_headers;
+use crate::landing_page::{LandingPage, PageBranding, RequestSummary};
 
-pub(crate) async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
-    let path = event.raw_http_path();
-    let method = event.method().as_str();
-    let query_params = event.query_string_parameters();
+/// GET shows the page of a link, POST posts its form. The router adds the action of the path as an extension.
+pub(crate) async fn handle_link_click(
+    State(app_state): State<AppState>,
+    Extension(action): Extension<LinkAction>,
+    method: Method,
+    headers: HeaderMap,
+    query: Result<Query<LinkParams>, QueryRejection>,
+    request_context: Option<Extension<RequestContext>>,
+    body: Bytes,
+) -> Response {
+    let event = LinkClickEvent {
+        method,
+        headers,
+        request_context: request_context.map(|Extension(request_context)| request_context),
+        body,
+    };
+    let click = match confirmation_link_of(action, query.ok().map(|Query(params)| params)) {
+        Ok(link) => link_click(&app_state, &event, link).await.unwrap_or_else(|error| {
+            tracing::error!("Could not handle the {} link click: {}", action.path(), error);
+            LinkClick::unbranded(LandingPage::ServiceError)
+        }),
+        Err(page) => LinkClick::unbranded(page),
+    };
+    respond(&event.headers, click)
+}
+
+/// The landing page as HTML or JSON, with CORS headers for the origins the client of the request allows.
+pub(crate) fn respond(headers: &HeaderMap, click: LinkClick) -> Response {
+    let response = match wants_json(headers) {
+        true => click.page.json_response(click.request.as_ref()),
+        false => click.page.response(&click.branding),
+    };
+    let mut response = match response {
+        Ok(response) => response,
+        Err(error) => {
+            tracing::error!("Could not render {:?}: {}", click.page, error);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let origin = headers.get(ORIGIN).and_then(|origin| origin.to_str().ok());
+    add_cors_headers(&mut response, origin, &click.allowed_origins, click.page == LandingPage::Preflight);
+    response.into_response()
+}
+
+/// Scripts of the client's web app ask for JSON, browsers following the link get HTML.
+fn wants_json(headers: &HeaderMap) -> bool {
+    headers.get_all(ACCEPT).iter()
+        .filter_map(|accept| accept.to_str().ok())
+        .flat_map(|accept| accept.split(','))
+        .any(|media_range| media_range.split(';').next().unwrap_or_default().trim().eq_ignore_ascii_case("application/json"))
+}
+
+/// What the handler uses of a link click request, once routed.
+struct LinkClickEvent {
+    method: Method,
+    headers: HeaderMap,
+    request_context: Option<RequestContext>, // added by lambda_http, None when not run behind API Gateway
+    body: Bytes,
+}
+
+/// What a click shows, with what is known of the request and its client.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct LinkClick {
+    page: LandingPage,
+    branding: PageBranding,
+    request: Option<RequestSummary>,
+    allowed_origins: Vec<String>,
+}
+
+impl LinkClick {
+    // Without a valid signature the client of the request is not known either, so these pages are not branded
+    pub(crate) fn unbranded(page: LandingPage) -> Self {
+        LinkClick { page, branding: PageBranding::default(), request: None, allowed_origins: vec![] }
+    }
+}
+
+async fn link_click(app_state: &AppState, event: &LinkClickEvent, link: ConfirmationLink) -> Result<LinkClick, Error> {
+    let AppState { service_url, api_key, self_service_url } = app_state;
+
+    let confirmation_request = match get_confirmation_request_by_principal(service_url, api_key, &link).await? {
+        RequestLookup::Found(confirmation_request) => *confirmation_request,
+        RequestLookup::InvalidSignature => return Ok(LinkClick::unbranded(LandingPage::InvalidSignature)),
+        RequestLookup::Unknown => return Ok(LinkClick::unbranded(LandingPage::UnknownRequest)),
+    };
+    let client_settings = get_client_settings(service_url, api_key, &confirmation_request.client_id).await;
+    let click = |page, request: &SanitizedEmailConfirmationRequest| LinkClick {
+        page,
+        branding: PageBranding::new(client_settings.branding.as_ref()),
+        request: Some(RequestSummary::from(request)),
+        allowed_origins: client_settings.allowed_origins.clone(),
+    };
+    if event.method == Method::OPTIONS {
+        return Ok(click(LandingPage::Preflight, &confirmation_request));
+    }
+    let opened = event.method == Method::GET || event.method == Method::HEAD;
+    let scanner = opened && is_scanner_click(&event.method, &event.headers, &client_settings.link_click);
+    if client_settings.link_click.record_clicks && opened {
+        if scanner {
+            tracing::info!("Scanner click on {} by {:?}", confirmation_request.pk, event.headers.get(USER_AGENT));
+        }
+        // The page is shown even if the click could not be recorded
+        if let Err(error) = record_click(service_url, api_key, &link, scanner).await {
+            tracing::warn!("Could not record the click on {}: {}", confirmation_request.pk, error);
+        }
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+    if let Some(page) = page_for_status(&confirmation_request, now) {
+        return Ok(click(redirect_of(&confirmation_request, page), &confirmation_request));
+    }
+    // A scanner, e.g. any HEAD, does not get a form token, which would replace the one of a page the recipient has open
+    if scanner {
+        return Ok(click(LandingPage::Pending, &confirmation_request));
+    }
 
-    if path != "/confirm" {
-        return Err(Error::from(format!("Invalid path: {}", path)));
+    let action_url = link_url(self_service_url, link.action, &link.principal, &link.signature, link.purpose);
+
+    if event.method == Method::GET {
+        let delay_secs = client_settings.link_click.confirm_delay_secs;
+        let form_token = issue_form_token(service_url, api_key, &confirmation_request.pk, &link.signature, link.purpose, delay_secs).await?;
+        let email = confirmation_request.email.clone();
+        let page = match link.action {
+            LinkAction::Confirm => {
+                let decline_url = link.decline_signature.as_ref()
+                    .map(|decline_signature| link_url(self_service_url, LinkAction::Decline, &link.principal, decline_signature, SignaturePurpose::Decline));
+                LandingPage::Confirm { email, action_url, form_token, decline_url, delay_secs }
+            },
+            LinkAction::Decline => LandingPage::Decline { email, action_url, form_token, delay_secs },
+        };
+        return Ok(click(page, &confirmation_request));
+    }
+
+    let form = form_of(&event.headers, &event.body);
+    let Some(form_token) = form.form_token else {
+        return Ok(click(LandingPage::FormExpired { confirm_url: action_url }, &confirmation_request));
+    };
+    let confirmation_context = confirmation_context_of(event.request_context.as_ref(), &event.headers);
+    match set_request_status(service_url, api_key, &link, form_token, form.do_not_contact, confirmation_context).await? {
+        Some(StatusUpdate { request: updated_request, display_code }) => {
+            let page = match link.action {
+                LinkAction::Confirm => redirect_of(&updated_request, LandingPage::Confirmed { email: updated_request.email.clone(), display_code }),
+                LinkAction::Decline => LandingPage::Declined { email: updated_request.email.clone() },
+            };
+            Ok(click(page, &updated_request))
+        },
+        // The request may have changed meanwhile, e.g. the same form was posted twice and the first post confirmed it
+        None => match get_confirmation_request_by_principal(service_url, api_key, &link).await? {
+            RequestLookup::Found(current_request) => {
+                let page = page_for_status(&current_request, now)
+                    .map(|page| redirect_of(&current_request, page))
+                    .unwrap_or(LandingPage::FormExpired { confirm_url: action_url });
+                Ok(click(page, &current_request))
+            },
+            _ => Ok(click(LandingPage::FormExpired { confirm_url: action_url }, &confirmation_request)),
+        },
     }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum LinkAction {
+    Confirm,
+    Decline, // "This wasn't me", linked from the confirm page
+}
+
+impl LinkAction {
+    pub(crate) fn path(&self) -> &'static str {
+        match self {
+            LinkAction::Confirm => "/confirm",
+            LinkAction::Decline => "/decline",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct ConfirmationLink {
+    action: LinkAction,
+    principal: String,
+    signature: String,
+    purpose: SignaturePurpose,
+    decline_signature: Option<String>,
+}
 
-    let principal  = query_params.first("principal").unwrap();
-    let signature = query_params.first("signature").unwrap();
-    let service_url = env::var("EMAIL_CONFIRMATION_REQUEST_SERVICE_URL")?;
-    let api_key = env::var("EMAIL_CONFIRMATION_REQUEST_SERVICE_INTERNAL_API_KEY")?;
-    let self_service_url = env::var("EMAIL_LINK_CLICK_HANDLER_SERVICE_URL")?;
+/// The query of a confirm or decline link.
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+pub(crate) struct LinkParams {
+    principal: String,
+    signature: String,
+    purpose: Option<SignaturePurpose>,
+    decline_signature: Option<String>,
+}
 
-    let confirmation_request = get_confirmation_request_by_principal(
-        &service_url, &api_key, principal.to_string(), signature.to_string()).await?;
+/// MalformedLink if the query is not the one of a link, e.g. the principal or signature is missing.
+fn confirmation_link_of(action: LinkAction, params: Option<LinkParams>) -> Result<ConfirmationLink, LandingPage> {
+    let Some(params) = params.filter(|params| !params.principal.is_empty() && !params.signature.is_empty()) else {
+        return Err(LandingPage::MalformedLink);
+    };
+    // Reminder emails carry a link signed for the Reminder purpose, the first email's link has no purpose.
+    // The path decides whether a signature is checked as a Decline one, so it cannot be used to confirm.
+    let purpose = match (action, params.purpose) {
+        (LinkAction::Decline, _) => SignaturePurpose::Decline,
+        (LinkAction::Confirm, Some(SignaturePurpose::Reminder)) => SignaturePurpose::Reminder,
+        (LinkAction::Confirm, _) => SignaturePurpose::Confirm,
+    };
+    let decline_signature = match action {
+        LinkAction::Confirm => params.decline_signature.filter(|decline_signature| !decline_signature.is_empty()),
+        LinkAction::Decline => None,
+    };
+    Ok(ConfirmationLink { action, principal: params.principal, signature: params.signature, purpose, decline_signature })
+}
 
-    if !expiration_date_is_valid(&confirmation_request) {
-        return get_expired_response().await;
+/// The page for a request that is not waiting for confirmation, None if it is.
+/// A confirmed or declined request shows the outcome again, without changing it again.
+fn page_for_status(confirmation_request: &SanitizedEmailConfirmationRequest, now: u64) -> Option<LandingPage> {
+    match confirmation_request.status {
+        Status::Confirmed | Status::Done => Some(LandingPage::Confirmed { email: confirmation_request.email.clone(), display_code: None }),
+        Status::Declined => Some(LandingPage::Declined { email: confirmation_request.email.clone() }),
+        Status::Cancelled => Some(LandingPage::Cancelled),
+        Status::Expired => Some(LandingPage::Expired),
+        Status::Pending if now < confirmation_request.expires_at => None,
+        Status::Pending => Some(LandingPage::Expired),
+        _ => Some(LandingPage::Unavailable),
     }
+}
 
-    if method == "GET" {
-        return get_confirm_button_response(&self_service_url, &confirmation_request.email, &principal, &signature).await;
+/// Sends the browser to the client's redirect url for the outcome instead of showing the page, if the request has one.
+/// A declined request never redirects: the recipient did not ask to be sent to the client.
+/// Nor does a confirmation with a display code, the recipient has to see the code.
+fn redirect_of(confirmation_request: &SanitizedEmailConfirmationRequest, page: LandingPage) -> LandingPage {
+    let (redirect_url, outcome) = match page {
+        LandingPage::Confirmed { display_code: None, .. } => (&confirmation_request.success_redirect_url, RedirectOutcome::Confirmed),
+        LandingPage::Expired => (&confirmation_request.expired_redirect_url, RedirectOutcome::Expired),
+        _ => return page,
+    };
+    match redirect_url.as_deref().and_then(|redirect_url| redirect_location(redirect_url, &confirmation_request.request_id, outcome)) {
+        Some(location) => LandingPage::Redirect { location, outcome },
+        None => page,
     }
+}
 
-    if method == "POST" {
-        let updated_request = set_request_status_as_confirmed(&service_url, &api_key, confirmation_request.pk, signature.to_string()).await?;
-        return get_confirmed_response(updated_request).await;
+/// The fields of the confirm and decline forms.
+#[derive(Deserialize, Debug, PartialEq, Eq, Default)]
+struct LinkForm {
+    form_token: Option<String>,
+    #[serde(default)]
+    do_not_contact: bool, // the decline form's checkbox
+}
+
+/// The posted form, form encoded by browsers or JSON from scripts.
+fn form_of(headers: &HeaderMap, body: &[u8]) -> LinkForm {
+    let content_type = headers.get(CONTENT_TYPE).and_then(|content_type| content_type.to_str().ok()).unwrap_or_default();
+    let form = match content_type.split(';').next().unwrap_or_default().trim() {
+        "application/x-www-form-urlencoded" => serde_urlencoded::from_bytes::<LinkForm>(body).ok(),
+        "application/json" => serde_json::from_slice::<LinkForm>(body).ok(),
+        _ => None,
+    };
+    match form {
+        Some(form) => LinkForm { form_token: form.form_token.filter(|form_token| !form_token.is_empty()), ..form },
+        None => LinkForm::default(),
     }
+}
+
+/// What the browser that posted the form sent, the confirmation evidence. The IP address is the one API Gateway saw,
+/// X-Forwarded-For is not used as anyone can set it.
+fn confirmation_context_of(request_context: Option<&RequestContext>, headers: &HeaderMap) -> ConfirmationContext {
+    let ip_address = match request_context {
+        Some(RequestContext::ApiGatewayV1(request_context)) => request_context.identity.source_ip.clone(),
+        Some(RequestContext::ApiGatewayV2(request_context)) => request_context.http.source_ip.clone(),
+        _ => None,
+    };
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    ConfirmationContext {
+        ip_address,
+        user_agent: header(USER_AGENT).map(str::to_string),
+        locale: header(ACCEPT_LANGUAGE).and_then(preferred_locale),
+    }
+}
+
+/// A click of a mail scanner rather than of the recipient. Browsers do not send HEAD when a link is followed.
+fn is_scanner_click(method: &Method, headers: &HeaderMap, settings: &LinkClickSettings) -> bool {
+    let user_agent = headers.get(USER_AGENT).and_then(|user_agent| user_agent.to_str().ok());
+    method == Method::HEAD || settings.is_scanner_user_agent(user_agent)
+}
+
+async fn record_click(service_url: &str, api_key: &str, link: &ConfirmationLink, scanner: bool) -> Result<(), Error> {
+    let post_url = format!("{}/email-confirmation-requests/{}/clicks", service_url, encode(&link.principal));
+    let reqwest_client = Client::new();
+    reqwest_client
+        .post(post_url)
+        .header("x-api-key", api_key)
+        .header("Content-Type", "application/json")
+        .json(&json!({"signature": link.signature, "purpose": link.purpose, "scanner": scanner}))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
 
-    Err(Error::from(format!("Invalid method: {}", method)))
+/// The token of the confirm form, valid for the form shown now only.
+async fn issue_form_token(service_url: &str, api_key: &str, principal: &str, signature: &str, purpose: SignaturePurpose, delay_secs: u8) -> Result<String, Error> {
+    let post_url = format!("{}/email-confirmation-requests/{}/form-token", service_url, encode(principal));
+    let reqwest_client = Client::new();
+    let response = reqwest_client
+        .post(post_url)
+        .header("x-api-key", api_key)
+        .header("Content-Type", "application/json")
+        .json(&json!({"signature": signature, "purpose": purpose, "delay_secs": delay_secs}))
+        .send()
+        .await?;
+
+    let json_data: serde_json::Value = response.json().await?;
+    match json_data["form_token"].as_str() {
+        Some(form_token) => Ok(form_token.to_string()),
+        None => Err(Error::from(format!("No form token for {}: {}", principal, json_data))),
+    }
 }
 
-async fn set_request_status_as_confirmed(service_url: &str, api_key: &str, principal: String, signature: String) -> Result<SanitizedEmailConfirmationRequest, Error> {
-    let put_url = format!("{}/email-confirmation-requests/{}/status", service_url, encode(&principal));
+/// Confirms or declines the request, as the link says. A confirmation is stored with its context as evidence.
+async fn set_request_status(service_url: &str, api_key: &str, link: &ConfirmationLink, form_token: String, do_not_contact: bool,
+                            confirmation_context: ConfirmationContext) -> Result<Option<StatusUpdate>, Error> {
+    let new_status = match link.action {
+        LinkAction::Confirm => Status::Confirmed,
+        LinkAction::Decline => Status::Declined,
+    };
+    let put_url = format!("{}/email-confirmation-requests/{}/status", service_url, encode(&link.principal));
     let reqwest_client = Client::new();
     let response = reqwest_client
         .put(put_url)
         .header("x-api-key", api_key)
         .header("Content-Type", "application/json")
-        .json(&json!({"status": "Confirmed", "signature": signature}))
+        .json(&json!({"status": new_status, "signature": link.signature, "purpose": link.purpose, "form_token": form_token,
+            "do_not_contact": do_not_contact && link.action == LinkAction::Decline,
+            "confirmation_context": Some(confirmation_context).filter(|_| link.action == LinkAction::Confirm)}))
         .send()
-        .await
-        .unwrap();
+        .await?;
+
+    let status = response.status();
+    status_update_of(status, response.json().await?)
+}
+
+/// Returns None if the service rejects the form token (missing, expired, replaced by a newer one or already used),
+/// or the request is no longer Pending.
+fn status_update_of(status: StatusCode, json_data: serde_json::Value) -> Result<Option<StatusUpdate>, Error> {
+    if status == StatusCode::CONFLICT || json_data["error_code"] == FORM_TOKEN_INVALID_ERROR_CODE {
+        return Ok(None);
+    }
+    if !status.is_success() {
+        return Err(Error::from(format!("Status update failed with {}: {}", status, json_data)));
+    }
+    let display_code = json_data["display_code"].as_str().map(str::to_string);
+    let json_data: EmailConfirmationServiceApiResponse = serde_json::from_value(json_data)?;
+    Ok(Some(StatusUpdate { request: json_data.request, display_code }))
+}
+
+/// The request after its status was set, with the display code if it is a cross-device request that was just confirmed.
+#[derive(Debug, PartialEq, Eq)]
+struct StatusUpdate {
+    request: SanitizedEmailConfirmationRequest,
+    display_code: Option<String>,
+}
 
-    let json_data : EmailConfirmationServiceApiResponse = response.json().await?;
-    Ok(json_data.request)
+#[derive(Debug, PartialEq, Eq)]
+enum RequestLookup {
+    Found(Box<SanitizedEmailConfirmationRequest>),
+    InvalidSignature,
+    Unknown,
 }
 
-async fn get_confirmation_request_by_principal(service_url: &str, api_key: &str, principal: String, signature: String) -> Result<SanitizedEmailConfirmationRequest, Error> {
-    let get_one_url = format!("{}/email-confirmation-requests/{}?signature={}", service_url, encode(&principal), signature);
+async fn get_confirmation_request_by_principal(service_url: &str, api_key: &str, link: &ConfirmationLink) -> Result<RequestLookup, Error> {
+    let get_one_url = format!("{}/email-confirmation-requests/{}?signature={}&purpose={}", service_url, encode(&link.principal), encode(&link.signature), link.purpose);
     let reqwest_client = Client::new();
     let response = reqwest_client
         .get(get_one_url)
         .header("x-api-key", api_key)
         .header("Content-Type", "application/json")
         .send()
-        .await
-        .unwrap();
-
-    let json_data : EmailConfirmationServiceApiResponse = response.json().await?;
-    Ok(json_data.request)
-}
-
-fn expiration_date_is_valid(confirmation_request: &SanitizedEmailConfirmationRequest) -> bool {
-    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-    now < confirmation_request.expires_at
-}
-
-async fn get_expired_response() -> Result<Response<Body>, Error> {
-    let resp = Response::builder()
-        .status(200)
-        .header("content-type", "text/html")
-        .body("<html><head>
-            <title>Confirmation request expired</title>
-        </head><body>
-            <h1>Confirmation request expired.</h1>
-            <p>Please, re-request confirmation.</p>
-        </body><html>".into())
-        .map_err(Box::new)?;
-    Ok(resp)
-}
-
-async fn get_confirm_button_response(self_service_url: &str, email: &str, principal: &str, signature: &str) -> Result<Response<Body>, Error> {
-    let action_url = format!("{}/confirm?principal={}&signature={}", self_service_url, encode(principal), signature);
-    let resp = Response::builder()
-        .status(200)
-        .header("content-type", "text/html")
-        .body(
-            format!("<html><head>
-                <title>Confirm email address</title>
-            </head><body>
-                <h1>Confirm email address</h1>
-                <p>Confirm your email address '{}' by clicking the button below.</p>
-                <form method=\"POST\" action=\"{}\">
-                    <input type=\"submit\" value=\"Confirm\" />
-                </form>
-            </body><html>", email, action_url.as_str()).into())
-        .map_err(Box::new)?;
-    Ok(resp)
-}
-
-async fn get_confirmed_response(updated_request: SanitizedEmailConfirmationRequest) -> Result<Response<Body>, Error> {
-    let resp = Response::builder()
-        .status(200)
-        .header("content-type", "text/html")
-        .body(format!("<html><head>
-            <title>Email address confirmed</title>
-        </head><body>
-            <h1>Email address confirmed</h1>
-            <p>Your email address '{}' is confirmed.</p>
-        </body><html>", updated_request.email).into())
-        .map_err(Box::new)?;
-    Ok(resp)
+        .await?;
+
+    let status = response.status();
+    request_lookup_of(status, response.json().await?)
+}
+
+fn request_lookup_of(status: StatusCode, json_data: serde_json::Value) -> Result<RequestLookup, Error> {
+    match status {
+        StatusCode::NOT_FOUND => Ok(RequestLookup::Unknown),
+        StatusCode::FORBIDDEN => Ok(RequestLookup::InvalidSignature),
+        status if status.is_success() => {
+            let json_data: EmailConfirmationServiceApiResponse = serde_json::from_value(json_data)?;
+            Ok(RequestLookup::Found(Box::new(json_data.request)))
+        },
+        status => Err(Error::from(format!("Request lookup failed with {}: {}", status, json_data))),
+    }
+}
+
+/// What the link click endpoint uses of the client config.
+#[derive(Debug, PartialEq, Eq, Default)]
+struct ClientSettings {
+    branding: Option<Branding>,
+    allowed_origins: Vec<String>,
+    link_click: LinkClickSettings,
+}
+
+async fn get_client_settings(service_url: &str, api_key: &str, client_id: &str) -> ClientSettings {
+    match get_client_config(service_url, api_key, client_id).await {
+        Ok(client_config) => client_settings_of(client_config.as_ref()).unwrap_or_else(|error| {
+            tracing::warn!("Invalid config of client {}: {}", client_id, error);
+            ClientSettings::default()
+        }),
+        Err(error) => {
+            // The pages still render without branding, only scripts of the client's origins miss the responses
+            tracing::warn!("Could not get the config of client {}: {}", client_id, error);
+            ClientSettings::default()
+        }
+    }
+}
+
+async fn get_client_config(service_url: &str, api_key: &str, client_id: &str) -> Result<Option<serde_json::Value>, Error> {
+    let get_url = format!("{}/clients/{}", service_url, encode(client_id));
+    let reqwest_client = Client::new();
+    let response = reqwest_client
+        .get(get_url)
+        .header("x-api-key", api_key)
+        .header("Content-Type", "application/json")
+        .send()
+        .await?;
+
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    let json_data: serde_json::Value = response.error_for_status()?.json().await?;
+    Ok(Some(json_data))
 }
 
+fn client_settings_of(json_data: Option<&serde_json::Value>) -> Result<ClientSettings, Error> {
+    let setting = |pointer| json_data.and_then(|json_data| json_data.pointer(pointer)).filter(|value| !value.is_null()).cloned();
+    Ok(ClientSettings {
+        branding: setting("/client/branding").map(serde_json::from_value).transpose()?,
+        allowed_origins: setting("/client/allowed_origins").map(serde_json::from_value).transpose()?.unwrap_or_default(),
+        link_click: setting("/client/link_click").map(serde_json::from_value).transpose()?.unwrap_or_default(),
+    })
+}
+
+fn link_url(self_service_url: &str, action: LinkAction, principal: &str, signature: &str, purpose: SignaturePurpose) -> String {
+    format!("{}{}?principal={}&signature={}&purpose={}", self_service_url, action.path(), encode(principal), encode(signature), encode(&purpose.to_string()))
+}
 
 #[cfg(test)]
 mod tests {
-    /*
+    use lambda_http::aws_lambda_events::apigw::{ApiGatewayProxyRequestContext, ApiGatewayRequestIdentity};
+    use lambda_http::http::HeaderValue;
     use super::*;
-    #[tokio::test]
-    async fn test_get_email_confirmation_button_html() {
-        let principal = "foobar@example.com";
-        let signature = "foobar";
-        let html =  get_email_confirmation_button_html(principal, signature);
-        println!("{:?}", &html);
-
-        assert_eq!(html, "
-        <html><head>
-            <title>Confirm email address</title>
-        </head><body>
-            <h1>Confirm email address</h1>
-            <p>Confirm your email address 'foobar@example.com' by clicking the button below.</p>
-            <form method=\"POST\" action=\"/confirm?principal=foobar%40example.com&signature=foobar\">
-                <input type=\"submit\" value=\"Confirm\" />
-            </form>
-        </body><html>
-    ");
-    }*/
-}
\ No newline at end of file
+
+    fn headers(headers: &[(&'static str, &str)]) -> HeaderMap {
+        let mut header_map = HeaderMap::new();
+        for (name, value) in headers {
+            header_map.append(*name, HeaderValue::from_str(value).unwrap());
+        }
+        header_map
+    }
+
+    fn request_accepting(accept: &[&str]) -> HeaderMap {
+        headers(&accept.iter().map(|accept| ("accept", *accept)).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn test_wants_json() {
+        assert!(wants_json(&request_accepting(&["application/json"])));
+        assert!(wants_json(&request_accepting(&["Application/JSON; charset=utf-8"])));
+        assert!(wants_json(&request_accepting(&["text/plain", "application/json, text/plain;q=0.9"])));
+        // What browsers send when a link is followed
+        assert!(!wants_json(&request_accepting(&["text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"])));
+        assert!(!wants_json(&request_accepting(&["*/*"])));
+        assert!(!wants_json(&request_accepting(&["application/jsonp"])));
+        assert!(!wants_json(&request_accepting(&[])));
+    }
+
+    #[test]
+    fn test_client_settings_of() {
+        let client_config = json!({"error": false, "client": {
+            "client_id": "client-1",
+            "branding": {"product_name": "Example App"},
+            "allowed_origins": ["https://app.example.com"]
+        }});
+        assert_eq!(ClientSettings {
+            branding: Some(Branding { product_name: Some("Example App".to_string()), ..Default::default() }),
+            allowed_origins: vec!["https://app.example.com".to_string()],
+            link_click: LinkClickSettings::default(),
+        }, client_settings_of(Some(&client_config)).unwrap());
+        let with_delay = json!({"client": {"link_click": {"confirm_delay_secs": 5}}});
+        assert_eq!(LinkClickSettings { confirm_delay_secs: 5, ..Default::default() }, client_settings_of(Some(&with_delay)).unwrap().link_click);
+        let without_settings = json!({"error": false, "client": {"client_id": "client-1", "branding": null}});
+        assert_eq!(ClientSettings::default(), client_settings_of(Some(&without_settings)).unwrap());
+        assert_eq!(ClientSettings::default(), client_settings_of(None).unwrap());
+        assert!(client_settings_of(Some(&json!({"client": {"allowed_origins": "https://app.example.com"}}))).is_err());
+    }
+
+    #[test]
+    fn test_confirmation_context_of() {
+        let request_context = RequestContext::ApiGatewayV1(ApiGatewayProxyRequestContext {
+            identity: ApiGatewayRequestIdentity { source_ip: Some("203.0.113.74".to_string()), ..Default::default() },
+            ..Default::default()
+        });
+        let browser = headers(&[
+            ("user-agent", "Mozilla/5.0 (X11; Linux x86_64; rv:131.0) Gecko/20100101 Firefox/131.0"),
+            ("accept-language", "sv-SE,sv;q=0.9,en;q=0.8"),
+            ("x-forwarded-for", "198.51.100.1"),
+        ]);
+        assert_eq!(ConfirmationContext {
+            ip_address: Some("203.0.113.74".to_string()),
+            user_agent: Some("Mozilla/5.0 (X11; Linux x86_64; rv:131.0) Gecko/20100101 Firefox/131.0".to_string()),
+            locale: Some("sv-SE".to_string()),
+        }, confirmation_context_of(Some(&request_context), &browser));
+        assert_eq!(ConfirmationContext::default(), confirmation_context_of(None, &form_post()));
+    }
+
+    #[test]
+    fn test_is_scanner_click() {
+        let settings = LinkClickSettings::default();
+        let firefox = headers(&[("user-agent", "Mozilla/5.0 (X11; Linux x86_64; rv:131.0) Gecko/20100101 Firefox/131.0")]);
+        let bing_preview = headers(&[("user-agent", "Mozilla/5.0 (compatible; BingPreview/1.0b)")]);
+        assert!(!is_scanner_click(&Method::GET, &firefox, &settings));
+        assert!(is_scanner_click(&Method::HEAD, &firefox, &settings));
+        assert!(is_scanner_click(&Method::GET, &bing_preview, &settings));
+        assert!(is_scanner_click(&Method::GET, &HeaderMap::new(), &settings));
+        // A HEAD is never the recipient's, also without scanner detection
+        let without_detection = LinkClickSettings { detect_scanners: false, ..Default::default() };
+        assert!(!is_scanner_click(&Method::GET, &bing_preview, &without_detection));
+        assert!(is_scanner_click(&Method::HEAD, &firefox, &without_detection));
+    }
+
+    #[test]
+    fn test_link_url() {
+        let action_url = link_url("https://confirm.example.com", LinkAction::Confirm, "foo+bar@example.com#client#request", "a/b+c=", SignaturePurpose::Reminder);
+        assert_eq!("https://confirm.example.com/confirm?principal=foo%2Bbar%40example.com%23client%23request&signature=a%2Fb%2Bc%3D&purpose=Reminder", action_url);
+        let decline_url = link_url("https://confirm.example.com", LinkAction::Decline, "foo+bar@example.com#client#request", "d/e", SignaturePurpose::Decline);
+        assert_eq!("https://confirm.example.com/decline?principal=foo%2Bbar%40example.com%23client%23request&signature=d%2Fe&purpose=Decline", decline_url);
+    }
+
+    fn form_post() -> HeaderMap {
+        headers(&[("content-type", "application/x-www-form-urlencoded")])
+    }
+
+    #[test]
+    fn test_form_of() {
+        assert_eq!(LinkForm { form_token: Some("0123abcd".to_string()), do_not_contact: false }, form_of(&form_post(), b"form_token=0123abcd"));
+        assert_eq!(LinkForm { form_token: Some("0123abcd".to_string()), do_not_contact: true }, form_of(&form_post(), b"form_token=0123abcd&do_not_contact=true"));
+        assert_eq!(None, form_of(&form_post(), b"form_token=").form_token);
+        assert_eq!(LinkForm::default(), form_of(&form_post(), b""));
+        assert_eq!(LinkForm::default(), form_of(&form_post(), b"other=1"));
+        let json = headers(&[("content-type", "application/json; charset=utf-8")]);
+        assert_eq!(LinkForm { form_token: Some("0123abcd".to_string()), do_not_contact: true }, form_of(&json, br#"{"form_token": "0123abcd", "do_not_contact": true}"#));
+        assert_eq!(LinkForm::default(), form_of(&HeaderMap::new(), b"form_token=0123abcd"));
+    }
+
+    fn link_params(principal: &str, signature: &str, purpose: Option<SignaturePurpose>, decline_signature: Option<&str>) -> Option<LinkParams> {
+        Some(LinkParams { principal: principal.to_string(), signature: signature.to_string(), purpose, decline_signature: decline_signature.map(str::to_string) })
+    }
+
+    #[test]
+    fn test_confirmation_link_of() {
+        let principal = "email@example.com#client-1#request-1";
+        assert_eq!(Ok(ConfirmationLink {
+            action: LinkAction::Confirm,
+            principal: principal.to_string(),
+            signature: "abc123".to_string(),
+            purpose: SignaturePurpose::Confirm,
+            decline_signature: None,
+        }), confirmation_link_of(LinkAction::Confirm, link_params(principal, "abc123", None, None)));
+        let reminder = confirmation_link_of(LinkAction::Confirm, link_params(principal, "abc123", Some(SignaturePurpose::Reminder), Some("def456"))).unwrap();
+        assert_eq!(SignaturePurpose::Reminder, reminder.purpose);
+        assert_eq!(Some("def456".to_string()), reminder.decline_signature);
+
+        // A decline link is always checked as a Decline signature, and a confirm link never is
+        let decline = confirmation_link_of(LinkAction::Decline, link_params(principal, "abc123", Some(SignaturePurpose::Confirm), Some("def456"))).unwrap();
+        assert_eq!((LinkAction::Decline, SignaturePurpose::Decline, None), (decline.action, decline.purpose, decline.decline_signature));
+        let confirm = confirmation_link_of(LinkAction::Confirm, link_params(principal, "abc123", Some(SignaturePurpose::Decline), None)).unwrap();
+        assert_eq!(SignaturePurpose::Confirm, confirm.purpose);
+
+        assert_eq!(Err(LandingPage::MalformedLink), confirmation_link_of(LinkAction::Confirm, None));
+        assert_eq!(Err(LandingPage::MalformedLink), confirmation_link_of(LinkAction::Confirm, link_params(principal, "", None, None)));
+        assert_eq!(Err(LandingPage::MalformedLink), confirmation_link_of(LinkAction::Decline, link_params("", "abc123", None, None)));
+    }
+
+    fn confirmation_request(status: Status, expires_at: u64) -> SanitizedEmailConfirmationRequest {
+        SanitizedEmailConfirmationRequest {
+            pk: "email@example.com#client-1#request-1".to_string(),
+            email: "email@example.com".to_string(),
+            client_id: "client-1".to_string(),
+            request_id: "request-1".to_string(),
+            callback_url: "https://client.example.com/callback".to_string(),
+            expires_at,
+            status,
+            status_reason: None,
+            callback_attempts: vec![],
+            callback_last_error: None,
+            callback_next_attempt_at: None,
+            success_redirect_url: None,
+            expired_redirect_url: None,
+            confirmation_evidence: None,
+            first_clicked_at: None,
+            click_count: 0,
+            scanner_click_count: 0,
+            cross_device: false,
+            display_code_verified_at: None,
+        }
+    }
+
+    #[test]
+    fn test_page_for_status() {
+        let now = 1741592536;
+        let confirmed = Some(LandingPage::Confirmed { email: "email@example.com".to_string(), display_code: None });
+        assert_eq!(None, page_for_status(&confirmation_request(Status::Pending, now + 1), now));
+        assert_eq!(Some(LandingPage::Expired), page_for_status(&confirmation_request(Status::Pending, now), now));
+        assert_eq!(Some(LandingPage::Expired), page_for_status(&confirmation_request(Status::Expired, now), now));
+        // Already confirmed shows success, also after the link expired
+        assert_eq!(confirmed, page_for_status(&confirmation_request(Status::Confirmed, now + 1), now));
+        assert_eq!(confirmed, page_for_status(&confirmation_request(Status::Done, now - 1), now));
+        assert_eq!(Some(LandingPage::Cancelled), page_for_status(&confirmation_request(Status::Cancelled, now + 1), now));
+        assert_eq!(Some(LandingPage::Declined { email: "email@example.com".to_string() }), page_for_status(&confirmation_request(Status::Declined, now - 1), now));
+        for status in [Status::Queued, Status::Bounced, Status::Complained, Status::Suppressed, Status::CallbackFailed] {
+            assert_eq!(Some(LandingPage::Unavailable), page_for_status(&confirmation_request(status, now + 1), now));
+        }
+    }
+
+    #[test]
+    fn test_redirect_of() {
+        let now = 1741592536;
+        let confirmed = LandingPage::Confirmed { email: "email@example.com".to_string(), display_code: None };
+        let declined = LandingPage::Declined { email: "email@example.com".to_string() };
+        let without_redirects = confirmation_request(Status::Confirmed, now);
+        assert_eq!(confirmed, redirect_of(&without_redirects, confirmed.clone()));
+        assert_eq!(LandingPage::Expired, redirect_of(&without_redirects, LandingPage::Expired));
+
+        let with_redirects = SanitizedEmailConfirmationRequest {
+            success_redirect_url: Some("https://app.example.com/welcome".to_string()),
+            expired_redirect_url: Some("https://app.example.com/retry?lang=sv".to_string()),
+            ..without_redirects
+        };
+        assert_eq!(LandingPage::Redirect { location: "https://app.example.com/welcome?request_id=request-1&outcome=confirmed".to_string(), outcome: RedirectOutcome::Confirmed },
+                   redirect_of(&with_redirects, confirmed));
+        assert_eq!(LandingPage::Redirect { location: "https://app.example.com/retry?lang=sv&request_id=request-1&outcome=expired".to_string(), outcome: RedirectOutcome::Expired },
+                   redirect_of(&with_redirects, LandingPage::Expired));
+        assert_eq!(declined, redirect_of(&with_redirects, declined.clone()));
+        assert_eq!(LandingPage::Cancelled, redirect_of(&with_redirects, LandingPage::Cancelled));
+        let with_display_code = LandingPage::Confirmed { email: "email@example.com".to_string(), display_code: Some("K7M-4QX".to_string()) };
+        assert_eq!(with_display_code, redirect_of(&with_redirects, with_display_code.clone()));
+    }
+
+    #[test]
+    fn test_request_lookup_of() {
+        let request = confirmation_request(Status::Pending, 1741592536);
+        let found = json!({"error": false, "request": request});
+        assert_eq!(RequestLookup::Found(Box::new(request)), request_lookup_of(StatusCode::OK, found).unwrap());
+        let invalid = json!({"error": true, "message": "Invalid request"});
+        assert_eq!(RequestLookup::InvalidSignature, request_lookup_of(StatusCode::FORBIDDEN, invalid.clone()).unwrap());
+        assert_eq!(RequestLookup::Unknown, request_lookup_of(StatusCode::NOT_FOUND, invalid.clone()).unwrap());
+        assert!(request_lookup_of(StatusCode::INTERNAL_SERVER_ERROR, invalid.clone()).is_err());
+        assert!(request_lookup_of(StatusCode::OK, invalid).is_err());
+    }
+
+    #[test]
+    fn test_status_update_of() {
+        let request = confirmation_request(Status::Confirmed, 1741592536);
+        let updated = json!({"error": false, "request": request});
+        assert_eq!(Some(StatusUpdate { request: request.clone(), display_code: None }), status_update_of(StatusCode::OK, updated).unwrap());
+        let cross_device = json!({"error": false, "request": request, "display_code": "K7M-4QX"});
+        assert_eq!(Some(StatusUpdate { request, display_code: Some("K7M-4QX".to_string()) }), status_update_of(StatusCode::OK, cross_device).unwrap());
+        let form_token_invalid = json!({"error": true, "error_code": "FORM_TOKEN_INVALID", "message": "Form token is missing, expired or already used"});
+        assert_eq!(None, status_update_of(StatusCode::FORBIDDEN, form_token_invalid).unwrap());
+        let conflict = json!({"error": true, "message": "Request with status Cancelled cannot be set Confirmed"});
+        assert_eq!(None, status_update_of(StatusCode::CONFLICT, conflict).unwrap());
+        assert!(status_update_of(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": true, "message": ""})).is_err());
+    }
+}
@@ -0,0 +1,589 @@
+use askama::Template;
+use lambda_http::{Body, Error, Response};
+use lambda_http::http::StatusCode;
+use serde::Serialize;
+use serde_json::json;
+use email_confirmation_service_common::client_config::{is_valid_color, is_valid_logo_url, Branding};
+use email_confirmation_service_common::email_confirmation_request::{SanitizedEmailConfirmationRequest, Status};
+use email_confirmation_service_common::redirect_url::RedirectOutcome;
+
+pub(crate) const DEFAULT_PRIMARY_COLOR: &str = "#0969da";
+pub(crate) const DEFAULT_BACKGROUND_COLOR: &str = "#f6f8fa";
+
+/// Branding as the templates use it, with the defaults filled in. Branding is validated when it is saved,
+/// anything that still is not safe to put in a style sheet or an img tag is left out here.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) struct PageBranding {
+    pub product_name: Option<String>,
+    pub logo_url: Option<String>,
+    pub primary_color: String,
+    pub background_color: String,
+}
+
+impl PageBranding {
+    pub fn new(branding: Option<&Branding>) -> Self {
+        let branding = branding.cloned().unwrap_or_default();
+        PageBranding {
+            product_name: branding.product_name.filter(|product_name| !product_name.trim().is_empty()),
+            logo_url: branding.logo_url.filter(|logo_url| is_valid_logo_url(logo_url)),
+            primary_color: branding.primary_color.filter(|color| is_valid_color(color)).unwrap_or(DEFAULT_PRIMARY_COLOR.to_string()),
+            background_color: branding.background_color.filter(|color| is_valid_color(color)).unwrap_or(DEFAULT_BACKGROUND_COLOR.to_string()),
+        }
+    }
+}
+
+impl Default for PageBranding {
+    fn default() -> Self {
+        PageBranding::new(None)
+    }
+}
+
+// The templates are in templates/, every value is HTML escaped
+
+#[derive(Template)]
+#[template(path = "confirm.html")]
+pub(crate) struct ConfirmPage<'a> {
+    pub branding: &'a PageBranding,
+    pub email: &'a str,
+    pub action_url: &'a str,
+    pub form_token: &'a str,
+    pub decline_url: Option<&'a str>, // links from emails sent before declining existed have no decline signature
+    pub delay_secs: u8, // the button becomes active after this long
+}
+
+#[derive(Template)]
+#[template(path = "decline.html")]
+pub(crate) struct DeclinePage<'a> {
+    pub branding: &'a PageBranding,
+    pub email: &'a str,
+    pub action_url: &'a str,
+    pub form_token: &'a str,
+    pub delay_secs: u8,
+}
+
+#[derive(Template)]
+#[template(path = "declined.html")]
+pub(crate) struct DeclinedPage<'a> {
+    pub branding: &'a PageBranding,
+    pub email: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "confirmed.html")]
+pub(crate) struct ConfirmedPage<'a> {
+    pub branding: &'a PageBranding,
+    pub email: &'a str,
+    pub display_code: Option<&'a str>, // of a cross-device request, shown right after it is confirmed
+}
+
+#[derive(Template)]
+#[template(path = "expired.html")]
+pub(crate) struct ExpiredPage<'a> {
+    pub branding: &'a PageBranding,
+}
+
+#[derive(Template)]
+#[template(path = "cancelled.html")]
+pub(crate) struct CancelledPage<'a> {
+    pub branding: &'a PageBranding,
+}
+
+/// Shown when the confirm form is posted without a valid form token.
+#[derive(Template)]
+#[template(path = "form-expired.html")]
+pub(crate) struct FormExpiredPage<'a> {
+    pub branding: &'a PageBranding,
+    pub confirm_url: &'a str,
+}
+
+/// A page with a heading and a message, for the links that lead nowhere.
+#[derive(Template)]
+#[template(path = "message.html")]
+pub(crate) struct MessagePage<'a> {
+    pub branding: &'a PageBranding,
+    pub heading: &'a str,
+    pub message: &'a str,
+}
+
+/// What a click on a confirmation link shows. Each outcome has its own HTTP status.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) enum LandingPage {
+    Confirm { email: String, action_url: String, form_token: String, decline_url: Option<String>, delay_secs: u8 },
+    Confirmed { email: String, display_code: Option<String> }, // also for a link clicked again after the confirmation
+    Decline { email: String, action_url: String, form_token: String, delay_secs: u8 },
+    Pending, // a scanner's GET or HEAD of a link waiting for confirmation, answered without a page and without issuing a form token
+    Declined { email: String },
+    Redirect { location: String, outcome: RedirectOutcome }, // to the client's success_redirect_url or expired_redirect_url
+    Preflight, // CORS preflight of a script calling the endpoint, answered with the CORS headers only
+    FormExpired { confirm_url: String },
+    MalformedLink, // principal or signature missing
+    InvalidSignature, // tampered with, or signed before the request changed
+    UnknownRequest,
+    Cancelled,
+    Expired,
+    Unavailable, // the request ended otherwise, e.g. the email bounced
+    NotFound,
+    MethodNotAllowed,
+    ServiceError,
+}
+
+impl LandingPage {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            LandingPage::Confirm { .. } => StatusCode::OK,
+            LandingPage::Confirmed { .. } => StatusCode::OK,
+            LandingPage::Decline { .. } => StatusCode::OK,
+            LandingPage::Pending => StatusCode::OK,
+            LandingPage::Declined { .. } => StatusCode::OK,
+            LandingPage::Redirect { .. } => StatusCode::SEE_OTHER,
+            LandingPage::Preflight => StatusCode::NO_CONTENT,
+            LandingPage::FormExpired { .. } => StatusCode::FORBIDDEN,
+            LandingPage::MalformedLink => StatusCode::BAD_REQUEST,
+            LandingPage::InvalidSignature => StatusCode::FORBIDDEN,
+            LandingPage::UnknownRequest => StatusCode::NOT_FOUND,
+            LandingPage::Cancelled => StatusCode::CONFLICT,
+            LandingPage::Expired => StatusCode::GONE,
+            LandingPage::Unavailable => StatusCode::UNPROCESSABLE_ENTITY,
+            LandingPage::NotFound => StatusCode::NOT_FOUND,
+            LandingPage::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            LandingPage::ServiceError => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    /// The heading and message of the pages without a template of their own.
+    pub fn message(&self) -> Option<(&'static str, &'static str)> {
+        match self {
+            LandingPage::MalformedLink => Some(("Invalid confirmation link",
+                "This link is incomplete. Please, open the link by clicking it in the email, or copy all of it.")),
+            LandingPage::InvalidSignature => Some(("Invalid confirmation link",
+                "This link is not valid. Please, open the link in the latest email you received.")),
+            LandingPage::UnknownRequest => Some(("Confirmation request not found",
+                "There is no confirmation request for this link. Please, re-request confirmation.")),
+            LandingPage::Unavailable => Some(("Confirmation link no longer valid",
+                "This link can no longer be used. Please, re-request confirmation.")),
+            LandingPage::NotFound => Some(("Page not found",
+                "There is no page at this address.")),
+            LandingPage::MethodNotAllowed => Some(("Method not allowed",
+                "Please, open the confirmation link by clicking it in the email.")),
+            LandingPage::ServiceError => Some(("Something went wrong",
+                "Your email address could not be confirmed right now. Please, try again in a moment.")),
+            _ => None,
+        }
+    }
+
+    /// The name of the outcome in JSON responses.
+    pub fn state(&self) -> &'static str {
+        match self {
+            LandingPage::Confirm { .. } => "confirm",
+            LandingPage::Confirmed { .. } => "confirmed",
+            LandingPage::Decline { .. } => "decline",
+            LandingPage::Pending => "pending",
+            LandingPage::Declined { .. } => "declined",
+            LandingPage::Redirect { outcome, .. } => outcome.as_str(),
+            LandingPage::Preflight => "preflight",
+            LandingPage::FormExpired { .. } => "form_expired",
+            LandingPage::MalformedLink => "malformed_link",
+            LandingPage::InvalidSignature => "invalid_signature",
+            LandingPage::UnknownRequest => "unknown_request",
+            LandingPage::Cancelled => "cancelled",
+            LandingPage::Expired => "expired",
+            LandingPage::Unavailable => "unavailable",
+            LandingPage::NotFound => "not_found",
+            LandingPage::MethodNotAllowed => "method_not_allowed",
+            LandingPage::ServiceError => "service_error",
+        }
+    }
+
+    pub fn response(&self, branding: &PageBranding) -> Result<Response<Body>, Error> {
+        let status = self.status();
+        if let Some((heading, message)) = self.message() {
+            return html_response(status, &MessagePage { branding, heading, message });
+        }
+        match self {
+            LandingPage::Confirm { email, action_url, form_token, decline_url, delay_secs } =>
+                html_response(status, &ConfirmPage { branding, email, action_url, form_token, decline_url: decline_url.as_deref(), delay_secs: *delay_secs }),
+            LandingPage::Confirmed { email, display_code } => html_response(status, &ConfirmedPage { branding, email, display_code: display_code.as_deref() }),
+            LandingPage::Decline { email, action_url, form_token, delay_secs } =>
+                html_response(status, &DeclinePage { branding, email, action_url, form_token, delay_secs: *delay_secs }),
+            LandingPage::Pending => empty_response(status),
+            LandingPage::Declined { email } => html_response(status, &DeclinedPage { branding, email }),
+            LandingPage::Redirect { location, .. } => redirect_response(status, location),
+            LandingPage::Preflight => empty_response(status),
+            LandingPage::FormExpired { confirm_url } => html_response(status, &FormExpiredPage { branding, confirm_url }),
+            LandingPage::Cancelled => html_response(status, &CancelledPage { branding }),
+            LandingPage::Expired => html_response(status, &ExpiredPage { branding }),
+            _ => Err(Error::from(format!("No page for {:?}", self))),
+        }
+    }
+
+    /// The outcome as JSON, for scripts that render their own pages. The status is the one of the page,
+    /// except that a redirect is returned as `redirect_url` for the script to follow.
+    pub fn json_response(&self, request: Option<&RequestSummary>) -> Result<Response<Body>, Error> {
+        let status = match self {
+            LandingPage::Redirect { .. } => StatusCode::OK,
+            LandingPage::Preflight => return empty_response(self.status()),
+            _ => self.status(),
+        };
+        let mut json_data = json!({
+            "error": status.is_client_error() || status.is_server_error(),
+            "state": self.state(),
+        });
+        if let Some(request) = request {
+            json_data["request"] = json!(request);
+        }
+        if let Some((_, message)) = self.message() {
+            json_data["message"] = json!(message);
+        }
+        match self {
+            LandingPage::Confirm { action_url, form_token, decline_url, delay_secs, .. } => {
+                json_data["action_url"] = json!(action_url);
+                json_data["form_token"] = json!(form_token);
+                json_data["decline_url"] = json!(decline_url);
+                json_data["delay_secs"] = json!(delay_secs);
+            },
+            LandingPage::Decline { action_url, form_token, delay_secs, .. } => {
+                json_data["action_url"] = json!(action_url);
+                json_data["form_token"] = json!(form_token);
+                json_data["delay_secs"] = json!(delay_secs);
+            },
+            LandingPage::Confirmed { display_code: Some(display_code), .. } => json_data["display_code"] = json!(display_code),
+            LandingPage::FormExpired { confirm_url } => json_data["confirm_url"] = json!(confirm_url),
+            LandingPage::Redirect { location, .. } => json_data["redirect_url"] = json!(location),
+            _ => {},
+        }
+        let resp = Response::builder()
+            .status(status)
+            .header("content-type", "application/json")
+            .body(json_data.to_string().into())
+            .map_err(Box::new)?;
+        Ok(resp)
+    }
+}
+
+/// The request as JSON responses show it, nothing about its callbacks.
+#[derive(Debug, Serialize, PartialEq, Eq, Clone)]
+pub(crate) struct RequestSummary {
+    pub request_id: String,
+    pub client_id: String,
+    pub email: String,
+    pub status: Status,
+    pub expires_at: u64,
+}
+
+impl From<&SanitizedEmailConfirmationRequest> for RequestSummary {
+    fn from(confirmation_request: &SanitizedEmailConfirmationRequest) -> Self {
+        RequestSummary {
+            request_id: confirmation_request.request_id.clone(),
+            client_id: confirmation_request.client_id.clone(),
+            email: confirmation_request.email.clone(),
+            status: confirmation_request.status.clone(),
+            expires_at: confirmation_request.expires_at,
+        }
+    }
+}
+
+pub(crate) fn html_response(status: StatusCode, page: &impl Template) -> Result<Response<Body>, Error> {
+    let resp = Response::builder()
+        .status(status)
+        .header("content-type", "text/html; charset=utf-8")
+        .body(page.render()?.into())
+        .map_err(Box::new)?;
+    Ok(resp)
+}
+
+pub(crate) fn empty_response(status: StatusCode) -> Result<Response<Body>, Error> {
+    let resp = Response::builder()
+        .status(status)
+        .body(Body::Empty)
+        .map_err(Box::new)?;
+    Ok(resp)
+}
+
+/// See Other, so that the browser follows a redirect after the form POST with a GET.
+pub(crate) fn redirect_response(status: StatusCode, location: &str) -> Result<Response<Body>, Error> {
+    let resp = Response::builder()
+        .status(status)
+        .header("location", location)
+        .body(Body::Empty)
+        .map_err(Box::new)?;
+    Ok(resp)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use super::*;
+
+    fn hostile_inputs() -> Vec<String> {
+        serde_json::from_str(include_str!("../fixtures/hostile-inputs.json")).unwrap()
+    }
+
+    fn branding() -> Branding {
+        Branding {
+            product_name: Some("Example App".to_string()),
+            logo_url: Some("https://cdn.example.com/logo.png".to_string()),
+            primary_color: Some("#0a66c2".to_string()),
+            background_color: Some("#ffffff".to_string()),
+        }
+    }
+
+    /// Markup the hostile input would add if it were not escaped.
+    fn assert_no_injected_markup(html: &str, input: &str) {
+        if input.contains(['<', '>', '"', '\'', '&']) {
+            assert!(!html.contains(input), "Unescaped {:?} in {}", input, html);
+        }
+        assert!(!html.to_lowercase().contains("<script"), "Script tag for {:?} in {}", input, html);
+        assert!(!html.contains("<svg"), "Svg tag for {:?} in {}", input, html);
+        assert!(!html.contains("<!--"), "Comment for {:?} in {}", input, html);
+        assert_eq!(1, html.matches("</title>").count(), "Title closed early for {:?} in {}", input, html);
+        assert_eq!(1, html.matches("</style>").count(), "Style closed early for {:?} in {}", input, html);
+    }
+
+    #[test]
+    fn test_hostile_email_is_escaped() {
+        let branding = PageBranding::default();
+        for input in hostile_inputs() {
+            let confirm_page = ConfirmPage { branding: &branding, email: &input, action_url: "https://confirm.example.com/confirm", form_token: "token", decline_url: None, delay_secs: 0 }.render().unwrap();
+            assert_no_injected_markup(&confirm_page, &input);
+            assert!(!confirm_page.contains("<img"));
+            let confirmed_page = ConfirmedPage { branding: &branding, email: &input, display_code: Some(&input) }.render().unwrap();
+            assert_no_injected_markup(&confirmed_page, &input);
+        }
+    }
+
+    #[test]
+    fn test_hostile_action_url_is_escaped() {
+        let branding = PageBranding::default();
+        for input in hostile_inputs() {
+            let html = ConfirmPage { branding: &branding, email: "email@example.com", action_url: &input, form_token: &input, decline_url: Some(&input), delay_secs: 0 }.render().unwrap();
+            assert_no_injected_markup(&html, &input);
+            assert_eq!(1, html.matches("action=\"").count());
+            assert_eq!(1, html.matches("value=\"").count());
+            assert_eq!(1, html.matches("href=\"").count());
+            let html = DeclinePage { branding: &branding, email: &input, action_url: &input, form_token: &input, delay_secs: 0 }.render().unwrap();
+            assert_no_injected_markup(&html, &input);
+            assert_eq!(1, html.matches("action=\"").count());
+            let html = FormExpiredPage { branding: &branding, confirm_url: &input }.render().unwrap();
+            assert_no_injected_markup(&html, &input);
+            assert_eq!(1, html.matches("href=\"").count());
+        }
+    }
+
+    #[test]
+    fn test_hostile_product_name_is_escaped() {
+        for input in hostile_inputs() {
+            let branding = PageBranding::new(Some(&Branding { product_name: Some(input.clone()), ..branding() }));
+            let html = ExpiredPage { branding: &branding }.render().unwrap();
+            assert_no_injected_markup(&html, &input);
+            assert_eq!(1, html.matches("<img").count());
+        }
+    }
+
+    #[test]
+    fn test_values_are_not_template_syntax() {
+        let branding = PageBranding::default();
+        let html = ConfirmedPage { branding: &branding, email: "{{ branding.primary_color }}@example.com", display_code: None }.render().unwrap();
+        assert!(html.contains("{{ branding.primary_color }}@example.com"));
+    }
+
+    #[test]
+    fn test_unsafe_branding_is_left_out() {
+        for input in hostile_inputs() {
+            let branding = PageBranding::new(Some(&Branding {
+                product_name: None,
+                logo_url: Some(input.clone()),
+                primary_color: Some(input.clone()),
+                background_color: Some(input.clone()),
+            }));
+            assert_eq!(PageBranding::default(), branding);
+            let html = CancelledPage { branding: &branding }.render().unwrap();
+            assert_no_injected_markup(&html, &input);
+            assert!(!html.contains("<img"));
+        }
+    }
+
+    #[test]
+    fn test_branding_is_rendered() {
+        let branding = PageBranding::new(Some(&branding()));
+        let html = ConfirmedPage { branding: &branding, email: "email@example.com", display_code: None }.render().unwrap();
+        assert!(html.contains("<title>Email address confirmed | Example App</title>"));
+        assert!(html.contains(r#"<img class="logo" src="https://cdn.example.com/logo.png" alt="Example App">"#));
+        assert!(html.contains("h1 { color: #0a66c2;"));
+        assert!(html.contains("background: #ffffff;"));
+    }
+
+    #[test]
+    fn test_pages_are_complete_documents() {
+        let branding = PageBranding::default();
+        let pages = [
+            ConfirmPage { branding: &branding, email: "email@example.com", action_url: "https://confirm.example.com/confirm", form_token: "token", decline_url: None, delay_secs: 0 }.render().unwrap(),
+            ConfirmedPage { branding: &branding, email: "email@example.com", display_code: None }.render().unwrap(),
+            ExpiredPage { branding: &branding }.render().unwrap(),
+            CancelledPage { branding: &branding }.render().unwrap(),
+            FormExpiredPage { branding: &branding, confirm_url: "https://confirm.example.com/confirm" }.render().unwrap(),
+        ];
+        for html in pages {
+            assert!(html.starts_with("<!DOCTYPE html>\n<html lang=\"en\">"), "{}", html);
+            assert!(html.trim_end().ends_with("</html>"), "{}", html);
+            assert_eq!(1, html.matches("<html").count());
+            assert_eq!(1, html.matches("</body>").count());
+            assert!(html.contains(DEFAULT_PRIMARY_COLOR));
+        }
+    }
+
+    #[test]
+    fn test_confirm_form_carries_the_form_token() {
+        let branding = PageBranding::default();
+        let html = ConfirmPage { branding: &branding, email: "email@example.com", action_url: "https://confirm.example.com/confirm", form_token: "0123abcd", decline_url: None, delay_secs: 0 }.render().unwrap();
+        assert!(html.contains(r#"<input type="hidden" name="form_token" value="0123abcd">"#));
+        assert!(!html.contains("This wasn't me"));
+        assert!(!html.contains("held-back"));
+        let html = DeclinePage { branding: &branding, email: "email@example.com", action_url: "https://confirm.example.com/decline", form_token: "0123abcd", delay_secs: 0 }.render().unwrap();
+        assert!(html.contains(r#"<input type="hidden" name="form_token" value="0123abcd">"#));
+        assert!(html.contains(r#"<input type="checkbox" name="do_not_contact" value="true">"#));
+    }
+
+    #[test]
+    fn test_buttons_are_held_back() {
+        let branding = PageBranding::default();
+        let html = ConfirmPage { branding: &branding, email: "email@example.com", action_url: "https://confirm.example.com/confirm", form_token: "0123abcd", decline_url: None, delay_secs: 5 }.render().unwrap();
+        assert!(html.contains("form button { animation: held-back 5s; }"), "{}", html);
+        assert_eq!(1, html.matches("</style>").count());
+        let html = DeclinePage { branding: &branding, email: "email@example.com", action_url: "https://confirm.example.com/decline", form_token: "0123abcd", delay_secs: 10 }.render().unwrap();
+        assert!(html.contains("form button { animation: held-back 10s; }"), "{}", html);
+    }
+
+    #[test]
+    fn test_confirmed_page_shows_the_display_code() {
+        let branding = PageBranding::default();
+        let html = ConfirmedPage { branding: &branding, email: "email@example.com", display_code: Some("K7M-4QX") }.render().unwrap();
+        assert!(html.contains(r#"<p class="display-code">K7M-4QX</p>"#), "{}", html);
+        assert_eq!(1, html.matches("</style>").count());
+        let html = ConfirmedPage { branding: &branding, email: "email@example.com", display_code: None }.render().unwrap();
+        assert!(!html.contains("display-code"), "{}", html);
+    }
+
+    #[test]
+    fn test_confirm_page_links_to_decline() {
+        let branding = PageBranding::default();
+        let html = ConfirmPage { branding: &branding, email: "email@example.com", action_url: "https://confirm.example.com/confirm",
+            form_token: "0123abcd", decline_url: Some("https://confirm.example.com/decline?principal=p&signature=s&purpose=Decline"), delay_secs: 0 }.render().unwrap();
+        assert!(html.contains(r#"<a href="https://confirm.example.com/decline?principal=p&#38;signature=s&#38;purpose=Decline">This wasn't me</a>"#), "{}", html);
+    }
+
+    #[test]
+    fn test_landing_page_statuses() {
+        let confirmed = LandingPage::Confirmed { email: "email@example.com".to_string(), display_code: None };
+        assert_eq!(StatusCode::OK, confirmed.status());
+        // The outcomes of a confirmation link each have their own status
+        let outcomes = [confirmed, LandingPage::MalformedLink, LandingPage::InvalidSignature, LandingPage::UnknownRequest,
+            LandingPage::Cancelled, LandingPage::Expired, LandingPage::Unavailable, LandingPage::ServiceError];
+        let statuses: HashSet<StatusCode> = outcomes.iter().map(LandingPage::status).collect();
+        assert_eq!(outcomes.len(), statuses.len());
+    }
+
+    #[test]
+    fn test_landing_page_responses() {
+        let branding = PageBranding::default();
+        let pages = [
+            (LandingPage::Confirm { email: "email@example.com".to_string(), action_url: "https://confirm.example.com/confirm".to_string(), form_token: "token".to_string(), decline_url: None, delay_secs: 0 }, 200, "Confirm email address"),
+            (LandingPage::Confirmed { email: "email@example.com".to_string(), display_code: None }, 200, "Email address confirmed"),
+            (LandingPage::Decline { email: "email@example.com".to_string(), action_url: "https://confirm.example.com/decline".to_string(), form_token: "token".to_string(), delay_secs: 0 }, 200, "Did not ask for this email?"),
+            (LandingPage::Declined { email: "email@example.com".to_string() }, 200, "will not be confirmed"),
+            (LandingPage::FormExpired { confirm_url: "https://confirm.example.com/confirm".to_string() }, 403, "Confirmation page expired"),
+            (LandingPage::MalformedLink, 400, "This link is incomplete."),
+            (LandingPage::InvalidSignature, 403, "This link is not valid."),
+            (LandingPage::UnknownRequest, 404, "Confirmation request not found"),
+            (LandingPage::Cancelled, 409, "Confirmation request cancelled"),
+            (LandingPage::Expired, 410, "Confirmation request expired"),
+            (LandingPage::Unavailable, 422, "Confirmation link no longer valid"),
+            (LandingPage::NotFound, 404, "Page not found"),
+            (LandingPage::MethodNotAllowed, 405, "Method not allowed"),
+            (LandingPage::ServiceError, 503, "Something went wrong"),
+        ];
+        for (page, status, text) in pages {
+            let response = page.response(&branding).unwrap();
+            assert_eq!(status, response.status().as_u16(), "{:?}", page);
+            let Body::Text(html) = response.body() else { panic!("No html for {:?}", page) };
+            assert!(html.contains(text), "{:?} in {}", text, html);
+        }
+    }
+
+    #[test]
+    fn test_html_response() {
+        let branding = PageBranding::default();
+        let response = html_response(StatusCode::FORBIDDEN, &ExpiredPage { branding: &branding }).unwrap();
+        assert_eq!(403, response.status().as_u16());
+        assert_eq!("text/html; charset=utf-8", response.headers()["content-type"]);
+    }
+
+    fn json_of(response: &Response<Body>) -> serde_json::Value {
+        assert_eq!("application/json", response.headers()["content-type"]);
+        let Body::Text(json) = response.body() else { panic!("No json in {:?}", response) };
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_json_responses() {
+        let request = RequestSummary {
+            request_id: "request-1".to_string(),
+            client_id: "client-1".to_string(),
+            email: "email@example.com".to_string(),
+            status: Status::Pending,
+            expires_at: 1741592536,
+        };
+        let confirm = LandingPage::Confirm { email: "email@example.com".to_string(), action_url: "https://confirm.example.com/confirm?principal=p".to_string(),
+            form_token: "token".to_string(), decline_url: None, delay_secs: 0 };
+        let response = confirm.json_response(Some(&request)).unwrap();
+        assert_eq!(200, response.status().as_u16());
+        assert_eq!(json!({
+            "error": false,
+            "state": "confirm",
+            "request": {"request_id": "request-1", "client_id": "client-1", "email": "email@example.com", "status": "Pending", "expires_at": 1741592536},
+            "action_url": "https://confirm.example.com/confirm?principal=p",
+            "form_token": "token",
+            "decline_url": null,
+            "delay_secs": 0
+        }), json_of(&response));
+
+        let confirmed = json_of(&LandingPage::Confirmed { email: "email@example.com".to_string(), display_code: None }.json_response(Some(&request)).unwrap());
+        assert_eq!(json!(false), confirmed["error"]);
+        assert_eq!("confirmed", confirmed["state"]);
+        assert!(confirmed.get("display_code").is_none());
+        let cross_device = LandingPage::Confirmed { email: "email@example.com".to_string(), display_code: Some("K7M-4QX".to_string()) };
+        assert_eq!("K7M-4QX", json_of(&cross_device.json_response(Some(&request)).unwrap())["display_code"]);
+
+        // The script follows the redirect itself
+        let redirect = LandingPage::Redirect { location: "https://app.example.com/welcome?request_id=request-1&outcome=confirmed".to_string(), outcome: RedirectOutcome::Confirmed };
+        let response = redirect.json_response(Some(&request)).unwrap();
+        assert_eq!(200, response.status().as_u16());
+        assert_eq!("confirmed", json_of(&response)["state"]);
+        assert_eq!("https://app.example.com/welcome?request_id=request-1&outcome=confirmed", json_of(&response)["redirect_url"]);
+
+        let response = LandingPage::InvalidSignature.json_response(None).unwrap();
+        assert_eq!(403, response.status().as_u16());
+        assert_eq!(json!({"error": true, "state": "invalid_signature", "message": "This link is not valid. Please, open the link in the latest email you received."}), json_of(&response));
+
+        let response = LandingPage::FormExpired { confirm_url: "https://confirm.example.com/confirm?principal=p".to_string() }.json_response(Some(&request)).unwrap();
+        assert_eq!(403, response.status().as_u16());
+        assert_eq!("form_expired", json_of(&response)["state"]);
+        assert_eq!("https://confirm.example.com/confirm?principal=p", json_of(&response)["confirm_url"]);
+
+        let response = LandingPage::Pending.json_response(Some(&request)).unwrap();
+        assert_eq!(200, response.status().as_u16());
+        assert_eq!("pending", json_of(&response)["state"]);
+
+        let response = LandingPage::Preflight.json_response(Some(&request)).unwrap();
+        assert_eq!(204, response.status().as_u16());
+        assert_eq!(&Body::Empty, response.body());
+    }
+
+    #[test]
+    fn test_redirect_response() {
+        let page = LandingPage::Redirect { location: "https://app.example.com/welcome?request_id=request-1&outcome=confirmed".to_string(), outcome: RedirectOutcome::Confirmed };
+        let response = page.response(&PageBranding::default()).unwrap();
+        assert_eq!(303, response.status().as_u16());
+        assert_eq!("https://app.example.com/welcome?request_id=request-1&outcome=confirmed", response.headers()["location"]);
+        assert_eq!(&Body::Empty, response.body());
+    }
+}
@@ -1,9 +1,16 @@
-use lambda_http::{run, service_fn, tracing, Error};
-use http_handler::function_handler;
+use std::env::set_var;
+use lambda_http::{run, tracing, Error};
+use crate::app_state::AppState;
+mod app_state;
+mod cors;
 mod http_handler;
+mod landing_page;
+mod router;
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     tracing::init_default_subscriber();
-    run(service_fn(function_handler)).await
+    set_var("AWS_LAMBDA_HTTP_IGNORE_STAGE_IN_PATH", "true");
+
+    run(router::app(AppState::from_env()?)).await
 }
@@ -0,0 +1,272 @@
+use axum::response::Response;
+use axum::routing::{get, MethodRouter};
+use axum::{Extension, Json, Router};
+use lambda_http::http::HeaderMap;
+use serde_json::json;
+use crate::app_state::AppState;
+use crate::http_handler::{handle_link_click, respond, LinkAction, LinkClick};
+use crate::landing_page::LandingPage;
+
+pub(crate) fn app(app_state: AppState) -> Router {
+    Router::new()
+        .route("/confirm", link_routes(LinkAction::Confirm))
+        .route("/decline", link_routes(LinkAction::Decline))
+        .route("/health", get(health))
+        .fallback(not_found)
+        .with_state(app_state)
+}
+
+/// GET also answers HEAD, which some mail scanners send. OPTIONS is the CORS preflight of a script posting the form.
+fn link_routes(action: LinkAction) -> MethodRouter<AppState> {
+    get(handle_link_click)
+        .post(handle_link_click)
+        .options(handle_link_click)
+        .fallback(method_not_allowed)
+        .layer(Extension(action))
+}
+
+/// For the load balancer and uptime checks, the REST API is not called.
+async fn health() -> Json<serde_json::Value> {
+    Json(json!({"error": false, "status": "ok"}))
+}
+
+async fn not_found(headers: HeaderMap) -> Response {
+    respond(&headers, LinkClick::unbranded(LandingPage::NotFound))
+}
+
+async fn method_not_allowed(headers: HeaderMap) -> Response {
+    respond(&headers, LinkClick::unbranded(LandingPage::MethodNotAllowed))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use axum::body::{to_bytes, Body};
+    use lambda_http::http::{Request, StatusCode};
+    use tower::ServiceExt;
+    use email_confirmation_service_common::email_confirmation_request::{EmailConfirmationRequest, SanitizedEmailConfirmationRequest, Status};
+    use email_confirmation_service_common::signature_request::{SignatureCreationData, SignaturePurpose, SignatureRequest, SignatureRequestPayload};
+    use email_confirmation_service_common::test_util::{http_stub, StubRequests};
+    use super::*;
+
+    const STATUS_URL: &str = "/email-confirmation-requests/email%40example.com%23client-1%23request-1/status";
+    const FIREFOX: &str = "Mozilla/5.0 (X11; Linux x86_64; rv:131.0) Gecko/20100101 Firefox/131.0";
+
+    fn test_app(service_url: &str) -> Router {
+        app(AppState {
+            service_url: service_url.to_string(),
+            api_key: "api-key".to_string(),
+            self_service_url: "https://confirm.example.com".to_string(),
+        })
+    }
+
+    async fn send_request(service_url: &str, request: Request<Body>) -> (StatusCode, String) {
+        let response = test_app(service_url).oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    // The links sent with this are turned away before the REST API would be called
+    async fn send(method: &str, uri: &str, accept: &str) -> (StatusCode, String) {
+        let request = Request::builder().method(method).uri(uri).header("accept", accept).body(Body::empty()).unwrap();
+        send_request("http://127.0.0.1:9", request).await
+    }
+
+    /// Pending request-1 of client-1, as the send email lambda left it.
+    fn pending_request() -> EmailConfirmationRequest {
+        let mut request = EmailConfirmationRequest::new("email@example.com".to_string(), "client-1".to_string(), "request-1".to_string(),
+            "https://client.example.com/callback".to_string());
+        request.status = Status::Pending;
+        request.updated_at += 1;
+        request
+    }
+
+    /// The signature lambda hashes the same data, the stub only needs to tell the data apart.
+    fn stub_signature(data: &SignatureCreationData) -> String {
+        format!("{}-{}-{}", data.signature_key, data.updated_at, data.purpose)
+    }
+
+    /// The query of the link in the email of the request.
+    fn link(request: &EmailConfirmationRequest, purpose: SignaturePurpose) -> String {
+        let SignatureRequestPayload::SignatureCreationRequest(data) = SignatureRequest::signature_creation_request_for(request.clone(), purpose)
+            .signature_request_payload else { unreachable!() };
+        format!("principal={}&signature={}", urlencoding::encode(&request.pk), stub_signature(&data))
+    }
+
+    fn signature_matches(request: &EmailConfirmationRequest, signature: Option<&str>, purpose: Option<SignaturePurpose>) -> bool {
+        let verification = SignatureRequest::signature_verification_request_for(request, signature.unwrap_or_default().to_string(), purpose.unwrap_or_default());
+        let SignatureRequestPayload::SignatureVerificationRequest(data) = verification.signature_request_payload else { unreachable!() };
+        data.signature_value.clone() == stub_signature(&SignatureCreationData::from(data))
+    }
+
+    fn request_response(request: &EmailConfirmationRequest) -> String {
+        json!({"error": false, "request": SanitizedEmailConfirmationRequest::from(request.clone())}).to_string()
+    }
+
+    /// Local REST API with the request of client-1 without a config. Checks signatures and sets the status like the REST API,
+    /// the status change sets updated_at.
+    async fn rest_api_stub(request: EmailConfirmationRequest) -> (String, StubRequests, Arc<Mutex<EmailConfirmationRequest>>) {
+        let stored = Arc::new(Mutex::new(request));
+        let confirmation_request = stored.clone();
+        let (url, requests) = http_stub("application/json", move |request| {
+            let request_line = request.lines().next().unwrap_or_default();
+            let body = request.split_once("\r\n\r\n").map(|(_, body)| body).unwrap_or_default();
+            let params: serde_json::Value = match request_line.split(' ').nth(1).and_then(|target| target.split_once('?')) {
+                Some((_, query)) => json!(serde_urlencoded::from_str::<HashMap<String, String>>(query).unwrap()),
+                None => serde_json::from_str(body).unwrap_or_default(),
+            };
+            let signature = params["signature"].as_str();
+            let purpose = serde_json::from_value(params["purpose"].clone()).ok();
+            let mut confirmation_request = confirmation_request.lock().unwrap();
+            match request_line.split(' ').take(2).collect::<Vec<&str>>()[..] {
+                ["GET", path] if path.starts_with("/clients/") => (404, r#"{"error":true,"message":"Client not found"}"#.to_string()),
+                _ if !signature_matches(&confirmation_request, signature, purpose) => (403, r#"{"error":true,"message":"Invalid request"}"#.to_string()),
+                ["GET", _] => (200, request_response(&confirmation_request)),
+                ["POST", path] if path.ends_with("/form-token") => (200, r#"{"error":false,"form_token":"token-1","expires_at":4102444800}"#.to_string()),
+                ["PUT", path] if path.ends_with("/status") && confirmation_request.status != Status::Pending =>
+                    (409, r#"{"error":true,"message":"Invalid status change"}"#.to_string()),
+                ["PUT", path] if path.ends_with("/status") => {
+                    confirmation_request.status = serde_json::from_value(params["status"].clone()).unwrap();
+                    confirmation_request.updated_at += 60;
+                    (200, request_response(&confirmation_request))
+                },
+                _ => (200, r#"{"error":false}"#.to_string()),
+            }
+        }).await;
+        (url, requests, stored)
+    }
+
+    fn form_post(path: &str, link: &str, form: &str) -> Request<Body> {
+        Request::builder().method("POST").uri(format!("{}?{}", path, link))
+            .header("accept", "text/html")
+            .header("user-agent", FIREFOX)
+            .header("content-type", "application/x-www-form-urlencoded")
+            .body(Body::from(form.to_string())).unwrap()
+    }
+
+    fn link_get(path: &str, link: &str, user_agent: &str) -> Request<Body> {
+        Request::builder().method("GET").uri(format!("{}?{}", path, link))
+            .header("accept", "text/html")
+            .header("user-agent", user_agent)
+            .body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_confirm_link_confirms() {
+        let request = pending_request();
+        let link = link(&request, SignaturePurpose::Confirm);
+        let (service_url, requests, _) = rest_api_stub(request).await;
+        let (status, body) = send_request(&service_url, link_get("/confirm", &link, FIREFOX)).await;
+        assert_eq!(StatusCode::OK, status);
+        assert!(body.contains("Confirm email address"), "{}", body);
+        assert!(body.contains(r#"name="form_token" value="token-1""#), "{}", body);
+
+        let (status, body) = send_request(&service_url, form_post("/confirm", &link, "form_token=token-1")).await;
+        assert_eq!(StatusCode::OK, status);
+        assert!(body.contains("Email address confirmed"), "{}", body);
+        let requests = requests.lock().unwrap();
+        let status_update = requests.iter().find(|request| request.starts_with(&format!("PUT {} ", STATUS_URL))).expect("No status update");
+        assert!(status_update.contains(r#""status":"Confirmed""#), "{}", status_update);
+        assert!(status_update.contains(r#""form_token":"token-1""#), "{}", status_update);
+        assert!(status_update.contains(FIREFOX), "{}", status_update);
+    }
+
+    #[tokio::test]
+    async fn test_decline_link_declines() {
+        let request = pending_request();
+        let link = link(&request, SignaturePurpose::Decline);
+        let (service_url, requests, _) = rest_api_stub(request).await;
+        let (status, body) = send_request(&service_url, link_get("/decline", &link, FIREFOX)).await;
+        assert_eq!(StatusCode::OK, status);
+        assert!(body.contains(r#"name="form_token" value="token-1""#), "{}", body);
+
+        let (status, body) = send_request(&service_url, form_post("/decline", &link, "form_token=token-1&do_not_contact=true")).await;
+        assert_eq!(StatusCode::OK, status);
+        assert!(body.contains("Thank you for letting us know"), "{}", body);
+        let requests = requests.lock().unwrap();
+        let status_update = requests.iter().find(|request| request.starts_with(&format!("PUT {} ", STATUS_URL))).expect("No status update");
+        assert!(status_update.contains(r#""status":"Declined""#), "{}", status_update);
+        assert!(status_update.contains(r#""do_not_contact":true"#), "{}", status_update);
+    }
+
+    #[tokio::test]
+    async fn test_link_shows_the_outcome_after_the_status_changed() {
+        let request = pending_request();
+        let confirm_link = link(&request, SignaturePurpose::Confirm);
+        let (service_url, _, stored) = rest_api_stub(request).await;
+        let (status, _) = send_request(&service_url, form_post("/confirm", &confirm_link, "form_token=token-1")).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_ne!(Status::Pending, stored.lock().unwrap().status);
+        let (status, body) = send_request(&service_url, link_get("/confirm", &confirm_link, FIREFOX)).await;
+        assert_eq!(StatusCode::OK, status);
+        assert!(body.contains("Email address confirmed"), "{}", body);
+
+        // The sweeper sets updated_at too when the request expires
+        let request = pending_request();
+        let confirm_link = link(&request, SignaturePurpose::Confirm);
+        *stored.lock().unwrap() = EmailConfirmationRequest { status: Status::Expired, updated_at: request.updated_at + 3600, ..request };
+        let (status, body) = send_request(&service_url, link_get("/confirm", &confirm_link, FIREFOX)).await;
+        assert_eq!(StatusCode::GONE, status);
+        assert!(body.contains("Confirmation request expired"), "{}", body);
+
+        // A wrong signature is still told apart
+        let (status, _) = send_request(&service_url, link_get("/confirm", &confirm_link.replace("-Confirm", "-Reminder"), FIREFOX)).await;
+        assert_eq!(StatusCode::FORBIDDEN, status);
+    }
+
+    #[tokio::test]
+    async fn test_scanner_gets_no_form_token() {
+        let request = pending_request();
+        let link = link(&request, SignaturePurpose::Confirm);
+        let (service_url, requests, _) = rest_api_stub(request).await;
+        let (status, body) = send_request(&service_url, link_get("/confirm", &link, "python-requests/2.32.3")).await;
+        assert_eq!((StatusCode::OK, String::new()), (status, body));
+        let requests = requests.lock().unwrap();
+        assert!(requests.iter().any(|request| request.contains("/clicks") && request.contains(r#""scanner":true"#)), "{:?}", requests);
+        assert!(!requests.iter().any(|request| request.contains("/form-token")), "{:?}", requests);
+    }
+
+    #[tokio::test]
+    async fn test_health() {
+        let (status, body) = send("GET", "/health", "*/*").await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(json!({"error": false, "status": "ok"}), serde_json::from_str::<serde_json::Value>(&body).unwrap());
+        assert_eq!((StatusCode::OK, String::new()), send("HEAD", "/health", "*/*").await);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_paths_are_not_found() {
+        for path in ["/", "/confirm/extra", "/confirmation", "/health/extra"] {
+            let (status, body) = send("GET", path, "text/html").await;
+            assert_eq!(StatusCode::NOT_FOUND, status, "{}", path);
+            assert!(body.contains("Page not found"), "{}", path);
+        }
+        let (status, body) = send("GET", "/", "application/json").await;
+        assert_eq!(StatusCode::NOT_FOUND, status);
+        assert_eq!("not_found", serde_json::from_str::<serde_json::Value>(&body).unwrap()["state"]);
+    }
+
+    #[tokio::test]
+    async fn test_other_methods_are_not_allowed() {
+        for (method, path) in [("DELETE", "/confirm"), ("PUT", "/decline"), ("PATCH", "/confirm")] {
+            let (status, body) = send(method, &format!("{}?principal=email%40example.com%23client-1%23request-1&signature=abc123", path), "text/html").await;
+            assert_eq!(StatusCode::METHOD_NOT_ALLOWED, status, "{} {}", method, path);
+            assert!(body.contains("Method not allowed"), "{} {}", method, path);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_malformed_links() {
+        for uri in ["/confirm", "/confirm?principal=email%40example.com", "/decline?signature=abc123", "/confirm?principal=&signature=abc123",
+                    "/confirm?principal=email%40example.com&signature=abc123&purpose=Unknown"] {
+            let (status, body) = send("GET", uri, "text/html").await;
+            assert_eq!(StatusCode::BAD_REQUEST, status, "{}", uri);
+            assert!(body.contains("Invalid confirmation link"), "{}", uri);
+        }
+        let (status, body) = send("POST", "/decline", "application/json").await;
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+        assert_eq!("malformed_link", serde_json::from_str::<serde_json::Value>(&body).unwrap()["state"]);
+    }
+}
@@ -0,0 +1,18 @@
+#!/opt/homebrew/opt/node/bin/node
+import * as cdk from 'aws-cdk-lib';
+import { CdkStack } from '../lib/cdk-stack';
+
+const sesNotificationSnsTopicArnFromEnv = process.env.SES_NOTIFICATION_SNS_TOPIC_ARN || "default-value";
+const emailConfirmationDynamoTableNameFromEnv = process.env.EMAIL_CONFIRMATION_REQUEST_SERVICE_DYNAMO_TABLE_NAME || "default-value";
+const signatureServiceLambdaFunctionNameFromEnv = process.env.SIGNATURE_SERVICE_LAMBDA_FUNCTION_NAME || "default-value";
+const emailConfirmationRequestServiceUrlFromEnv = process.env.EMAIL_CONFIRMATION_REQUEST_SERVICE_URL || "default-value";
+const emailConfirmationRequestInternalApiKeyFromEnv = process.env.EMAIL_CONFIRMATION_REQUEST_SERVICE_INTERNAL_API_KEY || "default-value";
+
+const app = new cdk.App();
+new CdkStack(app, 'EcsHsnelStack', {
+    sesNotificationSnsTopicArn: sesNotificationSnsTopicArnFromEnv,
+    emailConfirmationDynamoTableName: emailConfirmationDynamoTableNameFromEnv,
+    signatureServiceLambdaFunctionName: signatureServiceLambdaFunctionNameFromEnv,
+    emailConfirmationRequestServiceUrl: emailConfirmationRequestServiceUrlFromEnv,
+    emailConfirmationRequestInternalApiKey: emailConfirmationRequestInternalApiKeyFromEnv
+});
@@ -0,0 +1,52 @@
+import { Stack, StackProps } from "aws-cdk-lib";
+import { join } from "path";
+import * as lambda from 'aws-cdk-lib/aws-lambda';
+import { RustFunction } from 'cargo-lambda-cdk';
+import { Construct } from 'constructs';
+import { Topic } from "aws-cdk-lib/aws-sns";
+import { SnsEventSource } from "aws-cdk-lib/aws-lambda-event-sources";
+import { PolicyStatement } from "aws-cdk-lib/aws-iam";
+
+export interface HSNELStackProps extends StackProps {
+  sesNotificationSnsTopicArn: string;
+  emailConfirmationDynamoTableName: string;
+  signatureServiceLambdaFunctionName: string;
+  emailConfirmationRequestServiceUrl: string;
+  emailConfirmationRequestInternalApiKey: string;
+}
+
+export class CdkStack extends Stack {
+  constructor(scope: Construct, id: string, props: HSNELStackProps) {
+    super(scope, id, props);
+
+    const lambdaHandler = new RustFunction(this, 'HandleSesNotificationEventLambdaFunction', {
+      manifestPath: join(__dirname, '..', '..'),
+      environment: {
+        "EMAIL_CONFIRMATION_REQUEST_SERVICE_DYNAMO_TABLE_NAME": props.emailConfirmationDynamoTableName,
+        "SIGNATURE_SERVICE_LAMBDA_FUNCTION_NAME": props.signatureServiceLambdaFunctionName,
+        "EMAIL_CONFIRMATION_REQUEST_SERVICE_URL": props.emailConfirmationRequestServiceUrl,
+        "EMAIL_CONFIRMATION_REQUEST_SERVICE_INTERNAL_API_KEY": props.emailConfirmationRequestInternalApiKey
+      }
+    });
+
+    const sesNotificationTopic = Topic.fromTopicArn(this, 'SesNotificationTopic', props.sesNotificationSnsTopicArn);
+    lambdaHandler.addEventSource(new SnsEventSource(sesNotificationTopic));
+
+    const targetLambda = lambda.Function.fromFunctionName(this, 'SignatureLambda',
+        props.signatureServiceLambdaFunctionName
+    );
+
+    targetLambda.grantInvoke(lambdaHandler);
+
+    // Requests are looked up by message id directly from the index of the request table
+    lambdaHandler.addToRolePolicy(
+        new PolicyStatement({
+          actions: ["dynamodb:Query"],
+          resources: [
+            this.formatArn({ service: 'dynamodb', resource: 'table', resourceName: props.emailConfirmationDynamoTableName }),
+            this.formatArn({ service: 'dynamodb', resource: 'table', resourceName: `${props.emailConfirmationDynamoTableName}/index/*` }),
+          ],
+        })
+    );
+  }
+}
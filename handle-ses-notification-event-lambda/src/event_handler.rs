@@ -0,0 +1,193 @@
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+use lambda_runtime::{tracing, Error, LambdaEvent};
+use aws_lambda_events::event::sns::SnsEvent;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_lambda::Client;
+use aws_smithy_types::Blob;
+use urlencoding::encode;
+
+use serde_json::json;
+use serde_dynamo::from_item;
+
+use email_confirmation_service_common::email_confirmation_request::{EmailConfirmationRequest, EmailConfirmationServiceApiResponse, Status, SES_MESSAGE_ID_INDEX_NAME};
+use email_confirmation_service_common::signature_request::SignatureResponse::Signature;
+use email_confirmation_service_common::signature_request::{SignatureRequest, SignatureResponse};
+use email_confirmation_service_common::suppression::SuppressionEntry;
+
+use crate::ses_notification::{DeliveryProblem, SesNotification};
+
+pub(crate) async fn function_handler(event: LambdaEvent<SnsEvent>) -> Result<(), Error> {
+    let payload = event.payload;
+    tracing::info!("Payload: {:?}", payload);
+
+    for record in payload.records.iter() {
+        let Some(notification) = ses_notification(&record.sns.message) else {
+            continue
+        };
+
+        // Suppress the address even if the request cannot be found, it must not be sent to again
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        for suppression in notification.suppressions(now) {
+            tracing::info!("Suppressing {}: {}", &suppression.email, &suppression.reason);
+            put_suppression(&suppression).await?;
+        }
+
+        let Some(problem) = notification.delivery_problem() else {
+            continue
+        };
+
+        let Some(confirmation_request) = find_confirmation_request_by_message_id(&problem.message_id).await? else {
+            tracing::warn!("No confirmation request found for message {}.", &problem.message_id);
+            continue
+        };
+
+        // A late complaint must not undo a confirmation that has already happened
+        if confirmation_request.status != Status::Pending {
+            tracing::info!("Ignoring {} for {} in status {}.", &problem.status, &confirmation_request.pk, &confirmation_request.status);
+            continue
+        }
+
+        tracing::info!("Setting status to {} for {}: {}", &problem.status, &confirmation_request.pk, &problem.reason);
+        let signature = create_signature(&confirmation_request).await?;
+        set_status(&confirmation_request, &problem, signature).await?;
+    }
+    Ok(())
+}
+
+fn ses_notification(sns_message: &str) -> Option<SesNotification> {
+    match serde_json::from_str::<SesNotification>(sns_message) {
+        Ok(notification) => Some(notification),
+        Err(error) => {
+            tracing::warn!("Skipping message that is not an SES notification: {}", error);
+            None
+        }
+    }
+}
+
+async fn find_confirmation_request_by_message_id(message_id: &str) -> Result<Option<EmailConfirmationRequest>, Error> {
+    let config = aws_config::load_from_env().await;
+    let db_client = aws_sdk_dynamodb::Client::new(&config);
+    let table_name = env::var("EMAIL_CONFIRMATION_REQUEST_SERVICE_DYNAMO_TABLE_NAME")?;
+
+    let results = db_client
+        .query()
+        .table_name(table_name)
+        .index_name(SES_MESSAGE_ID_INDEX_NAME)
+        .key_condition_expression("#name = :value")
+        .expression_attribute_names("#name", "ses_message_id")
+        .expression_attribute_values(":value", AttributeValue::S(message_id.to_owned()))
+        .send()
+        .await?;
+
+    match results.items.unwrap_or_default().into_iter().next() {
+        Some(item) => Ok(Some(from_item(item)?)),
+        None => Ok(None),
+    }
+}
+
+async fn create_signature(email_confirmation_request: &EmailConfirmationRequest) -> Result<String, Error> {
+    let config = aws_config::load_from_env().await;
+    let client = Client::new(&config);
+    let payload = json!(SignatureRequest::signature_creation_request(email_confirmation_request.clone()));
+    let function_name = env::var("SIGNATURE_SERVICE_LAMBDA_FUNCTION_NAME");
+
+    if let Ok(function_name) = function_name {
+        let response = client.invoke()
+            .function_name(function_name)
+            .payload(Blob::new(payload.to_string()))
+            .send()
+            .await?;
+
+        if let Some(payload) = response.payload {
+            let response_str = String::from_utf8(payload.into_inner())?;
+            let result: SignatureResponse = serde_json::from_str(&response_str)?;
+
+            if let Signature(signature) = result {
+                return Ok(signature);
+            }
+        }
+    }
+    Err(Error::from("Error creating signature"))
+}
+
+async fn put_suppression(suppression: &SuppressionEntry) -> Result<(), Error> {
+    let service_url = env::var("EMAIL_CONFIRMATION_REQUEST_SERVICE_URL")?;
+    let api_key = env::var("EMAIL_CONFIRMATION_REQUEST_SERVICE_INTERNAL_API_KEY")?;
+
+    let put_url = format!("{}/suppressions/{}", service_url, encode(&suppression.email));
+    let reqwest_client = reqwest::Client::new();
+    let response = reqwest_client
+        .put(put_url)
+        .header("x-api-key", api_key)
+        .header("Content-Type", "application/json")
+        .json(&json!({"reason": suppression.reason, "detail": suppression.detail, "expires_at": suppression.expires_at}))
+        .send()
+        .await?;
+
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(Error::from(format!("Suppression service error: {}", response.status())))
+    }
+}
+
+async fn set_status(confirmation_request: &EmailConfirmationRequest, problem: &DeliveryProblem, signature: String) -> Result<(), Error> {
+    let service_url = env::var("EMAIL_CONFIRMATION_REQUEST_SERVICE_URL")?;
+    let api_key = env::var("EMAIL_CONFIRMATION_REQUEST_SERVICE_INTERNAL_API_KEY")?;
+
+    let put_url = format!("{}/email-confirmation-requests/{}/status", service_url, encode(&confirmation_request.pk));
+    let reqwest_client = reqwest::Client::new();
+    let response = reqwest_client
+        .put(put_url)
+        .header("x-api-key", api_key)
+        .header("Content-Type", "application/json")
+        .json(&json!({"status": problem.status, "signature": signature, "reason": problem.reason}))
+        .send()
+        .await?;
+
+    // The request was confirmed or has ended otherwise since it was read, a late notification does not change it
+    if response.status() == reqwest::StatusCode::CONFLICT {
+        tracing::info!("Request {} cannot be set {} any more, skipping.", &confirmation_request.pk, &problem.status);
+        return Ok(())
+    }
+    let json_data : EmailConfirmationServiceApiResponse = response.json().await?;
+    if json_data.error {
+        Err(Error::from("Email confirmation service error"))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lambda_runtime::Context;
+
+    #[test]
+    fn test_example_sns_bounce_event() {
+        let event = example_sns_bounce_event();
+        let problem = ses_notification(&event.records[0].sns.message).unwrap().delivery_problem().unwrap();
+        assert_eq!(Status::Bounced, problem.status);
+        assert_eq!("0100018e2f1c8a2b-3d5e7f90-1a2b-4c3d-9e8f-000000000000-000000", problem.message_id);
+    }
+
+    #[test]
+    fn test_non_ses_message_is_skipped() {
+        assert_eq!(None, ses_notification("{\"hello\": \"world\"}"));
+        assert_eq!(None, ses_notification("not json"));
+    }
+
+    #[tokio::test]
+    async fn test_delivery_notification_is_ignored() {
+        let mut event = example_sns_bounce_event();
+        event.records[0].sns.message = include_str!("../fixtures/ses-delivery-notification.json").to_string();
+        let result = function_handler(LambdaEvent::new(event, Context::default())).await;
+        assert!(result.is_ok());
+    }
+
+    fn example_sns_bounce_event() -> SnsEvent {
+        let data = include_bytes!("../fixtures/example-sns-bounce-event.json");
+        serde_json::from_slice(data).unwrap()
+    }
+}
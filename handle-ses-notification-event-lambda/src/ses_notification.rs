@@ -0,0 +1,176 @@
+use serde::{Deserialize, Serialize};
+use email_confirmation_service_common::email_confirmation_request::Status;
+use email_confirmation_service_common::suppression::{SuppressionEntry, SuppressionReason};
+
+// Notification content published by SES to the SNS topic, only the parts this service uses.
+// https://docs.aws.amazon.com/ses/latest/dg/notification-contents.html
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SesNotification {
+    pub notification_type: String,
+    pub mail: SesMail,
+    pub bounce: Option<SesBounce>,
+    pub complaint: Option<SesComplaint>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SesMail {
+    pub message_id: String,
+    #[serde(default)]
+    pub destination: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SesBounce {
+    pub bounce_type: String,
+    pub bounce_sub_type: String,
+    #[serde(default)]
+    pub bounced_recipients: Vec<SesBouncedRecipient>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SesBouncedRecipient {
+    pub email_address: String,
+    pub diagnostic_code: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SesComplaint {
+    #[serde(default)]
+    pub complained_recipients: Vec<SesComplainedRecipient>,
+    pub complaint_feedback_type: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SesComplainedRecipient {
+    pub email_address: String,
+}
+
+/// What happened to a sent email, in terms of the confirmation request status.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DeliveryProblem {
+    pub message_id: String,
+    pub status: Status,
+    pub reason: String,
+}
+
+impl SesNotification {
+    /// Returns None for notification types that do not concern us, e.g. Delivery.
+    pub fn delivery_problem(&self) -> Option<DeliveryProblem> {
+        match (self.notification_type.as_str(), &self.bounce, &self.complaint) {
+            ("Bounce", Some(bounce), _) => Some(DeliveryProblem {
+                message_id: self.mail.message_id.clone(),
+                status: Status::Bounced,
+                reason: bounce.reason(),
+            }),
+            ("Complaint", _, Some(complaint)) => Some(DeliveryProblem {
+                message_id: self.mail.message_id.clone(),
+                status: Status::Complained,
+                reason: complaint.reason(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Addresses that should not be sent to again, permanently or for a while.
+    pub fn suppressions(&self, now: u64) -> Vec<SuppressionEntry> {
+        match (self.notification_type.as_str(), &self.bounce, &self.complaint) {
+            ("Bounce", Some(bounce), _) => {
+                let reason = match bounce.bounce_type.as_str() {
+                    "Permanent" => SuppressionReason::HardBounce,
+                    _ => SuppressionReason::SoftBounce,
+                };
+                bounce.bounced_recipients.iter()
+                    .map(|recipient| SuppressionEntry::new(&recipient.email_address, reason.clone(), Some(bounce.reason()), now))
+                    .collect()
+            },
+            ("Complaint", _, Some(complaint)) => complaint.complained_recipients.iter()
+                .map(|recipient| SuppressionEntry::new(&recipient.email_address, SuppressionReason::Complaint, Some(complaint.reason()), now))
+                .collect(),
+            _ => vec![],
+        }
+    }
+}
+
+impl SesBounce {
+    fn reason(&self) -> String {
+        let diagnostic_code = self.bounced_recipients.iter()
+            .find_map(|recipient| recipient.diagnostic_code.clone());
+        match diagnostic_code {
+            Some(diagnostic_code) => format!("{} bounce ({}): {}", self.bounce_type, self.bounce_sub_type, diagnostic_code),
+            None => format!("{} bounce ({})", self.bounce_type, self.bounce_sub_type),
+        }
+    }
+}
+
+impl SesComplaint {
+    fn reason(&self) -> String {
+        match &self.complaint_feedback_type {
+            Some(feedback_type) => format!("Complaint: {}", feedback_type),
+            None => "Complaint".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permanent_bounce() {
+        let notification: SesNotification = serde_json::from_str(include_str!("../fixtures/ses-bounce-notification.json")).unwrap();
+        let problem = notification.delivery_problem().unwrap();
+        assert_eq!(DeliveryProblem {
+            message_id: "0100018e2f1c8a2b-3d5e7f90-1a2b-4c3d-9e8f-000000000000-000000".to_string(),
+            status: Status::Bounced,
+            reason: "Permanent bounce (General): smtp; 550 5.1.1 user unknown".to_string(),
+        }, problem);
+    }
+
+    #[test]
+    fn test_complaint() {
+        let notification: SesNotification = serde_json::from_str(include_str!("../fixtures/ses-complaint-notification.json")).unwrap();
+        let problem = notification.delivery_problem().unwrap();
+        assert_eq!(Status::Complained, problem.status);
+        assert_eq!("Complaint: abuse", problem.reason);
+    }
+
+    #[test]
+    fn test_permanent_bounce_is_suppressed_for_good() {
+        let notification: SesNotification = serde_json::from_str(include_str!("../fixtures/ses-bounce-notification.json")).unwrap();
+        let suppressions = notification.suppressions(1_000);
+        assert_eq!(1, suppressions.len());
+        assert_eq!("nobody@example.com", suppressions[0].email);
+        assert_eq!(SuppressionReason::HardBounce, suppressions[0].reason);
+        assert_eq!(None, suppressions[0].expires_at);
+    }
+
+    #[test]
+    fn test_transient_bounce_is_suppressed_for_a_while() {
+        let mut notification: SesNotification = serde_json::from_str(include_str!("../fixtures/ses-bounce-notification.json")).unwrap();
+        notification.bounce.as_mut().unwrap().bounce_type = "Transient".to_string();
+        let suppressions = notification.suppressions(1_000);
+        assert_eq!(SuppressionReason::SoftBounce, suppressions[0].reason);
+        assert!(suppressions[0].expires_at.is_some());
+    }
+
+    #[test]
+    fn test_complaint_is_suppressed() {
+        let notification: SesNotification = serde_json::from_str(include_str!("../fixtures/ses-complaint-notification.json")).unwrap();
+        let suppressions = notification.suppressions(1_000);
+        assert_eq!("annoyed@example.com", suppressions[0].email);
+        assert_eq!(SuppressionReason::Complaint, suppressions[0].reason);
+    }
+
+    #[test]
+    fn test_delivery_is_not_a_problem() {
+        let notification: SesNotification = serde_json::from_str(include_str!("../fixtures/ses-delivery-notification.json")).unwrap();
+        assert_eq!(None, notification.delivery_problem());
+        assert!(notification.suppressions(1_000).is_empty());
+    }
+}
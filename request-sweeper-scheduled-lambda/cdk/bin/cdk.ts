@@ -0,0 +1,10 @@
+#!/opt/homebrew/opt/node/bin/node
+import * as cdk from 'aws-cdk-lib';
+import { CdkStack } from '../lib/cdk-stack';
+
+const emailConfirmationDynamoTableNameFromEnv = process.env.EMAIL_CONFIRMATION_REQUEST_SERVICE_DYNAMO_TABLE_NAME || "default-value";
+
+const app = new cdk.App();
+new CdkStack(app, 'EcsRsslStack', {
+    emailConfirmationDynamoTableName: emailConfirmationDynamoTableNameFromEnv
+});
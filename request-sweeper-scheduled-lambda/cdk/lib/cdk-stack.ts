@@ -0,0 +1,41 @@
+import { Duration, Stack, StackProps } from "aws-cdk-lib";
+import { join } from "path";
+import { RustFunction } from 'cargo-lambda-cdk';
+import { Construct } from 'constructs';
+import { Rule, Schedule } from "aws-cdk-lib/aws-events";
+import { LambdaFunction } from "aws-cdk-lib/aws-events-targets";
+import { PolicyStatement } from "aws-cdk-lib/aws-iam";
+
+export interface RSSLStackProps extends StackProps {
+  emailConfirmationDynamoTableName: string;
+}
+
+export class CdkStack extends Stack {
+  constructor(scope: Construct, id: string, props: RSSLStackProps) {
+    super(scope, id, props);
+
+    const lambdaHandler = new RustFunction(this, 'RequestSweeperScheduledLambdaFunction', {
+      manifestPath: join(__dirname, '..', '..'),
+      timeout: Duration.minutes(1),
+      environment: {
+        "EMAIL_CONFIRMATION_REQUEST_SERVICE_DYNAMO_TABLE_NAME": props.emailConfirmationDynamoTableName
+      }
+    });
+
+    new Rule(this, 'RequestSweeperSchedule', {
+      schedule: Schedule.rate(Duration.minutes(5)),
+      targets: [new LambdaFunction(lambdaHandler)],
+    });
+
+    // Status changes are written directly with conditional updates, the stream triggers the callbacks
+    lambdaHandler.addToRolePolicy(
+        new PolicyStatement({
+          actions: ["dynamodb:Query", "dynamodb:UpdateItem"],
+          resources: [
+            this.formatArn({ service: 'dynamodb', resource: 'table', resourceName: props.emailConfirmationDynamoTableName }),
+            this.formatArn({ service: 'dynamodb', resource: 'table', resourceName: `${props.emailConfirmationDynamoTableName}/index/*` }),
+          ],
+        })
+    );
+  }
+}
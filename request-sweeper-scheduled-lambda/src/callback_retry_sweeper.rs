@@ -0,0 +1,84 @@
+use lambda_runtime::{tracing, Error};
+use email_confirmation_service_common::email_confirmation_request::EmailConfirmationRequest;
+
+use crate::clock::Clock;
+
+pub(crate) trait CallbackRetryStore {
+    /// Requests in the callback retry index whose callback_next_attempt_at is not after now.
+    async fn find_due_callback_retries(&self, now: u64) -> Result<Vec<EmailConfirmationRequest>, Error>;
+    /// Sets callback_retry_requested_at and takes the request out of the callback retry index.
+    /// Returns false if the retry was already requested. Leaves updated_at alone, it is part of the signature.
+    async fn request_callback_retry(&self, pk: &str, now: u64) -> Result<bool, Error>;
+}
+
+/// The callback itself is retried by the trigger callback lambda, triggered by callback_retry_requested_at in the stream.
+pub(crate) async fn sweep_due_callback_retries<S: CallbackRetryStore, C: Clock>(store: &S, clock: &C) -> Result<usize, Error> {
+    let now = clock.now();
+    let mut requested = 0;
+
+    for confirmation_request in store.find_due_callback_retries(now).await? {
+        if store.request_callback_retry(&confirmation_request.pk, now).await? {
+            tracing::info!("Requested callback retry for {}.", &confirmation_request.pk);
+            requested += 1;
+        }
+    }
+    Ok(requested)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+    use crate::expiry_sweeper::tests::{request, InMemoryRequestStore};
+    use email_confirmation_service_common::email_confirmation_request::Status;
+
+    const NOW: u64 = 1_741_592_476;
+
+    impl CallbackRetryStore for InMemoryRequestStore {
+        async fn find_due_callback_retries(&self, now: u64) -> Result<Vec<EmailConfirmationRequest>, Error> {
+            Ok(self.requests.lock().unwrap().values()
+                .filter(|request| request.callback_next_attempt_at.is_some_and(|next_attempt_at| next_attempt_at <= now))
+                .cloned()
+                .collect())
+        }
+
+        async fn request_callback_retry(&self, pk: &str, now: u64) -> Result<bool, Error> {
+            let mut requests = self.requests.lock().unwrap();
+            let request = requests.get_mut(pk).unwrap();
+            if request.callback_next_attempt_at.is_none() {
+                return Ok(false)
+            }
+            request.callback_retry_requested_at = Some(now);
+            request.callback_next_attempt_at = None;
+            Ok(true)
+        }
+    }
+
+    fn request_with_failed_callback(request_id: &str, next_attempt_at: u64) -> EmailConfirmationRequest {
+        let mut request = request(request_id, Status::Confirmed, NOW + 3_600);
+        request.callback_next_attempt_at = Some(next_attempt_at);
+        request
+    }
+
+    #[tokio::test]
+    async fn test_due_callback_retry_is_requested_once() {
+        let store = InMemoryRequestStore::default();
+        let due = request_with_failed_callback("due", NOW - 1);
+        let backing_off = request_with_failed_callback("backing-off", NOW + 120);
+        for request in [&due, &backing_off] {
+            store.insert(request.clone());
+        }
+
+        let clock = FixedClock::at(NOW);
+        assert_eq!(1, sweep_due_callback_retries(&store, &clock).await.unwrap());
+        assert_eq!(0, sweep_due_callback_retries(&store, &clock).await.unwrap());
+
+        clock.advance(120);
+        assert_eq!(1, sweep_due_callback_retries(&store, &clock).await.unwrap());
+
+        let requests = store.requests.lock().unwrap().clone();
+        assert_eq!(Some(NOW), requests[&due.pk].callback_retry_requested_at);
+        assert_eq!(Some(NOW + 120), requests[&backing_off.pk].callback_retry_requested_at);
+        assert_eq!(due.updated_at, requests[&due.pk].updated_at);
+    }
+}
@@ -0,0 +1,35 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Source of the current time in epoch seconds, so that sweeps can be tested at any moment.
+pub(crate) trait Clock {
+    fn now(&self) -> u64;
+}
+
+pub(crate) struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+    }
+}
+
+#[cfg(test)]
+pub(crate) struct FixedClock(pub std::cell::Cell<u64>);
+
+#[cfg(test)]
+impl FixedClock {
+    pub fn at(now: u64) -> Self {
+        FixedClock(std::cell::Cell::new(now))
+    }
+
+    pub fn advance(&self, seconds: u64) {
+        self.0.set(self.0.get() + seconds);
+    }
+}
+
+#[cfg(test)]
+impl Clock for FixedClock {
+    fn now(&self) -> u64 {
+        self.0.get()
+    }
+}
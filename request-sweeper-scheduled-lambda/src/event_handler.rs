@@ -0,0 +1,28 @@
+use std::env;
+use lambda_runtime::{tracing, Error, LambdaEvent};
+use aws_lambda_events::event::eventbridge::EventBridgeEvent;
+
+use crate::callback_retry_sweeper::sweep_due_callback_retries;
+use crate::clock::SystemClock;
+use crate::expiry_sweeper::sweep_expired_requests;
+use crate::reminder_sweeper::sweep_due_reminders;
+use crate::request_store::DynamoDbRequestStore;
+
+pub(crate) async fn function_handler(event: LambdaEvent<EventBridgeEvent>) -> Result<(), Error> {
+    tracing::info!("Scheduled event: {:?}", event.payload);
+
+    let config = aws_config::load_from_env().await;
+    let db_client = aws_sdk_dynamodb::Client::new(&config);
+    let table_name = env::var("EMAIL_CONFIRMATION_REQUEST_SERVICE_DYNAMO_TABLE_NAME")?;
+    let store = DynamoDbRequestStore::new(db_client, &table_name);
+
+    let reminder_result = sweep_due_reminders(&store, &SystemClock).await?;
+    tracing::info!("Requested {} reminders, dropped {} for requests no longer pending.", reminder_result.requested, reminder_result.dropped);
+
+    let callback_retries = sweep_due_callback_retries(&store, &SystemClock).await?;
+    tracing::info!("Requested {} callback retries.", callback_retries);
+
+    let expiry_result = sweep_expired_requests(&store, &SystemClock).await?;
+    tracing::info!("Expired {} requests, {} were changed meanwhile.", expiry_result.expired, expiry_result.skipped);
+    Ok(())
+}
@@ -0,0 +1,142 @@
+use lambda_runtime::{tracing, Error};
+use email_confirmation_service_common::email_confirmation_request::EmailConfirmationRequest;
+
+use crate::clock::Clock;
+
+pub(crate) trait ExpiryStore {
+    /// Pending requests whose expires_at is before now.
+    async fn find_expired_pending(&self, now: u64) -> Result<Vec<EmailConfirmationRequest>, Error>;
+    /// Sets the status to Expired only if the request is still Pending and past its expiry.
+    /// Returns false if the request was changed meanwhile, e.g. confirmed.
+    async fn expire(&self, pk: &str, now: u64) -> Result<bool, Error>;
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct ExpiryResult {
+    pub expired: usize,
+    pub skipped: usize,
+}
+
+/// The expiry callback to the client is triggered by the status change in the request table stream.
+pub(crate) async fn sweep_expired_requests<S: ExpiryStore, C: Clock>(store: &S, clock: &C) -> Result<ExpiryResult, Error> {
+    let now = clock.now();
+    let mut result = ExpiryResult::default();
+
+    for confirmation_request in store.find_expired_pending(now).await? {
+        if store.expire(&confirmation_request.pk, now).await? {
+            tracing::info!("Expired {}.", &confirmation_request.pk);
+            result.expired += 1;
+        } else {
+            tracing::info!("Not expiring {}, it was changed meanwhile.", &confirmation_request.pk);
+            result.skipped += 1;
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use super::*;
+    use crate::clock::FixedClock;
+    use email_confirmation_service_common::email_confirmation_request::Status;
+
+    const NOW: u64 = 1_741_592_476;
+
+    #[derive(Default)]
+    pub(crate) struct InMemoryRequestStore {
+        pub requests: Mutex<HashMap<String, EmailConfirmationRequest>>,
+    }
+
+    impl InMemoryRequestStore {
+        pub fn insert(&self, request: EmailConfirmationRequest) {
+            self.requests.lock().unwrap().insert(request.pk.clone(), request);
+        }
+
+        pub fn status_of(&self, pk: &str) -> Status {
+            self.requests.lock().unwrap()[pk].status.clone()
+        }
+
+        pub fn set_status(&self, pk: &str, status: Status) {
+            self.requests.lock().unwrap().get_mut(pk).unwrap().status = status;
+        }
+    }
+
+    impl ExpiryStore for InMemoryRequestStore {
+        async fn find_expired_pending(&self, now: u64) -> Result<Vec<EmailConfirmationRequest>, Error> {
+            Ok(self.requests.lock().unwrap().values()
+                .filter(|request| request.status == Status::Pending && request.expires_at < now)
+                .cloned()
+                .collect())
+        }
+
+        async fn expire(&self, pk: &str, now: u64) -> Result<bool, Error> {
+            let mut requests = self.requests.lock().unwrap();
+            let request = requests.get_mut(pk).unwrap();
+            if request.status != Status::Pending || request.expires_at >= now {
+                return Ok(false)
+            }
+            request.previous_status = Some(request.status.clone());
+            request.status = Status::Expired;
+            request.updated_at = now;
+            Ok(true)
+        }
+    }
+
+    pub(crate) fn request(request_id: &str, status: Status, expires_at: u64) -> EmailConfirmationRequest {
+        let mut request = EmailConfirmationRequest::new(
+            "email@example.com".to_string(), "client-1".to_string(), request_id.to_string(), "https://client.example.com/callback".to_string());
+        request.status = status;
+        request.expires_at = expires_at;
+        request
+    }
+
+    #[tokio::test]
+    async fn test_only_pending_requests_past_expiry_are_expired() {
+        let store = InMemoryRequestStore::default();
+        let expired = request("expired", Status::Pending, NOW - 1);
+        let still_valid = request("still-valid", Status::Pending, NOW + 60);
+        let confirmed = request("confirmed", Status::Confirmed, NOW - 1);
+        for request in [&expired, &still_valid, &confirmed] {
+            store.insert(request.clone());
+        }
+
+        let clock = FixedClock::at(NOW);
+        let result = sweep_expired_requests(&store, &clock).await.unwrap();
+
+        assert_eq!(ExpiryResult { expired: 1, skipped: 0 }, result);
+        assert_eq!(Status::Expired, store.status_of(&expired.pk));
+        assert_eq!(Status::Pending, store.status_of(&still_valid.pk));
+        assert_eq!(Status::Confirmed, store.status_of(&confirmed.pk));
+
+        clock.advance(61);
+        let result = sweep_expired_requests(&store, &clock).await.unwrap();
+        assert_eq!(ExpiryResult { expired: 1, skipped: 0 }, result);
+        assert_eq!(Status::Expired, store.status_of(&still_valid.pk));
+    }
+
+    #[tokio::test]
+    async fn test_request_confirmed_during_sweep_is_not_expired() {
+        let store = InMemoryRequestStore::default();
+        let racing = request("racing", Status::Pending, NOW - 1);
+        store.insert(racing.clone());
+
+        // The request is found, then confirmed before the conditional update
+        let found = store.find_expired_pending(NOW).await.unwrap();
+        store.set_status(&racing.pk, Status::Confirmed);
+        assert!(!store.expire(&found[0].pk, NOW).await.unwrap());
+        assert_eq!(Status::Confirmed, store.status_of(&racing.pk));
+    }
+
+    #[tokio::test]
+    async fn test_expiry_is_exclusive_of_the_expiry_second() {
+        let store = InMemoryRequestStore::default();
+        let on_the_second = request("on-the-second", Status::Pending, NOW);
+        store.insert(on_the_second.clone());
+
+        let result = sweep_expired_requests(&store, &FixedClock::at(NOW)).await.unwrap();
+        assert_eq!(ExpiryResult::default(), result);
+        assert_eq!(Status::Pending, store.status_of(&on_the_second.pk));
+    }
+}
@@ -0,0 +1,14 @@
+use lambda_runtime::{run, service_fn, tracing, Error};
+mod callback_retry_sweeper;
+mod clock;
+mod event_handler;
+mod expiry_sweeper;
+mod reminder_sweeper;
+mod request_store;
+use event_handler::function_handler;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing::init_default_subscriber();
+    run(service_fn(function_handler)).await
+}
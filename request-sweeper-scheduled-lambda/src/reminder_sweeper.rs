@@ -0,0 +1,118 @@
+use lambda_runtime::{tracing, Error};
+use email_confirmation_service_common::email_confirmation_request::EmailConfirmationRequest;
+
+use crate::clock::Clock;
+
+pub(crate) trait ReminderStore {
+    /// Requests still in the reminder index whose reminder_due_at is not after now.
+    async fn find_due_reminders(&self, now: u64) -> Result<Vec<EmailConfirmationRequest>, Error>;
+    /// Sets reminder_requested_at and takes the request out of the reminder index, only if it is still Pending
+    /// and no reminder was requested before. Leaves updated_at alone so the link in the first email stays valid.
+    async fn request_reminder(&self, pk: &str, now: u64) -> Result<bool, Error>;
+    /// Takes the request out of the reminder index without requesting a reminder.
+    async fn drop_reminder(&self, pk: &str) -> Result<(), Error>;
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct ReminderResult {
+    pub requested: usize,
+    pub dropped: usize,
+}
+
+/// The reminder email itself is sent by the send email lambda, triggered by reminder_requested_at in the stream.
+pub(crate) async fn sweep_due_reminders<S: ReminderStore, C: Clock>(store: &S, clock: &C) -> Result<ReminderResult, Error> {
+    let now = clock.now();
+    let mut result = ReminderResult::default();
+
+    for confirmation_request in store.find_due_reminders(now).await? {
+        if store.request_reminder(&confirmation_request.pk, now).await? {
+            tracing::info!("Requested reminder for {}.", &confirmation_request.pk);
+            result.requested += 1;
+        } else {
+            tracing::info!("Not reminding {}, it is no longer pending.", &confirmation_request.pk);
+            store.drop_reminder(&confirmation_request.pk).await?;
+            result.dropped += 1;
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+    use crate::expiry_sweeper::tests::{request, InMemoryRequestStore};
+    use email_confirmation_service_common::email_confirmation_request::Status;
+
+    const NOW: u64 = 1_741_592_476;
+
+    impl ReminderStore for InMemoryRequestStore {
+        async fn find_due_reminders(&self, now: u64) -> Result<Vec<EmailConfirmationRequest>, Error> {
+            Ok(self.requests.lock().unwrap().values()
+                .filter(|request| request.reminder_due_at.is_some_and(|due_at| due_at <= now))
+                .cloned()
+                .collect())
+        }
+
+        async fn request_reminder(&self, pk: &str, now: u64) -> Result<bool, Error> {
+            let mut requests = self.requests.lock().unwrap();
+            let request = requests.get_mut(pk).unwrap();
+            if request.status != Status::Pending || request.reminder_due_at.is_none() {
+                return Ok(false)
+            }
+            request.reminder_requested_at = Some(now);
+            request.reminder_due_at = None;
+            Ok(true)
+        }
+
+        async fn drop_reminder(&self, pk: &str) -> Result<(), Error> {
+            self.requests.lock().unwrap().get_mut(pk).unwrap().reminder_due_at = None;
+            Ok(())
+        }
+    }
+
+    fn request_with_reminder(request_id: &str, status: Status, reminder_due_at: u64) -> EmailConfirmationRequest {
+        let mut request = request(request_id, status, reminder_due_at + 3_600);
+        request.reminder_due_at = Some(reminder_due_at);
+        request
+    }
+
+    #[tokio::test]
+    async fn test_reminder_is_requested_once_when_due() {
+        let store = InMemoryRequestStore::default();
+        let due = request_with_reminder("due", Status::Pending, NOW);
+        let not_yet = request_with_reminder("not-yet", Status::Pending, NOW + 60);
+        let without_reminder = request("without-reminder", Status::Pending, NOW + 60);
+        for request in [&due, &not_yet, &without_reminder] {
+            store.insert(request.clone());
+        }
+
+        let clock = FixedClock::at(NOW);
+        let result = sweep_due_reminders(&store, &clock).await.unwrap();
+        assert_eq!(ReminderResult { requested: 1, dropped: 0 }, result);
+
+        let requests = store.requests.lock().unwrap().clone();
+        assert_eq!(Some(NOW), requests[&due.pk].reminder_requested_at);
+        assert_eq!(due.updated_at, requests[&due.pk].updated_at);
+        assert_eq!(None, requests[&not_yet.pk].reminder_requested_at);
+        assert_eq!(None, requests[&without_reminder.pk].reminder_requested_at);
+
+        // The next sweep does not request the same reminder again
+        let result = sweep_due_reminders(&store, &clock).await.unwrap();
+        assert_eq!(ReminderResult::default(), result);
+    }
+
+    #[tokio::test]
+    async fn test_confirmed_request_is_not_reminded() {
+        let store = InMemoryRequestStore::default();
+        let confirmed = request_with_reminder("confirmed", Status::Confirmed, NOW - 1);
+        store.insert(confirmed.clone());
+
+        let result = sweep_due_reminders(&store, &FixedClock::at(NOW)).await.unwrap();
+        assert_eq!(ReminderResult { requested: 0, dropped: 1 }, result);
+        assert_eq!(None, store.requests.lock().unwrap()[&confirmed.pk].reminder_requested_at);
+
+        let result = sweep_due_reminders(&store, &FixedClock::at(NOW)).await.unwrap();
+        assert_eq!(ReminderResult::default(), result);
+    }
+}
@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::operation::update_item::UpdateItemError;
+use aws_sdk_dynamodb::types::AttributeValue;
+use lambda_runtime::Error;
+use serde_dynamo::from_items;
+
+use email_confirmation_service_common::email_confirmation_request::{EmailConfirmationRequest, Status, CALLBACK_RETRY_INDEX_KEY, CALLBACK_RETRY_INDEX_KEY_VALUE, CALLBACK_RETRY_INDEX_NAME, PENDING_EXPIRY_INDEX_NAME, PENDING_INDEX_KEY, PENDING_INDEX_KEY_VALUE, REMINDER_DUE_INDEX_NAME, REMINDER_INDEX_KEY, REMINDER_INDEX_KEY_VALUE};
+
+use crate::callback_retry_sweeper::CallbackRetryStore;
+use crate::expiry_sweeper::ExpiryStore;
+use crate::reminder_sweeper::ReminderStore;
+
+type ExpressionNames = Vec<(&'static str, &'static str)>;
+type ExpressionValues = Vec<(&'static str, AttributeValue)>;
+
+/// A query of one of the sparse indexes, which have a constant partition key and a timestamp sort key.
+/// Built apart from the client, like the updates, so that the expressions can be tested.
+#[derive(Debug, PartialEq)]
+struct IndexQuery {
+    index_name: &'static str,
+    key_condition_expression: String,
+    names: ExpressionNames,
+    values: ExpressionValues,
+}
+
+impl IndexQuery {
+    fn new(index_name: &'static str, index_key: &'static str, index_key_value: &str, sort_key_condition: &str, sort_key: &'static str, now: u64) -> Self {
+        IndexQuery {
+            index_name,
+            key_condition_expression: format!("#name1 = :value1 AND #name2 {} :value2", sort_key_condition),
+            names: vec![("#name1", index_key), ("#name2", sort_key)],
+            values: vec![
+                (":value1", AttributeValue::S(index_key_value.to_string())),
+                (":value2", AttributeValue::N(now.to_string())),
+            ],
+        }
+    }
+}
+
+/// An update_item that is only done if its condition holds.
+#[derive(Debug, PartialEq)]
+struct ConditionalUpdate {
+    update_expression: &'static str,
+    condition_expression: &'static str,
+    names: ExpressionNames,
+    values: ExpressionValues,
+}
+
+fn expired_pending_query(now: u64) -> IndexQuery {
+    IndexQuery::new(PENDING_EXPIRY_INDEX_NAME, PENDING_INDEX_KEY, PENDING_INDEX_KEY_VALUE, "<", "expires_at", now)
+}
+
+/// Also takes the request out of the pending and reminder indexes.
+fn expire_update(now: u64) -> ConditionalUpdate {
+    ConditionalUpdate {
+        update_expression: "set #name6 = #name1, #name1 = :value1, #name2 = :value2 remove #name3, #name5",
+        condition_expression: "#name1 = :value3 AND #name4 < :value2",
+        names: vec![
+            ("#name1", "status"),
+            ("#name2", "updated_at"),
+            ("#name3", PENDING_INDEX_KEY),
+            ("#name4", "expires_at"),
+            ("#name5", REMINDER_INDEX_KEY),
+            ("#name6", "previous_status"),
+        ],
+        values: vec![
+            (":value1", AttributeValue::S(Status::Expired.to_string())),
+            (":value2", AttributeValue::N(now.to_string())),
+            (":value3", AttributeValue::S(Status::Pending.to_string())),
+        ],
+    }
+}
+
+fn due_reminders_query(now: u64) -> IndexQuery {
+    IndexQuery::new(REMINDER_DUE_INDEX_NAME, REMINDER_INDEX_KEY, REMINDER_INDEX_KEY_VALUE, "<=", "reminder_due_at", now)
+}
+
+fn request_reminder_update(now: u64) -> ConditionalUpdate {
+    ConditionalUpdate {
+        update_expression: "set #name1 = :value1 remove #name2",
+        condition_expression: "#name3 = :value2 AND attribute_exists(#name2)",
+        names: vec![
+            ("#name1", "reminder_requested_at"),
+            ("#name2", REMINDER_INDEX_KEY),
+            ("#name3", "status"),
+        ],
+        values: vec![
+            (":value1", AttributeValue::N(now.to_string())),
+            (":value2", AttributeValue::S(Status::Pending.to_string())),
+        ],
+    }
+}
+
+/// Conditional, so that a request deleted meanwhile is not recreated as an empty item.
+fn drop_reminder_update() -> ConditionalUpdate {
+    ConditionalUpdate {
+        update_expression: "remove #name1",
+        condition_expression: "attribute_exists(#name2)",
+        names: vec![("#name1", REMINDER_INDEX_KEY), ("#name2", "pk")],
+        values: vec![],
+    }
+}
+
+fn due_callback_retries_query(now: u64) -> IndexQuery {
+    IndexQuery::new(CALLBACK_RETRY_INDEX_NAME, CALLBACK_RETRY_INDEX_KEY, CALLBACK_RETRY_INDEX_KEY_VALUE, "<=", "callback_next_attempt_at", now)
+}
+
+fn request_callback_retry_update(now: u64) -> ConditionalUpdate {
+    ConditionalUpdate {
+        update_expression: "set #name1 = :value1 remove #name2, #name3",
+        condition_expression: "attribute_exists(#name2)",
+        names: vec![
+            ("#name1", "callback_retry_requested_at"),
+            ("#name2", CALLBACK_RETRY_INDEX_KEY),
+            ("#name3", "callback_next_attempt_at"),
+        ],
+        values: vec![(":value1", AttributeValue::N(now.to_string()))],
+    }
+}
+
+fn names_map(names: ExpressionNames) -> HashMap<String, String> {
+    names.into_iter().map(|(placeholder, name)| (placeholder.to_string(), name.to_string())).collect()
+}
+
+fn values_map(values: ExpressionValues) -> HashMap<String, AttributeValue> {
+    values.into_iter().map(|(placeholder, value)| (placeholder.to_string(), value)).collect()
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct DynamoDbRequestStore {
+    db_client: Client,
+    table_name: String,
+}
+
+impl DynamoDbRequestStore {
+    pub fn new(db_client: Client, table_name: &str) -> Self {
+        Self {
+            db_client,
+            table_name: table_name.to_owned(),
+        }
+    }
+
+    async fn query_index(&self, query: IndexQuery) -> Result<Vec<EmailConfirmationRequest>, Error> {
+        let builder = self.db_client
+            .query()
+            .table_name(&self.table_name)
+            .index_name(query.index_name)
+            .key_condition_expression(query.key_condition_expression)
+            .set_expression_attribute_names(Some(names_map(query.names)))
+            .set_expression_attribute_values(Some(values_map(query.values)));
+
+        let mut results = builder.clone().send().await?;
+        let mut requests: Vec<EmailConfirmationRequest> = from_items(results.items.clone().unwrap_or_default())?;
+        while let Some(last_evaluated_key) = &results.last_evaluated_key {
+            results = builder
+                .clone()
+                .set_exclusive_start_key(Some(last_evaluated_key.to_owned()))
+                .send()
+                .await?;
+            let mut new_requests: Vec<EmailConfirmationRequest> = from_items(results.items.clone().unwrap_or_default())?;
+            requests.append(&mut new_requests);
+        }
+        Ok(requests)
+    }
+
+    /// Returns false if the condition of the update did not hold.
+    async fn update(&self, pk: &str, update: ConditionalUpdate) -> Result<bool, Error> {
+        // DynamoDB refuses an empty map of values
+        let values = Some(values_map(update.values)).filter(|values| !values.is_empty());
+        let result = self.db_client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(pk.to_owned()))
+            .update_expression(update.update_expression)
+            .condition_expression(update.condition_expression)
+            .set_expression_attribute_names(Some(names_map(update.names)))
+            .set_expression_attribute_values(values)
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(error) => match error.into_service_error() {
+                UpdateItemError::ConditionalCheckFailedException(_) => Ok(false),
+                other => Err(Error::from(other)),
+            }
+        }
+    }
+}
+
+impl ExpiryStore for DynamoDbRequestStore {
+    async fn find_expired_pending(&self, now: u64) -> Result<Vec<EmailConfirmationRequest>, Error> {
+        self.query_index(expired_pending_query(now)).await
+    }
+
+    async fn expire(&self, pk: &str, now: u64) -> Result<bool, Error> {
+        self.update(pk, expire_update(now)).await
+    }
+}
+
+impl ReminderStore for DynamoDbRequestStore {
+    async fn find_due_reminders(&self, now: u64) -> Result<Vec<EmailConfirmationRequest>, Error> {
+        self.query_index(due_reminders_query(now)).await
+    }
+
+    async fn request_reminder(&self, pk: &str, now: u64) -> Result<bool, Error> {
+        self.update(pk, request_reminder_update(now)).await
+    }
+
+    async fn drop_reminder(&self, pk: &str) -> Result<(), Error> {
+        // Already gone is as good as dropped
+        self.update(pk, drop_reminder_update()).await?;
+        Ok(())
+    }
+}
+
+impl CallbackRetryStore for DynamoDbRequestStore {
+    async fn find_due_callback_retries(&self, now: u64) -> Result<Vec<EmailConfirmationRequest>, Error> {
+        self.query_index(due_callback_retries_query(now)).await
+    }
+
+    async fn request_callback_retry(&self, pk: &str, now: u64) -> Result<bool, Error> {
+        self.update(pk, request_callback_retry_update(now)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_741_592_476;
+
+    /// The expression as DynamoDB reads it, with the names and values filled in.
+    /// Fails on a placeholder that is not defined, or defined and not used, DynamoDB refuses both.
+    fn resolved(expressions: &[&str], names: &ExpressionNames, values: &ExpressionValues) -> Vec<String> {
+        for (placeholder, _) in names {
+            assert!(expressions.iter().any(|expression| expression.contains(placeholder)), "{} is not used", placeholder);
+        }
+        for (placeholder, _) in values {
+            assert!(expressions.iter().any(|expression| expression.contains(placeholder)), "{} is not used", placeholder);
+        }
+        expressions.iter().map(|expression| {
+            // Longest first, so that #name1 does not replace the start of #name10
+            let mut resolved = expression.to_string();
+            let mut names = names.clone();
+            names.sort_by_key(|(placeholder, _)| std::cmp::Reverse(placeholder.len()));
+            for (placeholder, name) in names {
+                resolved = resolved.replace(placeholder, name);
+            }
+            let mut values = values.clone();
+            values.sort_by_key(|(placeholder, _)| std::cmp::Reverse(placeholder.len()));
+            for (placeholder, value) in values {
+                let value = match value {
+                    AttributeValue::S(value) => format!("{:?}", value),
+                    AttributeValue::N(value) => value,
+                    other => panic!("Unexpected value {:?}", other),
+                };
+                resolved = resolved.replace(placeholder, &value);
+            }
+            assert!(!resolved.contains('#') && !resolved.contains(':'), "Undefined placeholder in {}", resolved);
+            resolved
+        }).collect()
+    }
+
+    fn resolved_query(query: &IndexQuery) -> String {
+        resolved(&[&query.key_condition_expression], &query.names, &query.values).remove(0)
+    }
+
+    /// The update and the condition expression.
+    fn resolved_update(update: &ConditionalUpdate) -> (String, String) {
+        let mut resolved = resolved(&[update.update_expression, update.condition_expression], &update.names, &update.values);
+        let condition = resolved.pop().unwrap();
+        (resolved.pop().unwrap(), condition)
+    }
+
+    #[test]
+    fn test_expired_pending_query() {
+        let query = expired_pending_query(NOW);
+        assert_eq!(PENDING_EXPIRY_INDEX_NAME, query.index_name);
+        assert_eq!(r#"pending_index_key = "Pending" AND expires_at < 1741592476"#, resolved_query(&query));
+    }
+
+    #[test]
+    fn test_expire_update() {
+        assert_eq!((
+            r#"set previous_status = status, status = "Expired", updated_at = 1741592476 remove pending_index_key, reminder_index_key"#.to_string(),
+            r#"status = "Pending" AND expires_at < 1741592476"#.to_string(),
+        ), resolved_update(&expire_update(NOW)));
+    }
+
+    #[test]
+    fn test_due_reminders_query() {
+        let query = due_reminders_query(NOW);
+        assert_eq!(REMINDER_DUE_INDEX_NAME, query.index_name);
+        assert_eq!(r#"reminder_index_key = "Due" AND reminder_due_at <= 1741592476"#, resolved_query(&query));
+    }
+
+    #[test]
+    fn test_reminder_updates() {
+        // updated_at is left alone, the link in the first email stays valid
+        assert_eq!((
+            "set reminder_requested_at = 1741592476 remove reminder_index_key".to_string(),
+            r#"status = "Pending" AND attribute_exists(reminder_index_key)"#.to_string(),
+        ), resolved_update(&request_reminder_update(NOW)));
+        assert_eq!((
+            "remove reminder_index_key".to_string(),
+            "attribute_exists(pk)".to_string(),
+        ), resolved_update(&drop_reminder_update()));
+    }
+
+    #[test]
+    fn test_callback_retry_expressions() {
+        let query = due_callback_retries_query(NOW);
+        assert_eq!(CALLBACK_RETRY_INDEX_NAME, query.index_name);
+        assert_eq!(r#"callback_retry_index_key = "Due" AND callback_next_attempt_at <= 1741592476"#, resolved_query(&query));
+        // Only once per failed attempt: the index key is gone after the first request
+        assert_eq!((
+            "set callback_retry_requested_at = 1741592476 remove callback_retry_index_key, callback_next_attempt_at".to_string(),
+            "attribute_exists(callback_retry_index_key)".to_string(),
+        ), resolved_update(&request_callback_retry_update(NOW)));
+    }
+}
@@ -1,10 +1,11 @@
-import { Stack, StackProps } from "aws-cdk-lib";
+import { RemovalPolicy, Stack, StackProps } from "aws-cdk-lib";
 import * as lambda from 'aws-cdk-lib/aws-lambda';
 import { join } from "path";
 import { RustFunction } from 'cargo-lambda-cdk';
 import { Construct } from 'constructs';
 import {EventSourceMapping, StartingPosition} from "aws-cdk-lib/aws-lambda";
 import {PolicyStatement} from "aws-cdk-lib/aws-iam";
+import {AttributeType, BillingMode, Table} from "aws-cdk-lib/aws-dynamodb";
 
 export interface SEELStackProps extends StackProps {
   signatureServiceLambdaFunctionName: string;
@@ -19,6 +20,14 @@ export class CdkStack extends Stack {
   constructor(scope: Construct, id: string, props: SEELStackProps) {
     super(scope, id, props);
 
+    // One record per sent email, so that stream retries never send the same email twice
+    const sendIdempotencyTable = new Table(this, 'EmailSendIdempotencyTable', {
+      partitionKey: { name: 'idempotency_key', type: AttributeType.STRING },
+      billingMode: BillingMode.PAY_PER_REQUEST,
+      timeToLiveAttribute: 'expires_at',
+      removalPolicy: RemovalPolicy.DESTROY,
+    });
+
     const lambdaHandler = new RustFunction(this, 'SendEmailEventLambdaFunction', {
       // Path to the root directory.
       manifestPath: join(__dirname, '..', '..'),
@@ -28,10 +37,13 @@ export class CdkStack extends Stack {
         "EMAIL_LINK_CLICK_HANDLER_SERVICE_URL": props.emailLinkClickHandlerServiceUrl,
         "EMAIL_CONFIRMATION_REQUEST_SERVICE_URL": props.emailConfirmationRequestServiceUrl,
         "EMAIL_CONFIRMATION_REQUEST_SERVICE_INTERNAL_API_KEY": props.emailConfirmationRequestInternalApiKey,
-        "EMAIL_SENDER_ADDRESS": props.emailSenderAddress
+        "EMAIL_SENDER_ADDRESS": props.emailSenderAddress,
+        "EMAIL_SEND_IDEMPOTENCY_DYNAMO_TABLE_NAME": sendIdempotencyTable.tableName
       }
     });
 
+    sendIdempotencyTable.grantReadWriteData(lambdaHandler);
+
     new EventSourceMapping(this, 'CreateConfirmationRequestEvent', {
       target: lambdaHandler,
       batchSize: 5,
@@ -2,7 +2,7 @@ use std::env;
 use lambda_runtime::{tracing, Error, LambdaEvent};
 use aws_sdk_lambda::{Client};
 use aws_smithy_types::Blob;
-use aws_lambda_events::event::dynamodb::Event;
+use aws_lambda_events::event::dynamodb::{Event, EventRecord};
 use urlencoding::encode;
 
 use aws_config::meta::region::RegionProviderChain;
@@ -10,62 +10,154 @@ use aws_sdk_ses::types::{Destination, Message, Body, Content};
 
 use chrono::{DateTime, Utc};
 use serde_json::{json};
-use serde_dynamo::{Item, AttributeValue::S, AttributeValue::N, from_item};
+use serde_dynamo::from_item;
 
-use email_confirmation_service_common::email_confirmation_request::{EmailConfirmationRequest, EmailConfirmationServiceApiResponse};
-use email_confirmation_service_common::email_confirmation_request::Status::{Pending, Queued};
+use email_confirmation_service_common::email_confirmation_request::{EmailConfirmationRequest, EmailConfirmationServiceApiResponse, EmailKind, Status};
+use email_confirmation_service_common::email_confirmation_request::Status::{Pending, Queued, Suppressed};
 use email_confirmation_service_common::signature_request::SignatureResponse::Signature;
-use email_confirmation_service_common::signature_request::{SignatureRequest, SignatureResponse};
+use email_confirmation_service_common::signature_request::{SignaturePurpose, SignatureRequest, SignatureResponse};
+use email_confirmation_service_common::suppression::{SuppressionApiResponse, SuppressionEntry};
+
+use crate::send_idempotency::{Claim, SendAttempt, SendIdempotencyStore, SendKind};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum RecordAction {
+    Skip,
+    SetPending(EmailConfirmationRequest),
+    SendConfirmationEmail(EmailConfirmationRequest),
+    SendReminderEmail(EmailConfirmationRequest),
+}
 
 pub(crate)async fn function_handler(event: LambdaEvent<Event>) -> Result<(), Error> {
     // Extract some useful information from the request
     let payload = event.payload;
-    println!("{:?}", &payload);
     tracing::info!("Payload: {:?}", payload);
 
     let records = payload.records;
     for record in records.iter() {
-        if record.event_name != "INSERT" && record.event_name != "MODIFY" {
-            return Ok(())
+        match record_action(record) {
+            RecordAction::Skip => continue,
+            RecordAction::SetPending(confirmation_request) => {
+                let signature = create_signature(&confirmation_request, SignaturePurpose::Confirm).await?;
+                set_status(&confirmation_request, Pending, None, signature).await?;
+            },
+            RecordAction::SendConfirmationEmail(confirmation_request) => {
+                send_email_of_kind(&confirmation_request, EmailKind::Confirmation).await?;
+            },
+            RecordAction::SendReminderEmail(confirmation_request) => {
+                send_email_of_kind(&confirmation_request, EmailKind::Reminder).await?;
+            }
         }
+    }
+    Ok(())
+}
+
+fn record_action(record: &EventRecord) -> RecordAction {
+    if record.event_name != "INSERT" && record.event_name != "MODIFY" {
+        return RecordAction::Skip
+    }
 
-        if record.event_source != Some("aws:dynamodb".to_string()) {
-            return Ok(())
+    if record.event_source != Some("aws:dynamodb".to_string()) {
+        return RecordAction::Skip
+    }
+
+    let confirmation_request: EmailConfirmationRequest = match from_item(record.change.new_image.clone()) {
+        Ok(confirmation_request) => confirmation_request,
+        Err(error) => {
+            tracing::warn!("Skipping record {} that is not a confirmation request: {}", &record.event_id, error);
+            return RecordAction::Skip
         }
+    };
 
-        let confirmation_request : EmailConfirmationRequest = from_item(record.clone().change.new_image).unwrap();
+    if record.event_name == "INSERT" && confirmation_request.status == Queued {
+        return RecordAction::SetPending(confirmation_request)
+    }
 
-        if record.event_name == "INSERT" {
-            if confirmation_request.status != Queued {
-                return Ok(())
-            }
-            let signature = create_signature(&confirmation_request).await?;
-            set_status_to_pending(&confirmation_request, signature).await?;
-            return Ok(())
-        } else if record.event_name == "MODIFY" {
-            if confirmation_request.status != Pending {
-                return Ok(())
-            }
+    // A set message id means SES has already accepted the email, this is a retry or a later modification
+    if record.event_name == "MODIFY" && confirmation_request.status == Pending && confirmation_request.ses_message_id.is_none() {
+        return RecordAction::SendConfirmationEmail(confirmation_request)
+    }
 
-            let signature = create_signature(&confirmation_request).await?;
-            let link_click_handler_service_url = env::var("EMAIL_LINK_CLICK_HANDLER_SERVICE_URL")?;
-            let link = format!("{}/confirm?principal={}&signature={}", link_click_handler_service_url, encode(&confirmation_request.pk), signature);
+    // The sweeper sets reminder_requested_at once the reminder is due, the reminder message id marks it as sent
+    if record.event_name == "MODIFY" && confirmation_request.status == Pending && confirmation_request.ses_message_id.is_some()
+        && confirmation_request.reminder_requested_at.is_some() && confirmation_request.reminder_message_id.is_none() {
+        return RecordAction::SendReminderEmail(confirmation_request)
+    }
 
-            println!("Created link: {}", &link);
-            tracing::info!("Created link: {}", &link);
+    RecordAction::Skip
+}
 
-            let email_message = format_email(confirmation_request.expires_at, link);
-            let result = send_email(confirmation_request.email.clone(), email_message).await?;
-            result
+async fn send_email_of_kind(confirmation_request: &EmailConfirmationRequest, email_kind: EmailKind) -> Result<(), Error> {
+    // The service API is always called with the Confirm signature, the reminder link carries its own
+    let signature = create_signature(confirmation_request, SignaturePurpose::Confirm).await?;
+    // Lets the landing page offer "This wasn't me", the decline signature cannot confirm
+    let decline_signature = create_signature(confirmation_request, SignaturePurpose::Decline).await?;
+    let link_click_handler_service_url = env::var("EMAIL_LINK_CLICK_HANDLER_SERVICE_URL")?;
+    let link = match email_kind {
+        EmailKind::Confirmation => format!("{}/confirm?principal={}&signature={}&decline_signature={}", link_click_handler_service_url, encode(&confirmation_request.pk), signature, decline_signature),
+        EmailKind::Reminder => {
+            let reminder_signature = create_signature(confirmation_request, SignaturePurpose::Reminder).await?;
+            format!("{}/confirm?principal={}&signature={}&purpose={}&decline_signature={}", link_click_handler_service_url, encode(&confirmation_request.pk), reminder_signature, SignaturePurpose::Reminder, decline_signature)
         }
+    };
+    tracing::info!("Created link: {}", &link);
+
+    // The address may have bounced or complained after the request was created
+    if let Some(suppression) = active_suppression(&confirmation_request.email).await? {
+        tracing::info!("Not sending to suppressed address of {}: {}", &confirmation_request.pk, &suppression.reason);
+        let reason = format!("Email address is suppressed: {}", suppression.reason);
+        return set_status(confirmation_request, Suppressed, Some(reason), signature).await
     }
-    Ok(())
+
+    let idempotency_store = send_idempotency_store().await?;
+    let send_kind = match email_kind {
+        EmailKind::Confirmation => SendKind::Confirmation,
+        EmailKind::Reminder => SendKind::Reminder,
+    };
+    let attempt = SendAttempt::new(confirmation_request, send_kind);
+    if let Some(message_id) = sent_message_id_of(idempotency_store.claim_or_resume(&attempt).await?, &attempt)? {
+        tracing::info!("Email {} already sent, storing its message id.", attempt.idempotency_key());
+        return set_message_id(confirmation_request, &message_id, email_kind, signature).await
+    }
+
+    let (subject, email_message) = match email_kind {
+        EmailKind::Confirmation => ("Please, confirm your email.", format_email(confirmation_request.expires_at, link)),
+        EmailKind::Reminder => ("Reminder: please, confirm your email.", format_reminder_email(confirmation_request.expires_at, link)),
+    };
+    let message_id = match send_email(confirmation_request.email.clone(), subject, email_message).await {
+        Ok(message_id) => message_id,
+        Err(error) => {
+            idempotency_store.release(&attempt).await?;
+            return Err(error)
+        }
+    };
+
+    idempotency_store.mark_sent(&attempt, &message_id).await?;
+    set_message_id(confirmation_request, &message_id, email_kind, signature).await
 }
 
-async fn create_signature(email_confirmation_request: &EmailConfirmationRequest) -> Result<String, Error> {
+/// None if the email is to be sent now, else the message id of the email sent for the attempt before.
+/// A claim in progress fails the record so that it is retried: the invocation that claimed it may have died before SES accepted the email,
+/// and once the lease of its claim ran out the retry claims the attempt itself.
+fn sent_message_id_of(claim: Claim, attempt: &SendAttempt) -> Result<Option<String>, Error> {
+    match claim {
+        Claim::Claimed => Ok(None),
+        Claim::AlreadySent(message_id) => Ok(Some(message_id)),
+        Claim::InProgress => Err(Error::from(format!("Email {} is being sent by another invocation, retrying the record", attempt.idempotency_key()))),
+    }
+}
+
+async fn send_idempotency_store() -> Result<SendIdempotencyStore, Error> {
+    let config = aws_config::load_from_env().await;
+    let db_client = aws_sdk_dynamodb::Client::new(&config);
+    let table_name = env::var("EMAIL_SEND_IDEMPOTENCY_DYNAMO_TABLE_NAME")?;
+    Ok(SendIdempotencyStore::new(db_client, &table_name))
+}
+
+async fn create_signature(email_confirmation_request: &EmailConfirmationRequest, purpose: SignaturePurpose) -> Result<String, Error> {
     let config = aws_config::load_from_env().await;
     let client = Client::new(&config);
-    let payload = json!(SignatureRequest::signature_creation_request(email_confirmation_request.clone()));
+    let payload = json!(SignatureRequest::signature_creation_request_for(email_confirmation_request.clone(), purpose));
     let function_name = env::var("SIGNATURE_SERVICE_LAMBDA_FUNCTION_NAME");
 
     if let Ok(function_name) = function_name {
@@ -88,7 +180,48 @@ async fn create_signature(email_confirmation_request: &EmailConfirmationRequest)
     Err(Error::from("Error creating signature"))
 }
 
-async fn set_status_to_pending(confirmation_request: &EmailConfirmationRequest, signature: String) -> Result<(), Error> {
+async fn set_message_id(confirmation_request: &EmailConfirmationRequest, message_id: &str, email_kind: EmailKind, signature: String) -> Result<(), Error> {
+    let service_url = env::var("EMAIL_CONFIRMATION_REQUEST_SERVICE_URL")?;
+    let api_key = env::var("EMAIL_CONFIRMATION_REQUEST_SERVICE_INTERNAL_API_KEY")?;
+
+    let put_url = format!("{}/email-confirmation-requests/{}/message-id", service_url, encode(&confirmation_request.pk));
+    let reqwest_client = reqwest::Client::new();
+    let response = reqwest_client
+        .put(put_url)
+        .header("x-api-key", api_key)
+        .header("Content-Type", "application/json")
+        .json(&json!({"message_id": message_id, "email_kind": email_kind, "signature": signature}))
+        .send()
+        .await?;
+
+    let json_data : EmailConfirmationServiceApiResponse = response.json().await?;
+    if json_data.error {
+        Err(Error::from("Email confirmation service error"))
+    } else {
+        Ok(())
+    }
+}
+
+async fn active_suppression(email: &str) -> Result<Option<SuppressionEntry>, Error> {
+    let service_url = env::var("EMAIL_CONFIRMATION_REQUEST_SERVICE_URL")?;
+    let api_key = env::var("EMAIL_CONFIRMATION_REQUEST_SERVICE_INTERNAL_API_KEY")?;
+
+    let get_url = format!("{}/suppressions/{}", service_url, encode(email));
+    let reqwest_client = reqwest::Client::new();
+    let response = reqwest_client
+        .get(get_url)
+        .header("x-api-key", api_key)
+        .send()
+        .await?;
+
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None)
+    }
+    let json_data : SuppressionApiResponse = response.error_for_status()?.json().await?;
+    Ok(Some(json_data.suppression))
+}
+
+async fn set_status(confirmation_request: &EmailConfirmationRequest, status: Status, reason: Option<String>, signature: String) -> Result<(), Error> {
     let service_url = env::var("EMAIL_CONFIRMATION_REQUEST_SERVICE_URL")?;
     let api_key = env::var("EMAIL_CONFIRMATION_REQUEST_SERVICE_INTERNAL_API_KEY")?;
 
@@ -98,11 +231,16 @@ async fn set_status_to_pending(confirmation_request: &EmailConfirmationRequest,
         .put(put_url)
         .header("x-api-key", api_key)
         .header("Content-Type", "application/json")
-        .json(&json!({"status": "Pending", "signature": signature}))
+        .json(&json!({"status": status, "signature": signature, "reason": reason}))
         .send()
         .await
         .unwrap();
 
+    // The request has moved on, e.g. the INSERT is redelivered after it was set Pending, or it was cancelled
+    if response.status() == reqwest::StatusCode::CONFLICT {
+        tracing::info!("Request {} cannot be set {} any more, skipping.", &confirmation_request.pk, status);
+        return Ok(())
+    }
     let json_data : EmailConfirmationServiceApiResponse = response.json().await?;
     if json_data.error {
         Err(Error::from("Email confirmation service error"))
@@ -113,10 +251,15 @@ async fn set_status_to_pending(confirmation_request: &EmailConfirmationRequest,
 
 fn format_email(expires_at: u64, link: String) -> String {
     let datetime: DateTime<Utc> = DateTime::<Utc>::from_timestamp(expires_at as i64, 0).unwrap();
-    format!("Hi! to confirm your email address, click the link below. The link will expire on {}. \n\n {}. ", datetime, link)
+    format!("Hi! to confirm your email address, click the link below. The link will expire on {}. If you did not ask for this email, open the link and choose \"This wasn't me\". \n\n {}. ", datetime, link)
+}
+
+fn format_reminder_email(expires_at: u64, link: String) -> String {
+    let datetime: DateTime<Utc> = DateTime::<Utc>::from_timestamp(expires_at as i64, 0).unwrap();
+    format!("Hi! we have not seen your confirmation yet. To confirm your email address, click the link below before it expires on {}. \n\n {}. ", datetime, link)
 }
 
-async fn send_email(email_address: String, email_message: String) -> Result<(), Error> {
+async fn send_email(email_address: String, subject: &str, email_message: String) -> Result<String, Error> {
     tracing::info!("Sending email");
     
     let region_provider = RegionProviderChain::default_provider();
@@ -127,7 +270,7 @@ async fn send_email(email_address: String, email_message: String) -> Result<(),
         .to_addresses(email_address)
         .build();
 
-    let subject = Content::builder().data("Please, confirm your email.").build()?;
+    let subject = Content::builder().data(subject).build()?;
     let body = Body::builder()
         .text(Content::builder().data(email_message).build()?)
         .build();
@@ -148,7 +291,7 @@ async fn send_email(email_address: String, email_message: String) -> Result<(),
         .send()
         .await?;
 
-    Ok(())
+    Ok(response.message_id().to_string())
 }
 
 #[cfg(test)]
@@ -160,8 +303,10 @@ mod tests {
     use super::*;
     use lambda_runtime::{Context, LambdaEvent};
     use chrono::{DateTime, TimeZone};
+    use serde_dynamo::{Item, AttributeValue::S, AttributeValue::N};
 
     #[tokio::test]
+    #[allow(clippy::let_unit_value)]
     async fn test_event_handler() {
         let event = LambdaEvent::new(example_dynamodb_event(), Context::default());
         let response = function_handler(event).await.unwrap();
@@ -169,12 +314,88 @@ mod tests {
     }
 
     #[tokio::test]
+    #[ignore = "calls the deployed signature lambda and REST API"]
+    #[allow(clippy::let_unit_value)]
     async fn test_another_event_handler() {
         let event = LambdaEvent::new(test_event(), Context::default());
         let response = function_handler(event).await.unwrap();
         assert_eq!((), response);
     }
 
+    #[test]
+    fn test_queued_insert_sets_pending() {
+        let event = test_event();
+        let action = record_action(&event.records[0]);
+        let RecordAction::SetPending(confirmation_request) = action else { panic!("Expected SetPending, got {:?}", action) };
+        assert_eq!("email@example.com#me_myself_and_i-3#req-3", confirmation_request.pk);
+    }
+
+    #[test]
+    fn test_pending_modification_sends_email() {
+        let record = modify_record("Pending", None);
+        let action = record_action(&record);
+        let RecordAction::SendConfirmationEmail(confirmation_request) = action else { panic!("Expected SendConfirmationEmail, got {:?}", action) };
+        assert_eq!(None, confirmation_request.ses_message_id);
+    }
+
+    #[test]
+    fn test_pending_modification_with_message_id_is_skipped() {
+        let record = modify_record("Pending", Some("0100018e2f1c8a2b-message-id"));
+        assert_eq!(RecordAction::Skip, record_action(&record));
+    }
+
+    #[test]
+    fn test_other_modifications_are_skipped() {
+        assert_eq!(RecordAction::Skip, record_action(&modify_record("Queued", None)));
+        assert_eq!(RecordAction::Skip, record_action(&modify_record("Confirmed", None)));
+    }
+
+    #[test]
+    fn test_retried_record_maps_to_same_send_attempt() {
+        let record = modify_record("Pending", None);
+        let RecordAction::SendConfirmationEmail(first) = record_action(&record) else { panic!() };
+        let RecordAction::SendConfirmationEmail(retried) = record_action(&record.clone()) else { panic!() };
+
+        let first_attempt = SendAttempt::new(&first, SendKind::Confirmation);
+        let retried_attempt = SendAttempt::new(&retried, SendKind::Confirmation);
+        assert_eq!(first_attempt.idempotency_key(), retried_attempt.idempotency_key());
+        assert_eq!("email@example.com#me_myself_and_i-3#req-3#confirmation#1741592476", first_attempt.idempotency_key());
+    }
+
+    #[test]
+    fn test_requested_reminder_is_sent() {
+        let mut record = modify_record("Pending", Some("0100018e2f1c8a2b-message-id"));
+        insert_attribute(&mut record, "reminder_requested_at", N("1741594276".to_string()));
+        let action = record_action(&record);
+        let RecordAction::SendReminderEmail(confirmation_request) = action else { panic!("Expected SendReminderEmail, got {:?}", action) };
+
+        let attempt = SendAttempt::new(&confirmation_request, SendKind::Reminder);
+        assert_eq!("email@example.com#me_myself_and_i-3#req-3#reminder#1741592476", attempt.idempotency_key());
+    }
+
+    #[test]
+    fn test_sent_or_unrequested_reminder_is_skipped() {
+        let mut sent = modify_record("Pending", Some("0100018e2f1c8a2b-message-id"));
+        insert_attribute(&mut sent, "reminder_requested_at", N("1741594276".to_string()));
+        insert_attribute(&mut sent, "reminder_message_id", S("0100018e2f1c8a2b-reminder-id".to_string()));
+        assert_eq!(RecordAction::Skip, record_action(&sent));
+
+        let mut confirmed = modify_record("Confirmed", Some("0100018e2f1c8a2b-message-id"));
+        insert_attribute(&mut confirmed, "reminder_requested_at", N("1741594276".to_string()));
+        assert_eq!(RecordAction::Skip, record_action(&confirmed));
+    }
+
+    #[test]
+    fn test_claim_in_progress_is_retried() {
+        let RecordAction::SendConfirmationEmail(confirmation_request) = record_action(&modify_record("Pending", None)) else { panic!() };
+        let attempt = SendAttempt::new(&confirmation_request, SendKind::Confirmation);
+        assert_eq!(None, sent_message_id_of(Claim::Claimed, &attempt).unwrap());
+        assert_eq!(Some("0100018e2f1c8a2b-message-id".to_string()),
+                   sent_message_id_of(Claim::AlreadySent("0100018e2f1c8a2b-message-id".to_string()), &attempt).unwrap());
+        // Acking the record would leave the email unsent if the claiming invocation died
+        assert!(sent_message_id_of(Claim::InProgress, &attempt).is_err());
+    }
+
     #[tokio::test]
     async fn test_another_event_to_json() {
         println!("{}", json!(test_event()));
@@ -200,6 +421,24 @@ mod tests {
         serde_json::from_slice(data).unwrap()
     }
 
+    fn modify_record(status: &str, ses_message_id: Option<&str>) -> EventRecord {
+        let mut record = test_event().records.pop().unwrap();
+        record.event_name = "MODIFY".to_string();
+        let mut new_image: HashMap<String, serde_dynamo::AttributeValue> = record.change.new_image.clone().into_inner();
+        new_image.insert("status".to_string(), S(status.to_string()));
+        if let Some(message_id) = ses_message_id {
+            new_image.insert("ses_message_id".to_string(), S(message_id.to_string()));
+        }
+        record.change.new_image = Item::from(new_image);
+        record
+    }
+
+    fn insert_attribute(record: &mut EventRecord, name: &str, value: serde_dynamo::AttributeValue) {
+        let mut new_image: HashMap<String, serde_dynamo::AttributeValue> = record.change.new_image.clone().into_inner();
+        new_image.insert(name.to_string(), value);
+        record.change.new_image = Item::from(new_image);
+    }
+
     fn test_event() -> Event {
         Event {
             records: [
@@ -1,5 +1,6 @@
 use lambda_runtime::{run, service_fn, tracing, Error};
 mod event_handler;
+mod send_idempotency;
 use event_handler::function_handler;
 
 #[tokio::main]
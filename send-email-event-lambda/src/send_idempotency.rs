@@ -0,0 +1,233 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::operation::put_item::PutItemError;
+use aws_sdk_dynamodb::types::AttributeValue;
+use lambda_runtime::Error;
+
+use email_confirmation_service_common::email_confirmation_request::EmailConfirmationRequest;
+
+// A claim that is still InProgress after this period is considered abandoned (e.g. lambda timeout before SES was called)
+pub const SEND_CLAIM_LEASE_SECONDS: u64 = 5 * 60;
+// Idempotency records are removed by DynamoDB TTL after a day, stream retries are over long before that
+pub const SEND_RECORD_TIME_TO_LIVE_SECONDS: u64 = 24 * 60 * 60;
+
+const STATE_IN_PROGRESS: &str = "InProgress";
+const STATE_SENT: &str = "Sent";
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SendKind {
+    Confirmation,
+    Reminder,
+}
+
+impl SendKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SendKind::Confirmation => "confirmation",
+            SendKind::Reminder => "reminder",
+        }
+    }
+}
+
+/// One send attempt of one email for one confirmation request.
+/// Retried stream records carry the same image, so they map to the same attempt.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SendAttempt {
+    pub pk: String,
+    pub kind: SendKind,
+    pub updated_at: u64,
+}
+
+impl SendAttempt {
+    pub fn new(confirmation_request: &EmailConfirmationRequest, kind: SendKind) -> Self {
+        SendAttempt {
+            pk: confirmation_request.pk.clone(),
+            kind,
+            updated_at: confirmation_request.updated_at,
+        }
+    }
+
+    pub fn idempotency_key(&self) -> String {
+        format!("{}#{}#{}", self.pk, self.kind.as_str(), self.updated_at)
+    }
+}
+
+/// What claiming an attempt found out.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Claim {
+    Claimed,
+    // An earlier invocation sent it, it may have failed before it stored the message id
+    AlreadySent(String),
+    // Another invocation is sending it, or died while sending it and its lease has not run out yet
+    InProgress,
+}
+
+#[derive(Clone, Debug)]
+pub struct SendIdempotencyStore {
+    db_client: Client,
+    table_name: String,
+}
+
+impl SendIdempotencyStore {
+    pub fn new(db_client: Client, table_name: &str) -> Self {
+        Self {
+            db_client,
+            table_name: table_name.to_owned(),
+        }
+    }
+
+    /// Returns false if another invocation has already sent, or is currently sending, this attempt.
+    pub async fn claim(&self, attempt: &SendAttempt) -> Result<bool, Error> {
+        let now = now_as_secs();
+        let result = self.db_client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("idempotency_key", AttributeValue::S(attempt.idempotency_key()))
+            .item("pk", AttributeValue::S(attempt.pk.clone()))
+            .item("state", AttributeValue::S(STATE_IN_PROGRESS.to_string()))
+            .item("claimed_at", AttributeValue::N(now.to_string()))
+            .item("expires_at", AttributeValue::N((now + SEND_RECORD_TIME_TO_LIVE_SECONDS).to_string()))
+            .condition_expression("attribute_not_exists(#key) OR (#state = :in_progress AND #claimed_at < :lease_cutoff)")
+            .expression_attribute_names("#key", "idempotency_key")
+            .expression_attribute_names("#state", "state")
+            .expression_attribute_names("#claimed_at", "claimed_at")
+            .expression_attribute_values(":in_progress", AttributeValue::S(STATE_IN_PROGRESS.to_string()))
+            .expression_attribute_values(":lease_cutoff", AttributeValue::N(now.saturating_sub(SEND_CLAIM_LEASE_SECONDS).to_string()))
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(error) => match error.into_service_error() {
+                PutItemError::ConditionalCheckFailedException(_) => Ok(false),
+                other => Err(Error::from(other)),
+            }
+        }
+    }
+
+    /// Claims the attempt, or finds the message id of the email already sent for it, e.g. when a stream record is retried.
+    /// A claim whose lease ran out is taken over.
+    pub async fn claim_or_resume(&self, attempt: &SendAttempt) -> Result<Claim, Error> {
+        if self.claim(attempt).await? {
+            return Ok(Claim::Claimed)
+        }
+        Ok(match self.sent_message_id(attempt).await? {
+            Some(message_id) => Claim::AlreadySent(message_id),
+            None => Claim::InProgress,
+        })
+    }
+
+    pub async fn mark_sent(&self, attempt: &SendAttempt, message_id: &str) -> Result<(), Error> {
+        self.db_client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("idempotency_key", AttributeValue::S(attempt.idempotency_key()))
+            .update_expression("set #state = :sent, #message_id = :message_id")
+            .expression_attribute_names("#state", "state")
+            .expression_attribute_names("#message_id", "message_id")
+            .expression_attribute_values(":sent", AttributeValue::S(STATE_SENT.to_string()))
+            .expression_attribute_values(":message_id", AttributeValue::S(message_id.to_string()))
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    pub async fn sent_message_id(&self, attempt: &SendAttempt) -> Result<Option<String>, Error> {
+        let result = self.db_client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("idempotency_key", AttributeValue::S(attempt.idempotency_key()))
+            .consistent_read(true)
+            .send()
+            .await?;
+
+        let message_id = result.item
+            .filter(|item| item.get("state") == Some(&AttributeValue::S(STATE_SENT.to_string())))
+            .and_then(|item| item.get("message_id").and_then(|value| value.as_s().ok()).cloned());
+        Ok(message_id)
+    }
+
+    /// Gives the attempt back so that the next stream retry can send it.
+    pub async fn release(&self, attempt: &SendAttempt) -> Result<(), Error> {
+        self.db_client
+            .delete_item()
+            .table_name(&self.table_name)
+            .key("idempotency_key", AttributeValue::S(attempt.idempotency_key()))
+            .condition_expression("#state = :in_progress")
+            .expression_attribute_names("#state", "state")
+            .expression_attribute_values(":in_progress", AttributeValue::S(STATE_IN_PROGRESS.to_string()))
+            .send()
+            .await?;
+        Ok(())
+    }
+}
+
+fn now_as_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use email_confirmation_service_common::test_util::{dynamodb_stub, StubRequests, CONDITIONAL_CHECK_FAILED};
+    use super::*;
+
+    async fn store_stub(responses: &'static [(&'static str, u16, &'static str)]) -> (SendIdempotencyStore, StubRequests) {
+        let (db_client, requests) = dynamodb_stub(responses).await;
+        (SendIdempotencyStore::new(db_client, "send-idempotency"), requests)
+    }
+
+    fn attempt() -> SendAttempt {
+        let mut confirmation_request = EmailConfirmationRequest::new("email@example.com".to_string(), "me_myself_and_i-3".to_string(), "req-3".to_string(), "https://client.example.com/callback".to_string());
+        confirmation_request.updated_at = 1741592476;
+        SendAttempt::new(&confirmation_request, SendKind::Confirmation)
+    }
+
+    #[tokio::test]
+    async fn test_first_delivery_is_claimed() {
+        let (store, requests) = store_stub(&[("PutItem", 200, "{}")]).await;
+        assert_eq!(Claim::Claimed, store.claim_or_resume(&attempt()).await.unwrap());
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(1, requests.len());
+        assert!(requests[0].contains(r#""idempotency_key":{"S":"email@example.com#me_myself_and_i-3#req-3#confirmation#1741592476"}"#), "{}", requests[0]);
+        assert!(requests[0].contains("attribute_not_exists(#key) OR (#state = :in_progress AND #claimed_at < :lease_cutoff)"), "{}", requests[0]);
+    }
+
+    #[tokio::test]
+    async fn test_retried_record_resumes_with_sent_message_id() {
+        let (store, requests) = store_stub(&[
+            ("PutItem", 400, CONDITIONAL_CHECK_FAILED),
+            ("GetItem", 200, r#"{"Item":{"idempotency_key":{"S":"email@example.com#me_myself_and_i-3#req-3#confirmation#1741592476"},"state":{"S":"Sent"},"message_id":{"S":"0100018e2f1c8a2b-message-id"}}}"#),
+        ]).await;
+        assert_eq!(Claim::AlreadySent("0100018e2f1c8a2b-message-id".to_string()), store.claim_or_resume(&attempt()).await.unwrap());
+
+        // The email is not sent again, the message id is only read back
+        let requests = requests.lock().unwrap();
+        assert_eq!(2, requests.len());
+        assert!(requests[1].contains("DynamoDB_20120810.GetItem"));
+        assert!(requests[1].contains(r#""ConsistentRead":true"#), "{}", requests[1]);
+    }
+
+    #[tokio::test]
+    async fn test_retried_record_while_sending_is_in_progress() {
+        let (store, _) = store_stub(&[
+            ("PutItem", 400, CONDITIONAL_CHECK_FAILED),
+            ("GetItem", 200, r#"{"Item":{"idempotency_key":{"S":"email@example.com#me_myself_and_i-3#req-3#confirmation#1741592476"},"state":{"S":"InProgress"}}}"#),
+        ]).await;
+        assert_eq!(Claim::InProgress, store.claim_or_resume(&attempt()).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_claim_of_crashed_invocation_is_taken_over_after_its_lease() {
+        // DynamoDB accepts the put once the InProgress claim is older than the lease
+        let (store, requests) = store_stub(&[("PutItem", 200, "{}")]).await;
+        let before = now_as_secs();
+        assert_eq!(Claim::Claimed, store.claim_or_resume(&attempt()).await.unwrap());
+        let after = now_as_secs();
+
+        let requests = requests.lock().unwrap();
+        let lease_cutoffs = (before..=after).map(|now| format!(r#"":lease_cutoff":{{"N":"{}"}}"#, now - SEND_CLAIM_LEASE_SECONDS)).collect::<Vec<String>>();
+        assert!(lease_cutoffs.iter().any(|lease_cutoff| requests[0].contains(lease_cutoff)), "{}", requests[0]);
+        assert!(requests[0].contains(r#"":in_progress":{"S":"InProgress"}"#), "{}", requests[0]);
+    }
+}
@@ -10,6 +10,7 @@
 # EmailConfirmationLambdaFunction
 export EMAIL_CONFIRMATION_REQUEST_SERVICE_DYNAMO_TABLE_NAME=
 export SIGNATURE_SERVICE_LAMBDA_FUNCTION_NAME=
+export ADMIN_API_KEY_IDS=
 
 # HandleEmailLinkClickLambdaFunction
 export EMAIL_CONFIRMATION_REQUEST_SERVICE_URL=
@@ -30,6 +31,20 @@ export EMAIL_SENDING_LAMBDA_ARN=
 # TriggerCallbackEventLambdaFunction
 export EMAIL_CONFIRMATION_DYNAMODB_STREAM_ARN=
 export SIGNATURE_SERVICE_LAMBDA_FUNCTION_NAME=
+export CLIENT_CONFIG_DYNAMO_TABLE_NAME=
+export CALLBACK_DELIVERY_DYNAMO_TABLE_NAME=
+export CALLBACK_HOST_DYNAMO_TABLE_NAME=
+export CALLBACK_AUTH_KMS_KEY_ARN=
+
+# HandleSesNotificationEventLambdaFunction
+export SES_NOTIFICATION_SNS_TOPIC_ARN=
+export EMAIL_CONFIRMATION_REQUEST_SERVICE_DYNAMO_TABLE_NAME=
+export SIGNATURE_SERVICE_LAMBDA_FUNCTION_NAME=
+export EMAIL_CONFIRMATION_REQUEST_SERVICE_URL=
+export EMAIL_CONFIRMATION_REQUEST_SERVICE_INTERNAL_API_KEY=
+
+# RequestSweeperScheduledLambdaFunction
+export EMAIL_CONFIRMATION_REQUEST_SERVICE_DYNAMO_TABLE_NAME=
 
 # print setup
 echo environment variables set:
@@ -42,3 +57,9 @@ echo EMAIL_LINK_CLICK_HANDLER_SERVICE_URL = $EMAIL_LINK_CLICK_HANDLER_SERVICE_UR
 echo EMAIL_CONFIRMATION_LAMBDA_ARN = $EMAIL_CONFIRMATION_LAMBDA_ARN
 echo EMAIL_SENDING_LAMBDA_ARN = $EMAIL_SENDING_LAMBDA_ARN
 echo EMAIL_SENDER_ADDRESS = $EMAIL_SENDER_ADDRESS
+echo SES_NOTIFICATION_SNS_TOPIC_ARN = $SES_NOTIFICATION_SNS_TOPIC_ARN
+echo CLIENT_CONFIG_DYNAMO_TABLE_NAME = $CLIENT_CONFIG_DYNAMO_TABLE_NAME
+echo CALLBACK_DELIVERY_DYNAMO_TABLE_NAME = $CALLBACK_DELIVERY_DYNAMO_TABLE_NAME
+echo CALLBACK_HOST_DYNAMO_TABLE_NAME = $CALLBACK_HOST_DYNAMO_TABLE_NAME
+echo CALLBACK_AUTH_KMS_KEY_ARN = $CALLBACK_AUTH_KMS_KEY_ARN
+echo ADMIN_API_KEY_IDS = $ADMIN_API_KEY_IDS
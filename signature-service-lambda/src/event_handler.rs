@@ -4,8 +4,6 @@ use email_confirmation_service_common::signature_request::*;
 use email_confirmation_service_common::signature_request::SignatureResponse::VerificationResult;
 use email_confirmation_service_common::signature_request::SignatureVerificationResult::{Success, Fail};
 use sha2::{Sha256, Digest};
-use hex;
-use serde_json::{json};
 
 
 pub(crate)async fn function_handler(event: LambdaEvent<SignatureRequest>) -> Result<SignatureResponse, Error> {
@@ -32,13 +30,15 @@ fn create_signature(signature_creation_data: SignatureCreationData) -> String {
     let signature_key = signature_creation_data.signature_key.clone();
     let updated_at = signature_creation_data.updated_at;
 
-    let data = format!("|{}|{}|{}|{}|{}|", email, client_id, request_id, signature_key, updated_at);
+    // Confirm signatures keep the original format, so links sent before purposes existed stay valid
+    let data = match signature_creation_data.purpose {
+        SignaturePurpose::Confirm => format!("|{}|{}|{}|{}|{}|", email, client_id, request_id, signature_key, updated_at),
+        purpose => format!("|{}|{}|{}|{}|{}|{}|", email, client_id, request_id, signature_key, updated_at, purpose),
+    };
     let mut hasher = Sha256::new();
     hasher.update(data);
     let result = hasher.finalize();
-    let signature = hex::encode(result);
-
-    signature
+    hex::encode(result)
 }
 
 fn verify_signature(signature_verification_data: SignatureVerificationData) -> SignatureVerificationResult {
@@ -57,6 +57,7 @@ mod tests {
     use super::*;
     use uuid::Uuid;
     use lambda_runtime::{Context};
+    use serde_json::json;
     use email_confirmation_service_common::signature_request::SignatureResponse::{Signature, VerificationResult};
 
     #[tokio::test]
@@ -75,13 +76,14 @@ mod tests {
                     client_id: test_client_id.clone(),
                     request_id: test_request_id.clone(),
                     updated_at: test_updated,
-                    signature_key: test_sign_key.clone()
+                    signature_key: test_sign_key.clone(),
+                    purpose: SignaturePurpose::Confirm,
                 }
             )
         };
 
         let data = creation_request.clone();
-        println!("{}", format!("{:?}", json!(data).to_string()));
+        println!("{:?}", json!(data).to_string());
 
         let event = LambdaEvent {
             payload: creation_request,
@@ -105,7 +107,8 @@ mod tests {
                     request_id: test_request_id.clone(),
                     updated_at: test_updated,
                     signature_key: test_sign_key.clone(),
-                    signature_value: signature.to_string()
+                    signature_value: signature.to_string(),
+                    purpose: SignaturePurpose::Confirm,
                 }
             )
         };
@@ -129,6 +132,7 @@ mod tests {
                     updated_at: test_updated,
                     signature_key: test_sign_key.clone(),
                     signature_value: Uuid::new_v4().to_string(),
+                    purpose: SignaturePurpose::Confirm,
                 }
             )
         };
@@ -141,4 +145,38 @@ mod tests {
         let VerificationResult(verification_result_2) = verification_response_2 else { todo!()};
         assert_eq!(Fail, verification_result_2);
     }
+
+    #[test]
+    fn test_purpose_scoped_signatures() {
+        let confirm_data = SignatureCreationData {
+            email: "test@example.com".to_string(),
+            client_id: "client-1".to_string(),
+            request_id: "request-1".to_string(),
+            updated_at: 1741592476,
+            signature_key: "75af2381-ecde-4113-af22-75c2c1407d98".to_string(),
+            purpose: SignaturePurpose::Confirm,
+        };
+        let reminder_data = SignatureCreationData { purpose: SignaturePurpose::Reminder, ..confirm_data.clone() };
+
+        let confirm_signature = create_signature(confirm_data.clone());
+        let reminder_signature = create_signature(reminder_data.clone());
+        assert_ne!(confirm_signature, reminder_signature);
+
+        // Unchanged format for Confirm
+        let legacy = hex::encode(Sha256::digest("|test@example.com|client-1|request-1|75af2381-ecde-4113-af22-75c2c1407d98|1741592476|"));
+        assert_eq!(legacy, confirm_signature);
+
+        let verify = |signature: &str, purpose: SignaturePurpose| verify_signature(SignatureVerificationData {
+            email: confirm_data.email.clone(),
+            client_id: confirm_data.client_id.clone(),
+            request_id: confirm_data.request_id.clone(),
+            updated_at: confirm_data.updated_at,
+            signature_key: confirm_data.signature_key.clone(),
+            signature_value: signature.to_string(),
+            purpose,
+        });
+        assert_eq!(Success, verify(&reminder_signature, SignaturePurpose::Reminder));
+        assert_eq!(Fail, verify(&reminder_signature, SignaturePurpose::Confirm));
+        assert_eq!(Fail, verify(&confirm_signature, SignaturePurpose::Reminder));
+    }
 }
@@ -6,6 +6,10 @@ const emailConfirmationDynamoDbStreamArnFromEnv = process.env.EMAIL_CONFIRMATION
 const signatureServiceLambdaFunctionNameFromEnv = process.env.SIGNATURE_SERVICE_LAMBDA_FUNCTION_NAME || "default-value";
 const emailConfirmationRequestServiceUrlFromEnv = process.env.EMAIL_CONFIRMATION_REQUEST_SERVICE_URL || "default-value";
 const emailConfirmationRequestInternalApiKeyFromEnv = process.env.EMAIL_CONFIRMATION_REQUEST_SERVICE_INTERNAL_API_KEY || "default-value";
+const clientConfigDynamoTableNameFromEnv = process.env.CLIENT_CONFIG_DYNAMO_TABLE_NAME || "default-value";
+const callbackDeliveryDynamoTableNameFromEnv = process.env.CALLBACK_DELIVERY_DYNAMO_TABLE_NAME || "default-value";
+const callbackHostDynamoTableNameFromEnv = process.env.CALLBACK_HOST_DYNAMO_TABLE_NAME || "default-value";
+const callbackAuthKmsKeyArnFromEnv = process.env.CALLBACK_AUTH_KMS_KEY_ARN || "default-value";
 
 
 const app = new cdk.App();
@@ -14,7 +18,11 @@ new CdkStack(app, 'EcsTcelStack', {
     emailConfirmationDynamoDbStreamArn: emailConfirmationDynamoDbStreamArnFromEnv,
     signatureServiceLambdaFunctionName: signatureServiceLambdaFunctionNameFromEnv,
     emailConfirmationRequestServiceUrl: emailConfirmationRequestServiceUrlFromEnv,
-    emailConfirmationRequestInternalApiKey: emailConfirmationRequestInternalApiKeyFromEnv
+    emailConfirmationRequestInternalApiKey: emailConfirmationRequestInternalApiKeyFromEnv,
+    clientConfigDynamoTableName: clientConfigDynamoTableNameFromEnv,
+    callbackDeliveryDynamoTableName: callbackDeliveryDynamoTableNameFromEnv,
+    callbackHostDynamoTableName: callbackHostDynamoTableNameFromEnv,
+    callbackAuthKmsKeyArn: callbackAuthKmsKeyArnFromEnv
 
     /* If you don't specify 'env', this stack will be environment-agnostic.
      * Account/Region-dependent features and context lookups will not work,
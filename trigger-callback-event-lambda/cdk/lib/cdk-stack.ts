@@ -5,12 +5,18 @@ import { RustFunction } from 'cargo-lambda-cdk';
 import { Construct } from 'constructs';
 import { EventSourceMapping, StartingPosition } from "aws-cdk-lib/aws-lambda";
 import { PolicyStatement } from "aws-cdk-lib/aws-iam";
+import { Table } from "aws-cdk-lib/aws-dynamodb";
+import { Key } from "aws-cdk-lib/aws-kms";
 
 export interface TCELStackProps extends StackProps {
   emailConfirmationDynamoDbStreamArn: string;
   signatureServiceLambdaFunctionName: string;
   emailConfirmationRequestServiceUrl: string;
   emailConfirmationRequestInternalApiKey: string;
+  clientConfigDynamoTableName: string;
+  callbackDeliveryDynamoTableName: string;
+  callbackHostDynamoTableName: string;
+  callbackAuthKmsKeyArn: string;
 }
 
 export class CdkStack extends Stack {
@@ -23,7 +29,10 @@ export class CdkStack extends Stack {
         "EMAIL_CONFIRMATION_DYNAMODB_STREAM_ARN": props.emailConfirmationDynamoDbStreamArn,
         "SIGNATURE_SERVICE_LAMBDA_FUNCTION_NAME": props.signatureServiceLambdaFunctionName,
         "EMAIL_CONFIRMATION_REQUEST_SERVICE_URL": props.emailConfirmationRequestServiceUrl,
-        "EMAIL_CONFIRMATION_REQUEST_SERVICE_INTERNAL_API_KEY": props.emailConfirmationRequestInternalApiKey
+        "EMAIL_CONFIRMATION_REQUEST_SERVICE_INTERNAL_API_KEY": props.emailConfirmationRequestInternalApiKey,
+        "CLIENT_CONFIG_DYNAMO_TABLE_NAME": props.clientConfigDynamoTableName,
+        "CALLBACK_DELIVERY_DYNAMO_TABLE_NAME": props.callbackDeliveryDynamoTableName,
+        "CALLBACK_HOST_DYNAMO_TABLE_NAME": props.callbackHostDynamoTableName
       }
     });
 
@@ -42,6 +51,29 @@ export class CdkStack extends Stack {
 
     targetLambda.grantInvoke(lambdaHandler);
 
+    // Webhook secrets of the clients, for signing the callbacks
+    Table.fromTableName(this, 'ClientConfigTable', props.clientConfigDynamoTableName).grantReadData(lambdaHandler);
+    // Log of the callback attempts, also read when replaying the latest one
+    Table.fromTableName(this, 'CallbackDeliveryTable', props.callbackDeliveryDynamoTableName).grantReadWriteData(lambdaHandler);
+    // Circuit breaker state of the callback hosts
+    Table.fromTableName(this, 'CallbackHostTable', props.callbackHostDynamoTableName).grantReadWriteData(lambdaHandler);
+    // Custom header values and OAuth2 client secrets of the clients, encrypted by the API
+    Key.fromKeyArn(this, 'CallbackAuthKey', props.callbackAuthKmsKeyArn).grantDecrypt(lambdaHandler);
+
+    // Callbacks to sqs:// and eventbridge:// urls. Only queues and event buses named for it can be used
+    lambdaHandler.addToRolePolicy(
+        new PolicyStatement({
+          actions: ["sqs:SendMessage"],
+          resources: [`arn:aws:sqs:*:${this.account}:email-confirmation-callback-*`],
+        })
+    );
+    lambdaHandler.addToRolePolicy(
+        new PolicyStatement({
+          actions: ["events:PutEvents"],
+          resources: [`arn:aws:events:*:${this.account}:event-bus/email-confirmation-callback-*`],
+        })
+    );
+
     lambdaHandler.addToRolePolicy(
         new PolicyStatement({
           actions: [
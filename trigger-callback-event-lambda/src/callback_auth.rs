@@ -0,0 +1,404 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use aws_sdk_kms::primitives::Blob;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use reqwest::dns::Resolve;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT, AUTHORIZATION};
+use reqwest::redirect::Policy;
+use reqwest::Url;
+use serde::Deserialize;
+use tokio::sync::OnceCell;
+use urlencoding::encode;
+
+use email_confirmation_service_common::client_config::{CallbackHeader, ClientConfig, EncryptedSecret, OAuth2ClientCredentials, SECRET_ENCRYPTION_CONTEXT_KEY};
+
+use crate::callback_delivery::{find_forbidden_target, read_body_prefix, status_is_retryable, DeliveryOutcome, CALLBACK_CONNECT_TIMEOUT, CALLBACK_RESPONSE_BODY_LIMIT, CALLBACK_TOTAL_TIMEOUT};
+use crate::callback_guard::{CallbackGuard, GuardedResolver, SystemResolver};
+use crate::callback_transport::classify_aws_error;
+
+// A token is refreshed this long before it expires, so that it does not expire on the way to the callback endpoint
+pub(crate) const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(60);
+// Lifetime of a token whose response leaves out expires_in
+pub(crate) const DEFAULT_TOKEN_LIFETIME: Duration = Duration::from_secs(5 * 60);
+
+/// What the HTTP transport needs to authenticate the callbacks of one client. The secrets are still encrypted.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) struct CallbackCredentials {
+    pub client_id: String,
+    pub headers: Vec<CallbackHeader>,
+    pub oauth2: Option<OAuth2ClientCredentials>,
+}
+
+impl CallbackCredentials {
+    /// None if the client has neither custom headers nor OAuth2 configured.
+    pub fn of(client_config: &ClientConfig) -> Option<Self> {
+        if client_config.callback_headers.is_empty() && client_config.callback_oauth2.is_none() {
+            return None
+        }
+        Some(CallbackCredentials {
+            client_id: client_config.client_id.clone(),
+            headers: client_config.callback_headers.clone(),
+            oauth2: client_config.callback_oauth2.clone(),
+        })
+    }
+}
+
+pub(crate) type Decrypting<'a> = Pin<Box<dyn Future<Output = Result<String, DeliveryOutcome>> + Send + 'a>>;
+
+/// Decrypts the secrets the REST API encrypted. Failures are classified like delivery failures, the callback is not sent without its secrets.
+pub(crate) trait SecretDecrypter: Send + Sync {
+    fn decrypt<'a>(&'a self, client_id: &'a str, secret: &'a EncryptedSecret) -> Decrypting<'a>;
+}
+
+/// The KMS client is created on the first secret to decrypt.
+#[derive(Default)]
+pub(crate) struct KmsDecrypter {
+    client: OnceCell<aws_sdk_kms::Client>,
+}
+
+impl SecretDecrypter for KmsDecrypter {
+    fn decrypt<'a>(&'a self, client_id: &'a str, secret: &'a EncryptedSecret) -> Decrypting<'a> {
+        Box::pin(async move {
+            let ciphertext = STANDARD.decode(&secret.0)
+                .map_err(|error| DeliveryOutcome::Permanent(format!("Invalid encrypted callback secret: {}", error)))?;
+            let client = self.client.get_or_init(|| async { aws_sdk_kms::Client::new(&aws_config::load_from_env().await) }).await;
+            let output = client.decrypt()
+                .ciphertext_blob(Blob::new(ciphertext))
+                .encryption_context(SECRET_ENCRYPTION_CONTEXT_KEY, client_id)
+                .send()
+                .await
+                .map_err(|error| classify_aws_error("Decrypting a callback secret", &error))?;
+            let plaintext = output.plaintext
+                .ok_or_else(|| DeliveryOutcome::Permanent("Decrypting a callback secret returned nothing".to_string()))?;
+            String::from_utf8(plaintext.into_inner())
+                .map_err(|_| DeliveryOutcome::Permanent("Decrypted callback secret is not UTF-8".to_string()))
+        })
+    }
+}
+
+/// Decrypted secrets are kept for the lifetime of the lambda container. Shared by the HTTP transport and the webhook signatures.
+pub(crate) struct DecryptedSecrets {
+    decrypter: Arc<dyn SecretDecrypter>,
+    // Keyed by client too: a ciphertext copied to another client must go through KMS, which checks the encryption context
+    secrets: Mutex<HashMap<(String, EncryptedSecret), String>>,
+}
+
+impl DecryptedSecrets {
+    pub fn new(decrypter: Arc<dyn SecretDecrypter>) -> Self {
+        DecryptedSecrets { decrypter, secrets: Mutex::default() }
+    }
+
+    pub async fn get(&self, client_id: &str, secret: &EncryptedSecret) -> Result<String, DeliveryOutcome> {
+        let key = (client_id.to_string(), secret.clone());
+        let cached = self.secrets.lock().unwrap().get(&key).cloned();
+        if let Some(plaintext) = cached {
+            return Ok(plaintext)
+        }
+        let plaintext = self.decrypter.decrypt(client_id, secret).await?;
+        self.secrets.lock().unwrap().insert(key, plaintext.clone());
+        Ok(plaintext)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct AccessToken {
+    access_token: String,
+    expires_at: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    token_type: Option<String>,
+    expires_in: Option<u64>,
+}
+
+/// Adds the custom headers and the OAuth2 bearer token of a client to its HTTP callbacks.
+/// Decrypted secrets and access tokens are kept for the lifetime of the lambda container, a token until shortly before it expires.
+pub(crate) struct CallbackAuthenticator {
+    secrets: Arc<DecryptedSecrets>,
+    client: reqwest::Client,
+    guard: CallbackGuard,
+    tokens: Mutex<HashMap<(String, OAuth2ClientCredentials), AccessToken>>,
+}
+
+impl CallbackAuthenticator {
+    pub fn new(secrets: Arc<DecryptedSecrets>) -> Result<Self, reqwest::Error> {
+        Self::with_options(secrets, CALLBACK_CONNECT_TIMEOUT, CALLBACK_TOTAL_TIMEOUT, CallbackGuard::default(), Arc::new(SystemResolver))
+    }
+
+    /// The token url is checked by the guard like a callback url.
+    pub fn with_options(secrets: Arc<DecryptedSecrets>, connect_timeout: Duration, total_timeout: Duration, guard: CallbackGuard, resolver: Arc<dyn Resolve>) -> Result<Self, reqwest::Error> {
+        let client = reqwest::Client::builder()
+            .connect_timeout(connect_timeout)
+            .timeout(total_timeout)
+            .dns_resolver(Arc::new(GuardedResolver::new(resolver, guard)))
+            // The client secret goes along with the request, it must not be sent on to wherever a redirect points
+            .redirect(Policy::none())
+            .no_proxy()
+            .build()?;
+        Ok(CallbackAuthenticator { secrets, client, guard, tokens: Mutex::default() })
+    }
+
+    /// The header values are marked sensitive, so they do not show up in logs.
+    pub async fn headers(&self, credentials: &CallbackCredentials, now: u64) -> Result<HeaderMap, DeliveryOutcome> {
+        let mut headers = HeaderMap::new();
+        for callback_header in &credentials.headers {
+            let name = HeaderName::from_bytes(callback_header.name.as_bytes())
+                .map_err(|error| DeliveryOutcome::Permanent(format!("Invalid callback header {}: {}", callback_header.name, error)))?;
+            let value = self.secrets.get(&credentials.client_id, &callback_header.value).await?;
+            headers.insert(name, sensitive_header_value(&value, &callback_header.name)?);
+        }
+        if let Some(oauth2) = &credentials.oauth2 {
+            let access_token = self.access_token(&credentials.client_id, oauth2, now).await?;
+            headers.insert(AUTHORIZATION, sensitive_header_value(&format!("Bearer {}", access_token), "Authorization")?);
+        }
+        Ok(headers)
+    }
+
+    /// Forgets the access token of the client, e.g. after the callback endpoint rejected it. Returns whether there was one.
+    pub fn invalidate_token(&self, credentials: &CallbackCredentials) -> bool {
+        let Some(oauth2) = &credentials.oauth2 else { return false };
+        self.tokens.lock().unwrap().remove(&(credentials.client_id.clone(), oauth2.clone())).is_some()
+    }
+
+    async fn access_token(&self, client_id: &str, oauth2: &OAuth2ClientCredentials, now: u64) -> Result<String, DeliveryOutcome> {
+        let key = (client_id.to_string(), oauth2.clone());
+        let cached = self.tokens.lock().unwrap().get(&key).cloned();
+        if let Some(token) = cached {
+            if now + TOKEN_REFRESH_MARGIN.as_secs() < token.expires_at {
+                return Ok(token.access_token)
+            }
+        }
+        let client_secret = self.secrets.get(client_id, &oauth2.client_secret).await?;
+        let token = self.request_token(oauth2, &client_secret, now).await?;
+        self.tokens.lock().unwrap().insert(key, token.clone());
+        Ok(token.access_token)
+    }
+
+    /// Client credentials grant with the client authenticating with HTTP basic auth (RFC 6749, section 4.4).
+    async fn request_token(&self, oauth2: &OAuth2ClientCredentials, client_secret: &str, now: u64) -> Result<AccessToken, DeliveryOutcome> {
+        let url = Url::parse(&oauth2.token_url)
+            .map_err(|error| DeliveryOutcome::Permanent(format!("Invalid token url: {}", error)))?;
+        self.guard.check_url(&url)
+            .map_err(|error| DeliveryOutcome::Permanent(format!("Token request refused: {}", error)))?;
+
+        let mut form = vec![("grant_type", "client_credentials")];
+        if let Some(scope) = &oauth2.scope {
+            form.push(("scope", scope));
+        }
+        let result = self.client
+            .post(url)
+            .basic_auth(encode(&oauth2.client_id), Some(encode(client_secret)))
+            .header(ACCEPT, HeaderValue::from_static("application/json"))
+            .form(&form)
+            .send()
+            .await;
+        let mut response = match result {
+            Ok(response) => response,
+            Err(error) => return Err(classify_token_error(&error)),
+        };
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = read_body_prefix(&mut response, CALLBACK_RESPONSE_BODY_LIMIT).await;
+            let error = if body.is_empty() {
+                format!("Token request responded {}", status)
+            } else {
+                format!("Token request responded {}: {}", status, body)
+            };
+            return Err(match status_is_retryable(status) {
+                true => DeliveryOutcome::Retryable(error),
+                false => DeliveryOutcome::Permanent(error),
+            })
+        }
+
+        let token: TokenResponse = response.json().await
+            .map_err(|error| DeliveryOutcome::Permanent(format!("Invalid token response: {}", error)))?;
+        if let Some(token_type) = &token.token_type {
+            if !token_type.eq_ignore_ascii_case("bearer") {
+                return Err(DeliveryOutcome::Permanent(format!("Unsupported token type: {}", token_type)))
+            }
+        }
+        Ok(AccessToken {
+            access_token: token.access_token,
+            expires_at: now + token.expires_in.unwrap_or(DEFAULT_TOKEN_LIFETIME.as_secs()),
+        })
+    }
+}
+
+fn sensitive_header_value(value: &str, name: &str) -> Result<HeaderValue, DeliveryOutcome> {
+    let mut header_value = HeaderValue::from_str(value)
+        .map_err(|_| DeliveryOutcome::Permanent(format!("Invalid value for callback header {}", name)))?;
+    header_value.set_sensitive(true);
+    Ok(header_value)
+}
+
+fn classify_token_error(error: &reqwest::Error) -> DeliveryOutcome {
+    if let Some(forbidden_target) = find_forbidden_target(error) {
+        DeliveryOutcome::Permanent(format!("Token request refused: {}", forbidden_target))
+    } else if error.is_builder() || error.is_redirect() {
+        DeliveryOutcome::Permanent(format!("Token request failed: {}", error))
+    } else if error.is_timeout() {
+        DeliveryOutcome::Retryable(format!("Token request timed out: {}", error))
+    } else {
+        DeliveryOutcome::Retryable(format!("Token request failed: {}", error))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use email_confirmation_service_common::test_util::{http_stub, StubRequests};
+    use crate::callback_delivery::tests::stub_server;
+    use crate::callback_guard::tests::stub_resolver;
+
+    const NOW: u64 = 1_741_592_476;
+
+    /// "Decrypts" secrets of the form `<client_id>:<secret>`, only for that client, like KMS with the encryption context.
+    #[derive(Default)]
+    pub(crate) struct InMemoryDecrypter {
+        pub decrypted: Mutex<usize>,
+    }
+
+    impl SecretDecrypter for InMemoryDecrypter {
+        fn decrypt<'a>(&'a self, client_id: &'a str, secret: &'a EncryptedSecret) -> Decrypting<'a> {
+            Box::pin(async move {
+                *self.decrypted.lock().unwrap() += 1;
+                match secret.0.split_once(':') {
+                    Some((secret_client_id, plaintext)) if secret_client_id == client_id => Ok(plaintext.to_string()),
+                    _ => Err(DeliveryOutcome::Permanent("Decrypting a callback secret failed: InvalidCiphertextException".to_string())),
+                }
+            })
+        }
+    }
+
+    /// Local OAuth2 token endpoint that hands out token-1, token-2, ... and keeps the requests it got.
+    pub(crate) async fn token_server(expires_in: u64) -> (String, StubRequests) {
+        let issued = AtomicUsize::new(0);
+        let (url, requests) = http_stub("application/json", move |_| {
+            let token_number = issued.fetch_add(1, Ordering::SeqCst) + 1;
+            (200, format!(r#"{{"access_token":"token-{}","token_type":"Bearer","expires_in":{}}}"#, token_number, expires_in))
+        }).await;
+        (format!("{}/oauth2/token", url), requests)
+    }
+
+    pub(crate) fn authenticator(decrypter: Arc<InMemoryDecrypter>) -> CallbackAuthenticator {
+        CallbackAuthenticator::with_options(Arc::new(DecryptedSecrets::new(decrypter)), Duration::from_millis(500), Duration::from_millis(500), CallbackGuard::local_testing(), Arc::new(SystemResolver)).unwrap()
+    }
+
+    pub(crate) fn oauth2_credentials(token_url: &str) -> CallbackCredentials {
+        CallbackCredentials {
+            client_id: "client-1".to_string(),
+            headers: vec![],
+            oauth2: Some(OAuth2ClientCredentials {
+                token_url: token_url.to_string(),
+                client_id: "email-confirmation".to_string(),
+                client_secret: EncryptedSecret("client-1:s3cret".to_string()),
+                scope: Some("callbacks:write".to_string()),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_credentials_only_when_configured() {
+        let mut client_config = ClientConfig::new("client-1");
+        assert_eq!(None, CallbackCredentials::of(&client_config));
+        client_config.callback_headers = vec![CallbackHeader { name: "X-Api-Key".to_string(), value: EncryptedSecret("client-1:key".to_string()) }];
+        assert_eq!(Some("client-1".to_string()), CallbackCredentials::of(&client_config).map(|credentials| credentials.client_id));
+    }
+
+    #[tokio::test]
+    async fn test_custom_headers_are_decrypted_once() {
+        let decrypter = Arc::new(InMemoryDecrypter::default());
+        let authenticator = authenticator(decrypter.clone());
+        let credentials = CallbackCredentials {
+            client_id: "client-1".to_string(),
+            headers: vec![CallbackHeader { name: "X-Api-Key".to_string(), value: EncryptedSecret("client-1:k3y".to_string()) }],
+            oauth2: None,
+        };
+        for _ in 0..2 {
+            let headers = authenticator.headers(&credentials, NOW).await.unwrap();
+            assert_eq!("k3y", headers["x-api-key"]);
+            assert!(headers["x-api-key"].is_sensitive());
+        }
+        assert_eq!(1, *decrypter.decrypted.lock().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_secret_of_another_client_is_not_decrypted() {
+        let authenticator = authenticator(Arc::default());
+        let header = CallbackHeader { name: "X-Api-Key".to_string(), value: EncryptedSecret("client-1:k3y".to_string()) };
+        let credentials = CallbackCredentials { client_id: "client-1".to_string(), headers: vec![header.clone()], oauth2: None };
+        assert!(authenticator.headers(&credentials, NOW).await.is_ok());
+
+        let copied = CallbackCredentials { client_id: "client-2".to_string(), headers: vec![header], oauth2: None };
+        assert!(matches!(authenticator.headers(&copied, NOW).await, Err(DeliveryOutcome::Permanent(_))));
+    }
+
+    #[tokio::test]
+    async fn test_token_is_requested_with_client_credentials() {
+        let (token_url, requests) = token_server(3600).await;
+        let authenticator = authenticator(Arc::default());
+        let headers = authenticator.headers(&oauth2_credentials(&token_url), NOW).await.unwrap();
+        assert_eq!("Bearer token-1", headers[AUTHORIZATION]);
+        assert!(headers[AUTHORIZATION].is_sensitive());
+
+        let request = requests.lock().unwrap()[0].clone();
+        assert!(request.starts_with("POST /oauth2/token "));
+        let basic_auth = format!("authorization: basic {}", STANDARD.encode("email-confirmation:s3cret"));
+        assert!(request.to_ascii_lowercase().contains(&basic_auth.to_ascii_lowercase()));
+        assert!(request.ends_with("grant_type=client_credentials&scope=callbacks%3Awrite"));
+    }
+
+    #[tokio::test]
+    async fn test_token_is_cached_until_shortly_before_it_expires() {
+        let (token_url, requests) = token_server(3600).await;
+        let authenticator = authenticator(Arc::default());
+        let credentials = oauth2_credentials(&token_url);
+        assert_eq!("Bearer token-1", authenticator.headers(&credentials, NOW).await.unwrap()[AUTHORIZATION]);
+        assert_eq!("Bearer token-1", authenticator.headers(&credentials, NOW + 3600 - TOKEN_REFRESH_MARGIN.as_secs() - 1).await.unwrap()[AUTHORIZATION]);
+        assert_eq!(1, requests.lock().unwrap().len());
+
+        assert_eq!("Bearer token-2", authenticator.headers(&credentials, NOW + 3600 - TOKEN_REFRESH_MARGIN.as_secs()).await.unwrap()[AUTHORIZATION]);
+        assert_eq!(2, requests.lock().unwrap().len());
+    }
+
+    #[tokio::test]
+    async fn test_invalidated_token_is_requested_again() {
+        let (token_url, _) = token_server(3600).await;
+        let authenticator = authenticator(Arc::default());
+        let credentials = oauth2_credentials(&token_url);
+        assert_eq!("Bearer token-1", authenticator.headers(&credentials, NOW).await.unwrap()[AUTHORIZATION]);
+        assert!(authenticator.invalidate_token(&credentials));
+        assert!(!authenticator.invalidate_token(&credentials));
+        assert_eq!("Bearer token-2", authenticator.headers(&credentials, NOW).await.unwrap()[AUTHORIZATION]);
+    }
+
+    #[tokio::test]
+    async fn test_token_endpoint_errors() {
+        let authenticator = authenticator(Arc::default());
+        let token_url = stub_server("401 Unauthorized", r#"{"error":"invalid_client"}"#.to_string(), Duration::ZERO).await;
+        assert_eq!(
+            Err(DeliveryOutcome::Permanent(r#"Token request responded 401 Unauthorized: {"error":"invalid_client"}"#.to_string())),
+            authenticator.headers(&oauth2_credentials(&token_url), NOW).await);
+
+        let token_url = stub_server("503 Service Unavailable", String::new(), Duration::ZERO).await;
+        assert_eq!(
+            Err(DeliveryOutcome::Retryable("Token request responded 503 Service Unavailable".to_string())),
+            authenticator.headers(&oauth2_credentials(&token_url), NOW).await);
+    }
+
+    #[tokio::test]
+    async fn test_token_url_on_internal_address_is_refused() {
+        let resolver = stub_resolver(&[("auth.example.com", "10.0.0.1")]);
+        let authenticator = CallbackAuthenticator::with_options(Arc::new(DecryptedSecrets::new(Arc::new(InMemoryDecrypter::default()))), Duration::from_millis(500), Duration::from_millis(500), CallbackGuard::default(), resolver).unwrap();
+        let Err(DeliveryOutcome::Permanent(error)) = authenticator.headers(&oauth2_credentials("https://auth.example.com/oauth2/token"), NOW).await else { panic!("Expected Permanent") };
+        assert!(error.starts_with("Token request refused"), "{}", error);
+    }
+}
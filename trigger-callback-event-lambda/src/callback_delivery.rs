@@ -0,0 +1,295 @@
+use std::error::Error as StdError;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use reqwest::dns::Resolve;
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use reqwest::{StatusCode, Url};
+
+use crate::callback_guard::{CallbackGuard, ForbiddenCallbackTarget, GuardedResolver, SystemResolver};
+
+pub(crate) const CALLBACK_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+pub(crate) const CALLBACK_TOTAL_TIMEOUT: Duration = Duration::from_secs(10);
+// Only the beginning of the response body is read, for the error message of a failed delivery
+pub(crate) const CALLBACK_RESPONSE_BODY_LIMIT: usize = 1024;
+
+/// Result of one callback delivery attempt.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) enum DeliveryOutcome {
+    Delivered(u16),
+    // Worth another attempt later, e.g. a 503 or a timeout
+    Retryable(String),
+    // Will not get better by retrying, e.g. a 404 or an invalid callback url
+    Permanent(String),
+    // Not attempted, the circuit of the callback host is open or the host is busy. Tried again at until
+    Deferred { until: u64, reason: String },
+}
+
+/// The outcome with what the delivery log needs to know about the attempt.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) struct DeliveryReport {
+    pub outcome: DeliveryOutcome,
+    pub response_status: Option<u16>,
+    pub latency: Duration,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct CallbackClient {
+    client: reqwest::Client,
+    guard: CallbackGuard,
+}
+
+impl CallbackClient {
+    pub fn new() -> Result<Self, reqwest::Error> {
+        Self::with_options(CALLBACK_CONNECT_TIMEOUT, CALLBACK_TOTAL_TIMEOUT, CallbackGuard::default(), Arc::new(SystemResolver))
+    }
+
+    pub fn with_options(connect_timeout: Duration, total_timeout: Duration, guard: CallbackGuard, resolver: Arc<dyn Resolve>) -> Result<Self, reqwest::Error> {
+        let client = reqwest::Client::builder()
+            .connect_timeout(connect_timeout)
+            .timeout(total_timeout)
+            .dns_resolver(Arc::new(GuardedResolver::new(resolver, guard)))
+            .redirect(guard.redirect_policy())
+            // A proxy would resolve the host itself, past the guard
+            .no_proxy()
+            .build()?;
+        Ok(CallbackClient { client, guard })
+    }
+
+    /// The body is sent as is, the signature headers are computed over these exact bytes.
+    pub async fn deliver(&self, callback_url: &str, body: String, headers: HeaderMap) -> DeliveryReport {
+        let started_at = Instant::now();
+        let (outcome, response_status) = self.send(callback_url, body, headers).await;
+        DeliveryReport { outcome, response_status, latency: started_at.elapsed() }
+    }
+
+    async fn send(&self, callback_url: &str, body: String, headers: HeaderMap) -> (DeliveryOutcome, Option<u16>) {
+        let url = match Url::parse(callback_url) {
+            Ok(url) => url,
+            Err(error) => return (DeliveryOutcome::Permanent(format!("Invalid callback url: {}", error)), None),
+        };
+        if let Err(error) = self.guard.check_url(&url) {
+            return (DeliveryOutcome::Permanent(format!("Callback refused: {}", error)), None)
+        }
+
+        let result = self.client
+            .post(url)
+            .headers(headers)
+            .header(CONTENT_TYPE, HeaderValue::from_static("application/json"))
+            .body(body)
+            .send()
+            .await;
+
+        let mut response = match result {
+            Ok(response) => response,
+            Err(error) => return (classify_transport_error(&error), None),
+        };
+
+        let status = response.status();
+        if status.is_success() {
+            return (DeliveryOutcome::Delivered(status.as_u16()), Some(status.as_u16()))
+        }
+
+        let body = read_body_prefix(&mut response, CALLBACK_RESPONSE_BODY_LIMIT).await;
+        let error = if body.is_empty() {
+            format!("Callback responded {}", status)
+        } else {
+            format!("Callback responded {}: {}", status, body)
+        };
+        let outcome = if status_is_retryable(status) {
+            DeliveryOutcome::Retryable(error)
+        } else {
+            DeliveryOutcome::Permanent(error)
+        };
+        (outcome, Some(status.as_u16()))
+    }
+}
+
+/// Server errors, throttling and timeouts may pass, other client errors and redirects are the callback's fault.
+pub(crate) fn status_is_retryable(status: StatusCode) -> bool {
+    status.is_server_error()
+        || status == StatusCode::REQUEST_TIMEOUT
+        || status == StatusCode::TOO_EARLY
+        || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+fn classify_transport_error(error: &reqwest::Error) -> DeliveryOutcome {
+    if let Some(forbidden_target) = find_forbidden_target(error) {
+        DeliveryOutcome::Permanent(format!("Callback refused: {}", forbidden_target))
+    } else if error.is_builder() || error.is_redirect() {
+        DeliveryOutcome::Permanent(format!("Callback request failed: {}", error))
+    } else if error.is_timeout() {
+        DeliveryOutcome::Retryable(format!("Callback timed out: {}", error))
+    } else {
+        DeliveryOutcome::Retryable(format!("Callback request failed: {}", error))
+    }
+}
+
+/// The guard's refusal is wrapped in the connect or redirect error of reqwest.
+pub(crate) fn find_forbidden_target(error: &reqwest::Error) -> Option<&ForbiddenCallbackTarget> {
+    let mut source: Option<&(dyn StdError + 'static)> = error.source();
+    while let Some(error) = source {
+        if let Some(forbidden_target) = error.downcast_ref::<ForbiddenCallbackTarget>() {
+            return Some(forbidden_target)
+        }
+        source = error.source();
+    }
+    None
+}
+
+/// Reads at most limit bytes of the body, the rest is never downloaded.
+pub(crate) async fn read_body_prefix(response: &mut reqwest::Response, limit: usize) -> String {
+    let mut body: Vec<u8> = Vec::new();
+    while body.len() < limit {
+        match response.chunk().await {
+            Ok(Some(chunk)) => body.extend_from_slice(&chunk[..chunk.len().min(limit - body.len())]),
+            _ => break,
+        }
+    }
+    String::from_utf8_lossy(&body).trim().to_string()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use crate::callback_guard::tests::stub_resolver;
+
+    /// Local HTTP server that answers every request with the same canned response, after an optional delay.
+    pub(crate) async fn stub_server(status_line: &'static str, body: String, delay: Duration) -> String {
+        let response = format!("HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status_line, body.len(), body);
+        serve(response, delay).await
+    }
+
+    /// Local HTTP server that redirects every request to the location.
+    async fn redirect_server(location: &str) -> String {
+        let response = format!("HTTP/1.1 302 Found\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", location);
+        serve(response, Duration::ZERO).await
+    }
+
+    async fn serve(response: String, delay: Duration) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut socket, _)) = listener.accept().await else { return };
+                let response = response.clone();
+                tokio::spawn(async move {
+                    let mut request = vec![0u8; 8192];
+                    let _ = socket.read(&mut request).await;
+                    tokio::time::sleep(delay).await;
+                    let _ = socket.write_all(response.as_bytes()).await;
+                    let _ = socket.shutdown().await;
+                });
+            }
+        });
+        format!("http://{}/callback", address)
+    }
+
+    fn test_client() -> CallbackClient {
+        guarded_test_client(CallbackGuard::local_testing(), Arc::new(SystemResolver))
+    }
+
+    fn guarded_test_client(guard: CallbackGuard, resolver: Arc<dyn Resolve>) -> CallbackClient {
+        CallbackClient::with_options(Duration::from_millis(500), Duration::from_millis(500), guard, resolver).unwrap()
+    }
+
+    fn message() -> String {
+        r#"{"email":"email@example.com","status":"Confirmed"}"#.to_string()
+    }
+
+    #[tokio::test]
+    async fn test_2xx_is_delivered() {
+        let url = stub_server("204 No Content", String::new(), Duration::ZERO).await;
+        assert_eq!(DeliveryOutcome::Delivered(204), test_client().deliver(&url, message(), HeaderMap::new()).await.outcome);
+    }
+
+    #[tokio::test]
+    async fn test_server_error_is_retryable() {
+        let url = stub_server("503 Service Unavailable", "down for maintenance".to_string(), Duration::ZERO).await;
+        assert_eq!(
+            DeliveryOutcome::Retryable("Callback responded 503 Service Unavailable: down for maintenance".to_string()),
+            test_client().deliver(&url, message(), HeaderMap::new()).await.outcome);
+    }
+
+    #[tokio::test]
+    async fn test_throttling_is_retryable() {
+        let url = stub_server("429 Too Many Requests", String::new(), Duration::ZERO).await;
+        assert!(matches!(test_client().deliver(&url, message(), HeaderMap::new()).await.outcome, DeliveryOutcome::Retryable(_)));
+    }
+
+    #[tokio::test]
+    async fn test_report_has_response_status_and_latency() {
+        let url = stub_server("503 Service Unavailable", String::new(), Duration::from_millis(50)).await;
+        let report = test_client().deliver(&url, message(), HeaderMap::new()).await;
+        assert_eq!(Some(503), report.response_status);
+        assert!(report.latency >= Duration::from_millis(50));
+
+        let report = test_client().deliver("https://169.254.169.254/", message(), HeaderMap::new()).await;
+        assert_eq!(None, report.response_status);
+    }
+
+    #[tokio::test]
+    async fn test_client_error_is_permanent() {
+        let url = stub_server("404 Not Found", String::new(), Duration::ZERO).await;
+        assert_eq!(
+            DeliveryOutcome::Permanent("Callback responded 404 Not Found".to_string()),
+            test_client().deliver(&url, message(), HeaderMap::new()).await.outcome);
+    }
+
+    #[tokio::test]
+    async fn test_slow_callback_times_out() {
+        let url = stub_server("200 OK", String::new(), Duration::from_secs(5)).await;
+        let DeliveryOutcome::Retryable(error) = test_client().deliver(&url, message(), HeaderMap::new()).await.outcome else { panic!("Expected Retryable") };
+        assert!(error.starts_with("Callback timed out"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_response_body_is_capped() {
+        let url = stub_server("500 Internal Server Error", "x".repeat(1024 * 1024), Duration::ZERO).await;
+        let DeliveryOutcome::Retryable(error) = test_client().deliver(&url, message(), HeaderMap::new()).await.outcome else { panic!("Expected Retryable") };
+        assert_eq!("Callback responded 500 Internal Server Error: ".len() + CALLBACK_RESPONSE_BODY_LIMIT, error.len());
+    }
+
+    #[tokio::test]
+    async fn test_refused_connection_is_retryable() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/callback", listener.local_addr().unwrap());
+        drop(listener);
+        assert!(matches!(test_client().deliver(&url, message(), HeaderMap::new()).await.outcome, DeliveryOutcome::Retryable(_)));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_url_is_permanent() {
+        assert!(matches!(test_client().deliver("not a url", message(), HeaderMap::new()).await.outcome, DeliveryOutcome::Permanent(_)));
+    }
+
+    #[tokio::test]
+    async fn test_internal_callback_url_is_refused() {
+        let client = guarded_test_client(CallbackGuard::default(), stub_resolver(&[("internal.example.com", "10.0.0.5")]));
+        for callback_url in ["https://internal.example.com/hook", "https://169.254.169.254/latest/meta-data", "http://callback.example.com/hook"] {
+            let DeliveryOutcome::Permanent(error) = client.deliver(callback_url, message(), HeaderMap::new()).await.outcome else { panic!("Expected Permanent for {}", callback_url) };
+            assert!(error.starts_with("Callback refused"), "{}", error);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolved_host_is_delivered() {
+        let url = stub_server("204 No Content", String::new(), Duration::ZERO).await.replace("127.0.0.1", "callback.test");
+        let client = guarded_test_client(CallbackGuard::local_testing(), stub_resolver(&[("callback.test", "127.0.0.1")]));
+        assert_eq!(DeliveryOutcome::Delivered(204), client.deliver(&url, message(), HeaderMap::new()).await.outcome);
+    }
+
+    #[tokio::test]
+    async fn test_redirect_to_internal_host_is_refused() {
+        let client = guarded_test_client(CallbackGuard::local_testing(), stub_resolver(&[("callback.test", "127.0.0.1"), ("metadata.test", "169.254.169.254")]));
+
+        let url = redirect_server("http://metadata.test/latest/meta-data").await.replace("127.0.0.1", "callback.test");
+        let DeliveryOutcome::Permanent(error) = client.deliver(&url, message(), HeaderMap::new()).await.outcome else { panic!("Expected Permanent") };
+        assert!(error.contains("Callback host metadata.test resolves to the internal address 169.254.169.254"), "{}", error);
+
+        let url = redirect_server("http://169.254.169.254/latest/meta-data").await;
+        let DeliveryOutcome::Permanent(error) = client.deliver(&url, message(), HeaderMap::new()).await.outcome else { panic!("Expected Permanent") };
+        assert!(error.contains("169.254.169.254"), "{}", error);
+    }
+}
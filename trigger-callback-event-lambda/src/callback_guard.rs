@@ -0,0 +1,192 @@
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect::Policy;
+use reqwest::Url;
+
+use email_confirmation_service_common::callback_url::is_forbidden_ip;
+
+// Fewer than the reqwest default of 10, a callback endpoint has no business redirecting much
+pub(crate) const CALLBACK_MAX_REDIRECTS: usize = 5;
+
+/// Keeps callbacks from reaching internal addresses. The callback url was validated when the request was created,
+/// but its host is only resolved now, and a redirect can point anywhere, so every hop is checked again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct CallbackGuard {
+    https_only: bool,
+    loopback_allowed: bool,
+}
+
+impl Default for CallbackGuard {
+    fn default() -> Self {
+        CallbackGuard { https_only: true, loopback_allowed: false }
+    }
+}
+
+/// A callback url, or an address its host resolved to, that must not be called.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) struct ForbiddenCallbackTarget(pub String);
+
+impl fmt::Display for ForbiddenCallbackTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ForbiddenCallbackTarget {}
+
+impl CallbackGuard {
+    /// For tests against a stub server on localhost, which speaks plain http.
+    #[cfg(test)]
+    pub(crate) fn local_testing() -> Self {
+        CallbackGuard { https_only: false, loopback_allowed: true }
+    }
+
+    pub(crate) fn check_ip(&self, host: &str, ip: IpAddr) -> Result<(), ForbiddenCallbackTarget> {
+        if is_forbidden_ip(ip) && !(self.loopback_allowed && ip.is_loopback()) {
+            return Err(ForbiddenCallbackTarget(format!("Callback host {} resolves to the internal address {}", host, ip)))
+        }
+        Ok(())
+    }
+
+    /// Host names are checked by the resolver, IP addresses in the url never get there.
+    pub(crate) fn check_url(&self, url: &Url) -> Result<(), ForbiddenCallbackTarget> {
+        if self.https_only && url.scheme() != "https" {
+            return Err(ForbiddenCallbackTarget(format!("Callback url is not https: {}", url)))
+        }
+        match url.host() {
+            Some(url::Host::Ipv4(ip)) => self.check_ip(&ip.to_string(), IpAddr::V4(ip)),
+            Some(url::Host::Ipv6(ip)) => self.check_ip(&ip.to_string(), IpAddr::V6(ip)),
+            Some(url::Host::Domain(_)) => Ok(()),
+            None => Err(ForbiddenCallbackTarget(format!("Callback url has no host: {}", url))),
+        }
+    }
+
+    pub(crate) fn redirect_policy(self) -> Policy {
+        Policy::custom(move |attempt| {
+            if attempt.previous().len() > CALLBACK_MAX_REDIRECTS {
+                return attempt.error(format!("More than {} redirects", CALLBACK_MAX_REDIRECTS))
+            }
+            match self.check_url(attempt.url()) {
+                Ok(()) => attempt.follow(),
+                Err(error) => attempt.error(error),
+            }
+        })
+    }
+}
+
+/// Resolves with the inner resolver and refuses the host if any of its addresses is internal.
+/// Refusing all of them, rather than filtering, keeps a host with a mix of addresses from working only some of the time.
+pub(crate) struct GuardedResolver {
+    inner: Arc<dyn Resolve>,
+    guard: CallbackGuard,
+}
+
+impl GuardedResolver {
+    pub(crate) fn new(inner: Arc<dyn Resolve>, guard: CallbackGuard) -> Self {
+        GuardedResolver { inner, guard }
+    }
+}
+
+impl Resolve for GuardedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        let guard = self.guard;
+        let resolving = self.inner.resolve(name);
+        Box::pin(async move {
+            let addresses: Vec<SocketAddr> = resolving.await?.collect();
+            for address in addresses.iter() {
+                guard.check_ip(&host, address.ip())?;
+            }
+            let addresses: Addrs = Box::new(addresses.into_iter());
+            Ok(addresses)
+        })
+    }
+}
+
+/// The operating system resolver, as reqwest uses by default.
+pub(crate) struct SystemResolver;
+
+impl Resolve for SystemResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            let addresses: Addrs = Box::new(addresses.into_iter());
+            Ok(addresses)
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::HashMap;
+    use super::*;
+
+    /// Answers from a fixed table instead of DNS, an unknown host does not resolve.
+    pub(crate) struct StubResolver(pub HashMap<&'static str, Vec<IpAddr>>);
+
+    impl Resolve for StubResolver {
+        fn resolve(&self, name: Name) -> Resolving {
+            let result = match self.0.get(name.as_str()) {
+                Some(ips) => {
+                    let addresses: Addrs = Box::new(ips.iter().map(|ip| SocketAddr::new(*ip, 0)).collect::<Vec<_>>().into_iter());
+                    Ok(addresses)
+                },
+                None => Err(format!("No such host: {}", name.as_str()).into()),
+            };
+            Box::pin(async move { result })
+        }
+    }
+
+    pub(crate) fn stub_resolver(hosts: &[(&'static str, &str)]) -> Arc<dyn Resolve> {
+        let mut table: HashMap<&'static str, Vec<IpAddr>> = HashMap::new();
+        for (host, ip) in hosts {
+            table.entry(host).or_default().push(ip.parse().unwrap());
+        }
+        Arc::new(StubResolver(table))
+    }
+
+    async fn resolve(resolver: &GuardedResolver, host: &str) -> Result<Vec<SocketAddr>, String> {
+        let name: Name = host.parse().unwrap();
+        resolver.resolve(name).await.map(|addresses| addresses.collect()).map_err(|error| error.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_public_addresses_resolve() {
+        let resolver = GuardedResolver::new(stub_resolver(&[("callback.example.com", "93.184.215.14")]), CallbackGuard::default());
+        assert_eq!(Ok(vec!["93.184.215.14:0".parse().unwrap()]), resolve(&resolver, "callback.example.com").await);
+    }
+
+    #[tokio::test]
+    async fn test_internal_addresses_are_refused() {
+        let resolver = GuardedResolver::new(stub_resolver(&[
+            ("private.example.com", "10.0.0.5"),
+            ("metadata.example.com", "169.254.169.254"),
+            ("loopback.example.com", "::1"),
+            ("mixed.example.com", "93.184.215.14"),
+            ("mixed.example.com", "192.168.1.1"),
+        ]), CallbackGuard::default());
+        for host in ["private.example.com", "metadata.example.com", "loopback.example.com", "mixed.example.com"] {
+            let error = resolve(&resolver, host).await.unwrap_err();
+            assert!(error.starts_with(&format!("Callback host {} resolves to the internal address", host)), "{}", error);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_loopback_is_allowed_only_for_local_testing() {
+        let resolver = GuardedResolver::new(stub_resolver(&[("callback.test", "127.0.0.1"), ("private.test", "10.0.0.5")]), CallbackGuard::local_testing());
+        assert!(resolve(&resolver, "callback.test").await.is_ok());
+        assert!(resolve(&resolver, "private.test").await.is_err());
+    }
+
+    #[test]
+    fn test_url_checks() {
+        let guard = CallbackGuard::default();
+        assert!(guard.check_url(&Url::parse("https://callback.example.com/hook").unwrap()).is_ok());
+        assert!(guard.check_url(&Url::parse("http://callback.example.com/hook").unwrap()).is_err());
+        assert!(guard.check_url(&Url::parse("https://169.254.169.254/latest/meta-data").unwrap()).is_err());
+        assert!(guard.check_url(&Url::parse("https://[fd00::1]/hook").unwrap()).is_err());
+    }
+}
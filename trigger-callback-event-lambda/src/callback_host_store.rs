@@ -0,0 +1,86 @@
+use std::env;
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::operation::put_item::PutItemError;
+use aws_sdk_dynamodb::types::AttributeValue;
+use lambda_runtime::Error;
+use serde_dynamo::{from_item, to_item};
+
+use email_confirmation_service_common::callback_host::{Admission, CallbackHostHealth};
+
+// Callback lambdas of other batches update the same host, a write that lost the race is tried again on a fresh read
+const UPDATE_ATTEMPTS: usize = 3;
+
+/// Keeps the circuit breaker state of callback hosts, shared by all invocations of the lambda.
+/// Like the other stores, the table is only connected to when the batch has a callback to send.
+#[derive(Debug, Default)]
+pub(crate) struct CallbackHostStore {
+    table: Option<(Client, String)>,
+}
+
+impl CallbackHostStore {
+    async fn table(&mut self) -> Result<(Client, String), Error> {
+        match &self.table {
+            Some(table) => Ok(table.clone()),
+            None => {
+                let config = aws_config::load_from_env().await;
+                let table = (Client::new(&config), env::var("CALLBACK_HOST_DYNAMO_TABLE_NAME")?);
+                self.table = Some(table.clone());
+                Ok(table)
+            }
+        }
+    }
+
+    pub async fn admit(&mut self, host: &str, now: u64) -> Result<Admission, Error> {
+        self.update(host, |health| health.admit(now)).await
+    }
+
+    /// started_at is the time the delivery was admitted at, error is set when the host looked unhealthy.
+    pub async fn record(&mut self, host: &str, started_at: u64, error: Option<&str>, now: u64) -> Result<(), Error> {
+        self.update(host, |health| health.record(started_at, error, now)).await
+    }
+
+    /// Applies change to the stored health of the host, and writes it back if it changed,
+    /// on condition that no one else wrote it in the meantime.
+    async fn update<T>(&mut self, host: &str, change: impl Fn(&mut CallbackHostHealth) -> T) -> Result<T, Error> {
+        let (db_client, table_name) = self.table().await?;
+        for _ in 0..UPDATE_ATTEMPTS {
+            let results = db_client
+                .get_item()
+                .table_name(&table_name)
+                .key("host", AttributeValue::S(host.to_owned()))
+                .consistent_read(true)
+                .send()
+                .await?;
+            let health: CallbackHostHealth = match results.item {
+                Some(item) => from_item(item)?,
+                None => CallbackHostHealth::new(host),
+            };
+
+            let mut updated_health = health.clone();
+            let result = change(&mut updated_health);
+            if updated_health == health {
+                return Ok(result)
+            }
+            updated_health.version = health.version + 1;
+
+            let put_result = db_client
+                .put_item()
+                .table_name(&table_name)
+                .set_item(Some(to_item(&updated_health)?))
+                .condition_expression("attribute_not_exists(#host) OR #version = :version")
+                .expression_attribute_names("#host", "host")
+                .expression_attribute_names("#version", "version")
+                .expression_attribute_values(":version", AttributeValue::N(health.version.to_string()))
+                .send()
+                .await;
+            match put_result {
+                Ok(_) => return Ok(result),
+                Err(error) => match error.into_service_error() {
+                    PutItemError::ConditionalCheckFailedException(_) => continue,
+                    other => return Err(other.into()),
+                },
+            }
+        }
+        Err(Error::from(format!("Health of callback host {} is contended", host)))
+    }
+}
@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+// With the sweeper running every 5 minutes the delays are effectively rounded up to the next sweep
+pub(crate) const CALLBACK_MAX_ATTEMPTS: usize = 8;
+pub(crate) const CALLBACK_RETRY_BASE_DELAY: Duration = Duration::from_secs(60);
+pub(crate) const CALLBACK_RETRY_MAX_DELAY: Duration = Duration::from_secs(6 * 60 * 60);
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) struct RetryPolicy {
+    pub max_attempts: usize,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) enum FailedAttemptOutcome {
+    RetryAt(u64),
+    GiveUp,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: CALLBACK_MAX_ATTEMPTS,
+            base_delay: CALLBACK_RETRY_BASE_DELAY,
+            max_delay: CALLBACK_RETRY_MAX_DELAY,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// attempt_number is the 1-based number of the attempt that just failed, jitter is a random number in [0, 1).
+    /// The delay doubles per attempt up to max_delay, and the jitter spreads it over its upper half,
+    /// so that callbacks failing together (e.g. the client was down) are not all retried at the same moment.
+    pub fn after_failed_attempt(&self, attempt_number: usize, now: u64, jitter: f64) -> FailedAttemptOutcome {
+        if attempt_number >= self.max_attempts {
+            return FailedAttemptOutcome::GiveUp
+        }
+        let exponent = u32::try_from(attempt_number.saturating_sub(1)).unwrap_or(u32::MAX).min(31);
+        let delay = self.base_delay.as_secs()
+            .saturating_mul(1 << exponent)
+            .min(self.max_delay.as_secs());
+        let jittered_delay = delay / 2 + (delay as f64 / 2.0 * jitter.clamp(0.0, 1.0)) as u64;
+        FailedAttemptOutcome::RetryAt(now + jittered_delay.max(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_741_592_476;
+
+    #[test]
+    fn test_delay_doubles_per_attempt() {
+        let policy = RetryPolicy::default();
+        assert_eq!(FailedAttemptOutcome::RetryAt(NOW + 60), policy.after_failed_attempt(1, NOW, 1.0));
+        assert_eq!(FailedAttemptOutcome::RetryAt(NOW + 120), policy.after_failed_attempt(2, NOW, 1.0));
+        assert_eq!(FailedAttemptOutcome::RetryAt(NOW + 240), policy.after_failed_attempt(3, NOW, 1.0));
+    }
+
+    #[test]
+    fn test_jitter_stays_in_upper_half_of_delay() {
+        let policy = RetryPolicy::default();
+        assert_eq!(FailedAttemptOutcome::RetryAt(NOW + 120), policy.after_failed_attempt(3, NOW, 0.0));
+        assert_eq!(FailedAttemptOutcome::RetryAt(NOW + 180), policy.after_failed_attempt(3, NOW, 0.5));
+    }
+
+    #[test]
+    fn test_delay_is_capped() {
+        let policy = RetryPolicy { max_attempts: 100, ..RetryPolicy::default() };
+        assert_eq!(FailedAttemptOutcome::RetryAt(NOW + CALLBACK_RETRY_MAX_DELAY.as_secs()), policy.after_failed_attempt(50, NOW, 1.0));
+    }
+
+    #[test]
+    fn test_gives_up_after_max_attempts() {
+        let policy = RetryPolicy::default();
+        assert!(matches!(policy.after_failed_attempt(CALLBACK_MAX_ATTEMPTS - 1, NOW, 0.5), FailedAttemptOutcome::RetryAt(_)));
+        assert_eq!(FailedAttemptOutcome::GiveUp, policy.after_failed_attempt(CALLBACK_MAX_ATTEMPTS, NOW, 0.5));
+    }
+}
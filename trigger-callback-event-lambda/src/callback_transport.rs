@@ -0,0 +1,473 @@
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use aws_sdk_eventbridge::types::PutEventsRequestEntry;
+use aws_sdk_sqs::error::{DisplayErrorContext, ProvideErrorMetadata, SdkError};
+use aws_sdk_sqs::types::MessageAttributeValue;
+use reqwest::header::HeaderMap;
+use tokio::sync::OnceCell;
+
+use email_confirmation_service_common::callback_event::CallbackEventType;
+use email_confirmation_service_common::callback_url::CallbackTarget;
+
+use email_confirmation_service_common::client_config::{ClientConfig, WEBHOOK_SECRET_PREFIX};
+
+use crate::callback_auth::{CallbackAuthenticator, CallbackCredentials, DecryptedSecrets, KmsDecrypter};
+use crate::callback_delivery::{CallbackClient, DeliveryOutcome, DeliveryReport};
+
+// Source of the events put on an event bus
+pub(crate) const EVENT_BUS_SOURCE: &str = "email-confirmation-service";
+// Detail type of an event without an event type, i.e. a V1 callback
+pub(crate) const EVENT_BUS_DEFAULT_DETAIL_TYPE: &str = "email-confirmation.callback";
+// The AWS API call itself succeeded, there is no response from the client
+const AWS_DELIVERED_STATUS: u16 = 200;
+// Errors of SQS, EventBridge and KMS that will not go away by retrying
+const PERMANENT_AWS_ERROR_CODES: [&str; 13] = [
+    "AccessDenied",
+    "AccessDeniedException",
+    "AWS.SimpleQueueService.NonExistentQueue",
+    "QueueDoesNotExist",
+    "InvalidMessageContents",
+    "InvalidParameterValue",
+    "ResourceNotFoundException",
+    "ValidationException",
+    "DisabledException",
+    "IncorrectKeyException",
+    "InvalidCiphertextException",
+    "KMSInvalidStateException",
+    "NotFoundException",
+];
+
+/// One callback, as sent by any transport. The headers are the webhook signature headers.
+/// The credentials are only used by the HTTP transport, queues and event buses are protected by IAM.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) struct CallbackMessage {
+    pub body: String,
+    pub headers: HeaderMap,
+    pub event_type: Option<CallbackEventType>,
+    pub credentials: Option<CallbackCredentials>,
+}
+
+pub(crate) type Delivering<'a> = Pin<Box<dyn Future<Output = DeliveryReport> + Send + 'a>>;
+
+/// Delivers callbacks to one kind of target. Failures are classified into retryable and permanent, like HTTP responses are.
+pub(crate) trait CallbackTransport: Send + Sync {
+    fn deliver<'a>(&'a self, target: &'a CallbackTarget, message: CallbackMessage) -> Delivering<'a>;
+}
+
+/// A transport per kind of target, chosen by the scheme of the callback url.
+#[derive(Clone)]
+pub(crate) struct CallbackTransports {
+    http: Arc<dyn CallbackTransport>,
+    queue: Arc<dyn CallbackTransport>,
+    event_bus: Arc<dyn CallbackTransport>,
+    secrets: Arc<DecryptedSecrets>,
+}
+
+impl CallbackTransports {
+    pub fn new(http: Arc<dyn CallbackTransport>, queue: Arc<dyn CallbackTransport>, event_bus: Arc<dyn CallbackTransport>) -> Self {
+        CallbackTransports { http, queue, event_bus, secrets: Arc::new(DecryptedSecrets::new(Arc::new(KmsDecrypter::default()))) }
+    }
+
+    /// The AWS clients are created on the first callback to a queue or an event bus.
+    pub fn with_defaults() -> Result<Self, reqwest::Error> {
+        let secrets = Arc::new(DecryptedSecrets::new(Arc::new(KmsDecrypter::default())));
+        let http = HttpTransport { client: CallbackClient::new()?, authenticator: CallbackAuthenticator::new(secrets.clone())? };
+        Ok(CallbackTransports {
+            secrets,
+            ..Self::new(Arc::new(http), Arc::new(SqsTransport::default()), Arc::new(EventBridgeTransport::default()))
+        })
+    }
+
+    /// The active webhook secrets of the client in plaintext, newest first.
+    /// Secrets created before they were encrypted are stored in plaintext and used as they are.
+    pub async fn webhook_secrets(&self, client_config: &ClientConfig, now: u64) -> Result<Vec<String>, DeliveryOutcome> {
+        let mut secrets = vec![];
+        for secret in client_config.active_webhook_secrets(now) {
+            match secret.0.starts_with(WEBHOOK_SECRET_PREFIX) {
+                true => secrets.push(secret.0.clone()),
+                false => secrets.push(self.secrets.get(&client_config.client_id, secret).await?),
+            }
+        }
+        Ok(secrets)
+    }
+
+    pub async fn deliver(&self, callback_url: &str, message: CallbackMessage) -> DeliveryReport {
+        let target = match CallbackTarget::parse(callback_url) {
+            Ok(target) => target,
+            Err(error) => return DeliveryReport {
+                outcome: DeliveryOutcome::Permanent(format!("Invalid callback url: {}", error)),
+                response_status: None,
+                latency: Duration::ZERO,
+            },
+        };
+        let transport = match &target {
+            CallbackTarget::Http(_) => &self.http,
+            CallbackTarget::Queue { .. } => &self.queue,
+            CallbackTarget::EventBus { .. } => &self.event_bus,
+        };
+        transport.deliver(&target, message).await
+    }
+}
+
+/// POSTs the body to the callback url, with the signature headers and the client's custom headers and bearer token.
+pub(crate) struct HttpTransport {
+    pub client: CallbackClient,
+    pub authenticator: CallbackAuthenticator,
+}
+
+impl CallbackTransport for HttpTransport {
+    fn deliver<'a>(&'a self, target: &'a CallbackTarget, message: CallbackMessage) -> Delivering<'a> {
+        Box::pin(async move {
+            let CallbackTarget::Http(url) = target else { return wrong_target(target) };
+            let Some(credentials) = &message.credentials else {
+                return self.client.deliver(url.as_str(), message.body, message.headers).await
+            };
+            let report = self.deliver_authenticated(url.as_str(), &message, credentials).await;
+            // The token may have been revoked before it expired, a fresh one is tried once
+            if report.response_status == Some(401) && self.authenticator.invalidate_token(credentials) {
+                return self.deliver_authenticated(url.as_str(), &message, credentials).await
+            }
+            report
+        })
+    }
+}
+
+impl HttpTransport {
+    /// A callback whose secrets cannot be decrypted or whose token cannot be had is not sent.
+    async fn deliver_authenticated(&self, callback_url: &str, message: &CallbackMessage, credentials: &CallbackCredentials) -> DeliveryReport {
+        let started_at = Instant::now();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let mut headers = match self.authenticator.headers(credentials, now).await {
+            Ok(headers) => headers,
+            Err(outcome) => return DeliveryReport { outcome, response_status: None, latency: started_at.elapsed() },
+        };
+        headers.extend(message.headers.clone());
+        self.client.deliver(callback_url, message.body.clone(), headers).await
+    }
+}
+
+/// An SQS message, the body is the callback body and the headers are message attributes.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) struct QueueMessage {
+    pub queue_url: String,
+    pub body: String,
+    pub attributes: Vec<(String, String)>,
+}
+
+impl QueueMessage {
+    pub fn new(queue_url: &str, message: CallbackMessage) -> Self {
+        let mut attributes: Vec<(String, String)> = message.headers.iter()
+            .filter_map(|(name, value)| value.to_str().ok().map(|value| (name.as_str().to_string(), value.to_string())))
+            .collect();
+        if let Some(event_type) = message.event_type {
+            attributes.push(("event_type".to_string(), event_type.as_str().to_string()));
+        }
+        QueueMessage { queue_url: queue_url.to_string(), body: message.body, attributes }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct SqsTransport {
+    client: OnceCell<aws_sdk_sqs::Client>,
+}
+
+impl CallbackTransport for SqsTransport {
+    fn deliver<'a>(&'a self, target: &'a CallbackTarget, message: CallbackMessage) -> Delivering<'a> {
+        Box::pin(async move {
+            let CallbackTarget::Queue { queue_url } = target else { return wrong_target(target) };
+            let started_at = Instant::now();
+            let queue_message = QueueMessage::new(queue_url, message);
+            let client = self.client.get_or_init(|| async { aws_sdk_sqs::Client::new(&aws_config::load_from_env().await) }).await;
+
+            let mut request = client.send_message()
+                .queue_url(&queue_message.queue_url)
+                .message_body(&queue_message.body);
+            for (name, value) in queue_message.attributes {
+                match MessageAttributeValue::builder().data_type("String").string_value(value).build() {
+                    Ok(attribute) => request = request.message_attributes(name, attribute),
+                    Err(error) => return aws_report(DeliveryOutcome::Permanent(format!("Invalid message attribute {}: {}", name, error)), started_at),
+                }
+            }
+            let outcome = match request.send().await {
+                Ok(_) => DeliveryOutcome::Delivered(AWS_DELIVERED_STATUS),
+                Err(error) => classify_aws_error("Sending to the callback queue", &error),
+            };
+            aws_report(outcome, started_at)
+        })
+    }
+}
+
+/// An EventBridge event, the detail is the callback body. The bus is reached with IAM permissions, so the
+/// signature headers are left out.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) struct EventBusEntry {
+    pub event_bus_name: String,
+    pub source: String,
+    pub detail_type: String,
+    pub detail: String,
+}
+
+impl EventBusEntry {
+    pub fn new(event_bus_name: &str, message: CallbackMessage) -> Self {
+        EventBusEntry {
+            event_bus_name: event_bus_name.to_string(),
+            source: EVENT_BUS_SOURCE.to_string(),
+            detail_type: message.event_type.map_or(EVENT_BUS_DEFAULT_DETAIL_TYPE, |event_type| event_type.as_str()).to_string(),
+            detail: message.body,
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct EventBridgeTransport {
+    client: OnceCell<aws_sdk_eventbridge::Client>,
+}
+
+impl CallbackTransport for EventBridgeTransport {
+    fn deliver<'a>(&'a self, target: &'a CallbackTarget, message: CallbackMessage) -> Delivering<'a> {
+        Box::pin(async move {
+            let CallbackTarget::EventBus { event_bus_name } = target else { return wrong_target(target) };
+            let started_at = Instant::now();
+            let entry = EventBusEntry::new(event_bus_name, message);
+            let client = self.client.get_or_init(|| async { aws_sdk_eventbridge::Client::new(&aws_config::load_from_env().await) }).await;
+
+            let result = client.put_events()
+                .entries(PutEventsRequestEntry::builder()
+                    .event_bus_name(entry.event_bus_name)
+                    .source(entry.source)
+                    .detail_type(entry.detail_type)
+                    .detail(entry.detail)
+                    .build())
+                .send()
+                .await;
+            let outcome = match result {
+                // PutEvents succeeds as a call even when the entry fails
+                Ok(output) if output.failed_entry_count() > 0 => {
+                    let failed_entry = output.entries().first();
+                    classify_failed_event(
+                        failed_entry.and_then(|entry| entry.error_code()).unwrap_or_default(),
+                        failed_entry.and_then(|entry| entry.error_message()).unwrap_or_default())
+                },
+                Ok(_) => DeliveryOutcome::Delivered(AWS_DELIVERED_STATUS),
+                Err(error) => classify_aws_error("Putting the callback event", &error),
+            };
+            aws_report(outcome, started_at)
+        })
+    }
+}
+
+fn classify_failed_event(error_code: &str, error_message: &str) -> DeliveryOutcome {
+    let error = format!("Putting the callback event failed: {} {}", error_code, error_message);
+    match error_code {
+        "ThrottlingException" | "InternalFailure" | "InternalException" => DeliveryOutcome::Retryable(error),
+        _ => DeliveryOutcome::Permanent(error),
+    }
+}
+
+/// Timeouts, connection problems and most service errors are retryable, a request that cannot be built or
+/// a missing queue, event bus or permission is not.
+pub(crate) fn classify_aws_error<E, R>(action: &str, error: &SdkError<E, R>) -> DeliveryOutcome
+where
+    E: ProvideErrorMetadata + std::error::Error + 'static,
+    R: Debug,
+{
+    let message = format!("{} failed: {}", action, DisplayErrorContext(error));
+    match error {
+        SdkError::ConstructionFailure(_) => DeliveryOutcome::Permanent(message),
+        SdkError::ServiceError(service_error) => match service_error.err().code() {
+            Some(code) if PERMANENT_AWS_ERROR_CODES.contains(&code) => DeliveryOutcome::Permanent(message),
+            _ => DeliveryOutcome::Retryable(message),
+        },
+        _ => DeliveryOutcome::Retryable(message),
+    }
+}
+
+fn aws_report(outcome: DeliveryOutcome, started_at: Instant) -> DeliveryReport {
+    let response_status = match outcome {
+        DeliveryOutcome::Delivered(status) => Some(status),
+        _ => None,
+    };
+    DeliveryReport { outcome, response_status, latency: started_at.elapsed() }
+}
+
+fn wrong_target(target: &CallbackTarget) -> DeliveryReport {
+    DeliveryReport {
+        outcome: DeliveryOutcome::Permanent(format!("No transport for callback target {:?}", target)),
+        response_status: None,
+        latency: Duration::ZERO,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::Mutex;
+    use reqwest::header::HeaderValue;
+    use reqwest::Url;
+    use email_confirmation_service_common::client_config::{EncryptedSecret, WebhookSecret};
+    use super::*;
+    use crate::callback_auth::tests::{authenticator, oauth2_credentials, token_server, InMemoryDecrypter};
+    use crate::callback_delivery::tests::stub_server;
+    use crate::callback_guard::{CallbackGuard, SystemResolver};
+
+    /// Records what an HTTP endpoint would have received.
+    #[derive(Default)]
+    pub(crate) struct InMemoryHttpEndpoint {
+        pub received: Mutex<Vec<(Url, CallbackMessage)>>,
+    }
+
+    impl CallbackTransport for InMemoryHttpEndpoint {
+        fn deliver<'a>(&'a self, target: &'a CallbackTarget, message: CallbackMessage) -> Delivering<'a> {
+            Box::pin(async move {
+                let CallbackTarget::Http(url) = target else { return wrong_target(target) };
+                self.received.lock().unwrap().push((url.clone(), message));
+                DeliveryReport { outcome: DeliveryOutcome::Delivered(204), response_status: Some(204), latency: Duration::ZERO }
+            })
+        }
+    }
+
+    /// Records the SQS messages, or fails every send with the given outcome.
+    #[derive(Default)]
+    pub(crate) struct InMemoryQueue {
+        pub messages: Mutex<Vec<QueueMessage>>,
+        pub failure: Option<DeliveryOutcome>,
+    }
+
+    impl CallbackTransport for InMemoryQueue {
+        fn deliver<'a>(&'a self, target: &'a CallbackTarget, message: CallbackMessage) -> Delivering<'a> {
+            Box::pin(async move {
+                let CallbackTarget::Queue { queue_url } = target else { return wrong_target(target) };
+                if let Some(failure) = &self.failure {
+                    return aws_report(failure.clone(), Instant::now())
+                }
+                self.messages.lock().unwrap().push(QueueMessage::new(queue_url, message));
+                aws_report(DeliveryOutcome::Delivered(AWS_DELIVERED_STATUS), Instant::now())
+            })
+        }
+    }
+
+    /// Records the EventBridge events.
+    #[derive(Default)]
+    pub(crate) struct InMemoryEventBus {
+        pub entries: Mutex<Vec<EventBusEntry>>,
+    }
+
+    impl CallbackTransport for InMemoryEventBus {
+        fn deliver<'a>(&'a self, target: &'a CallbackTarget, message: CallbackMessage) -> Delivering<'a> {
+            Box::pin(async move {
+                let CallbackTarget::EventBus { event_bus_name } = target else { return wrong_target(target) };
+                self.entries.lock().unwrap().push(EventBusEntry::new(event_bus_name, message));
+                aws_report(DeliveryOutcome::Delivered(AWS_DELIVERED_STATUS), Instant::now())
+            })
+        }
+    }
+
+    fn message() -> CallbackMessage {
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Webhook-Timestamp", HeaderValue::from_static("1741592476"));
+        headers.insert("X-Webhook-Signature", HeaderValue::from_static("v1=abc"));
+        CallbackMessage {
+            body: r#"{"version":2,"event_type":"request.confirmed"}"#.to_string(),
+            headers,
+            event_type: Some(CallbackEventType::RequestConfirmed),
+            credentials: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transport_is_chosen_by_scheme() {
+        let http = Arc::new(InMemoryHttpEndpoint::default());
+        let queue = Arc::new(InMemoryQueue::default());
+        let event_bus = Arc::new(InMemoryEventBus::default());
+        let transports = CallbackTransports::new(http.clone(), queue.clone(), event_bus.clone());
+
+        assert_eq!(DeliveryOutcome::Delivered(204), transports.deliver("https://callback.example.com/hook", message()).await.outcome);
+        assert_eq!(DeliveryOutcome::Delivered(200), transports.deliver("sqs://sqs.eu-north-1.amazonaws.com/123456789012/confirmations", message()).await.outcome);
+        assert_eq!(DeliveryOutcome::Delivered(200), transports.deliver("eventbridge://confirmations", message()).await.outcome);
+
+        assert_eq!("https://callback.example.com/hook", http.received.lock().unwrap()[0].0.as_str());
+        assert_eq!("https://sqs.eu-north-1.amazonaws.com/123456789012/confirmations", queue.messages.lock().unwrap()[0].queue_url);
+        assert_eq!("confirmations", event_bus.entries.lock().unwrap()[0].event_bus_name);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_scheme_is_permanent() {
+        let transports = CallbackTransports::new(Arc::new(InMemoryHttpEndpoint::default()), Arc::new(InMemoryQueue::default()), Arc::new(InMemoryEventBus::default()));
+        assert!(matches!(transports.deliver("ftp://callback.example.com/hook", message()).await.outcome, DeliveryOutcome::Permanent(_)));
+        assert!(matches!(transports.deliver("sqs://callback.example.com/hook", message()).await.outcome, DeliveryOutcome::Permanent(_)));
+    }
+
+    #[tokio::test]
+    async fn test_webhook_secrets_are_decrypted() {
+        let decrypter = Arc::new(InMemoryDecrypter::default());
+        let transports = CallbackTransports {
+            secrets: Arc::new(DecryptedSecrets::new(decrypter.clone())),
+            ..CallbackTransports::new(Arc::new(InMemoryHttpEndpoint::default()), Arc::new(InMemoryQueue::default()), Arc::new(InMemoryEventBus::default()))
+        };
+        let now = 1_741_592_476;
+        let mut client_config = ClientConfig::new("client-1");
+        client_config.rotate_webhook_secret(WebhookSecret::new(EncryptedSecret("whsec_stored_in_plaintext".to_string()), now - 60), now - 60);
+        client_config.rotate_webhook_secret(WebhookSecret::new(EncryptedSecret("client-1:whsec_new".to_string()), now), now);
+        assert_eq!(Ok(vec!["whsec_new".to_string(), "whsec_stored_in_plaintext".to_string()]), transports.webhook_secrets(&client_config, now).await);
+        assert_eq!(Ok(vec!["whsec_new".to_string(), "whsec_stored_in_plaintext".to_string()]), transports.webhook_secrets(&client_config, now).await);
+        assert_eq!(1, *decrypter.decrypted.lock().unwrap());
+
+        // Copied from another client
+        let mut client_config = ClientConfig::new("client-2");
+        client_config.rotate_webhook_secret(WebhookSecret::new(EncryptedSecret("client-1:whsec_new".to_string()), now), now);
+        assert!(matches!(transports.webhook_secrets(&client_config, now).await, Err(DeliveryOutcome::Permanent(_))));
+    }
+
+    #[tokio::test]
+    async fn test_queue_failure_is_reported() {
+        let queue = InMemoryQueue { failure: Some(DeliveryOutcome::Retryable("throttled".to_string())), ..Default::default() };
+        let transports = CallbackTransports::new(Arc::new(InMemoryHttpEndpoint::default()), Arc::new(queue), Arc::new(InMemoryEventBus::default()));
+        let report = transports.deliver("sqs://sqs.eu-north-1.amazonaws.com/123456789012/confirmations", message()).await;
+        assert_eq!(DeliveryOutcome::Retryable("throttled".to_string()), report.outcome);
+        assert_eq!(None, report.response_status);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_token_is_refreshed_once() {
+        let (token_url, token_requests) = token_server(3600).await;
+        let callback_url = stub_server("401 Unauthorized", String::new(), Duration::ZERO).await;
+        let http = HttpTransport {
+            client: CallbackClient::with_options(Duration::from_millis(500), Duration::from_millis(500), CallbackGuard::local_testing(), Arc::new(SystemResolver)).unwrap(),
+            authenticator: authenticator(Arc::default()),
+        };
+        let message = CallbackMessage { credentials: Some(oauth2_credentials(&token_url)), ..message() };
+        let report = http.deliver(&CallbackTarget::Http(Url::parse(&callback_url).unwrap()), message).await;
+        assert_eq!(DeliveryOutcome::Permanent("Callback responded 401 Unauthorized".to_string()), report.outcome);
+        assert_eq!(2, token_requests.lock().unwrap().len());
+    }
+
+    #[test]
+    fn test_queue_message_has_signature_headers_as_attributes() {
+        let queue_message = QueueMessage::new("https://sqs.eu-north-1.amazonaws.com/123456789012/confirmations", message());
+        assert_eq!(message().body, queue_message.body);
+        assert_eq!(vec![
+            ("x-webhook-timestamp".to_string(), "1741592476".to_string()),
+            ("x-webhook-signature".to_string(), "v1=abc".to_string()),
+            ("event_type".to_string(), "request.confirmed".to_string()),
+        ], queue_message.attributes);
+    }
+
+    #[test]
+    fn test_event_bus_entry() {
+        let entry = EventBusEntry::new("confirmations", message());
+        assert_eq!(EVENT_BUS_SOURCE, entry.source);
+        assert_eq!("request.confirmed", entry.detail_type);
+        assert_eq!(message().body, entry.detail);
+
+        let v1_message = CallbackMessage { event_type: None, ..message() };
+        assert_eq!(EVENT_BUS_DEFAULT_DETAIL_TYPE, EventBusEntry::new("confirmations", v1_message).detail_type);
+    }
+
+    #[test]
+    fn test_failed_event_classification() {
+        assert!(matches!(classify_failed_event("ThrottlingException", "Rate exceeded"), DeliveryOutcome::Retryable(_)));
+        assert!(matches!(classify_failed_event("AccessDeniedException", "Not authorized"), DeliveryOutcome::Permanent(_)));
+    }
+}
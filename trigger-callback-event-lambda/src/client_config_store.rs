@@ -0,0 +1,48 @@
+use std::collections::HashMap;
+use std::env;
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::types::AttributeValue;
+use lambda_runtime::Error;
+use serde_dynamo::from_item;
+
+use email_confirmation_service_common::client_config::ClientConfig;
+
+/// Reads client configs straight from their table, so that webhook secrets never go through the REST API.
+/// Configs are cached for the lifetime of the store, i.e. one batch of stream records.
+/// The table is only connected to when the batch has a callback to send.
+#[derive(Debug, Default)]
+pub(crate) struct ClientConfigStore {
+    table: Option<(Client, String)>,
+    cache: HashMap<String, Option<ClientConfig>>,
+}
+
+impl ClientConfigStore {
+    pub async fn get(&mut self, client_id: &str) -> Result<Option<ClientConfig>, Error> {
+        if let Some(client_config) = self.cache.get(client_id) {
+            return Ok(client_config.clone())
+        }
+
+        let (db_client, table_name) = match &self.table {
+            Some(table) => table.clone(),
+            None => {
+                let config = aws_config::load_from_env().await;
+                let table = (Client::new(&config), env::var("CLIENT_CONFIG_DYNAMO_TABLE_NAME")?);
+                self.table = Some(table.clone());
+                table
+            }
+        };
+        let results = db_client
+            .get_item()
+            .table_name(&table_name)
+            .key("client_id", AttributeValue::S(client_id.to_owned()))
+            .send()
+            .await?;
+        let client_config: Option<ClientConfig> = match results.item {
+            Some(item) => Some(from_item(item)?),
+            None => None,
+        };
+
+        self.cache.insert(client_id.to_owned(), client_config.clone());
+        Ok(client_config)
+    }
+}
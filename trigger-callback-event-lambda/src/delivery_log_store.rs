@@ -0,0 +1,96 @@
+use std::env;
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::types::AttributeValue;
+use lambda_runtime::Error;
+use serde_dynamo::{from_item, to_item};
+
+use email_confirmation_service_common::delivery_log::{truncate_error, CallbackDelivery, DeliveryResult, DELIVERY_LOG_TIME_TO_LIVE};
+use email_confirmation_service_common::email_confirmation_request::EmailConfirmationRequest;
+
+use crate::callback_delivery::{DeliveryOutcome, DeliveryReport};
+use crate::callback_transport::CallbackMessage;
+
+/// Writes every callback attempt to the delivery log table, read by the REST API.
+/// Like the client config store, the table is only connected to when the batch has a callback to send.
+#[derive(Debug, Default)]
+pub(crate) struct DeliveryLogStore {
+    table: Option<(Client, String)>,
+}
+
+impl DeliveryLogStore {
+    async fn table(&mut self) -> Result<(Client, String), Error> {
+        match &self.table {
+            Some(table) => Ok(table.clone()),
+            None => {
+                let config = aws_config::load_from_env().await;
+                let table = (Client::new(&config), env::var("CALLBACK_DELIVERY_DYNAMO_TABLE_NAME")?);
+                self.table = Some(table.clone());
+                Ok(table)
+            }
+        }
+    }
+
+    pub async fn record(&mut self, delivery: &CallbackDelivery) -> Result<(), Error> {
+        let (db_client, table_name) = self.table().await?;
+        db_client
+            .put_item()
+            .table_name(&table_name)
+            .set_item(Some(to_item(delivery)?))
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    /// The most recent attempt of the request, the one a replay sends again.
+    pub async fn latest(&mut self, pk: &str) -> Result<Option<CallbackDelivery>, Error> {
+        let (db_client, table_name) = self.table().await?;
+        let results = db_client
+            .query()
+            .table_name(&table_name)
+            .key_condition_expression("#pk = :pk")
+            .expression_attribute_names("#pk", "pk")
+            .expression_attribute_values(":pk", AttributeValue::S(pk.to_owned()))
+            .scan_index_forward(false)
+            .limit(1)
+            .consistent_read(true)
+            .send()
+            .await?;
+        match results.items.unwrap_or_default().into_iter().next() {
+            Some(item) => Ok(Some(from_item(item)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// The log entry of one attempt, attempted_at_millis is when the attempt started.
+pub(crate) fn delivery_log_entry(
+    confirmation_request: &EmailConfirmationRequest,
+    event_id: Option<String>,
+    message: &CallbackMessage,
+    report: &DeliveryReport,
+    attempted_at_millis: u64,
+    replay: bool,
+) -> CallbackDelivery {
+    let attempted_at = attempted_at_millis / 1000;
+    let (result, error) = match &report.outcome {
+        DeliveryOutcome::Delivered(_) => (DeliveryResult::Delivered, None),
+        DeliveryOutcome::Retryable(error) => (DeliveryResult::Retryable, Some(truncate_error(error))),
+        DeliveryOutcome::Permanent(error) => (DeliveryResult::Permanent, Some(truncate_error(error))),
+        DeliveryOutcome::Deferred { reason, .. } => (DeliveryResult::Deferred, Some(truncate_error(reason))),
+    };
+    CallbackDelivery {
+        pk: confirmation_request.pk.clone(),
+        delivery_id: CallbackDelivery::delivery_id(attempted_at_millis, rand::random::<u32>()),
+        event_id,
+        event_type: message.event_type,
+        callback_url: confirmation_request.callback_url.clone(),
+        attempted_at,
+        latency_ms: report.latency.as_millis() as u64,
+        response_status: report.response_status,
+        result,
+        error,
+        replay,
+        payload: message.body.clone(),
+        expires_at: attempted_at + DELIVERY_LOG_TIME_TO_LIVE.as_secs(),
+    }
+}
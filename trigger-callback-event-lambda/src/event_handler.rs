@@ -1,78 +1,335 @@
 use std::env;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use lambda_runtime::{tracing, Error, LambdaEvent};
-use aws_lambda_events::event::dynamodb::Event;
+use aws_lambda_events::event::dynamodb::{Event, EventRecord};
 use aws_sdk_lambda::Client;
 use aws_smithy_types::Blob;
-use chrono::{Utc};
 use urlencoding::encode;
 
 use serde_json::{json};
-use serde_dynamo::{Item, AttributeValue::S, AttributeValue::N, from_item};
+use serde_dynamo::from_item;
 
 use email_confirmation_service_common::email_confirmation_request::{EmailConfirmationRequest, EmailConfirmationServiceApiResponse, Status};
-use email_confirmation_service_common::email_confirmation_request::Status::{Confirmed};
+use email_confirmation_service_common::email_confirmation_request::Status::{CallbackFailed, Confirmed, Done};
 use email_confirmation_service_common::signature_request::SignatureResponse::Signature;
 use email_confirmation_service_common::signature_request::{SignatureRequest, SignatureResponse};
-use serde::{Serialize,Deserialize};
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
-struct ConfirmationMessage {
-    email: String,
-    status: Status,
-}
+use email_confirmation_service_common::callback_host::{callback_host, Admission};
+use email_confirmation_service_common::callback_url::CallbackTarget;
+use email_confirmation_service_common::callback_event::{CallbackEvent, CallbackEventType, CallbackPayload, DEFAULT_SUBSCRIBED_EVENTS};
+use email_confirmation_service_common::client_config::ClientConfig;
+use email_confirmation_service_common::webhook_signature::{webhook_signature_header, WEBHOOK_SIGNATURE_HEADER, WEBHOOK_TIMESTAMP_HEADER};
+use reqwest::header::{HeaderMap, HeaderValue};
+
+use crate::callback_auth::CallbackCredentials;
+use crate::callback_delivery::{DeliveryOutcome, DeliveryReport};
+use crate::callback_host_store::CallbackHostStore;
+use crate::callback_transport::{CallbackMessage, CallbackTransports};
+use crate::client_config_store::ClientConfigStore;
+use crate::delivery_log_store::{delivery_log_entry, DeliveryLogStore};
+use crate::callback_retry::{FailedAttemptOutcome, RetryPolicy};
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
-struct ConfirmationMessageResponse {
-    message: String,
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum RecordAction {
+    Skip,
+    // Callback, then mark the request Done
+    CompleteConfirmation(EmailConfirmationRequest),
+    // Callback only, nothing else to do for the status (pending, bounced, expired, cancelled, ...)
+    NotifyStatusChange(EmailConfirmationRequest),
+    // Send the latest logged callback again, as it was, on request through the API
+    ReplayCallback(EmailConfirmationRequest),
 }
 
-pub(crate)async fn function_handler(event: LambdaEvent<Event>) -> Result<(), Error> {
+/// The transports are shared by the invocations of the lambda container, so that access tokens and decrypted secrets are reused.
+pub(crate)async fn function_handler(callback_transports: &CallbackTransports, event: LambdaEvent<Event>) -> Result<(), Error> {
     // Extract some useful information from the request
     let payload = event.payload;
     tracing::info!("Payload: {:?}", payload);
 
+    let mut client_config_store = ClientConfigStore::default();
+    let mut delivery_log_store = DeliveryLogStore::default();
+    let mut callback_host_store = CallbackHostStore::default();
     let records = payload.records;
     for record in records.iter() {
-        if record.event_name != "MODIFY" {
-            return Ok(())
+        match record_action(record) {
+            RecordAction::Skip => continue,
+            RecordAction::CompleteConfirmation(confirmation_request) => {
+                let client_config = client_config_store.get(&confirmation_request.client_id).await?;
+                if !is_subscribed(client_config.as_ref(), &confirmation_request) {
+                    tracing::info!("Client {} is not subscribed to confirmations, skipping callback for {}.", &confirmation_request.client_id, &confirmation_request.pk);
+                    let signature = create_signature(&confirmation_request).await?;
+                    set_status(&confirmation_request, Done, None, signature).await?;
+                    continue
+                }
+                match trigger_callback(callback_transports, &mut delivery_log_store, &mut callback_host_store, client_config.as_ref(), &confirmation_request).await? {
+                    DeliveryOutcome::Delivered(_) => {
+                        let signature = create_signature(&confirmation_request).await?;
+                        tracing::info!("Setting status to done for {}.", &confirmation_request.pk);
+                        set_status(&confirmation_request, Done, None, signature).await?;
+                    },
+                    DeliveryOutcome::Retryable(error) => handle_callback_failure(&confirmation_request, error, false).await?,
+                    DeliveryOutcome::Permanent(error) => handle_callback_failure(&confirmation_request, error, true).await?,
+                    DeliveryOutcome::Deferred { until, reason } => defer_callback(&confirmation_request, until, &reason).await?,
+                }
+            },
+            RecordAction::NotifyStatusChange(confirmation_request) => {
+                let client_config = client_config_store.get(&confirmation_request.client_id).await?;
+                if !is_subscribed(client_config.as_ref(), &confirmation_request) {
+                    tracing::info!("Client {} is not subscribed to status {}, skipping callback for {}.", &confirmation_request.client_id, &confirmation_request.status, &confirmation_request.pk);
+                    continue
+                }
+                match trigger_callback(callback_transports, &mut delivery_log_store, &mut callback_host_store, client_config.as_ref(), &confirmation_request).await? {
+                    DeliveryOutcome::Delivered(_) => {},
+                    DeliveryOutcome::Retryable(error) => handle_callback_failure(&confirmation_request, error, false).await?,
+                    DeliveryOutcome::Permanent(error) => handle_callback_failure(&confirmation_request, error, true).await?,
+                    DeliveryOutcome::Deferred { until, reason } => defer_callback(&confirmation_request, until, &reason).await?,
+                }
+            },
+            RecordAction::ReplayCallback(confirmation_request) => {
+                // A replay is a one-off: its failure is logged, but not retried and does not change the status
+                let Some(latest_delivery) = delivery_log_store.latest(&confirmation_request.pk).await? else {
+                    tracing::warn!("No callback to replay for {}.", &confirmation_request.pk);
+                    continue
+                };
+                let client_config = client_config_store.get(&confirmation_request.client_id).await?;
+                let callback = Callback {
+                    event_id: latest_delivery.event_id,
+                    event_type: latest_delivery.event_type,
+                    body: latest_delivery.payload,
+                    replay: true,
+                };
+                let outcome = send_callback(callback_transports, &mut delivery_log_store, &mut callback_host_store, client_config.as_ref(), &confirmation_request, callback).await?;
+                tracing::info!("Replayed callback {} for {}: {:?}", &latest_delivery.delivery_id, &confirmation_request.pk, outcome);
+            }
         }
+    }
+    Ok(())
+}
 
-        if record.event_source != Some("aws:dynamodb".to_string()) {
-            return Ok(())
+fn record_action(record: &EventRecord) -> RecordAction {
+    if record.event_name != "MODIFY" {
+        return RecordAction::Skip
+    }
+
+    if record.event_source != Some("aws:dynamodb".to_string()) {
+        return RecordAction::Skip
+    }
+
+    let confirmation_request: EmailConfirmationRequest = match from_item(record.change.new_image.clone()) {
+        Ok(confirmation_request) => confirmation_request,
+        Err(error) => {
+            tracing::warn!("Skipping record {} that is not a confirmation request: {}", &record.event_id, error);
+            return RecordAction::Skip
         }
+    };
 
-        let confirmation_request: EmailConfirmationRequest = from_item(record.clone().change.new_image).unwrap();
-        if confirmation_request.status != Confirmed {
-            return Ok(())
+    let previous_request = from_item::<_, EmailConfirmationRequest>(record.change.old_image.clone()).ok();
+    let status_changed = previous_request.as_ref().map(|previous_request| &previous_request.status) != Some(&confirmation_request.status);
+    // The sweeper sets callback_retry_requested_at when a failed callback is due for another attempt
+    let retry_requested = confirmation_request.callback_retry_requested_at.is_some()
+        && previous_request.as_ref().and_then(|previous_request| previous_request.callback_retry_requested_at) != confirmation_request.callback_retry_requested_at;
+    // Set through the API, the latest callback is sent again whatever the status
+    let replay_requested = confirmation_request.callback_replay_requested_at.is_some()
+        && previous_request.as_ref().and_then(|previous_request| previous_request.callback_replay_requested_at) != confirmation_request.callback_replay_requested_at;
+    if !status_changed && !retry_requested {
+        return match replay_requested {
+            true => RecordAction::ReplayCallback(confirmation_request),
+            false => RecordAction::Skip,
         }
+    }
 
-        let result = trigger_callback(confirmation_request.clone()).await;
-        match result {
-            Ok(_) => {
-                let signature = create_signature(&confirmation_request).await?;
-                tracing::info!("Setting status to done for {}.", &confirmation_request.pk);
-                set_status_to_done(&confirmation_request, signature).await?;
-            },
-            Err(_) => {
-                tracing::error!("Callback failed for {}.", &confirmation_request.pk);
-            }
+    // Statuses without an event (Queued, Done, CallbackFailed) are bookkeeping of this service
+    if CallbackEventType::for_status(&confirmation_request.status).is_none() {
+        return RecordAction::Skip
+    }
+
+    match confirmation_request.status {
+        Confirmed => RecordAction::CompleteConfirmation(confirmation_request),
+        _ => RecordAction::NotifyStatusChange(confirmation_request),
+    }
+}
+
+/// Clients without a config get the default subscriptions, which leave out the chattier events (pending, cancelled).
+fn is_subscribed(client_config: Option<&ClientConfig>, confirmation_request: &EmailConfirmationRequest) -> bool {
+    let Some(event_type) = CallbackEventType::for_status(&confirmation_request.status) else { return false };
+    match client_config {
+        Some(client_config) => client_config.is_subscribed_to(event_type),
+        None => DEFAULT_SUBSCRIBED_EVENTS.contains(&event_type),
+    }
+}
+
+/// Records the failed attempt with the time of the next one, or gives up, see status_on_give_up.
+/// A permanent failure gives up right away.
+async fn handle_callback_failure(confirmation_request: &EmailConfirmationRequest, error: String, permanent: bool) -> Result<(), Error> {
+    let attempt_number = confirmation_request.callback_attempts.len() + 1;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    tracing::error!("Callback attempt {} failed for {}: {}", attempt_number, &confirmation_request.pk, &error);
+
+    let signature = create_signature(confirmation_request).await?;
+    let outcome = if permanent {
+        FailedAttemptOutcome::GiveUp
+    } else {
+        RetryPolicy::default().after_failed_attempt(attempt_number, now, rand::random::<f64>())
+    };
+    match outcome {
+        FailedAttemptOutcome::RetryAt(next_attempt_at) => {
+            record_callback_attempt(confirmation_request, attempt_number, now, &error, Some(next_attempt_at), signature).await
+        },
+        FailedAttemptOutcome::GiveUp => {
+            record_callback_attempt(confirmation_request, attempt_number, now, &error, None, signature.clone()).await?;
+            let Some(status) = status_on_give_up(&confirmation_request.status) else {
+                tracing::warn!("Gave up on the callback for {}, it keeps the status {}.", &confirmation_request.pk, &confirmation_request.status);
+                return Ok(())
+            };
+            let reason = format!("Callback failed after {} attempts: {}", attempt_number, error);
+            set_status(confirmation_request, status, Some(reason), signature).await
         }
     }
-    Ok(())
 }
 
-async fn trigger_callback(email_confirmation_request: EmailConfirmationRequest) -> Result<(), Error> {
-    let callback_url = email_confirmation_request.callback_url;
-    let message_json = json!(ConfirmationMessage{email: email_confirmation_request.email.clone(), status: Confirmed});
+/// Only a confirmation ends as CallbackFailed. A request that has ended otherwise, e.g. Expired, keeps its status and status_reason.
+fn status_on_give_up(status: &Status) -> Option<Status> {
+    match status {
+        Confirmed => Some(CallbackFailed),
+        _ => None,
+    }
+}
+
+/// Puts the callback off without using up an attempt, the sweeper requests it again at until.
+async fn defer_callback(confirmation_request: &EmailConfirmationRequest, until: u64, reason: &str) -> Result<(), Error> {
+    tracing::warn!("Callback for {} deferred until {}: {}", &confirmation_request.pk, until, reason);
+    let signature = create_signature(confirmation_request).await?;
+    let service_url = env::var("EMAIL_CONFIRMATION_REQUEST_SERVICE_URL")?;
+    let api_key = env::var("EMAIL_CONFIRMATION_REQUEST_SERVICE_INTERNAL_API_KEY")?;
+
+    let put_url = format!("{}/email-confirmation-requests/{}/callback-next-attempt", service_url, encode(&confirmation_request.pk));
     let reqwest_client = reqwest::Client::new();
     let response = reqwest_client
-        .post(callback_url)
+        .put(put_url)
+        .header("x-api-key", api_key)
         .header("Content-Type", "application/json")
-        .json(&message_json)
+        .json(&json!({
+            "next_attempt_at": until,
+            "reason": reason,
+            "signature": signature
+        }))
         .send()
-        .await
-        .unwrap();
-    Ok(())
+        .await?;
+
+    let json_data : EmailConfirmationServiceApiResponse = response.json().await?;
+    if json_data.error {
+        Err(Error::from("Email confirmation service error"))
+    } else {
+        Ok(())
+    }
+}
+
+/// A callback body with what the delivery log needs to know about it.
+struct Callback {
+    event_id: Option<String>,
+    event_type: Option<CallbackEventType>,
+    body: String,
+    replay: bool,
+}
+
+async fn trigger_callback(callback_transports: &CallbackTransports, delivery_log_store: &mut DeliveryLogStore, callback_host_store: &mut CallbackHostStore, client_config: Option<&ClientConfig>, email_confirmation_request: &EmailConfirmationRequest) -> Result<DeliveryOutcome, Error> {
+    let payload_version = client_config.map(|client_config| client_config.callback_payload_version).unwrap_or_default();
+    let Some(payload) = CallbackPayload::new(payload_version, email_confirmation_request) else {
+        return Ok(DeliveryOutcome::Permanent(format!("No callback for status {}", email_confirmation_request.status)))
+    };
+    let event_type = CallbackEventType::for_status(&email_confirmation_request.status);
+    let callback = Callback {
+        event_id: event_type.map(|event_type| CallbackEvent::event_id(email_confirmation_request, event_type)),
+        event_type,
+        body: serde_json::to_string(&payload)?,
+        replay: false,
+    };
+    send_callback(callback_transports, delivery_log_store, callback_host_store, client_config, email_confirmation_request, callback).await
+}
+
+/// Signs and sends the body with the transport of the callback url, and logs the attempt. A failure to log does not fail the callback.
+/// HTTP callbacks go through the circuit breaker of their host. Replays skip it, they are sent on request, but their outcome counts.
+/// The breaker fails open: when its state cannot be read or written the callback is sent anyway.
+async fn send_callback(
+    callback_transports: &CallbackTransports,
+    delivery_log_store: &mut DeliveryLogStore,
+    callback_host_store: &mut CallbackHostStore,
+    client_config: Option<&ClientConfig>,
+    email_confirmation_request: &EmailConfirmationRequest,
+    callback: Callback,
+) -> Result<DeliveryOutcome, Error> {
+    let started_at = SystemTime::now().duration_since(UNIX_EPOCH)?;
+    let secrets = match client_config {
+        Some(client_config) => callback_transports.webhook_secrets(client_config, started_at.as_secs()).await,
+        None => Ok(vec![]),
+    };
+    let secrets = match secrets {
+        Ok(secrets) => secrets,
+        // Not sent, a callback is never sent unsigned when the client has a webhook secret
+        Err(outcome) => {
+            tracing::error!("Could not decrypt the webhook secrets of client {}: {:?}", &email_confirmation_request.client_id, outcome);
+            return Ok(outcome)
+        },
+    };
+    let headers = signature_headers(&secrets, started_at.as_secs(), &callback.body)?;
+    if headers.is_empty() {
+        tracing::warn!("No webhook secret for client {}, sending the callback unsigned.", &email_confirmation_request.client_id);
+    }
+    let message = CallbackMessage {
+        body: callback.body,
+        headers,
+        event_type: callback.event_type,
+        credentials: client_config.and_then(CallbackCredentials::of),
+    };
+
+    let host = CallbackTarget::parse(&email_confirmation_request.callback_url).ok().as_ref().and_then(callback_host);
+    let admission = match &host {
+        Some(host) if !callback.replay => callback_host_store.admit(host, started_at.as_secs()).await.unwrap_or_else(|error| {
+            tracing::error!("Could not check the circuit of callback host {}: {}", host, error);
+            Admission::Deliver
+        }),
+        _ => Admission::Deliver,
+    };
+    let report = match admission {
+        Admission::Defer { until, reason } => DeliveryReport {
+            outcome: DeliveryOutcome::Deferred { until, reason },
+            response_status: None,
+            latency: Duration::ZERO,
+        },
+        Admission::Deliver | Admission::Probe => {
+            let report = callback_transports.deliver(&email_confirmation_request.callback_url, message.clone()).await;
+            if let Some(host) = &host {
+                // Only failures that say something about the host count, a 404 means the host is up
+                let error = match &report.outcome {
+                    DeliveryOutcome::Retryable(error) => Some(error.as_str()),
+                    _ => None,
+                };
+                let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+                if let Err(error) = callback_host_store.record(host, started_at.as_secs(), error, now).await {
+                    tracing::error!("Could not record the health of callback host {}: {}", host, error);
+                }
+            }
+            report
+        },
+    };
+
+    let delivery = delivery_log_entry(email_confirmation_request, callback.event_id, &message, &report, started_at.as_millis() as u64, callback.replay);
+    if let Err(error) = delivery_log_store.record(&delivery).await {
+        tracing::error!("Could not log callback delivery {} for {}: {}", &delivery.delivery_id, &email_confirmation_request.pk, error);
+    }
+    Ok(report.outcome)
+}
+
+/// Timestamp and signature headers with a signature per active webhook secret of the client, none if it has no secret.
+fn signature_headers(secrets: &[String], now: u64, body: &str) -> Result<HeaderMap, Error> {
+    let mut headers = HeaderMap::new();
+    if secrets.is_empty() {
+        return Ok(headers)
+    }
+    let secrets: Vec<&str> = secrets.iter().map(String::as_str).collect();
+    headers.insert(WEBHOOK_TIMESTAMP_HEADER, HeaderValue::from_str(&now.to_string())?);
+    headers.insert(WEBHOOK_SIGNATURE_HEADER, HeaderValue::from_str(&webhook_signature_header(&secrets, now, body.as_bytes()))?);
+    Ok(headers)
 }
 
 async fn create_signature(email_confirmation_request: &EmailConfirmationRequest) -> Result<String, Error> {
@@ -101,20 +358,47 @@ async fn create_signature(email_confirmation_request: &EmailConfirmationRequest)
     Err(Error::from("Error creating signature"))
 }
 
-async fn set_status_to_done(confirmation_request: &EmailConfirmationRequest, signature: String) -> Result<(), Error> {
+async fn record_callback_attempt(confirmation_request: &EmailConfirmationRequest, attempt_number: usize, attempted_at: u64, error: &str, next_attempt_at: Option<u64>, signature: String) -> Result<(), Error> {
     let service_url = env::var("EMAIL_CONFIRMATION_REQUEST_SERVICE_URL")?;
     let api_key = env::var("EMAIL_CONFIRMATION_REQUEST_SERVICE_INTERNAL_API_KEY")?;
 
-    let put_url = format!("{}/email-confirmation-requests/{}/status", service_url, encode(&confirmation_request.clone().pk));
+    let post_url = format!("{}/email-confirmation-requests/{}/callback-attempts", service_url, encode(&confirmation_request.pk));
+    let reqwest_client = reqwest::Client::new();
+    let response = reqwest_client
+        .post(post_url)
+        .header("x-api-key", api_key)
+        .header("Content-Type", "application/json")
+        .json(&json!({
+            "attempt_number": attempt_number,
+            "attempted_at": attempted_at,
+            "error": error,
+            "next_attempt_at": next_attempt_at,
+            "signature": signature
+        }))
+        .send()
+        .await?;
+
+    let json_data : EmailConfirmationServiceApiResponse = response.json().await?;
+    if json_data.error {
+        Err(Error::from("Email confirmation service error"))
+    } else {
+        Ok(())
+    }
+}
+
+async fn set_status(confirmation_request: &EmailConfirmationRequest, status: Status, reason: Option<String>, signature: String) -> Result<(), Error> {
+    let service_url = env::var("EMAIL_CONFIRMATION_REQUEST_SERVICE_URL")?;
+    let api_key = env::var("EMAIL_CONFIRMATION_REQUEST_SERVICE_INTERNAL_API_KEY")?;
+
+    let put_url = format!("{}/email-confirmation-requests/{}/status", service_url, encode(&confirmation_request.pk));
     let reqwest_client = reqwest::Client::new();
     let response = reqwest_client
         .put(put_url)
         .header("x-api-key", api_key)
         .header("Content-Type", "application/json")
-        .json(&json!({"status": "Done", "signature": signature}))
+        .json(&json!({"status": status, "signature": signature, "reason": reason}))
         .send()
-        .await
-        .unwrap();
+        .await?;
 
     let json_data : EmailConfirmationServiceApiResponse = response.json().await?;
     if json_data.error {
@@ -125,5 +409,218 @@ async fn set_status_to_done(confirmation_request: &EmailConfirmationRequest, sig
 }
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+    use super::*;
+    use std::time::Duration;
+    use email_confirmation_service_common::client_config::new_webhook_secret;
+    use email_confirmation_service_common::delivery_log::{DeliveryResult, DELIVERY_LOG_ERROR_LIMIT};
+    use crate::callback_delivery::DeliveryReport;
+    use email_confirmation_service_common::webhook_signature::verify_webhook;
+    use lambda_runtime::{Context, LambdaEvent};
+    use serde_dynamo::{Item, AttributeValue, AttributeValue::S, AttributeValue::N};
+
+    #[tokio::test]
+    async fn test_event_handler() {
+        let event = LambdaEvent::new(example_dynamodb_event(), Context::default());
+        let callback_transports = CallbackTransports::with_defaults().unwrap();
+        assert!(function_handler(&callback_transports, event).await.is_ok());
+    }
 
+    #[test]
+    fn test_confirmed_request_is_completed() {
+        let record = modify_record("Pending", "Confirmed");
+        assert!(matches!(record_action(&record), RecordAction::CompleteConfirmation(_)));
+    }
+
+    #[test]
+    fn test_status_on_give_up() {
+        assert_eq!(Some(CallbackFailed), status_on_give_up(&Confirmed));
+        for status in [Status::Bounced, Status::Complained, Status::Expired, Status::Suppressed, Status::Cancelled, Status::Declined] {
+            assert_eq!(None, status_on_give_up(&status), "{}", status);
+        }
+    }
+
+    #[test]
+    fn test_failed_pending_callback_keeps_status() {
+        let RecordAction::NotifyStatusChange(pending_request) = record_action(&modify_record("Queued", "Pending")) else { panic!("Expected NotifyStatusChange") };
+        // Only the attempt is recorded, the request can still be confirmed
+        assert_eq!(None, status_on_give_up(&pending_request.status));
+    }
+
+    #[test]
+    fn test_bounce_is_notified_once() {
+        let record = modify_record("Pending", "Bounced");
+        let RecordAction::NotifyStatusChange(confirmation_request) = record_action(&record) else { panic!("Expected NotifyStatusChange") };
+        assert_eq!(Some("Permanent bounce (General)".to_string()), confirmation_request.status_reason);
+
+        assert_eq!(RecordAction::Skip, record_action(&modify_record("Bounced", "Bounced")));
+        assert!(matches!(record_action(&modify_record("Pending", "Complained")), RecordAction::NotifyStatusChange(_)));
+        assert!(matches!(record_action(&modify_record("Pending", "Suppressed")), RecordAction::NotifyStatusChange(_)));
+    }
+
+    #[test]
+    fn test_expiry_is_notified_once() {
+        assert!(matches!(record_action(&modify_record("Pending", "Expired")), RecordAction::NotifyStatusChange(_)));
+        assert_eq!(RecordAction::Skip, record_action(&modify_record("Expired", "Expired")));
+    }
+
+    #[test]
+    fn test_lifecycle_events_are_notified() {
+        assert!(matches!(record_action(&modify_record("Queued", "Pending")), RecordAction::NotifyStatusChange(_)));
+        assert!(matches!(record_action(&modify_record("Pending", "Cancelled")), RecordAction::NotifyStatusChange(_)));
+        assert_eq!(RecordAction::Skip, record_action(&modify_record("Cancelled", "Cancelled")));
+        let RecordAction::NotifyStatusChange(declined_request) = record_action(&modify_record("Pending", "Declined")) else { panic!("Expected NotifyStatusChange") };
+        // Clients get declines without subscribing, like confirmations
+        assert!(is_subscribed(None, &declined_request));
+    }
+
+    #[test]
+    fn test_other_modifications_are_skipped() {
+        assert_eq!(RecordAction::Skip, record_action(&modify_record("Pending", "Queued")));
+        assert_eq!(RecordAction::Skip, record_action(&modify_record("Confirmed", "Done")));
+        assert_eq!(RecordAction::Skip, record_action(&modify_record("Confirmed", "CallbackFailed")));
+    }
+
+    #[test]
+    fn test_recorded_attempt_does_not_trigger_callback() {
+        let mut record = modify_record("Confirmed", "Confirmed");
+        insert_attribute(&mut record, "callback_next_attempt_at", N("1741592536".to_string()));
+        assert_eq!(RecordAction::Skip, record_action(&record));
+    }
+
+    #[test]
+    fn test_requested_retry_triggers_callback() {
+        let mut record = modify_record("Confirmed", "Confirmed");
+        insert_attribute(&mut record, "callback_retry_requested_at", N("1741592536".to_string()));
+        let RecordAction::CompleteConfirmation(confirmation_request) = record_action(&record) else { panic!("Expected CompleteConfirmation") };
+        assert_eq!(1, confirmation_request.callback_attempts.len());
+
+        let mut record = modify_record("Expired", "Expired");
+        insert_attribute(&mut record, "callback_retry_requested_at", N("1741592536".to_string()));
+        assert!(matches!(record_action(&record), RecordAction::NotifyStatusChange(_)));
+    }
+
+    #[test]
+    fn test_requested_replay_sends_latest_callback_again() {
+        let mut record = modify_record("Done", "Done");
+        insert_attribute(&mut record, "callback_replay_requested_at", N("1741592536".to_string()));
+        assert!(matches!(record_action(&record), RecordAction::ReplayCallback(_)));
+
+        // Already requested in the old image too, i.e. some other change
+        let mut record = modify_record("Done", "Done");
+        insert_attribute(&mut record, "callback_replay_requested_at", N("1741592536".to_string()));
+        record.change.old_image = record.change.new_image.clone();
+        assert_eq!(RecordAction::Skip, record_action(&record));
+    }
+
+    #[test]
+    fn test_delivery_log_entry() {
+        let RecordAction::NotifyStatusChange(confirmation_request) = record_action(&modify_record("Pending", "Bounced")) else { panic!("Expected NotifyStatusChange") };
+        let report = DeliveryReport {
+            outcome: DeliveryOutcome::Retryable(format!("Callback responded 503 Service Unavailable: {}", "x".repeat(1000))),
+            response_status: Some(503),
+            latency: Duration::from_millis(1234),
+        };
+        let message = CallbackMessage { body: "{}".to_string(), headers: HeaderMap::new(), event_type: Some(CallbackEventType::RequestBounced), credentials: None };
+        let delivery = delivery_log_entry(&confirmation_request, Some("evt_1".to_string()), &message, &report, 1_741_592_476_789, false);
+        assert_eq!(confirmation_request.pk, delivery.pk);
+        assert!(delivery.delivery_id.starts_with("0001741592476789-"));
+        assert_eq!(1_741_592_476, delivery.attempted_at);
+        assert_eq!(1234, delivery.latency_ms);
+        assert_eq!(Some(503), delivery.response_status);
+        assert_eq!(DeliveryResult::Retryable, delivery.result);
+        assert_eq!(DELIVERY_LOG_ERROR_LIMIT + "...".len(), delivery.error.unwrap().len());
+        assert_eq!(confirmation_request.callback_url, delivery.callback_url);
+        assert_eq!(Some(CallbackEventType::RequestBounced), delivery.event_type);
+        assert_eq!("{}", delivery.payload);
+    }
+
+    #[test]
+    fn test_deferred_delivery_log_entry() {
+        let RecordAction::CompleteConfirmation(confirmation_request) = record_action(&modify_record("Pending", "Confirmed")) else { panic!("Expected CompleteConfirmation") };
+        let report = DeliveryReport {
+            outcome: DeliveryOutcome::Deferred { until: 1_741_592_776, reason: "Circuit open for callback.example.com".to_string() },
+            response_status: None,
+            latency: Duration::ZERO,
+        };
+        let message = CallbackMessage { body: "{}".to_string(), headers: HeaderMap::new(), event_type: Some(CallbackEventType::RequestConfirmed), credentials: None };
+        let delivery = delivery_log_entry(&confirmation_request, Some("evt_1".to_string()), &message, &report, 1_741_592_476_789, false);
+        assert_eq!(DeliveryResult::Deferred, delivery.result);
+        assert_eq!(Some("Circuit open for callback.example.com".to_string()), delivery.error);
+        assert_eq!(None, delivery.response_status);
+    }
+
+    #[test]
+    fn test_callback_is_signed_with_active_secrets() {
+        let now = 1_741_592_476;
+        let new_secret = new_webhook_secret();
+        let old_secret = new_webhook_secret();
+
+        let body = r#"{"email":"email@example.com","status":"Confirmed"}"#;
+        let headers = signature_headers(&[new_secret.clone(), old_secret.clone()], now, body).unwrap();
+        let timestamp = headers[WEBHOOK_TIMESTAMP_HEADER].to_str().unwrap();
+        let signature = headers[WEBHOOK_SIGNATURE_HEADER].to_str().unwrap();
+        assert_eq!(Ok(()), verify_webhook(&new_secret, timestamp, signature, body.as_bytes(), now));
+        assert_eq!(Ok(()), verify_webhook(&old_secret, timestamp, signature, body.as_bytes(), now));
+    }
+
+    #[test]
+    fn test_callback_without_secret_is_unsigned() {
+        assert!(signature_headers(&[], 1_741_592_476, "{}").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_subscriptions() {
+        let RecordAction::NotifyStatusChange(pending_request) = record_action(&modify_record("Queued", "Pending")) else { panic!("Expected NotifyStatusChange") };
+        let RecordAction::NotifyStatusChange(bounced_request) = record_action(&modify_record("Pending", "Bounced")) else { panic!("Expected NotifyStatusChange") };
+        assert!(!is_subscribed(None, &pending_request));
+        assert!(is_subscribed(None, &bounced_request));
+
+        let mut client_config = ClientConfig::new("me_myself_and_i-3");
+        client_config.subscribed_events = vec![CallbackEventType::RequestPending];
+        assert!(is_subscribed(Some(&client_config), &pending_request));
+        assert!(!is_subscribed(Some(&client_config), &bounced_request));
+    }
+
+    fn example_dynamodb_event() -> Event {
+        let data = include_bytes!("../fixtures/example-dynamodb-event.json");
+        serde_json::from_slice(data).unwrap()
+    }
+
+    fn request_image(status: &str) -> Item {
+        let mut image: HashMap<String, AttributeValue> = HashMap::from([
+            ("signature_key".to_string(), S("75af2381-ecde-4113-af22-75c2c1407d98".to_string())),
+            ("request_id".to_string(), S("req-3".to_string())),
+            ("status".to_string(), S(status.to_string())),
+            ("callback_url".to_string(), S("https://client.example.com/email-confirmation".to_string())),
+            ("pk".to_string(), S("email@example.com#me_myself_and_i-3#req-3".to_string())),
+            ("expires_at".to_string(), N("1741596076".to_string())),
+            ("client_id".to_string(), S("me_myself_and_i-3".to_string())),
+            ("email".to_string(), S("email@example.com".to_string())),
+            ("updated_at".to_string(), N("1741592476".to_string())),
+            ("created_at".to_string(), N("1741592476".to_string())),
+        ]);
+        if status == "Bounced" {
+            image.insert("status_reason".to_string(), S("Permanent bounce (General)".to_string()));
+        }
+        Item::from(image)
+    }
+
+    // Adds the attribute to the new image, along with the failed attempt that led to it
+    fn insert_attribute(record: &mut EventRecord, name: &str, value: AttributeValue) {
+        let mut new_image: HashMap<String, AttributeValue> = record.change.new_image.clone().into_inner();
+        new_image.insert(name.to_string(), value);
+        new_image.insert("callback_attempts".to_string(), AttributeValue::L(vec![AttributeValue::M(HashMap::from([
+            ("attempted_at".to_string(), N("1741592476".to_string())),
+            ("error".to_string(), S("connection refused".to_string())),
+        ]))]));
+        record.change.new_image = Item::from(new_image);
+    }
+
+    fn modify_record(old_status: &str, new_status: &str) -> EventRecord {
+        let mut record = example_dynamodb_event().records.pop().unwrap();
+        record.change.old_image = request_image(old_status);
+        record.change.new_image = request_image(new_status);
+        record
+    }
 }
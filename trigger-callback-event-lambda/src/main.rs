@@ -1,12 +1,22 @@
 use lambda_runtime::{run, service_fn, tracing, Error};
 
+mod callback_auth;
+mod callback_delivery;
+mod callback_guard;
+mod callback_host_store;
+mod callback_transport;
+mod callback_retry;
+mod client_config_store;
+mod delivery_log_store;
 mod event_handler;
 use event_handler::function_handler;
+use crate::callback_transport::CallbackTransports;
 
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     tracing::init_default_subscriber();
 
-    run(service_fn(function_handler)).await
+    let callback_transports = CallbackTransports::with_defaults()?;
+    run(service_fn(|event| function_handler(&callback_transports, event))).await
 }